@@ -642,12 +642,12 @@ impl<'model> RustCodeGen<'model> {
                         SHAPE_TIMESTAMP => "Timestamp",
                         SHAPE_BIGINTEGER => {
                             cfg_if::cfg_if! {
-                                if #[cfg(feature = "BigInteger")] { "BigInteger" } else { return Err(Error::UnsupportedBigInteger) }
+                                if #[cfg(feature = "BigInteger")] { "BigInteger" } else { "String" }
                             }
                         }
                         SHAPE_BIGDECIMAL => {
                             cfg_if::cfg_if! {
-                                if #[cfg(feature = "BigDecimal")] { "BigDecimal" } else { return Err(Error::UnsupportedBigDecimal) }
+                                if #[cfg(feature = "BigDecimal")] { "BigDecimal" } else { "String" }
                             }
                         }
                         _ => return Err(Error::UnsupportedType(name)),
@@ -734,12 +734,12 @@ impl<'model> RustCodeGen<'model> {
             Simple::Timestamp => "Timestamp",
             Simple::BigInteger => {
                 cfg_if::cfg_if! {
-                    if #[cfg(feature = "BigInteger")] { "BigInteger" } else { return Err(Error::UnsupportedBigInteger) }
+                    if #[cfg(feature = "BigInteger")] { "BigInteger" } else { "String" }
                 }
             }
             Simple::BigDecimal => {
                 cfg_if::cfg_if! {
-                    if #[cfg(feature = "BigDecimal")] { "BigDecimal" } else { return Err(Error::UnsupportedBigDecimal) }
+                    if #[cfg(feature = "BigDecimal")] { "BigDecimal" } else { "String" }
                 }
             }
         };
@@ -1096,6 +1096,8 @@ impl<'model> RustCodeGen<'model> {
         "#,
         );
 
+        // OperationInfo entries, written after the dispatch function
+        let mut op_info = String::new();
         for method_id in service.service.operations() {
             // we don't add operations defined in another namespace
             if let Some(ref ns) = self.namespace {
@@ -1105,6 +1107,25 @@ impl<'model> RustCodeGen<'model> {
             }
             let method_ident = method_id.shape_name();
             let (op, method_traits) = get_operation(model, method_id, service.id)?;
+            if method_traits.contains_key(crate::model::one_way_trait()) && op.output().is_some() {
+                return Err(Error::Model(format!(
+                    "operation {method_ident} is oneWay, so it can't have an output"
+                )));
+            }
+            let type_name = |shape: &Option<ShapeID>| match shape {
+                Some(id) => format!("Some(\"{}\")", id.shape_name()),
+                None => "None".to_string(),
+            };
+            writeln!(
+                op_info,
+                "{}::common::OperationInfo {{ name: \"{}\", input_type: {}, output_type: {}, one_way: {} }},",
+                self.import_core,
+                self.full_dispatch_name(service.id, method_ident),
+                type_name(op.input()),
+                type_name(op.output()),
+                method_traits.contains_key(crate::model::one_way_trait()),
+            )
+            .unwrap();
             w.write(b"\"");
             w.write(&self.op_dispatch_name(method_ident));
             w.write(b"\" => {\n");
@@ -1188,7 +1209,19 @@ impl<'model> RustCodeGen<'model> {
         w.write(b"_ => Err(RpcError::MethodNotHandled(format!(\"");
         self.write_ident(w, service.id);
         w.write(b"::{}\", message.method))),\n");
-        w.write(b"}\n}\n}\n\n"); // end match, end fn dispatch, end trait
+        w.write(b"}\n}\n\n"); // end match, end fn dispatch
+        writeln!(
+            w,
+            r#"/// Operations of the {} service, with their input and output type names
+            fn service_operation_info(&self) -> &'static [{}::common::OperationInfo] {{
+                &[
+                {}
+                ]
+            }}"#,
+            service.id, self.import_core, op_info,
+        )
+        .unwrap();
+        w.write(b"}\n\n"); // end trait
 
         Ok(())
     }
@@ -1301,7 +1334,12 @@ impl<'model> RustCodeGen<'model> {
             w.write(b"\"");
             w.write(&self.full_dispatch_name(service.id, method_ident));
             //w.write(&self.op_dispatch_name(method_ident));
-            w.write(b"\", arg: Cow::Borrowed(&buf)}, None).await?;\n");
+            if method_traits.contains_key(crate::model::one_way_trait()) {
+                // one-way: publish, and don't wait for a response
+                w.write(b"\", arg: Cow::Borrowed(&buf)}, Some(SendOpts::default().no_reply(true))).await?;\n");
+            } else {
+                w.write(b"\", arg: Cow::Borrowed(&buf)}, None).await?;\n");
+            }
             if let Some(op_output) = op.output() {
                 let symbol = op_output.shape_name().to_string();
                 if has_cbor {
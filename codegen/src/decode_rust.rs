@@ -24,6 +24,24 @@ use crate::{
     writer::Writer,
 };
 
+/// Returns the decoder of an optional field's value as the Result that `Decoder::optional`
+/// expects: a single call like "d.u64()?" loses its `?`, and "d.str()?.to_string()"
+/// maps the call's result. Other decoders are wrapped in `Ok`.
+fn optional_decoder(decoder: &str) -> String {
+    match decoder.split_once('?') {
+        Some((call, rest)) if !call.contains(char::is_whitespace) && !rest.contains('?') => {
+            if rest.is_empty() {
+                call.to_string()
+            } else if rest.starts_with('.') && !rest.contains(char::is_whitespace) {
+                format!("{call}.map(|v| v{rest})")
+            } else {
+                format!("Ok({decoder})")
+            }
+        }
+        _ => format!("Ok({decoder})"),
+    }
+}
+
 // decodes byte slice of definite length; returns <&'b [u8]>
 fn decode_blob() -> &'static str {
     "d.bytes()?.to_vec()"
@@ -69,10 +87,23 @@ fn decode_timestamp() -> &'static str {
     "wasmbus_rpc::Timestamp{ sec: d.i64()?, nsec: d.u32()? }"
 }
 fn decode_big_integer() -> &'static str {
-    todo!(); // tag big int
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "BigInteger")] {
+            "d.big_integer()?"
+        } else {
+            // without the feature, the value is carried as a decimal string
+            "d.big_integer_str()?"
+        }
+    }
 }
 fn decode_big_decimal() -> &'static str {
-    todo!() // tag big decimal
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "BigDecimal")] {
+            todo!() // tag big decimal
+        } else {
+            "d.big_decimal_str()?"
+        }
+    }
 }
 fn decode_document() -> &'static str {
     "wasmbus_rpc::common::decode_document(d)?"
@@ -327,14 +358,12 @@ impl<'model> RustCodeGen<'model> {
             if is_optional_type(field) {
                 write!(
                     s,
-                    r#"{} => {} = if {}::cbor::Type::Null == d.datatype()? {{
-                                        d.skip()?;
-                                        Some(None)
-                                    }} else {{
-                                        Some(Some( {} ))
-                                    }},
+                    r#"{} => {} = Some(d.optional("{}", |d| {} )?),
                    "#,
-                    ix, field_name, self.import_core, field_decoder,
+                    ix,
+                    field_name,
+                    field.id(),
+                    optional_decoder(&field_decoder),
                 )
                 .unwrap();
             } else {
@@ -380,17 +409,12 @@ impl<'model> RustCodeGen<'model> {
             if is_optional_type(field) {
                 write!(
                     s,
-                    r#""{}" => {} = if {}::cbor::Type::Null == d.datatype()? {{
-                                        d.skip()?;
-                                        Some(None)
-                                    }} else {{
-                                        Some(Some( {} ))
-                                    }},
+                    r#""{}" => {} = Some(d.optional("{}", |d| {} )?),
                    "#,
                     field.id(),
                     field_name,
-                    self.import_core,
-                    field_decoder,
+                    field.id(),
+                    optional_decoder(&field_decoder),
                 )
                 .unwrap();
             } else {
@@ -501,3 +525,23 @@ impl<'model> RustCodeGen<'model> {
         Ok(())
     }
 }
+
+// optional field decoders return the Result directly where they can
+#[test]
+fn optional_field_decoders() {
+    assert_eq!(optional_decoder(decode_unsigned_long()), "d.u64()");
+    assert_eq!(
+        optional_decoder(decode_str()),
+        "d.str().map(|v| v.to_string())"
+    );
+    assert_eq!(
+        optional_decoder(decode_blob()),
+        "d.bytes().map(|v| v.to_vec())"
+    );
+    assert_eq!(
+        optional_decoder(decode_timestamp()),
+        format!("Ok({})", decode_timestamp())
+    );
+    let nested = "decode_log_level(d).map_err(|e| format!(\"decoding: {}\", e))?";
+    assert_eq!(optional_decoder(nested), format!("Ok({nested})"));
+}
@@ -123,11 +123,26 @@ fn encode_timestamp(val: ValExpr) -> String {
         val.as_str()
     )
 }
-fn encode_big_integer(_val: ValExpr) -> String {
-    todo!(); // tag big int
+#[allow(unused_variables)]
+fn encode_big_integer(val: ValExpr) -> String {
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "BigInteger")] {
+            format!("e.big_integer({})?;\n", val.as_ref())
+        } else {
+            // without the feature, the value is carried as a decimal string
+            format!("e.big_integer_str({})?;\n", val.as_ref())
+        }
+    }
 }
-fn encode_big_decimal(_val: ValExpr) -> String {
-    todo!() // tag big decimal
+#[allow(unused_variables)]
+fn encode_big_decimal(val: ValExpr) -> String {
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "BigDecimal")] {
+            todo!() // tag big decimal
+        } else {
+            format!("e.big_decimal_str({})?;\n", val.as_ref())
+        }
+    }
 }
 
 impl<'model> RustCodeGen<'model> {
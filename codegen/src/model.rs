@@ -36,6 +36,7 @@ const TRAIT_WASMBUS: &str = "wasmbus";
 const TRAIT_WASMBUS_DATA: &str = "wasmbusData";
 const TRAIT_FIELD_NUM: &str = "n";
 const TRAIT_RENAME: &str = "rename";
+const TRAIT_ONE_WAY: &str = "oneWay";
 
 lazy_static! {
     static ref WASMCLOUD_MODEL_NAMESPACE_ID: NamespaceID =
@@ -74,6 +75,11 @@ lazy_static! {
         Identifier::from_str(TRAIT_RENAME).unwrap(),
         None
     );
+    static ref ONE_WAY_TRAIT_ID: ShapeID = ShapeID::new(
+        NamespaceID::new_unchecked(WASMCLOUD_MODEL_NAMESPACE),
+        Identifier::from_str(TRAIT_ONE_WAY).unwrap(),
+        None
+    );
     static ref UNIT_ID: ShapeID = ShapeID::new_unchecked(WASMCLOUD_MODEL_NAMESPACE, "Unit", None);
 }
 
@@ -121,6 +127,11 @@ pub fn rename_trait() -> &'static ShapeID {
     &RENAME_TRAIT_ID
 }
 
+/// shape id of trait @oneWay, for operations that send no response
+pub fn one_way_trait() -> &'static ShapeID {
+    &ONE_WAY_TRAIT_ID
+}
+
 pub fn unit_shape() -> &'static ShapeID {
     &UNIT_ID
 }
@@ -331,22 +342,10 @@ pub fn resolve<'model>(model: &'model Model, shape: &'model ShapeID) -> &'model
 /// and empty sets, list, and maps.
 pub fn has_default(model: &'_ Model, member: &MemberShape) -> bool {
     let id = resolve(model, member.target());
-    #[allow(unused_mut)]
-    let mut has = false;
     let name = id.shape_name().to_string();
 
     if id.namespace().eq(prelude_namespace_id()) {
-        cfg_if::cfg_if! {
-            if #[cfg(feature = "BigInteger")] {
-                has = has || &name == "bigInteger";
-            }
-        }
-        cfg_if::cfg_if! {
-            if #[cfg(feature = "BigDecimal")] {
-                has = has || &name == "bigDecimal";
-            }
-        }
-        has || matches!(
+        matches!(
             name.as_str(),
             // some aggregate types
             "List" | "Set" | "Map"
@@ -354,6 +353,8 @@ pub fn has_default(model: &'_ Model, member: &MemberShape) -> bool {
             | "Blob" | "Boolean" | "String" | "Byte" | "Short"
             | "Integer" | "Long" | "Float" | "Double"
             | "Timestamp"
+            // BigInteger and BigDecimal, or String if those features are disabled
+            | "bigInteger" | "bigDecimal"
         )
         // excluded: Resource, Operation, Service, Document, Union
     } else if id.namespace() == wasmcloud_model_namespace() {
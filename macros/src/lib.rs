@@ -152,10 +152,28 @@ pub fn derive_actor(input: TokenStream) -> TokenStream {
     output.into()
 }
 
-#[proc_macro_derive(ActorHealthResponder)]
+/// returns true if the attributes include `#[health_responder(custom)]`
+fn has_custom_health(attrs: &[Attribute]) -> bool {
+    attrs
+        .iter()
+        .flat_map(|attr| attr_traits(attr, "health_responder"))
+        .any(|p| p.is_ident("custom"))
+}
+
+/// Implements the `Actor` trait, with `health_request` delegating to the
+/// `wasmbus_rpc::actor::HealthResponder` trait. Unless `#[health_responder(custom)]` is present,
+/// `HealthResponder` is also implemented, using its default (always healthy) method.
+#[proc_macro_derive(ActorHealthResponder, attributes(health_responder))]
 pub fn derive_health_responder(input: TokenStream) -> TokenStream {
     let actor_receiver = parse_macro_input!(input as ReceiverDef);
     let actor_ident = actor_receiver.ident;
+    let default_impl = if has_custom_health(&actor_receiver.attrs) {
+        quote!()
+    } else {
+        quote!(
+            impl wasmbus_rpc::actor::HealthResponder for #actor_ident {}
+        )
+    };
     let output = quote!(
 
         #[async_trait]
@@ -165,18 +183,18 @@ pub fn derive_health_responder(input: TokenStream) -> TokenStream {
                 ctx: &wasmbus_rpc::common::Context,
                 arg: &wasmbus_rpc::core::HealthCheckRequest,
             ) -> wasmbus_rpc::error::RpcResult<wasmbus_rpc::core::HealthCheckResponse> {
-                Ok(wasmbus_rpc::core::HealthCheckResponse {
-                    healthy: true,
-                    message: None,
-                })
+                wasmbus_rpc::actor::HealthResponder::health(self, ctx, arg).await
             }
         }
+
+        #default_impl
     ); // end quote
     output.into()
 }
 
 fn gen_dispatch(traits: &[syn::Path], ident: &Ident) -> TokenStream2 {
     let mut methods = Vec::new();
+    let mut operations = Vec::new();
     let mut trait_receiver_impl = Vec::new();
 
     for path in traits.iter() {
@@ -185,6 +203,9 @@ fn gen_dispatch(traits: &[syn::Path], ident: &Ident) -> TokenStream2 {
         methods.push(quote!(
             #path_str => #id::dispatch(self, ctx, message).await
         ));
+        operations.push(quote!(
+            ops.extend_from_slice(#id::service_operation_info(self));
+        ));
         trait_receiver_impl.push(quote!(
             impl #id for #ident { }
         ));
@@ -212,6 +233,12 @@ fn gen_dispatch(traits: &[syn::Path], ident: &Ident) -> TokenStream2 {
                             format!("{}.{} - unknown method", trait_name,message.method)))
                 }
             }
+
+            fn operation_info(&self) -> Vec<wasmbus_rpc::common::OperationInfo> {
+                let mut ops = Vec::new();
+                #( #operations )*
+                ops
+            }
         }
 
       #( #trait_receiver_impl )*
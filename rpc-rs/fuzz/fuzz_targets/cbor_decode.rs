@@ -0,0 +1,20 @@
+//! Decoding arbitrary input may fail, but must never panic, hang,
+//! or allocate based on untrusted lengths.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use wasmbus_rpc::{cbor::Decoder, common::decode_document};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decode_document(&mut Decoder::new(data));
+    let _ = Decoder::new(data).skip();
+
+    // a sequence of primitive reads, as generated code would do for a struct
+    let mut d = Decoder::new(data);
+    let _ = d.fixed_array();
+    let _ = d.u32();
+    let _ = d.str();
+    let _ = d.bytes();
+    let _ = d.f64();
+    let _ = d.i64();
+});
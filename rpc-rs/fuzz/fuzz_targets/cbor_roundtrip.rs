@@ -0,0 +1,33 @@
+//! Any input that decodes to a Document must re-encode to bytes
+//! that decode back to an equal Document.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use wasmbus_rpc::{
+    cbor::{vec_encoder, Decoder},
+    common::{decode_document, encode_document, Document, Number},
+};
+
+/// NaN never compares equal, so documents containing one are not compared
+fn has_nan(doc: &Document) -> bool {
+    match doc {
+        Document::Number(Number::Float(f)) => f.is_nan(),
+        Document::Array(arr) => arr.iter().any(has_nan),
+        Document::Object(map) => map.values().any(has_nan),
+        _ => false,
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let doc = match decode_document(&mut Decoder::new(data)) {
+        Ok(doc) => doc,
+        Err(_) => return,
+    };
+    let mut e = vec_encoder(false);
+    encode_document(&mut e, &doc).expect("encoding a decoded document");
+    let buf = e.into_inner();
+    let decoded = decode_document(&mut Decoder::new(&buf)).expect("decoding a re-encoded document");
+    if !has_nan(&doc) {
+        assert_eq!(doc, decoded);
+    }
+});
@@ -0,0 +1,145 @@
+//! Per-request memory accounting, with the `alloc-tracking` feature.
+//!
+//! A provider that installs [TrackingAllocator] as its global allocator can measure how much
+//! memory each operation's handler allocates. With a [MetricsSink] set in
+//! [ProviderOptions::metrics_sink](crate::provider::ProviderOptions::metrics_sink), the
+//! peak allocation of each dispatch is reported to the sink with the operation name.
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static ALLOC: wasmbus_rpc::alloc_tracking::TrackingAllocator =
+//!     wasmbus_rpc::alloc_tracking::TrackingAllocator::new();
+//! ```
+//!
+//! Allocations are attributed to a future while it's being polled, on whichever thread polls it,
+//! so memory allocated by tasks the handler spawns is not counted.
+//! Without the tracking allocator installed, every measurement is zero.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    future::Future,
+};
+
+/// Receives measurements of the operations a provider handles
+pub trait MetricsSink: Send + Sync {
+    /// Called after each dispatch with the peak number of bytes the handler had allocated,
+    /// above what it had at the start. Called inline, so it should return quickly.
+    fn record_allocation(&self, operation: &str, peak_bytes: usize);
+}
+
+impl std::fmt::Debug for dyn MetricsSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("MetricsSink")
+    }
+}
+
+/// Allocations on this thread while a tracked future is polled
+struct Tracked {
+    active: Cell<bool>,
+    /// bytes allocated less bytes freed
+    net: Cell<isize>,
+    /// highest value of `net`
+    peak: Cell<isize>,
+}
+
+thread_local! {
+    static TRACKED: Tracked = const {
+        Tracked {
+            active: Cell::new(false),
+            net: Cell::new(0),
+            peak: Cell::new(0),
+        }
+    };
+}
+
+fn record(bytes: isize) {
+    // during thread teardown the counters may be gone; those allocations aren't counted
+    let _ = TRACKED.try_with(|t| {
+        if t.active.get() {
+            let net = t.net.get() + bytes;
+            t.net.set(net);
+            if net > t.peak.get() {
+                t.peak.set(net);
+            }
+        }
+    });
+}
+
+/// Global allocator that counts the allocations made while polling a future
+/// passed to [track_allocations], and forwards every request to the wrapped allocator
+#[derive(Debug, Default)]
+pub struct TrackingAllocator<A = System> {
+    inner: A,
+}
+
+impl TrackingAllocator<System> {
+    /// Constructs a tracking allocator over the system allocator
+    pub const fn new() -> Self {
+        TrackingAllocator { inner: System }
+    }
+}
+
+impl<A> TrackingAllocator<A> {
+    /// Constructs a tracking allocator over another allocator
+    pub const fn wrap(inner: A) -> Self {
+        TrackingAllocator { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            record(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            record(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        record(-(layout.size() as isize));
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            record(new_size as isize - layout.size() as isize);
+        }
+        new_ptr
+    }
+}
+
+/// Runs the future, returning its output and the peak number of bytes allocated while
+/// polling it, above what was allocated when it started. Calls may be nested;
+/// an inner future's allocations count toward the outer one's too.
+pub async fn track_allocations<F: Future>(fut: F) -> (F::Output, usize) {
+    futures::pin_mut!(fut);
+    // net allocation of the future so far, and its peak, across polls
+    let (mut net, mut peak) = (0isize, 0isize);
+    let output = futures::future::poll_fn(|cx| {
+        let outer = TRACKED.with(|t| (t.active.replace(true), t.net.replace(0), t.peak.replace(0)));
+        let poll = fut.as_mut().poll(cx);
+        let (poll_net, poll_peak) = TRACKED.with(|t| {
+            let (active, outer_net, outer_peak) = outer;
+            let (poll_net, poll_peak) = (t.net.get(), t.peak.get());
+            t.active.set(active);
+            t.net.set(outer_net + poll_net);
+            t.peak.set(outer_peak.max(outer_net + poll_peak));
+            (poll_net, poll_peak)
+        });
+        peak = peak.max(net + poll_peak);
+        net += poll_net;
+        poll
+    })
+    .await;
+    (output, peak.max(0) as usize)
+}
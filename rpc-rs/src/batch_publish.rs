@@ -0,0 +1,292 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+//! Buffered publishing for providers that send many messages.
+//!
+//! [RpcClient::publish](crate::rpc_client::RpcClient::publish) flushes the connection
+//! after every message, which favors latency. A [BatchingPublisher] queues messages
+//! and flushes once per batch: when the batch is full, when the flush interval
+//! elapses, or when [flush](BatchingPublisher::flush) is called.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::sync::{mpsc, oneshot};
+use tracing::error;
+
+use crate::{
+    dispatch::{YieldEvery, DEFAULT_YIELD_INTERVAL},
+    error::{RpcError, RpcResult},
+};
+
+/// Default time between flushes of a partial batch
+pub const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_millis(10);
+/// Default number of messages that triggers a flush
+pub const DEFAULT_MAX_BATCH: usize = 128;
+/// Default number of queued messages before `publish` waits
+pub const DEFAULT_BUFFER_CAPACITY: usize = 1024;
+
+/// Destination of a [BatchingPublisher]. Implemented for the nats client.
+#[async_trait]
+pub trait PublishSink: Send + Sync + 'static {
+    /// Sends one message, without waiting for it to be written to the network
+    async fn publish(&self, subject: String, payload: Vec<u8>) -> RpcResult<()>;
+
+    /// Writes all sent messages to the network
+    async fn flush(&self) -> RpcResult<()>;
+}
+
+#[async_trait]
+impl PublishSink for crate::async_nats::Client {
+    async fn publish(&self, subject: String, payload: Vec<u8>) -> RpcResult<()> {
+        crate::async_nats::Client::publish(self, subject, payload.into())
+            .await
+            .map_err(|e| RpcError::Nats(format!("publish: {e}")))
+    }
+
+    async fn flush(&self) -> RpcResult<()> {
+        crate::async_nats::Client::flush(self)
+            .await
+            .map_err(|e| RpcError::Nats(format!("flush: {e}")))
+    }
+}
+
+/// Settings for a [BatchingPublisher]
+#[derive(Clone, Debug)]
+pub struct BatchOptions {
+    flush_interval: Duration,
+    max_batch: usize,
+    capacity: usize,
+    yield_interval: usize,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptions {
+            flush_interval: DEFAULT_FLUSH_INTERVAL,
+            max_batch: DEFAULT_MAX_BATCH,
+            capacity: DEFAULT_BUFFER_CAPACITY,
+            yield_interval: DEFAULT_YIELD_INTERVAL,
+        }
+    }
+}
+
+impl BatchOptions {
+    /// Maximum time a message waits in a partial batch
+    #[must_use]
+    pub fn flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = interval;
+        self
+    }
+
+    /// Number of messages that triggers a flush
+    #[must_use]
+    pub fn max_batch(mut self, max_batch: usize) -> Self {
+        self.max_batch = max_batch.max(1);
+        self
+    }
+
+    /// Number of queued messages; when the queue is full, `publish` waits for room
+    #[must_use]
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Number of messages sent between yields to other tasks, so a full queue doesn't
+    /// starve them. 0 never yields. The default is [DEFAULT_YIELD_INTERVAL]
+    #[must_use]
+    pub fn yield_interval(mut self, interval: usize) -> Self {
+        self.yield_interval = interval;
+        self
+    }
+}
+
+enum Command {
+    Publish(String, Vec<u8>),
+    Flush(oneshot::Sender<RpcResult<()>>),
+}
+
+/// Queues messages and sends them in batches, from a background task.
+/// Messages are sent in the order they were published, so ordering within a subject is preserved.
+/// Dropping the publisher flushes any queued messages.
+#[derive(Clone)]
+pub struct BatchingPublisher {
+    tx: mpsc::Sender<Command>,
+}
+
+impl std::fmt::Debug for BatchingPublisher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BatchingPublisher").finish_non_exhaustive()
+    }
+}
+
+impl BatchingPublisher {
+    /// Constructs a publisher with default options. Must be called within a tokio runtime.
+    pub fn new<S: PublishSink>(sink: S) -> Self {
+        Self::with_options(sink, BatchOptions::default())
+    }
+
+    /// Constructs a publisher. Must be called within a tokio runtime.
+    pub fn with_options<S: PublishSink>(sink: S, opts: BatchOptions) -> Self {
+        let (tx, rx) = mpsc::channel(opts.capacity);
+        tokio::spawn(run_batches(sink, opts, rx));
+        BatchingPublisher { tx }
+    }
+
+    /// Queues a message. Waits if the queue is full.
+    pub async fn publish(&self, subject: impl Into<String>, payload: Vec<u8>) -> RpcResult<()> {
+        self.tx
+            .send(Command::Publish(subject.into(), payload))
+            .await
+            .map_err(|_| closed())
+    }
+
+    /// Sends all queued messages and flushes the connection.
+    /// Returns the first error from sending a message since the previous flush, if any.
+    pub async fn flush(&self) -> RpcResult<()> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.tx.send(Command::Flush(ack_tx)).await.map_err(|_| closed())?;
+        ack_rx.await.map_err(|_| closed())?
+    }
+}
+
+fn closed() -> RpcError {
+    RpcError::Other("batching publisher has stopped".to_string())
+}
+
+async fn run_batches<S: PublishSink>(sink: S, opts: BatchOptions, mut rx: mpsc::Receiver<Command>) {
+    let mut ticker = tokio::time::interval(opts.flush_interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // messages sent since the last flush
+    let mut pending = 0usize;
+    // first send error since the last flush
+    let mut failed: Option<RpcError> = None;
+    // a full queue and a sink that never waits would otherwise keep this task running
+    let mut yielder = YieldEvery::new(opts.yield_interval);
+    loop {
+        tokio::select! {
+            cmd = rx.recv() => match cmd {
+                Some(Command::Publish(subject, payload)) => {
+                    if let Err(e) = sink.publish(subject, payload).await {
+                        error!(error = %e, "batched publish");
+                        failed.get_or_insert(e);
+                    }
+                    pending += 1;
+                    if pending >= opts.max_batch {
+                        flush_batch(&sink, &mut pending).await.ok();
+                    }
+                    yielder.tick().await;
+                }
+                Some(Command::Flush(ack)) => {
+                    let res = flush_batch(&sink, &mut pending).await;
+                    let _ = ack.send(match failed.take() {
+                        Some(e) => Err(e),
+                        None => res,
+                    });
+                }
+                None => {
+                    if pending > 0 {
+                        flush_batch(&sink, &mut pending).await.ok();
+                    }
+                    break;
+                }
+            },
+            _ = ticker.tick() => {
+                if pending > 0 {
+                    flush_batch(&sink, &mut pending).await.ok();
+                }
+            }
+        }
+    }
+}
+
+async fn flush_batch<S: PublishSink>(sink: &S, pending: &mut usize) -> RpcResult<()> {
+    *pending = 0;
+    sink.flush().await.map_err(|e| {
+        error!(error = %e, "flushing batch");
+        e
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+
+    use super::{BatchOptions, BatchingPublisher, PublishSink};
+    use crate::error::RpcResult;
+
+    type Batch = Vec<(String, Vec<u8>)>;
+
+    /// Records messages, grouped by the flush that sent them
+    #[derive(Clone, Default)]
+    struct Recorder {
+        unflushed: Arc<Mutex<Batch>>,
+        batches: Arc<Mutex<Vec<Batch>>>,
+    }
+
+    #[async_trait]
+    impl PublishSink for Recorder {
+        async fn publish(&self, subject: String, payload: Vec<u8>) -> RpcResult<()> {
+            self.unflushed.lock().unwrap().push((subject, payload));
+            Ok(())
+        }
+
+        async fn flush(&self) -> RpcResult<()> {
+            let batch = std::mem::take(&mut *self.unflushed.lock().unwrap());
+            self.batches.lock().unwrap().push(batch);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn batches_in_order() {
+        let sink = Recorder::default();
+        let publisher = BatchingPublisher::with_options(
+            sink.clone(),
+            BatchOptions::default()
+                .max_batch(3)
+                .flush_interval(Duration::from_secs(3600)),
+        );
+        let mut sent = Vec::new();
+        for i in 0..7u8 {
+            let subject = if i % 2 == 0 { "even" } else { "odd" };
+            publisher.publish(subject, vec![i]).await.unwrap();
+            sent.push((subject.to_string(), vec![i]));
+        }
+        publisher.flush().await.unwrap();
+
+        let batches = sink.batches.lock().unwrap().clone();
+        assert_eq!(
+            batches.iter().map(|b| b.len()).collect::<Vec<_>>(),
+            vec![3, 3, 1]
+        );
+        assert_eq!(batches.concat(), sent);
+    }
+
+    #[tokio::test]
+    async fn flushes_on_interval() {
+        let sink = Recorder::default();
+        let publisher = BatchingPublisher::with_options(
+            sink.clone(),
+            BatchOptions::default().flush_interval(Duration::from_millis(10)),
+        );
+        publisher.publish("a", b"1".to_vec()).await.unwrap();
+        publisher.publish("a", b"2".to_vec()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // sent without an explicit flush, in one batch
+        let batches = sink.batches.lock().unwrap().clone();
+        assert_eq!(
+            batches,
+            vec![vec![
+                ("a".to_string(), b"1".to_vec()),
+                ("a".to_string(), b"2".to_vec())
+            ]]
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! Reusable serialization buffers.
+//!
+//! By default [serialize](crate::common::serialize) grows a new Vec for every message,
+//! reallocating several times for large messages. In pooled mode, enabled with
+//! [set_pooled], messages are written into a buffer taken from a shared pool,
+//! and the buffer is cleared and returned to the pool when it is dropped.
+//! `serialize` returns a copy of the message, so each message still allocates once,
+//! for a Vec of exactly its size.
+
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use bytes::BytesMut;
+
+/// Initial capacity of a new pooled buffer
+pub const DEFAULT_BUFFER_CAPACITY: usize = 4096;
+/// Maximum number of idle buffers kept by a pool
+pub const DEFAULT_MAX_IDLE: usize = 64;
+/// Buffers that have grown larger than this are not returned to the pool, so one large
+/// message doesn't pin its memory
+pub const MAX_POOLED_CAPACITY: usize = 1 << 20;
+
+static POOLED: AtomicBool = AtomicBool::new(false);
+static GLOBAL: BufferPool = BufferPool::new(DEFAULT_BUFFER_CAPACITY, DEFAULT_MAX_IDLE);
+
+/// Turns pooled mode on or off for [serialize](crate::common::serialize).
+/// Off by default.
+pub fn set_pooled(enabled: bool) {
+    POOLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns true if pooled mode is on
+pub fn is_pooled() -> bool {
+    POOLED.load(Ordering::Relaxed)
+}
+
+/// The pool used by [serialize](crate::common::serialize) in pooled mode
+pub fn global() -> &'static BufferPool {
+    &GLOBAL
+}
+
+/// A thread-safe pool of byte buffers
+#[derive(Debug)]
+pub struct BufferPool {
+    idle: Mutex<Vec<BytesMut>>,
+    buffer_capacity: usize,
+    max_idle: usize,
+    allocated: AtomicUsize,
+}
+
+impl BufferPool {
+    /// Constructs an empty pool. New buffers have `buffer_capacity` bytes;
+    /// at most `max_idle` buffers are kept for reuse.
+    pub const fn new(buffer_capacity: usize, max_idle: usize) -> Self {
+        BufferPool {
+            idle: Mutex::new(Vec::new()),
+            buffer_capacity,
+            max_idle,
+            allocated: AtomicUsize::new(0),
+        }
+    }
+
+    /// Takes an empty buffer from the pool, or allocates one if none are idle
+    pub fn get(&self) -> PooledBuffer<'_> {
+        let buf = self.idle.lock().unwrap().pop().unwrap_or_else(|| {
+            self.allocated.fetch_add(1, Ordering::Relaxed);
+            BytesMut::with_capacity(self.buffer_capacity)
+        });
+        PooledBuffer { buf: Some(buf), pool: self }
+    }
+
+    /// The number of buffers this pool has allocated
+    pub fn allocated(&self) -> usize {
+        self.allocated.load(Ordering::Relaxed)
+    }
+
+    /// The number of buffers waiting to be reused
+    pub fn idle(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+
+    fn put(&self, mut buf: BytesMut) {
+        if buf.capacity() > MAX_POOLED_CAPACITY {
+            return;
+        }
+        buf.clear();
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.max_idle {
+            idle.push(buf);
+        }
+    }
+}
+
+/// A buffer borrowed from a [BufferPool]. It is cleared and returned to the pool when dropped.
+#[derive(Debug)]
+pub struct PooledBuffer<'a> {
+    buf: Option<BytesMut>,
+    pool: &'a BufferPool,
+}
+
+impl Deref for PooledBuffer<'_> {
+    type Target = BytesMut;
+
+    fn deref(&self) -> &BytesMut {
+        self.buf.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut BytesMut {
+        self.buf.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledBuffer<'_> {
+    fn drop(&mut self) {
+        if let Some(buf) = self.buf.take() {
+            self.pool.put(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::BufMut;
+
+    use super::BufferPool;
+
+    #[test]
+    fn buffers_are_reused_and_cleared() {
+        let pool = BufferPool::new(16, 2);
+        {
+            let mut a = pool.get();
+            let mut b = pool.get();
+            a.put_slice(b"hello");
+            b.put_slice(&[0u8; 100]);
+        }
+        assert_eq!(pool.allocated(), 2);
+        assert_eq!(pool.idle(), 2);
+
+        for _ in 0..10 {
+            let buf = pool.get();
+            assert!(buf.is_empty());
+        }
+        assert_eq!(pool.allocated(), 2);
+
+        // idle buffers are capped
+        let held: Vec<_> = (0..4).map(|_| pool.get()).collect();
+        assert_eq!(pool.allocated(), 4);
+        drop(held);
+        assert_eq!(pool.idle(), 2);
+    }
+}
@@ -0,0 +1,94 @@
+//! Rewrites msgpack so that map entries are in a stable order.
+//! Used by [serialize_stable](crate::common::serialize_stable)
+
+use crate::error::{RpcError, RpcResult};
+
+/// Returns a copy of the msgpack value with the entries of every map, at any depth,
+/// sorted by the encoded bytes of their keys. Everything else is copied unchanged.
+pub(crate) fn sort_map_keys(input: &[u8]) -> RpcResult<Vec<u8>> {
+    let mut reader = Reader { input, pos: 0 };
+    let mut out = Vec::with_capacity(input.len());
+    reader.copy_value(&mut out)?;
+    if reader.pos != input.len() {
+        return Err(truncated());
+    }
+    Ok(out)
+}
+
+fn truncated() -> RpcError {
+    RpcError::Ser("invalid msgpack while sorting map keys".to_string())
+}
+
+struct Reader<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> RpcResult<&'a [u8]> {
+        let end = self.pos.checked_add(len).ok_or_else(truncated)?;
+        let bytes = self.input.get(self.pos..end).ok_or_else(truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Reads a big-endian unsigned length of `width` bytes
+    fn read_len(&mut self, width: usize) -> RpcResult<usize> {
+        Ok(self.take(width)?.iter().fold(0usize, |n, b| (n << 8) | *b as usize))
+    }
+
+    /// Copies one value, with its nested values, to `out`
+    fn copy_value(&mut self, out: &mut Vec<u8>) -> RpcResult<()> {
+        let start = self.pos;
+        let marker = self.take(1)?[0];
+        // (number of nested values, if an array), (number of entries, if a map),
+        // or number of payload bytes that follow the header
+        let (items, entries, payload) = match marker {
+            0x00..=0x7f | 0xe0..=0xff | 0xc0 | 0xc2 | 0xc3 => (0, 0, 0),
+            0x80..=0x8f => (0, (marker & 0x0f) as usize, 0),
+            0x90..=0x9f => ((marker & 0x0f) as usize, 0, 0),
+            0xa0..=0xbf => (0, 0, (marker & 0x1f) as usize),
+            0xc4 | 0xd9 => (0, 0, self.read_len(1)?),
+            0xc5 | 0xda => (0, 0, self.read_len(2)?),
+            0xc6 | 0xdb => (0, 0, self.read_len(4)?),
+            // ext: length, then a type byte
+            0xc7 => (0, 0, self.read_len(1)? + 1),
+            0xc8 => (0, 0, self.read_len(2)? + 1),
+            0xc9 => (0, 0, self.read_len(4)? + 1),
+            0xca => (0, 0, 4),
+            0xcb => (0, 0, 8),
+            0xcc | 0xd0 => (0, 0, 1),
+            0xcd | 0xd1 => (0, 0, 2),
+            0xce | 0xd2 => (0, 0, 4),
+            0xcf | 0xd3 => (0, 0, 8),
+            // fixext: type byte, then 1, 2, 4, 8, or 16 bytes
+            0xd4..=0xd8 => (0, 0, 1 + (1 << (marker - 0xd4))),
+            0xdc => (self.read_len(2)?, 0, 0),
+            0xdd => (self.read_len(4)?, 0, 0),
+            0xde => (0, self.read_len(2)?, 0),
+            0xdf => (0, self.read_len(4)?, 0),
+            0xc1 => return Err(truncated()),
+        };
+        self.take(payload)?;
+        out.extend_from_slice(&self.input[start..self.pos]);
+        for _ in 0..items {
+            self.copy_value(out)?;
+        }
+        if entries > 0 {
+            let mut sorted = Vec::with_capacity(entries.min(self.input.len()));
+            for _ in 0..entries {
+                let mut key = Vec::new();
+                self.copy_value(&mut key)?;
+                let mut value = Vec::new();
+                self.copy_value(&mut value)?;
+                sorted.push((key, value));
+            }
+            sorted.sort();
+            for (key, value) in sorted {
+                out.extend_from_slice(&key);
+                out.extend_from_slice(&value);
+            }
+        }
+        Ok(())
+    }
+}
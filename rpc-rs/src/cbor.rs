@@ -12,13 +12,32 @@ use std::fmt::Debug;
 #[derive(Clone)]
 pub struct Decoder<'b> {
     inner: minicbor::Decoder<'b>,
+    lenient_optional: bool,
 }
 
 /// A non-allocating CBOR decoder
 impl<'b> Decoder<'b> {
     /// Construct a Decoder for the given byte slice
     pub fn new(bytes: &'b [u8]) -> Self {
-        Self { inner: minicbor::Decoder::new(bytes) }
+        Self {
+            inner: minicbor::Decoder::new(bytes),
+            lenient_optional: false,
+        }
+    }
+
+    /// Turns lenient decoding of optional struct fields on or off. Off by default.
+    /// When on, an optional field whose value is well-formed cbor of the wrong type,
+    /// such as a string where a number is expected, decodes as None with a warning,
+    /// instead of failing the whole message. Required fields still fail.
+    #[must_use]
+    pub fn lenient_optional(mut self, enabled: bool) -> Self {
+        self.lenient_optional = enabled;
+        self
+    }
+
+    /// Returns true if lenient decoding of optional fields is on
+    pub fn is_lenient_optional(&self) -> bool {
+        self.lenient_optional
     }
 
     /// Decode a bool value
@@ -81,9 +100,19 @@ impl<'b> Decoder<'b> {
         Ok(self.inner.char()?)
     }
 
-    /// Decode a string slice
+    /// Decode a string slice.
+    /// If the string isn't valid utf-8, the error names the map keys and array indices
+    /// leading to the string, and the position of the first invalid byte in the input.
     pub fn str(&mut self) -> RpcResult<&'b str> {
-        Ok(self.inner.str()?)
+        let start = self.inner.position();
+        self.inner.str().map_err(|e| {
+            match std::error::Error::source(&e)
+                .and_then(|s| s.downcast_ref::<std::str::Utf8Error>())
+            {
+                Some(utf8) => invalid_utf8(self.inner.input(), start, utf8),
+                None => e.into(),
+            }
+        })
     }
 
     /// Decode a null
@@ -129,7 +158,154 @@ impl<'b> Decoder<'b> {
 
     /// Skip over the current value
     pub fn skip(&mut self) -> RpcResult<()> {
-        Ok(self.inner.skip()?)
+        // minicbor's skip never terminates on a malformed chunk inside an
+        // indefinite-length string (the chunk iterator keeps returning the error),
+        // so nested values are walked here and scalars are delegated.
+        // Each stack entry is the number of items remaining at that level,
+        // or None for an indefinite-length container that ends with a break.
+        let mut stack: Vec<Option<u64>> = vec![Some(1)];
+        while let Some(top) = stack.last_mut() {
+            // a finished level is popped before peeking, since the value may end the input
+            if *top == Some(0) {
+                stack.pop();
+                continue;
+            }
+            let ty = self.inner.datatype()?;
+            match top {
+                None if ty == MT::Break => {
+                    self.inner.set_position(self.inner.position() + 1);
+                    stack.pop();
+                    continue;
+                }
+                Some(n) => *n -= 1,
+                None => {}
+            }
+            match ty {
+                MT::Array | MT::ArrayIndef => stack.push(self.inner.array()?),
+                MT::Map | MT::MapIndef => {
+                    stack.push(self.inner.map()?.map(|n| n.saturating_mul(2)))
+                }
+                MT::Tag => {
+                    self.inner.tag()?;
+                    stack.push(Some(1));
+                }
+                MT::BytesIndef => {
+                    for chunk in self.inner.bytes_iter()? {
+                        chunk?;
+                    }
+                }
+                MT::StringIndef => {
+                    for chunk in self.inner.str_iter()? {
+                        chunk?;
+                    }
+                }
+                MT::Break => {
+                    return Err(RpcError::Deser("unexpected break in cbor".to_string()));
+                }
+                _ => self.inner.skip()?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes the value of the optional struct field `field`: None if the value is null,
+    /// otherwise the result of `decode`. In lenient mode (see [lenient_optional](Decoder::lenient_optional)),
+    /// a value that `decode` rejects is skipped, and decodes as None.
+    /// Used by generated code.
+    pub fn optional<T, F>(&mut self, field: &str, decode: F) -> RpcResult<Option<T>>
+    where
+        F: FnOnce(&mut Decoder<'b>) -> RpcResult<T>,
+    {
+        if self.datatype()? == Type::Null {
+            self.skip()?;
+            return Ok(None);
+        }
+        let start = self.inner.position();
+        match decode(self) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if self.lenient_optional => {
+                tracing::warn!(%field, error = %e, "ignoring malformed optional field");
+                self.inner.set_position(start);
+                self.skip()?;
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Decode a smithy bigInteger.
+    /// Accepts cbor integers and bignums (tags 2 and 3) of any size.
+    #[cfg(feature = "BigInteger")]
+    pub fn big_integer(&mut self) -> RpcResult<num_bigint::BigInt> {
+        use minicbor::data::Tag;
+        use num_bigint::{BigInt, Sign};
+        match self.inner.datatype()? {
+            MT::Tag => {
+                let tag = self.inner.tag()?;
+                if tag != Tag::PosBignum && tag != Tag::NegBignum {
+                    return Err(RpcError::Deser(format!(
+                        "expected bignum, found tag {tag:?}"
+                    )));
+                }
+                let n = BigInt::from_bytes_be(Sign::Plus, self.inner.bytes()?);
+                // a negative bignum encodes -1 - n
+                Ok(if tag == Tag::NegBignum { -n - 1 } else { n })
+            }
+            _ => Ok(i128::from(self.inner.int()?).into()),
+        }
+    }
+
+    /// Decode a smithy bigInteger as its decimal string. Used by generated code
+    /// when the `BigInteger` feature is not enabled.
+    /// Accepts cbor integers and bignums (tags 2 and 3); returns an error
+    /// if the value is outside the range of i128.
+    pub fn big_integer_str(&mut self) -> RpcResult<String> {
+        Ok(self.int_or_bignum()?.to_string())
+    }
+
+    fn int_or_bignum(&mut self) -> RpcResult<i128> {
+        use minicbor::data::Tag;
+        match self.inner.datatype()? {
+            MT::Tag => {
+                let tag = self.inner.tag()?;
+                if tag != Tag::PosBignum && tag != Tag::NegBignum {
+                    return Err(RpcError::Deser(format!(
+                        "expected bignum, found tag {tag:?}"
+                    )));
+                }
+                let bytes = self.inner.bytes()?;
+                let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+                let bytes = &bytes[start..];
+                if bytes.len() > 16 {
+                    return Err(big_int_range());
+                }
+                let n = bytes.iter().fold(0u128, |acc, b| (acc << 8) | *b as u128);
+                let n = i128::try_from(n).map_err(|_| big_int_range())?;
+                // a negative bignum encodes -1 - n
+                Ok(if tag == Tag::NegBignum { -1 - n } else { n })
+            }
+            _ => Ok(self.inner.int()?.into()),
+        }
+    }
+
+    /// Decode a smithy bigDecimal as its decimal string. Used by generated code
+    /// when the `BigDecimal` feature is not enabled.
+    /// Accepts cbor floats and integers; decimal fractions (tag 4) are not supported.
+    pub fn big_decimal_str(&mut self) -> RpcResult<String> {
+        match self.inner.datatype()? {
+            MT::F16 | MT::F32 => Ok(self.inner.f32()?.to_string()),
+            MT::F64 => Ok(self.inner.f64()?.to_string()),
+            MT::Tag => Err(RpcError::Deser(
+                "BigDecimal feature not enabled: only float and integer values can be decoded"
+                    .to_string(),
+            )),
+            _ => Ok(self.int_or_bignum()?.to_string()),
+        }
+    }
+
+    /// Returns the number of input bytes not yet decoded
+    pub(crate) fn remaining(&self) -> usize {
+        self.inner.input().len().saturating_sub(self.inner.position())
     }
 
     // Pierce the veil.
@@ -144,6 +320,100 @@ impl<'b> Decoder<'b> {
     //}
 }
 
+/// Returns the error for a string with invalid utf-8 that begins at `start`
+fn invalid_utf8(input: &[u8], start: usize, err: &std::str::Utf8Error) -> RpcError {
+    // length of the string's header: initial byte, plus the length argument, if any
+    let header = match input.get(start).map(|b| b & 0x1f) {
+        Some(24) => 2,
+        Some(25) => 3,
+        Some(26) => 5,
+        Some(27) => 9,
+        _ => 1,
+    };
+    let mut path = Vec::new();
+    let mut d = Decoder::new(input);
+    // the input may hold a sequence of values
+    let mut found = Some(false);
+    while found == Some(false) && d.remaining() > 0 {
+        found = find_path(&mut d, start, &mut path, 0);
+    }
+    let field = if found == Some(true) && !path.is_empty() {
+        path.concat().trim_start_matches('.').to_string()
+    } else {
+        "<root>".to_string()
+    };
+    RpcError::Deser(format!(
+        "invalid utf-8 at field {field}, byte {}",
+        start + header + err.valid_up_to()
+    ))
+}
+
+/// Limit on nesting depth when searching for the path to an invalid value
+const MAX_PATH_DEPTH: usize = 64;
+
+/// Walks the value at the decoder's position, looking for a value that begins at `target`.
+/// Returns Some(true) if found, with `path` holding the map keys (".key") and
+/// array indices ("[n]") leading to it, Some(false) if the value was passed over,
+/// or None if the input couldn't be read.
+fn find_path(
+    d: &mut Decoder<'_>,
+    target: usize,
+    path: &mut Vec<String>,
+    depth: usize,
+) -> Option<bool> {
+    if d.inner.position() == target {
+        return Some(true);
+    }
+    if depth > MAX_PATH_DEPTH || d.inner.position() > target {
+        return None;
+    }
+    let (is_map, len) = match d.inner.datatype().ok()? {
+        MT::Map | MT::MapIndef => (true, d.inner.map().ok()?),
+        MT::Array | MT::ArrayIndef => (false, d.inner.array().ok()?),
+        MT::Tag => {
+            d.inner.tag().ok()?;
+            return find_path(d, target, path, depth + 1);
+        }
+        _ => {
+            d.skip().ok()?;
+            return Some(false);
+        }
+    };
+    let mut ix = 0u64;
+    while len.map(|n| ix < n).unwrap_or(true) {
+        if len.is_none() && d.inner.datatype().ok()? == MT::Break {
+            d.inner.set_position(d.inner.position() + 1);
+            break;
+        }
+        if is_map {
+            // a key that is itself invalid has no name, so it's reported at the map
+            if d.inner.position() == target {
+                return Some(true);
+            }
+            if d.inner.datatype().ok()? == MT::String {
+                path.push(format!(".{}", d.inner.str().ok()?));
+            } else {
+                d.skip().ok()?;
+                path.push(format!(".<key {ix}>"));
+            }
+        } else {
+            path.push(format!("[{ix}]"));
+        }
+        if find_path(d, target, path, depth + 1)? {
+            return Some(true);
+        }
+        path.pop();
+        ix += 1;
+    }
+    Some(false)
+}
+
+const BIG_INT_RANGE: &str = "BigInteger feature not enabled: value exceeds i128 range";
+
+fn big_int_range() -> RpcError {
+    RpcError::Deser(BIG_INT_RANGE.to_string())
+}
+
 impl<'b> Debug for Decoder<'b> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> core::fmt::Result {
         self.inner.fmt(f)
@@ -189,6 +459,7 @@ pub use minicbor::encode::Write;
 
 pub struct Encoder<W: Write> {
     pub inner: minicbor::Encoder<W>,
+    enum_repr: crate::int_enum::EnumRepr,
 }
 
 pub fn vec_encoder(header: bool) -> Encoder<Vec<u8>> {
@@ -196,7 +467,7 @@ pub fn vec_encoder(header: bool) -> Encoder<Vec<u8>> {
     if header {
         crate::common::MessageFormat::Cbor.write_header(&mut buf).unwrap();
     }
-    Encoder { inner: minicbor::Encoder::new(buf) }
+    Encoder::new(buf)
 }
 
 /// A non-allocating CBOR encoder
@@ -207,7 +478,23 @@ where
 {
     /// Constructs an Encoder around the writer
     pub fn new(writer: W) -> Self {
-        Self { inner: minicbor::Encoder::new(writer) }
+        Self {
+            inner: minicbor::Encoder::new(writer),
+            enum_repr: crate::int_enum::EnumRepr::default(),
+        }
+    }
+
+    /// Sets how enums declared with [int_enum](crate::int_enum) are encoded.
+    /// The default is [EnumRepr::Int](crate::int_enum::EnumRepr::Int)
+    #[must_use]
+    pub fn with_enum_repr(mut self, repr: crate::int_enum::EnumRepr) -> Self {
+        self.enum_repr = repr;
+        self
+    }
+
+    /// Returns the encoding for enums declared with [int_enum](crate::int_enum)
+    pub fn enum_repr(&self) -> crate::int_enum::EnumRepr {
+        self.enum_repr
     }
 
     /// Encode a bool value
@@ -355,6 +642,67 @@ where
         Ok(self)
     }
 
+    /// Write a smithy bigInteger.
+    /// Values outside the 64-bit cbor integer range are written as bignums (tags 2 and 3).
+    #[cfg(feature = "BigInteger")]
+    pub fn big_integer(&mut self, x: &num_bigint::BigInt) -> RpcResult<&mut Self> {
+        use num_bigint::Sign;
+        if let Some(int) =
+            i128::try_from(x).ok().and_then(|n| minicbor::data::Int::try_from(n).ok())
+        {
+            self.inner.int(int)?;
+            return Ok(self);
+        }
+        let (tag, magnitude) = match x.sign() {
+            Sign::Minus => (3, -x - 1u32),
+            _ => (2, x.clone()),
+        };
+        self.tag(tag)?;
+        self.inner.bytes(&magnitude.to_bytes_be().1)?;
+        Ok(self)
+    }
+
+    /// Write a smithy bigInteger from its decimal string. Used by generated code
+    /// when the `BigInteger` feature is not enabled.
+    /// Values outside the 64-bit cbor integer range are written as bignums;
+    /// returns an error if the value is outside the range of i128.
+    pub fn big_integer_str(&mut self, x: &str) -> RpcResult<&mut Self> {
+        let n: i128 = x.trim().parse().map_err(|e| {
+            if x.trim().trim_start_matches(['-', '+']).bytes().all(|b| b.is_ascii_digit()) {
+                RpcError::Ser(BIG_INT_RANGE.to_string())
+            } else {
+                RpcError::Ser(format!("invalid bigInteger '{x}': {e}"))
+            }
+        })?;
+        if let Ok(int) = minicbor::data::Int::try_from(n) {
+            self.inner.int(int)?;
+            return Ok(self);
+        }
+        let (tag, magnitude) = if n < 0 { (3, (-1 - n) as u128) } else { (2, n as u128) };
+        let bytes = magnitude.to_be_bytes();
+        let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+        self.tag(tag)?;
+        self.inner.bytes(&bytes[start..])?;
+        Ok(self)
+    }
+
+    /// Write a smithy bigDecimal from its decimal string, as a 64-bit float.
+    /// Used by generated code when the `BigDecimal` feature is not enabled.
+    /// Returns an error if the value is outside the range of f64.
+    pub fn big_decimal_str(&mut self, x: &str) -> RpcResult<&mut Self> {
+        let f: f64 = x
+            .trim()
+            .parse()
+            .map_err(|e| RpcError::Ser(format!("invalid bigDecimal '{x}': {e}")))?;
+        if !f.is_finite() {
+            return Err(RpcError::Ser(
+                "BigDecimal feature not enabled: value exceeds f64 range".to_string(),
+            ));
+        }
+        self.inner.f64(f)?;
+        Ok(self)
+    }
+
     // Pierce the veil.
     // This module exposes public functions to support code generated
     // by `weld-codegen`. Its purpose is to create an abstraction layer
@@ -540,3 +888,830 @@ pub fn decode_float(d: &mut Decoder<'_>) -> RpcResult<f32> {
 pub fn decode_double(d: &mut Decoder<'_>) -> RpcResult<f64> {
     d.f64()
 }
+
+// Unions
+//
+// A union value is encoded as a map with a single entry: the key is the variant name,
+// and the value is the variant's value. For example, variant `count` holding 5
+// is encoded as `{ "count": 5 }`.
+// The generated code for smithy unions uses [field_num, value] instead;
+// these helpers are for hand-written union types, which may be extended with new variants
+// that older decoders won't recognize.
+
+/// Result of decoding a union
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Union<T> {
+    /// A variant recognized by the decoder
+    Known(T),
+    /// A variant not recognized by the decoder. Holds the variant name; the value is skipped.
+    Unknown(String),
+}
+
+/// Encodes a union value, as a single-entry map from the variant name to the value.
+/// `encode_value` must write exactly one cbor value
+pub fn encode_union<W, F>(e: &mut Encoder<W>, variant: &str, encode_value: F) -> RpcResult<()>
+where
+    W: Write,
+    W::Error: std::fmt::Display,
+    F: FnOnce(&mut Encoder<W>) -> RpcResult<()>,
+{
+    e.map(1)?;
+    e.str(variant)?;
+    encode_value(e)
+}
+
+/// Decodes a union value written by [encode_union].
+/// `decode_variant` is called with the variant name. It should decode the value and return Some,
+/// or, if it doesn't recognize the name, return None without reading from the decoder.
+/// For unrecognized variants, the value is skipped and [Union::Unknown] is returned.
+pub fn decode_union<'b, T, F>(d: &mut Decoder<'b>, decode_variant: F) -> RpcResult<Union<T>>
+where
+    F: FnOnce(&str, &mut Decoder<'b>) -> RpcResult<Option<T>>,
+{
+    let len = d.fixed_map()?;
+    if len != 1 {
+        return Err(RpcError::Deser(format!(
+            "union must be a map with one entry, found {len}"
+        )));
+    }
+    let variant = d.str()?;
+    match decode_variant(variant, d)? {
+        Some(val) => Ok(Union::Known(val)),
+        None => {
+            d.skip()?;
+            Ok(Union::Unknown(variant.to_string()))
+        }
+    }
+}
+
+// Dynamic values
+//
+// For handlers that forward or inspect payloads without a generated type, such as proxies.
+
+/// A cbor value of any type, decoded without a schema by [decode_dynamic]
+/// and written back with [encode_dynamic]
+#[derive(Clone, Debug, PartialEq)]
+pub enum DynValue {
+    Null,
+    Undefined,
+    Bool(bool),
+    /// Any cbor integer, from -2<sup>64</sup> to 2<sup>64</sup> - 1
+    Integer(i128),
+    Float(f64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<DynValue>),
+    /// Map entries, in the order they were decoded. Keys may be of any type
+    Map(Vec<(DynValue, DynValue)>),
+    /// A tagged value, such as a bignum (tag 2 or 3), with the tag number
+    Tag(u64, Box<DynValue>),
+    /// A simple value other than false, true, null, and undefined
+    Simple(u8),
+}
+
+impl DynValue {
+    /// Returns the value of the map entry with the text key, if this is a map that has one
+    pub fn get(&self, key: &str) -> Option<&DynValue> {
+        match self {
+            DynValue::Map(entries) => entries.iter().find_map(|(k, v)| match k {
+                DynValue::Text(k) if k == key => Some(v),
+                _ => None,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes a cbor message (without the message format prefix) into a [DynValue].
+/// Returns `RpcError::Deser` if the message is not a single well-formed cbor value,
+/// or is nested deeper than the default [DecodeLimits](crate::common::DecodeLimits).
+pub fn decode_dynamic(buf: &[u8]) -> RpcResult<DynValue> {
+    let mut d = Decoder::new(buf);
+    let max_depth = crate::common::DecodeLimits::default().max_depth;
+    let value = decode_dynamic_value(&mut d, max_depth)?;
+    if d.remaining() > 0 {
+        return Err(RpcError::Deser(format!(
+            "{} bytes after the cbor value",
+            d.remaining()
+        )));
+    }
+    Ok(value)
+}
+
+fn decode_dynamic_value(d: &mut Decoder<'_>, depth: usize) -> RpcResult<DynValue> {
+    if depth == 0 {
+        return Err(RpcError::Deser("cbor value nested too deeply".to_string()));
+    }
+    let value = match d.inner.datatype()? {
+        MT::Null => {
+            d.inner.null()?;
+            DynValue::Null
+        }
+        MT::Undefined => {
+            d.inner.undefined()?;
+            DynValue::Undefined
+        }
+        MT::Bool => DynValue::Bool(d.inner.bool()?),
+        MT::U8 | MT::U16 | MT::U32 | MT::U64 | MT::I8 | MT::I16 | MT::I32 | MT::I64 | MT::Int => {
+            DynValue::Integer(d.inner.int()?.into())
+        }
+        MT::F16 | MT::F32 | MT::F64 => DynValue::Float(d.inner.f64()?),
+        MT::Simple => DynValue::Simple(d.inner.simple()?),
+        MT::Bytes => DynValue::Bytes(d.inner.bytes()?.to_vec()),
+        MT::BytesIndef => {
+            let mut bytes = Vec::new();
+            for chunk in d.inner.bytes_iter()? {
+                bytes.extend_from_slice(chunk?);
+            }
+            DynValue::Bytes(bytes)
+        }
+        MT::String => DynValue::Text(d.str()?.to_string()),
+        MT::StringIndef => {
+            let mut text = String::new();
+            for chunk in d.inner.str_iter()? {
+                text.push_str(chunk?);
+            }
+            DynValue::Text(text)
+        }
+        MT::Array | MT::ArrayIndef => {
+            let len = d.inner.array()?;
+            let mut items = Vec::new();
+            while !at_end(d, len, items.len())? {
+                items.push(decode_dynamic_value(d, depth - 1)?);
+            }
+            DynValue::Array(items)
+        }
+        MT::Map | MT::MapIndef => {
+            let len = d.inner.map()?;
+            let mut entries = Vec::new();
+            while !at_end(d, len, entries.len())? {
+                let key = decode_dynamic_value(d, depth - 1)?;
+                entries.push((key, decode_dynamic_value(d, depth - 1)?));
+            }
+            DynValue::Map(entries)
+        }
+        MT::Tag => {
+            let tag = tag_number(d.inner.tag()?);
+            DynValue::Tag(tag, Box::new(decode_dynamic_value(d, depth - 1)?))
+        }
+        ty @ (MT::Break | MT::Unknown(_)) => {
+            return Err(RpcError::Deser(format!("unexpected cbor {ty}")));
+        }
+    };
+    Ok(value)
+}
+
+/// Returns true if a container of `len` items (None if indefinite) has no more after `read`,
+/// consuming the break of an indefinite container
+fn at_end(d: &mut Decoder<'_>, len: Option<u64>, read: usize) -> RpcResult<bool> {
+    match len {
+        Some(n) => Ok(read as u64 >= n),
+        None if d.inner.datatype()? == MT::Break => {
+            d.inner.set_position(d.inner.position() + 1);
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// The number of a tag, which minicbor doesn't expose for named tags
+fn tag_number(tag: minicbor::data::Tag) -> u64 {
+    use minicbor::data::Tag;
+    match tag {
+        Tag::DateTime => 0x00,
+        Tag::Timestamp => 0x01,
+        Tag::PosBignum => 0x02,
+        Tag::NegBignum => 0x03,
+        Tag::Decimal => 0x04,
+        Tag::Bigfloat => 0x05,
+        Tag::ToBase64Url => 0x15,
+        Tag::ToBase64 => 0x16,
+        Tag::ToBase16 => 0x17,
+        Tag::Cbor => 0x18,
+        Tag::Uri => 0x20,
+        Tag::Base64Url => 0x21,
+        Tag::Base64 => 0x22,
+        Tag::Regex => 0x23,
+        Tag::Mime => 0x24,
+        Tag::Unassigned(n) => n,
+    }
+}
+
+/// Encodes a [DynValue] as cbor. The result decodes to an equal value, but may not have
+/// the same bytes as the message it was decoded from: containers and strings are written
+/// with definite lengths, and floats as f64.
+pub fn encode_dynamic(value: &DynValue) -> RpcResult<Vec<u8>> {
+    let mut e = vec_encoder(false);
+    encode_dynamic_value(&mut e, value)?;
+    Ok(e.into_inner())
+}
+
+fn encode_dynamic_value(e: &mut Encoder<Vec<u8>>, value: &DynValue) -> RpcResult<()> {
+    match value {
+        DynValue::Null => {
+            e.null()?;
+        }
+        DynValue::Undefined => {
+            e.inner.undefined()?;
+        }
+        DynValue::Bool(b) => {
+            e.bool(*b)?;
+        }
+        DynValue::Integer(n) => {
+            let int = minicbor::data::Int::try_from(*n)
+                .map_err(|_| RpcError::Ser(format!("integer {n} is outside the cbor range")))?;
+            e.inner.int(int)?;
+        }
+        DynValue::Float(f) => {
+            e.f64(*f)?;
+        }
+        DynValue::Simple(n) => {
+            e.inner.simple(*n)?;
+        }
+        DynValue::Bytes(bytes) => {
+            e.bytes(bytes)?;
+        }
+        DynValue::Text(text) => {
+            e.str(text)?;
+        }
+        DynValue::Array(items) => {
+            e.array(items.len() as u64)?;
+            for item in items {
+                encode_dynamic_value(e, item)?;
+            }
+        }
+        DynValue::Map(entries) => {
+            e.map(entries.len() as u64)?;
+            for (key, value) in entries {
+                encode_dynamic_value(e, key)?;
+                encode_dynamic_value(e, value)?;
+            }
+        }
+        DynValue::Tag(tag, value) => {
+            e.tag(*tag)?;
+            encode_dynamic_value(e, value)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        decode_dynamic, decode_union, encode_dynamic, encode_union, vec_encoder, Decoder, DynValue,
+        Encoder, Union,
+    };
+    use crate::common::{decode_document, encode_document, Document, Number};
+    use std::collections::HashMap;
+
+    fn encoded<F>(f: F) -> Vec<u8>
+    where
+        F: FnOnce(&mut Encoder<Vec<u8>>) -> crate::error::RpcResult<()>,
+    {
+        let mut e = vec_encoder(false);
+        f(&mut e).unwrap();
+        e.into_inner()
+    }
+
+    #[test]
+    fn int_representation() {
+        // integers always use the smallest encoding, with big-endian argument bytes
+        assert_eq!(encoded(|e| e.u8(23).map(|_| ())), vec![0x17]);
+        assert_eq!(encoded(|e| e.u8(24).map(|_| ())), vec![0x18, 0x18]);
+        assert_eq!(
+            encoded(|e| e.u16(0x1234).map(|_| ())),
+            vec![0x19, 0x12, 0x34]
+        );
+        assert_eq!(
+            encoded(|e| e.u32(0x1234_5678).map(|_| ())),
+            vec![0x1a, 0x12, 0x34, 0x56, 0x78]
+        );
+        assert_eq!(
+            encoded(|e| e.u64(0x0102_0304_0506_0708).map(|_| ())),
+            vec![0x1b, 1, 2, 3, 4, 5, 6, 7, 8]
+        );
+        assert_eq!(encoded(|e| e.u64(1).map(|_| ())), vec![0x01]);
+        // negative n is encoded as -1-n
+        assert_eq!(encoded(|e| e.i8(-1).map(|_| ())), vec![0x20]);
+        assert_eq!(encoded(|e| e.i16(-500).map(|_| ())), vec![0x39, 0x01, 0xf3]);
+        assert_eq!(
+            encoded(|e| e.i64(i64::MIN).map(|_| ())),
+            vec![0x3b, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+    }
+
+    #[test]
+    fn float_representation() {
+        assert_eq!(
+            encoded(|e| e.f32(1.5).map(|_| ())),
+            vec![0xfa, 0x3f, 0xc0, 0x00, 0x00]
+        );
+        assert_eq!(
+            encoded(|e| e.f64(-2.25).map(|_| ())),
+            vec![0xfb, 0xc0, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn other_representation() {
+        assert_eq!(encoded(|e| e.bool(true).map(|_| ())), vec![0xf5]);
+        assert_eq!(encoded(|e| e.null().map(|_| ())), vec![0xf6]);
+        assert_eq!(encoded(|e| e.str("ab").map(|_| ())), vec![0x62, b'a', b'b']);
+        assert_eq!(encoded(|e| e.bytes(&[9, 8]).map(|_| ())), vec![0x42, 9, 8]);
+        assert_eq!(encoded(|e| e.array(3).map(|_| ())), vec![0x83]);
+        assert_eq!(
+            encoded(|e| e.map(0x100).map(|_| ())),
+            vec![0xb9, 0x01, 0x00]
+        );
+        // header byte
+        assert_eq!(vec_encoder(true).into_inner(), vec![0x7f]);
+    }
+
+    #[test]
+    fn decode_wider_types() {
+        // a value encoded as u8 can be decoded into any wider int type
+        let buf = encoded(|e| e.u8(200).map(|_| ()));
+        assert_eq!(Decoder::new(&buf).u64().unwrap(), 200);
+        assert_eq!(Decoder::new(&buf).i32().unwrap(), 200);
+        // but not into one that's too narrow
+        assert!(Decoder::new(&buf).i8().is_err());
+    }
+
+    /// Small deterministic PRNG (splitmix64), so failures are reproducible
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = self.0;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+
+        fn string(&mut self) -> String {
+            const CHARS: &[char] = &['a', 'Z', '0', ' ', '.', 'é', '😀', '\0', '\u{ffff}'];
+            (0..self.below(8))
+                .map(|_| CHARS[self.below(CHARS.len() as u64) as usize])
+                .collect()
+        }
+
+        fn document(&mut self, depth: usize) -> Document {
+            // only generate leaves once we're deep enough
+            let kind = if depth == 0 { 3 + self.below(4) } else { self.below(7) };
+            match kind {
+                0 => (0..self.below(4))
+                    .map(|_| (self.string(), self.document(depth - 1)))
+                    .collect(),
+                1 => Document::Blob((0..self.below(16)).map(|_| self.next() as u8).collect()),
+                2 => (0..self.below(4)).map(|_| self.document(depth - 1)).collect(),
+                3 => Document::Number(match self.below(3) {
+                    0 => Number::PosInt(self.next() >> self.below(64)),
+                    1 => Number::NegInt(self.next() as i64 >> self.below(64)),
+                    _ => {
+                        let f = f64::from_bits(self.next());
+                        // NaN != NaN, so it can't be checked with PartialEq
+                        Number::Float(if f.is_nan() { 0.0 } else { f })
+                    }
+                }),
+                4 => Document::String(self.string()),
+                5 => Document::Bool(self.below(2) == 1),
+                _ => Document::Null,
+            }
+        }
+    }
+
+    fn encode_doc(doc: &Document) -> Vec<u8> {
+        let mut e = vec_encoder(false);
+        encode_document(&mut e, doc).unwrap();
+        e.into_inner()
+    }
+
+    /// try every decoding function on the input. Errors are fine, panics are not.
+    fn decode_everything(buf: &[u8]) {
+        let _ = decode_document(&mut Decoder::new(buf));
+        let _ = Decoder::new(buf).skip();
+        let _ = Decoder::new(buf).datatype();
+        let _ = Decoder::new(buf).u64();
+        let _ = Decoder::new(buf).i64();
+        let _ = Decoder::new(buf).f32();
+        let _ = Decoder::new(buf).f64();
+        let _ = Decoder::new(buf).str();
+        let _ = Decoder::new(buf).bytes();
+        let _ = Decoder::new(buf).char();
+        let _ = Decoder::new(buf).fixed_array();
+        let _ = Decoder::new(buf).fixed_map();
+    }
+
+    #[test]
+    fn prop_document_round_trip() {
+        let mut rng = Rng(0x5eed);
+        for _ in 0..2000 {
+            let doc = rng.document(4);
+            let buf = encode_doc(&doc);
+            let decoded = decode_document(&mut Decoder::new(&buf)).unwrap();
+            assert_eq!(doc, decoded, "round trip of {buf:x?}");
+        }
+    }
+
+    #[test]
+    fn prop_arbitrary_bytes_never_panic() {
+        let mut rng = Rng(0xdec0de);
+        for _ in 0..5000 {
+            let len = rng.below(48) as usize;
+            let buf: Vec<u8> = (0..len).map(|_| rng.next() as u8).collect();
+            decode_everything(&buf);
+        }
+        // valid encodings that are then mutated or truncated
+        for _ in 0..2000 {
+            let mut buf = encode_doc(&rng.document(3));
+            if buf.is_empty() {
+                continue;
+            }
+            if rng.below(2) == 0 {
+                let pos = rng.below(buf.len() as u64) as usize;
+                buf[pos] = rng.next() as u8;
+            } else {
+                buf.truncate(rng.below(buf.len() as u64) as usize);
+            }
+            decode_everything(&buf);
+        }
+    }
+
+    #[test]
+    fn invalid_utf8_path() {
+        let decode_err =
+            |buf: &[u8], f: &dyn Fn(&mut Decoder) -> crate::error::RpcResult<()>| match f(
+                &mut Decoder::new(buf),
+            )
+            .unwrap_err()
+            {
+                crate::error::RpcError::Deser(m) => m,
+                e => panic!("unexpected error {e}"),
+            };
+
+        // {"name": "ok", "labels": {"k": <0xc3 0x28>}}
+        let buf = [
+            0xa2, 0x64, b'n', b'a', b'm', b'e', 0x62, b'o', b'k', 0x66, b'l', b'a', b'b', b'e',
+            b'l', b's', 0xa1, 0x61, b'k', 0x62, 0xc3, 0x28,
+        ];
+        let m = decode_err(&buf, &|d| {
+            for _ in 0..d.fixed_map()? {
+                match d.str()? {
+                    "labels" => {
+                        d.fixed_map()?;
+                        d.str()?;
+                        d.str()?;
+                    }
+                    _ => d.skip()?,
+                }
+            }
+            Ok(())
+        });
+        // 0xc3 is valid as a lead byte; 0x28 is not a continuation byte
+        assert_eq!(m, "invalid utf-8 at field labels.k, byte 20");
+
+        // [[1, "a"], {"tags": ["x", <0xff>]}]
+        let buf = [
+            0x82, 0x82, 0x01, 0x61, b'a', 0xa1, 0x64, b't', b'a', b'g', b's', 0x82, 0x61, b'x',
+            0x61, 0xff,
+        ];
+        let m = decode_err(&buf, &|d| {
+            d.array()?;
+            d.skip()?;
+            d.map()?;
+            d.str()?;
+            d.array()?;
+            d.str()?;
+            d.str()?;
+            Ok(())
+        });
+        assert_eq!(m, "invalid utf-8 at field [1].tags[1], byte 15");
+
+        // an invalid key is reported at its map, and a top-level string at the root
+        let m = decode_err(&[0xa1, 0x61, 0xff, 0x00], &|d| {
+            d.map()?;
+            d.str()?;
+            Ok(())
+        });
+        assert_eq!(m, "invalid utf-8 at field <root>, byte 2");
+    }
+
+    #[test]
+    fn tricky_values() {
+        // Document::Object declaring u64::MAX entries - must not preallocate
+        decode_everything(&[
+            0x82, 0x00, 0xbb, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        ]);
+        // Document::Array declaring u64::MAX elements
+        decode_everything(&[
+            0x82, 0x02, 0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        ]);
+        // string with invalid utf-8
+        decode_everything(&[0x82, 0x04, 0x62, 0xc3, 0x28]);
+        // indefinite-length map where a fixed map is expected
+        decode_everything(&[0x82, 0x00, 0xbf, 0x61, b'a', 0xf6, 0xff]);
+        // half-precision float in a Number
+        decode_everything(&[0x82, 0x03, 0x82, 0x02, 0xf9, 0x3c, 0x00]);
+        // indefinite-length byte string containing a text chunk
+        assert!(Decoder::new(&[0x5f, 0x74, 0xce, 0xef, 0x18, 0x34, 0x63]).skip().is_err());
+        // skip over nested definite and indefinite containers, stopping at the next value
+        let buf = [
+            0x82, 0x9f, 0xa1, 0x01, 0x5f, 0x41, 0x00, 0xff, 0xff, 0xc1, 0x00, 0x07,
+        ];
+        let mut d = Decoder::new(&buf);
+        d.skip().unwrap();
+        assert_eq!(d.u8().unwrap(), 7);
+
+        // non-ascii keys and empty containers survive the round trip
+        let mut map = HashMap::new();
+        map.insert("ключ".to_string(), Document::Array(Vec::new()));
+        map.insert(String::new(), Document::Object(HashMap::new()));
+        let doc = Document::Object(map);
+        let buf = encode_doc(&doc);
+        assert_eq!(decode_document(&mut Decoder::new(&buf)).unwrap(), doc);
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Shape {
+        Circle(f64),
+        Label(String),
+    }
+
+    fn encode_shape(e: &mut Encoder<Vec<u8>>, shape: &Shape) -> crate::error::RpcResult<()> {
+        match shape {
+            Shape::Circle(r) => encode_union(e, "circle", |e| e.f64(*r).map(|_| ())),
+            Shape::Label(s) => encode_union(e, "label", |e| e.str(s).map(|_| ())),
+        }
+    }
+
+    fn decode_shape(d: &mut Decoder<'_>) -> crate::error::RpcResult<Union<Shape>> {
+        decode_union(d, |variant, d| {
+            Ok(match variant {
+                "circle" => Some(Shape::Circle(d.f64()?)),
+                "label" => Some(Shape::Label(d.str()?.to_string())),
+                _ => None,
+            })
+        })
+    }
+
+    #[test]
+    fn union_round_trip() {
+        for shape in [Shape::Circle(2.5), Shape::Label("hi".to_string())] {
+            let buf = encoded(|e| encode_shape(e, &shape));
+            assert_eq!(
+                decode_shape(&mut Decoder::new(&buf)).unwrap(),
+                Union::Known(shape)
+            );
+        }
+        // documented encoding: single-entry map
+        let buf = encoded(|e| encode_shape(e, &Shape::Label("x".to_string())));
+        assert_eq!(
+            buf,
+            vec![0xa1, 0x65, b'l', b'a', b'b', b'e', b'l', 0x61, b'x']
+        );
+    }
+
+    #[test]
+    fn union_unknown_variant() {
+        // a variant from a newer version, holding a value this decoder doesn't understand,
+        // followed by another value that must still be readable
+        let mut e = vec_encoder(false);
+        encode_union(&mut e, "square", |e| {
+            e.array(2)?.u32(3)?.u32(4)?;
+            Ok(())
+        })
+        .unwrap();
+        e.u8(42).unwrap();
+        let buf = e.into_inner();
+
+        let mut d = Decoder::new(&buf);
+        assert_eq!(
+            decode_shape(&mut d).unwrap(),
+            Union::Unknown("square".to_string())
+        );
+        assert_eq!(d.u8().unwrap(), 42);
+
+        // not a single-entry map
+        let buf = encoded(|e| e.map(2).map(|_| ()));
+        assert!(decode_shape(&mut Decoder::new(&buf)).is_err());
+    }
+
+    #[test]
+    fn big_integer_strings() {
+        let cases = [
+            ("0", vec![0x00]),
+            ("-1", vec![0x20]),
+            (
+                "18446744073709551615",
+                vec![0x1b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+            ),
+            // one past the 64-bit cbor integer range is a bignum
+            (
+                "18446744073709551616",
+                vec![0xc2, 0x49, 1, 0, 0, 0, 0, 0, 0, 0, 0],
+            ),
+            (
+                "-18446744073709551617",
+                vec![0xc3, 0x49, 1, 0, 0, 0, 0, 0, 0, 0, 0],
+            ),
+        ];
+        for (text, cbor) in cases {
+            let buf = encoded(|e| e.big_integer_str(text).map(|_| ()));
+            assert_eq!(buf, cbor, "encoding {text}");
+            assert_eq!(Decoder::new(&buf).big_integer_str().unwrap(), text);
+        }
+        for n in [i128::MAX, i128::MIN] {
+            let text = n.to_string();
+            let buf = encoded(|e| e.big_integer_str(&text).map(|_| ()));
+            assert_eq!(Decoder::new(&buf).big_integer_str().unwrap(), text);
+        }
+
+        // beyond i128
+        let too_big = format!("{}0", i128::MAX);
+        let mut e = vec_encoder(false);
+        assert!(matches!(
+            e.big_integer_str(&too_big),
+            Err(crate::error::RpcError::Ser(m)) if m.contains("BigInteger feature not enabled")
+        ));
+        let mut bignum = vec![0xc2, 0x51];
+        bignum.extend_from_slice(&[0xff; 17]);
+        assert!(matches!(
+            Decoder::new(&bignum).big_integer_str(),
+            Err(crate::error::RpcError::Deser(m)) if m.contains("BigInteger feature not enabled")
+        ));
+        assert!(vec_encoder(false).big_integer_str("12abc").is_err());
+    }
+
+    #[test]
+    fn bignum_tags() {
+        // tag 2, 2^64: one more than the largest cbor integer
+        let pos = [0xc2, 0x49, 0x01, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            Decoder::new(&pos).big_integer_str().unwrap(),
+            "18446744073709551616"
+        );
+        // tag 3, -1 - 2^64
+        let neg = [0xc3, 0x49, 0x01, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            Decoder::new(&neg).big_integer_str().unwrap(),
+            "-18446744073709551617"
+        );
+        // leading zero bytes and small magnitudes are accepted
+        assert_eq!(
+            Decoder::new(&[0xc2, 0x42, 0x00, 0x05]).big_integer_str().unwrap(),
+            "5"
+        );
+        assert_eq!(Decoder::new(&[0xc3, 0x40]).big_integer_str().unwrap(), "-1");
+        // other tags are rejected
+        assert!(Decoder::new(&[0xc1, 0x00]).big_integer_str().is_err());
+    }
+
+    #[test]
+    fn big_decimal_strings() {
+        let buf = encoded(|e| e.big_decimal_str("-2.5").map(|_| ()));
+        assert_eq!(Decoder::new(&buf).big_decimal_str().unwrap(), "-2.5");
+        let buf = encoded(|e| e.u32(7).map(|_| ()));
+        assert_eq!(Decoder::new(&buf).big_decimal_str().unwrap(), "7");
+
+        assert!(vec_encoder(false).big_decimal_str("1e400").is_err());
+        // decimal fraction 27315e-2
+        let fraction = [0xc4, 0x82, 0x21, 0x19, 0x6a, 0xb3];
+        assert!(Decoder::new(&fraction).big_decimal_str().is_err());
+    }
+
+    #[cfg(feature = "BigInteger")]
+    #[test]
+    fn big_integer_str_parses_as_bigint() {
+        use std::str::FromStr;
+
+        let text = format!("-{}", u64::MAX as u128 * 3);
+        let buf = encoded(|e| e.big_integer_str(&text).map(|_| ()));
+        let decoded = Decoder::new(&buf).big_integer_str().unwrap();
+        assert_eq!(
+            num_bigint::BigInt::from_str(&decoded).unwrap().to_string(),
+            text
+        );
+    }
+
+    #[cfg(feature = "BigInteger")]
+    #[test]
+    fn bignum_tags_as_bigint() {
+        use num_bigint::BigInt;
+        use std::str::FromStr;
+
+        let pos = [0xc2, 0x49, 0x01, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            Decoder::new(&pos).big_integer().unwrap(),
+            BigInt::from_str("18446744073709551616").unwrap()
+        );
+        let neg = [0xc3, 0x49, 0x01, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            Decoder::new(&neg).big_integer().unwrap(),
+            BigInt::from_str("-18446744073709551617").unwrap()
+        );
+        // beyond i128, with a round trip through the encoder
+        let huge = BigInt::from_str(&format!("-{}", "9".repeat(60))).unwrap();
+        let buf = encoded(|e| e.big_integer(&huge).map(|_| ()));
+        assert_eq!(buf[0], 0xc3);
+        assert_eq!(Decoder::new(&buf).big_integer().unwrap(), huge);
+        let buf = encoded(|e| e.big_integer(&BigInt::from(-7)).map(|_| ()));
+        assert_eq!(buf, [0x26]);
+    }
+
+    #[test]
+    fn lenient_optional_fields() {
+        use crate::core::decode_health_check_response;
+
+        // {"healthy": true, "message": 5}: the optional message has the wrong type
+        let bad_optional = encoded(|e| {
+            e.map(2)?.str("healthy")?.bool(true)?.str("message")?.u32(5)?;
+            Ok(())
+        });
+        // {"healthy": "yes"}: the required field has the wrong type
+        let bad_required = encoded(|e| {
+            e.map(1)?.str("healthy")?.str("yes")?;
+            Ok(())
+        });
+
+        assert!(decode_health_check_response(&mut Decoder::new(&bad_optional)).is_err());
+
+        let lenient = |buf| Decoder::new(buf).lenient_optional(true);
+        let optional = decode_health_check_response(&mut lenient(&bad_optional));
+        let required = decode_health_check_response(&mut lenient(&bad_required));
+
+        let resp = optional.unwrap();
+        assert!(resp.healthy);
+        assert_eq!(resp.message, None);
+        assert!(required.is_err());
+    }
+
+    #[test]
+    fn dynamic_round_trip() {
+        // {"id": -3, "tags": ["a", h'0102'], "nested": {1: [null, 2.5, 2(h'01')]}, "ok": true}
+        let buf = encoded(|e| {
+            e.map(4)?.str("id")?.i32(-3)?;
+            e.str("tags")?.array(2)?.str("a")?.bytes(&[1, 2])?;
+            e.str("nested")?.map(1)?.u8(1)?.array(3)?.null()?.f64(2.5)?;
+            e.tag(2)?.bytes(&[1])?;
+            e.str("ok")?.bool(true)?;
+            Ok(())
+        });
+        let value = decode_dynamic(&buf).unwrap();
+        assert_eq!(value.get("id"), Some(&DynValue::Integer(-3)));
+        let nested = value.get("nested").unwrap();
+        assert_eq!(
+            nested,
+            &DynValue::Map(vec![(
+                DynValue::Integer(1),
+                DynValue::Array(vec![
+                    DynValue::Null,
+                    DynValue::Float(2.5),
+                    DynValue::Tag(2, Box::new(DynValue::Bytes(vec![1]))),
+                ])
+            )])
+        );
+        // definite lengths and f64 floats, as written here, re-encode to the same bytes
+        assert_eq!(encode_dynamic(&value).unwrap(), buf);
+
+        // indefinite lengths decode to the same value
+        let indefinite = encoded(|e| {
+            e.begin_array()?
+                .str("x")?
+                .begin_map()?
+                .str("y")?
+                .u64(u64::MAX)?
+                .end()?
+                .end()?;
+            Ok(())
+        });
+        let value = decode_dynamic(&indefinite).unwrap();
+        let expected = DynValue::Array(vec![
+            DynValue::Text("x".to_string()),
+            DynValue::Map(vec![(
+                DynValue::Text("y".to_string()),
+                DynValue::Integer(u64::MAX as i128),
+            )]),
+        ]);
+        assert_eq!(value, expected);
+        assert_eq!(
+            decode_dynamic(&encode_dynamic(&value).unwrap()).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn dynamic_errors() {
+        assert!(decode_dynamic(&[0x82, 0x01]).is_err());
+        // two values
+        assert!(decode_dynamic(&[0x01, 0x02]).is_err());
+        // nested deeper than the default limit
+        let deep = vec![0x81; 10_000];
+        assert!(decode_dynamic(&deep).is_err());
+        assert!(encode_dynamic(&DynValue::Integer(i128::MAX)).is_err());
+    }
+}
@@ -124,11 +124,7 @@ impl ChunkEndpoint {
 
     /// chunkify a message
     #[instrument(level = "trace", skip(self, bytes))]
-    pub async fn chunkify(
-        &self,
-        inv_id: &str,
-        mut bytes: (impl AsyncRead + Unpin),
-    ) -> RpcResult<()> {
+    pub async fn chunkify(&self, inv_id: &str, mut bytes: impl AsyncRead + Unpin) -> RpcResult<()> {
         let store = self.create_or_reuse_store().await?;
         debug!(invocation_id = %inv_id, "chunkify starting to send");
         let info = store
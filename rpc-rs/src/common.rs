@@ -1,12 +1,19 @@
-use crate::error::{RpcError, RpcResult};
+use crate::{
+    core::WasmCloudEntity,
+    error::{RpcError, RpcResult},
+};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use std::{borrow::Cow, fmt};
+#[cfg(not(target_arch = "wasm32"))]
+use wascap::{jwt, prelude::Claims};
 
 pub use crate::document::{
     decode_document, decode_number, encode_document, encode_document_ref, encode_number, Document,
     DocumentRef, Number,
 };
+#[cfg(not(target_arch = "wasm32"))]
+pub use channel_transport::ChannelTransport;
 
 /// A wasmcloud message
 #[derive(Debug)]
@@ -19,16 +26,184 @@ pub struct Message<'m> {
 }
 
 /// Context - message passing metadata used by wasmhost Actors and Capability Providers
-#[derive(Default, Debug, Clone)]
+///
+/// A context can be saved with [to_bytes](Context::to_bytes), for example with work
+/// queued for later, and restored with [from_bytes](Context::from_bytes).
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Context {
     /// Messages received by Context Provider will have actor set to the actor's public key
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub actor: Option<String>,
 
     /// Span name/context for tracing. This is a placeholder for now
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub span: Option<String>,
+
+    /// Validated claims from the invocation token, set on messages received by a Capability Provider.
+    /// The claims are signed by the host that forwarded the invocation, and bind the
+    /// invocation's origin and target urls to a hash of the message.
+    #[cfg(not(target_arch = "wasm32"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub claims: Option<Claims<jwt::Invocation>>,
+
+    /// Idempotency key supplied by the sender, if any. Requests carrying the same key
+    /// are retries of the same logical operation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+
+    /// Schema hash of the operation, as sent by the sender (see [SendOpts::schema_hash])
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema_hash: Option<u64>,
+
+    /// Time after which the sender no longer waits for a response, if known.
+    /// A handler doing slow work can check this to give up early.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<crate::Timestamp>,
+
+    /// Priority requested by the sender (see [SendOpts::priority])
+    pub priority: Priority,
+
+    /// The entity the message was sent to, set on messages received by a Capability Provider
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<WasmCloudEntity>,
+
+    /// Time the message was sent, by the sender's clock, if the sender set [SendOpts::send_time]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<crate::Timestamp>,
+
+    /// Time the message was received, by the receiver's clock,
+    /// set on messages received by a Capability Provider
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub received_at: Option<crate::Timestamp>,
+
+    /// Headers of the message, as (name, value) pairs sorted by name,
+    /// set on messages received by a Capability Provider.
+    /// These include the sender's [SendOpts::headers]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub headers: Vec<(String, String)>,
+
+    /// Format for the response, chosen from those the sender accepts
+    /// (see [SendOpts::accept_formats]) if the sender listed any,
+    /// or else the [request_format](Context::request_format) if the receiver supports it.
+    /// This is what the sender would like; a reply is only labelled with a format
+    /// by [DispatchResult::serialize_reply] or [DispatchResult::with_format].
+    #[serde(skip)]
+    pub reply_format: Option<MessageFormat>,
+
+    /// Format of the request payload, if the sender named it in the
+    /// [FORMAT_HEADER](crate::rpc_client::FORMAT_HEADER) header
+    #[serde(skip)]
+    pub request_format: Option<MessageFormat>,
+}
+
+/// Priority of a request. A provider can use it to choose a queue for the work;
+/// it doesn't change how the request is routed.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    /// Value of the priority header
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        }
+    }
+
+    /// Parses the value of the priority header. Unrecognized values are Normal
+    pub fn from_header(value: &str) -> Priority {
+        match value {
+            "low" => Priority::Low,
+            "high" => Priority::High,
+            _ => Priority::Normal,
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Context {
+    /// Serializes the context, including its claims and other metadata, in the crate's
+    /// msgpack encoding. Equal contexts produce identical bytes.
+    pub fn to_bytes(&self) -> RpcResult<Vec<u8>> {
+        serialize_stable(self)
+    }
+
+    /// Deserializes a context written by [to_bytes](Context::to_bytes).
+    /// Fields missing from the bytes, such as those added in later versions, get their defaults.
+    pub fn from_bytes(buf: &[u8]) -> RpcResult<Context> {
+        deserialize(buf)
+    }
+
+    /// Returns the value of the message header, if it was sent. See [Context::headers]
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /// Returns the difference between the time the message was sent, by the sender's clock,
+    /// and the time it was received, by ours, or None if either is unknown.
+    /// The difference includes the time in transit, so only a value well above
+    /// the usual latency means the clocks disagree.
+    pub fn apparent_skew(&self) -> Option<std::time::Duration> {
+        let (sent, received) = (self.sent_at?.as_nanos(), self.received_at?.as_nanos());
+        let skew = u64::try_from(sent.abs_diff(received)).unwrap_or(u64::MAX);
+        Some(std::time::Duration::from_nanos(skew))
+    }
+
+    /// Returns the invocation claims of the inbound message, if it was received over the lattice
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn claims(&self) -> Option<&Claims<jwt::Invocation>> {
+        self.claims.as_ref()
+    }
+
+    /// Returns a copy of the context with the origin replaced by `origin`,
+    /// for forwarding a request on behalf of another actor, such as an upgraded one
+    /// with a new public key. Other metadata is kept. The invocation claims are dropped,
+    /// because they are bound to the original origin.
+    #[must_use]
+    pub fn with_origin(&self, origin: WasmCloudEntity) -> Context {
+        Context {
+            actor: Some(origin.public_key),
+            #[cfg(not(target_arch = "wasm32"))]
+            claims: None,
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of the context with the target replaced by `target`.
+    /// Other metadata is kept. The invocation claims are dropped,
+    /// because they are bound to the original target.
+    #[must_use]
+    pub fn with_target(&self, target: WasmCloudEntity) -> Context {
+        Context {
+            target: Some(target),
+            #[cfg(not(target_arch = "wasm32"))]
+            claims: None,
+            ..self.clone()
+        }
+    }
 }
 
 /// Client config defines the intended recipient of a message and parameters that transport may use to adapt sending it
+///
+/// The timeout for a request is chosen in this order: [SendOpts::timeout], if set;
+/// then the transport's timeout (see [Transport::set_timeout]); then the client's default.
+/// [SendOpts::effective_timeout] applies this rule, and is what the transports use.
+/// To pass on the time remaining for an inbound request, use [SendOpts::from_context_deadline].
 #[derive(Default, Debug)]
 pub struct SendOpts {
     /// Optional flag for idempotent messages - transport may perform retries within configured timeouts
@@ -36,9 +211,86 @@ pub struct SendOpts {
 
     /// Optional flag for read-only messages - those that do not change the responder's state. read-only messages may be retried within configured timeouts.
     pub read_only: bool,
+
+    /// Optional key identifying this request for at-most-once handling. A receiver that
+    /// remembers recent keys can return the earlier response to a retried request
+    /// instead of running the operation again.
+    pub idempotency_key: Option<String>,
+
+    /// Optional hash of the operation's schema, so the receiver can detect
+    /// that it was generated from a different model.
+    /// See [SchemaRegistry](crate::dispatch::SchemaRegistry)
+    pub schema_hash: Option<u64>,
+
+    /// Optional timeout for this request, overriding the transport's timeout
+    pub timeout: Option<std::time::Duration>,
+
+    /// Priority of the request. Low and High are sent in a message header;
+    /// Normal, the default, sends no header.
+    pub priority: Priority,
+
+    /// Optional flag to send a checksum of the message, so the receiver rejects a payload
+    /// corrupted in transit with `RpcError::Deser("checksum mismatch")`.
+    /// A receiver that got a checksum sends one with its response too.
+    pub checksum: bool,
+
+    /// Optional flag for one-way messages: the message is published with no reply subject,
+    /// and the send returns an empty response as soon as it's published, without waiting.
+    /// Generated senders set this for operations with the `@oneWay` trait.
+    pub no_reply: bool,
+
+    /// Optional flag to send the time of sending, so the receiver can estimate
+    /// how far its clock is from the sender's. See [Context::apparent_skew]
+    pub send_time: bool,
+
+    /// Formats the sender can decode a response in, most preferred first, sent in the
+    /// [ACCEPT_FORMATS_HEADER](crate::rpc_client::ACCEPT_FORMATS_HEADER) header.
+    /// The receiver replies with the format it chose. Empty, the default, sends no header.
+    pub accept_formats: Vec<MessageFormat>,
+
+    /// Format the sender encoded the argument in, sent in the
+    /// [FORMAT_HEADER](crate::rpc_client::FORMAT_HEADER) header. A receiver that supports it
+    /// replies in the same format, unless `accept_formats` names others.
+    /// None, the default, sends no header.
+    pub format: Option<MessageFormat>,
+
+    /// Optional flag to accept a compressed response, sent in the
+    /// [ACCEPT_ENCODING_HEADER](crate::rpc_client::ACCEPT_ENCODING_HEADER) header.
+    /// Whether the response is compressed is up to the receiver; the client decompresses it.
+    pub accept_compression: bool,
+
+    /// Additional headers to send with the message, as (name, value) pairs.
+    /// Names may contain only ascii letters, digits, and '-', and may not start with `Wasmbus-`,
+    /// which is reserved for the client's own headers. Values may not contain line breaks.
+    /// Otherwise the send fails with `RpcError::InvalidParameter`.
+    /// Headers the client sets for other options replace custom headers of the same name.
+    pub headers: Vec<(String, String)>,
 }
 
 impl SendOpts {
+    /// Returns options with the timeout set to the time remaining until the context's
+    /// deadline, so a request made while handling an inbound request gives up
+    /// when the original sender does. If the deadline has passed, the timeout is zero.
+    /// If the context has no deadline, the timeout is not set.
+    pub fn from_context_deadline(ctx: &Context) -> SendOpts {
+        SendOpts {
+            timeout: ctx.deadline.map(|deadline| {
+                let now = crate::Timestamp::now().as_nanos();
+                std::time::Duration::from_nanos(deadline.as_nanos().saturating_sub(now) as u64)
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the timeout to use for the request: this request's timeout if set,
+    /// otherwise `fallback`, the transport's or client's timeout
+    pub fn effective_timeout(
+        &self,
+        fallback: Option<std::time::Duration>,
+    ) -> Option<std::time::Duration> {
+        self.timeout.or(fallback)
+    }
+
     #[must_use]
     pub fn idempotent(mut self, val: bool) -> SendOpts {
         self.idempotent = val;
@@ -50,6 +302,73 @@ impl SendOpts {
         self.read_only = val;
         self
     }
+
+    #[must_use]
+    pub fn idempotency_key<K: Into<String>>(mut self, key: K) -> SendOpts {
+        self.idempotency_key = Some(key.into());
+        self
+    }
+
+    #[must_use]
+    pub fn schema_hash(mut self, hash: u64) -> SendOpts {
+        self.schema_hash = Some(hash);
+        self
+    }
+
+    #[must_use]
+    pub fn timeout(mut self, val: std::time::Duration) -> SendOpts {
+        self.timeout = Some(val);
+        self
+    }
+
+    #[must_use]
+    pub fn priority(mut self, val: Priority) -> SendOpts {
+        self.priority = val;
+        self
+    }
+
+    #[must_use]
+    pub fn checksum(mut self, val: bool) -> SendOpts {
+        self.checksum = val;
+        self
+    }
+
+    #[must_use]
+    pub fn no_reply(mut self, val: bool) -> SendOpts {
+        self.no_reply = val;
+        self
+    }
+
+    #[must_use]
+    pub fn send_time(mut self, val: bool) -> SendOpts {
+        self.send_time = val;
+        self
+    }
+
+    #[must_use]
+    pub fn accept_formats(mut self, val: Vec<MessageFormat>) -> SendOpts {
+        self.accept_formats = val;
+        self
+    }
+
+    #[must_use]
+    pub fn format(mut self, val: Option<MessageFormat>) -> SendOpts {
+        self.format = val;
+        self
+    }
+
+    #[must_use]
+    pub fn accept_compression(mut self, val: bool) -> SendOpts {
+        self.accept_compression = val;
+        self
+    }
+
+    /// Adds a header to send with the message. See [SendOpts::headers]
+    #[must_use]
+    pub fn header(mut self, name: &str, value: &str) -> SendOpts {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
 }
 
 /// Transport determines how messages are sent
@@ -67,27 +386,434 @@ pub trait Transport: Send + Sync + Clone {
     fn set_timeout(&self, interval: std::time::Duration);
 }
 
-// select serialization/deserialization mode
+/// Sends the message to each target concurrently, with the whole batch bounded by `deadline`.
+/// Returns the results in the order of `targets`. Sends still unfinished at the deadline
+/// are cancelled and return `RpcError::DeadlineExceeded`; each send's timeout is
+/// the time remaining, so receivers can see the deadline too.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn send_batch_within<T: Transport>(
+    ctx: &Context,
+    targets: &[T],
+    msg: Message<'_>,
+    deadline: crate::Timestamp,
+) -> Vec<RpcResult<Vec<u8>>> {
+    let remaining = std::time::Duration::from_nanos(
+        deadline.as_nanos().saturating_sub(crate::Timestamp::now().as_nanos()) as u64,
+    );
+    let expires = tokio::time::Instant::now() + remaining;
+    let method = msg.method;
+    let sends = targets.iter().map(|target| {
+        let msg = Message {
+            method: msg.method,
+            arg: Cow::Borrowed(&msg.arg),
+        };
+        let opts = SendOpts::default().timeout(remaining);
+        async move {
+            tokio::time::timeout_at(expires, target.send(ctx, msg, Some(opts)))
+                .await
+                .unwrap_or_else(|_| {
+                    Err(RpcError::DeadlineExceeded(format!(
+                        "{method} unfinished when the batch deadline passed"
+                    )))
+                })
+        }
+    });
+    futures::future::join_all(sends).await
+}
+
+/// Limits on the messages accepted by [deserialize_with_limits].
+/// The defaults, used by [deserialize], are generous: they only reject messages
+/// far larger or deeper than any real message.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct DecodeLimits {
+    /// Maximum size of the message, in bytes
+    pub max_size: usize,
+    /// Maximum nesting of arrays, maps, and structs. A struct with no nested values has depth 1
+    pub max_depth: usize,
+    /// Maximum length, in bytes, of any string or byte array in the message
+    pub max_string_len: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_size: 1 << 30,
+            max_depth: 256,
+            max_string_len: 1 << 30,
+        }
+    }
+}
+
+impl DecodeLimits {
+    #[must_use]
+    pub fn max_size(mut self, val: usize) -> DecodeLimits {
+        self.max_size = val;
+        self
+    }
+
+    #[must_use]
+    pub fn max_depth(mut self, val: usize) -> DecodeLimits {
+        self.max_depth = val;
+        self
+    }
+
+    #[must_use]
+    pub fn max_string_len(mut self, val: usize) -> DecodeLimits {
+        self.max_string_len = val;
+        self
+    }
+}
+
+/// Deserializes a message, using the default [DecodeLimits]
 pub fn deserialize<'de, T: Deserialize<'de>>(buf: &'de [u8]) -> Result<T, RpcError> {
+    deserialize_with_limits(buf, &DecodeLimits::default())
+}
+
+/// Deserializes a message, as [deserialize] does, after checking it against the limits.
+/// A message that exceeds a limit fails with `RpcError::Deser`, before any of it is decoded.
+/// With the `legacy-decode` feature, only the size of a cbor message is checked.
+pub fn deserialize_with_limits<'de, T: Deserialize<'de>>(
+    buf: &'de [u8],
+    limits: &DecodeLimits,
+) -> Result<T, RpcError> {
+    #[cfg(not(feature = "legacy-decode"))]
+    crate::decode_limits::check_limits(buf, true, limits)?;
+    #[cfg(feature = "legacy-decode")]
+    match message_format(buf) {
+        (MessageFormat::Cbor, _) => crate::decode_limits::check_limits(buf, false, limits)?,
+        (MessageFormat::Msgpack, offset) => {
+            crate::decode_limits::check_limits(&buf[offset..], true, limits)?
+        }
+        _ => crate::decode_limits::check_limits(buf, true, limits)?,
+    }
+    decode_message(buf)
+}
+
+// select serialization/deserialization mode
+#[cfg(not(feature = "legacy-decode"))]
+fn decode_message<'de, T: Deserialize<'de>>(buf: &'de [u8]) -> Result<T, RpcError> {
     rmp_serde::from_slice(buf).map_err(|e| RpcError::Deser(e.to_string()))
 }
 
+/// Deserializes a message that may be in either the current or the legacy format.
+/// The format is chosen from the message header (see [message_format]):
+/// cbor is decoded as cbor, and anything else as msgpack.
+/// If a payload that looks like cbor fails to decode, it is decoded again as
+/// unprefixed (legacy) msgpack, and a warning is logged the first time this happens.
+#[cfg(feature = "legacy-decode")]
+fn decode_message<'de, T: Deserialize<'de>>(buf: &'de [u8]) -> Result<T, RpcError> {
+    static LEGACY_WARNING: std::sync::Once = std::sync::Once::new();
+
+    let msgpack =
+        |buf: &'de [u8]| rmp_serde::from_slice(buf).map_err(|e| RpcError::Deser(e.to_string()));
+    match message_format(buf) {
+        (MessageFormat::Cbor, offset) => match minicbor_ser::from_slice(&buf[offset..]) {
+            Ok(val) => Ok(val),
+            Err(cbor_err) => {
+                let val = msgpack(buf).map_err(|_| RpcError::Deser(cbor_err.to_string()))?;
+                LEGACY_WARNING.call_once(|| {
+                    tracing::warn!(
+                        "decoded a message in legacy msgpack format, after it failed to decode as cbor"
+                    )
+                });
+                Ok(val)
+            }
+        },
+        (MessageFormat::Msgpack, offset) => msgpack(&buf[offset..]),
+        _ => msgpack(buf),
+    }
+}
+
+/// Serializes the data as msgpack.
+/// In pooled mode (see [buffer_pool](crate::buffer_pool)), the message is written into a
+/// reused buffer, then copied into a Vec of exactly its size. That saves the reallocations
+/// of growing a new Vec, but not the copy.
+/// If an element of the data fails to serialize, the error names its path,
+/// such as `items[3].name`.
 pub fn serialize<T: Serialize>(data: &T) -> Result<Vec<u8>, RpcError> {
-    rmp_serde::to_vec_named(data).map_err(|e| RpcError::Ser(e.to_string()))
+    if crate::buffer_pool::is_pooled() {
+        return serialize_pooled(data).map(|buf| buf.to_vec());
+    }
+    rmp_serde::to_vec_named(data).map_err(|e| ser_error(data, e))
     // for benchmarking: the following line uses msgpack without field names
     //rmp_serde::to_vec(data).map_err(|e| RpcError::Ser(e.to_string()))
 }
 
+/// Serializes the data into a buffer from the [global](crate::buffer_pool::global) pool,
+/// whether or not pooled mode is on. The buffer returns to the pool when dropped.
+/// RpcClient sends take an owned Vec, so they use [serialize]; this is for callers
+/// that use the bytes in place, such as to hash them.
+pub fn serialize_pooled<T: Serialize>(
+    data: &T,
+) -> RpcResult<crate::buffer_pool::PooledBuffer<'static>> {
+    use bytes::BufMut;
+
+    let mut buf = crate::buffer_pool::global().get();
+    rmp_serde::encode::write_named(&mut (&mut *buf).writer(), data)
+        .map_err(|e| ser_error(data, e))?;
+    Ok(buf)
+}
+
+/// Error for data that failed to serialize, with the path to the failing element if it's
+/// inside a collection or struct. The data is walked again only after a failure.
+fn ser_error<T: Serialize + ?Sized, E: std::fmt::Display>(data: &T, e: E) -> RpcError {
+    match crate::error_path::locate(data) {
+        Some(path) => RpcError::Ser(format!("{e} (at {path})")),
+        None => RpcError::Ser(e.to_string()),
+    }
+}
+
+/// Options for [serialize_with]. The default options serialize the same way as [serialize]
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct SerializeOptions {
+    /// If true, serializing a NaN or infinite float fails with `RpcError::Ser`,
+    /// because some decoders reject them. If false (the default), they are
+    /// encoded as IEEE 754 special values.
+    pub strict_floats: bool,
+
+    /// If true, the entries of every map are written in a stable order (see [serialize_stable]).
+    /// If false (the default), maps are written in iteration order, which for a HashMap
+    /// can differ between runs.
+    pub stable_map_order: bool,
+
+    /// How enums declared with [int_enum](crate::int_enum) are written.
+    /// The default is their integer value.
+    pub enum_repr: crate::int_enum::EnumRepr,
+}
+
+impl SerializeOptions {
+    #[must_use]
+    pub fn strict_floats(mut self, val: bool) -> SerializeOptions {
+        self.strict_floats = val;
+        self
+    }
+
+    #[must_use]
+    pub fn stable_map_order(mut self, val: bool) -> SerializeOptions {
+        self.stable_map_order = val;
+        self
+    }
+
+    #[must_use]
+    pub fn enum_repr(mut self, val: crate::int_enum::EnumRepr) -> SerializeOptions {
+        self.enum_repr = val;
+        self
+    }
+}
+
+/// Serializes the data, as [serialize] does, with options
+pub fn serialize_with<T: Serialize>(data: &T, opts: &SerializeOptions) -> RpcResult<Vec<u8>> {
+    if opts.strict_floats {
+        crate::error_path::check_finite(data)?;
+    }
+    let buf = crate::int_enum::with_serde_repr(opts.enum_repr, || serialize(data))?;
+    if opts.stable_map_order {
+        crate::canonical::sort_map_keys(&buf)
+    } else {
+        Ok(buf)
+    }
+}
+
+/// Serializes the data so that equal values always produce identical bytes,
+/// for example for golden-file tests.
+/// The entries of every map are sorted by the serialized bytes of their keys.
+/// This includes structs, which are written as maps of field names, so fields
+/// may not be in declaration order. The output is still readable by [deserialize].
+pub fn serialize_stable<T: Serialize>(data: &T) -> RpcResult<Vec<u8>> {
+    serialize_with(data, &SerializeOptions::default().stable_map_order(true))
+}
+
+/// Serializes the data in the format, such as a [Context::reply_format].
+/// Cbor is written with its prefix (see [message_format]); json and msgpack are not.
+pub fn serialize_as<T: Serialize>(data: &T, format: MessageFormat) -> RpcResult<Vec<u8>> {
+    match format {
+        MessageFormat::Msgpack => serialize(data),
+        MessageFormat::Cbor => {
+            let mut buf = Vec::new();
+            MessageFormat::Cbor.write_header(&mut buf).unwrap();
+            minicbor_ser::to_writer(data, &mut buf).map_err(|e| ser_error(data, e))?;
+            Ok(buf)
+        }
+        MessageFormat::Json => serde_json::to_vec(data).map_err(|e| ser_error(data, e)),
+        _ => Err(RpcError::Ser(format!("cannot serialize as {format}"))),
+    }
+}
+
+/// Returns the number of bytes [serialize] would produce for the value,
+/// without allocating the output buffer.
+/// Useful for metrics, or for checking a message against the maximum payload size.
+pub fn serialized_size<T: Serialize>(data: &T) -> RpcResult<usize> {
+    let mut counter = ByteCounter(0);
+    rmp_serde::encode::write_named(&mut counter, data).map_err(|e| ser_error(data, e))?;
+    Ok(counter.0)
+}
+
+/// Writer that discards its input and counts the bytes written
+struct ByteCounter(usize);
+
+impl std::io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 #[async_trait]
 pub trait MessageDispatch {
     async fn dispatch(&self, ctx: &Context, message: Message<'_>) -> Result<Vec<u8>, RpcError>;
+
+    /// Dispatches the message, returning the response body with headers
+    /// for the transport to set on the reply.
+    /// The default implementation returns the body from [dispatch](MessageDispatch::dispatch)
+    /// with no headers. Providers call this method, so a dispatcher that sets
+    /// headers, such as content-type, overrides it.
+    /// The wrappers in [dispatch](crate::dispatch) forward it to the dispatcher they wrap.
+    async fn dispatch_with_metadata(
+        &self,
+        ctx: &Context,
+        message: Message<'_>,
+    ) -> RpcResult<DispatchResult> {
+        self.dispatch(ctx, message).await.map(DispatchResult::from)
+    }
+
+    /// Returns the operations this dispatcher handles, for reflection.
+    /// The derive macros list the operations of each service;
+    /// the default implementation returns an empty list.
+    fn operation_info(&self) -> Vec<OperationInfo> {
+        Vec::new()
+    }
+}
+
+/// Response of [MessageDispatch::dispatch_with_metadata]: the body, and headers
+/// that the provider sets as nats headers on the reply
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DispatchResult {
+    pub body: Vec<u8>,
+    pub headers: Vec<(String, String)>,
+}
+
+impl DispatchResult {
+    /// Adds a header to the reply. A provider fails the request with
+    /// `RpcError::InvalidParameter` if the header can't be sent: its name must have only
+    /// ascii letters, digits, and '-', and its value no line breaks.
+    #[must_use]
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Names the format of the body in the reply's
+    /// [FORMAT_HEADER](crate::rpc_client::FORMAT_HEADER) header.
+    /// A reply without one names no format, whatever the [reply_format](Context::reply_format).
+    #[cfg(not(target_arch = "wasm32"))]
+    #[must_use]
+    pub fn with_format(self, format: MessageFormat) -> Self {
+        self.with_header(crate::rpc_client::FORMAT_HEADER, format.to_string())
+    }
+
+    /// Serializes the data in the [reply_format](Context::reply_format), naming the
+    /// format in the reply, or as msgpack with no format named if there is none
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn serialize_reply<T: Serialize>(ctx: &Context, data: &T) -> RpcResult<DispatchResult> {
+        match ctx.reply_format {
+            Some(format) => serialize_as(data, format)
+                .map(|body| DispatchResult::from(body).with_format(format)),
+            None => serialize(data).map(DispatchResult::from),
+        }
+    }
+
+    /// Returns the format named in the headers, if any
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn format(&self) -> Option<MessageFormat> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(crate::rpc_client::FORMAT_HEADER))
+            .and_then(|(_, value)| MessageFormat::from_name(value))
+    }
+}
+
+impl From<Vec<u8>> for DispatchResult {
+    fn from(body: Vec<u8>) -> DispatchResult {
+        DispatchResult { body, headers: Vec::new() }
+    }
+}
+
+/// A response serialized once, for operations whose result rarely changes.
+/// A provider keeps it, for example in a `OnceCell`, and returns
+/// [dispatch_result](CachedResponse::dispatch_result) from
+/// [dispatch_with_metadata](MessageDispatch::dispatch_with_metadata).
+/// Senders that accept the saved format get a copy of the saved bytes, which costs
+/// a clone but not serializing again; the value is serialized again only for a sender
+/// that asked for another format.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Debug)]
+pub struct CachedResponse<T> {
+    value: T,
+    format: MessageFormat,
+    bytes: Vec<u8>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: Serialize> CachedResponse<T> {
+    /// Serializes the value in the format (see [serialize_as])
+    pub fn new(value: T, format: MessageFormat) -> RpcResult<CachedResponse<T>> {
+        let bytes = serialize_as(&value, format)?;
+        Ok(CachedResponse { value, format, bytes })
+    }
+
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    pub fn format(&self) -> MessageFormat {
+        self.format
+    }
+
+    /// Returns the saved bytes
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns the response to a request, in the [reply_format](Context::reply_format)
+    /// if there is one and otherwise in the saved format, naming the format in its headers
+    pub fn dispatch_result(&self, ctx: &Context) -> RpcResult<DispatchResult> {
+        match ctx.reply_format {
+            Some(format) if format != self.format => {
+                Ok(DispatchResult::from(serialize_as(&self.value, format)?).with_format(format))
+            }
+            _ => Ok(DispatchResult::from(self.bytes.clone()).with_format(self.format)),
+        }
+    }
+}
+
+/// Name of an operation, with the smithy names of its input and output types.
+/// Generated for each operation of a service.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OperationInfo {
+    /// full operation name, such as "KeyValue.Get"
+    pub name: &'static str,
+    /// input type, or None if the operation has no input
+    pub input_type: Option<&'static str>,
+    /// output type, or None if the operation has no output
+    pub output_type: Option<&'static str>,
+    /// whether the operation is one-way (has the `@oneWay` trait): senders don't wait
+    /// for a response, and providers don't send one
+    pub one_way: bool,
 }
 
 /// Message encoding format
-#[derive(Clone, Eq, PartialEq)]
+#[derive(Clone, Copy, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum MessageFormat {
     Msgpack,
     Cbor,
+    Json,
     Empty,
     Unknown,
 }
@@ -97,6 +823,7 @@ impl fmt::Display for MessageFormat {
         f.write_str(match self {
             MessageFormat::Msgpack => "msgpack",
             MessageFormat::Cbor => "cbor",
+            MessageFormat::Json => "json",
             MessageFormat::Empty => "empty",
             MessageFormat::Unknown => "unknown",
         })
@@ -114,10 +841,33 @@ impl MessageFormat {
         match self {
             MessageFormat::Cbor => buf.write(&[127u8]),    // 0x7f
             MessageFormat::Msgpack => buf.write(&[193u8]), // 0xc1
+            MessageFormat::Json => Ok(0),
             MessageFormat::Empty => Ok(0),
             MessageFormat::Unknown => Ok(0),
         }
     }
+
+    /// Returns the format with the name shown by `Display`, ignoring case,
+    /// or None if it isn't one that messages can be encoded in
+    pub fn from_name(name: &str) -> Option<MessageFormat> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "msgpack" => Some(MessageFormat::Msgpack),
+            "cbor" => Some(MessageFormat::Cbor),
+            "json" => Some(MessageFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Chooses the format for a response: the first of the formats offered by the sender,
+/// a comma-separated list of names, that is also supported by the receiver.
+/// Names of unknown formats are skipped. If there is no format in common, returns Cbor.
+pub fn negotiate_format(offered: &str, supported: &[MessageFormat]) -> MessageFormat {
+    offered
+        .split(',')
+        .filter_map(MessageFormat::from_name)
+        .find(|format| supported.contains(format))
+        .unwrap_or(MessageFormat::Cbor)
 }
 
 /// returns serialization format,
@@ -249,7 +999,7 @@ impl<T: Transport> AnySender<T> {
     }
 
     /// Send rpc with serializable payload using cbor encode/decode
-    pub async fn send_cbor<'de, In: minicbor::Encode<()>, Out: crate::cbor::MDecodeOwned<()>>(
+    pub async fn send_cbor<In: minicbor::Encode<()>, Out: crate::cbor::MDecodeOwned<()>>(
         &mut self,
         ctx: &Context,
         method: &str,
@@ -266,3 +1016,551 @@ impl<T: Transport> AnySender<T> {
 }
 
 pub type Unit = ();
+
+#[cfg(not(target_arch = "wasm32"))]
+mod channel_transport {
+    use std::{
+        borrow::Cow,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+    use tokio::sync::{mpsc, oneshot};
+
+    use super::{Context, Message, MessageDispatch, SendOpts, Transport};
+    use crate::{
+        error::{RpcError, RpcResult},
+        rpc_client::DEFAULT_RPC_TIMEOUT_MILLIS,
+    };
+
+    /// Default number of requests that can be queued before `send` waits
+    const DEFAULT_CHANNEL_CAPACITY: usize = 64;
+
+    struct ChannelRequest {
+        ctx: Context,
+        method: String,
+        arg: Vec<u8>,
+        reply: oneshot::Sender<RpcResult<Vec<u8>>>,
+    }
+
+    /// Transport that delivers messages to a dispatcher in the same process,
+    /// over a tokio channel, without nats.
+    /// This lets an actor and a provider be wired together in one binary,
+    /// for example in integration tests.
+    ///
+    /// Each message is dispatched on its own task, so slow requests don't block others.
+    /// Clones share the same channel and dispatcher.
+    /// Must be created within a tokio runtime.
+    #[derive(Clone)]
+    pub struct ChannelTransport {
+        sender: mpsc::Sender<ChannelRequest>,
+        timeout_ms: Arc<AtomicU64>,
+    }
+
+    impl ChannelTransport {
+        /// Creates a transport that sends to the dispatcher
+        pub fn new<D>(dispatcher: D) -> Self
+        where
+            D: MessageDispatch + Send + Sync + 'static,
+        {
+            Self::with_capacity(dispatcher, DEFAULT_CHANNEL_CAPACITY)
+        }
+
+        /// Creates a transport that sends to the dispatcher,
+        /// with room for `capacity` queued requests
+        pub fn with_capacity<D>(dispatcher: D, capacity: usize) -> Self
+        where
+            D: MessageDispatch + Send + Sync + 'static,
+        {
+            let (sender, mut receiver) = mpsc::channel::<ChannelRequest>(capacity.max(1));
+            let dispatcher = Arc::new(dispatcher);
+            tokio::spawn(async move {
+                while let Some(req) = receiver.recv().await {
+                    let dispatcher = dispatcher.clone();
+                    tokio::spawn(async move {
+                        let message = Message {
+                            method: &req.method,
+                            arg: Cow::Owned(req.arg),
+                        };
+                        let result = dispatcher.dispatch(&req.ctx, message).await;
+                        // sender may have timed out and gone away
+                        let _ = req.reply.send(result);
+                    });
+                }
+            });
+            ChannelTransport {
+                sender,
+                timeout_ms: Arc::new(AtomicU64::new(DEFAULT_RPC_TIMEOUT_MILLIS.as_millis() as u64)),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Transport for ChannelTransport {
+        async fn send(
+            &self,
+            ctx: &Context,
+            req: Message<'_>,
+            opts: Option<SendOpts>,
+        ) -> RpcResult<Vec<u8>> {
+            let (reply, response) = oneshot::channel();
+            let request = ChannelRequest {
+                ctx: ctx.clone(),
+                method: req.method.to_string(),
+                arg: req.arg.into_owned(),
+                reply,
+            };
+            let timeout = Duration::from_millis(self.timeout_ms.load(Ordering::Relaxed));
+            let timeout = match &opts {
+                Some(opts) => opts.effective_timeout(Some(timeout)).unwrap_or(timeout),
+                None => timeout,
+            };
+            let result = tokio::time::timeout(timeout, async move {
+                self.sender
+                    .send(request)
+                    .await
+                    .map_err(|_| RpcError::Other("channel transport closed".to_string()))?;
+                response.await.map_err(|_| {
+                    RpcError::Other("channel transport dropped the request".to_string())
+                })?
+            })
+            .await;
+            match result {
+                Ok(result) => result,
+                Err(elapsed) => Err(RpcError::Timeout(elapsed.to_string())),
+            }
+        }
+
+        fn set_timeout(&self, interval: Duration) {
+            self.timeout_ms.store(interval.as_millis() as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        deserialize, deserialize_with_limits, negotiate_format, serialize, serialize_as,
+        serialize_stable, serialize_with, serialized_size, DecodeLimits, MessageFormat,
+        SerializeOptions,
+    };
+    use crate::error::RpcError;
+    use serde::{Deserialize, Serialize};
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[tokio::test]
+    async fn batch_deadline_cancels_stragglers() {
+        use super::{send_batch_within, Context, Message, SendOpts, Transport};
+        use std::time::Duration;
+
+        /// Transport that replies with its name after a delay
+        #[derive(Clone)]
+        struct Delayed(&'static str, Duration);
+
+        #[async_trait::async_trait]
+        impl Transport for Delayed {
+            async fn send(
+                &self,
+                _ctx: &Context,
+                _req: Message<'_>,
+                opts: Option<SendOpts>,
+            ) -> Result<Vec<u8>, RpcError> {
+                assert!(opts.and_then(|o| o.timeout).is_some());
+                tokio::time::sleep(self.1).await;
+                Ok(self.0.as_bytes().to_vec())
+            }
+
+            fn set_timeout(&self, _interval: Duration) {}
+        }
+
+        let targets = [
+            Delayed("fast", Duration::ZERO),
+            Delayed("slow", Duration::from_secs(10)),
+            Delayed("quick", Duration::from_millis(10)),
+        ];
+        let msg = Message {
+            method: "Thing.Do",
+            arg: b"".as_ref().into(),
+        };
+        let deadline = crate::Timestamp::from_millis(crate::Timestamp::now().to_millis() + 200);
+        let started = std::time::Instant::now();
+        let results = send_batch_within(&Context::default(), &targets, msg, deadline).await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(results[0].as_ref().unwrap(), b"fast");
+        assert!(
+            matches!(&results[1], Err(RpcError::DeadlineExceeded(s)) if s.starts_with("Thing.Do")),
+            "{:?}",
+            results[1]
+        );
+        assert_eq!(results[2].as_ref().unwrap(), b"quick");
+    }
+
+    #[test]
+    fn format_negotiation() {
+        use MessageFormat::{Cbor, Msgpack};
+
+        assert_eq!(negotiate_format("msgpack, cbor", &[Cbor, Msgpack]), Msgpack);
+        assert_eq!(negotiate_format("json,CBOR", &[Msgpack, Cbor]), Cbor);
+        // nothing in common, or nothing offered
+        assert_eq!(negotiate_format("json", &[Msgpack]), Cbor);
+        assert_eq!(negotiate_format("", &[Msgpack]), Cbor);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn context_bytes_round_trip() {
+        use super::{Context, Priority};
+        use crate::{core::WasmCloudEntity, Timestamp};
+        use wascap::{jwt, prelude::Claims};
+
+        let target = WasmCloudEntity::new_provider("wasmcloud:thing", "default").unwrap();
+        let ctx = Context {
+            actor: Some("MACTOR".to_string()),
+            claims: Some(Claims::<jwt::Invocation>::new(
+                "NHOST".to_string(),
+                "inv-1".to_string(),
+                &target.url(),
+                "wasmbus://MACTOR",
+                "hash",
+            )),
+            idempotency_key: Some("order-17".to_string()),
+            schema_hash: Some(0xfeed),
+            deadline: Some(Timestamp::from_millis(1_700_000_000_123)),
+            priority: Priority::High,
+            target: Some(target),
+            ..Default::default()
+        };
+        let buf = ctx.to_bytes().unwrap();
+        assert_eq!(buf, ctx.clone().to_bytes().unwrap());
+        let restored = Context::from_bytes(&buf).unwrap();
+        assert_eq!(restored.actor, ctx.actor);
+        assert_eq!(restored.span, None);
+        assert_eq!(restored.claims, ctx.claims);
+        assert_eq!(restored.idempotency_key, ctx.idempotency_key);
+        assert_eq!(restored.schema_hash, ctx.schema_hash);
+        assert_eq!(restored.deadline, ctx.deadline);
+        assert_eq!(restored.priority, Priority::High);
+        assert_eq!(restored.target, ctx.target);
+
+        // without metadata
+        let restored = Context::from_bytes(&Context::default().to_bytes().unwrap()).unwrap();
+        assert!(restored.claims.is_none());
+        assert_eq!(restored.priority, Priority::Normal);
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Point {
+        name: String,
+        x: u32,
+    }
+
+    #[cfg(feature = "legacy-decode")]
+    fn point() -> Point {
+        Point { name: "a".to_string(), x: 3 }
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Reading {
+        point: Point,
+        samples: Vec<f32>,
+        average: Option<f64>,
+    }
+
+    fn reading(samples: Vec<f32>, average: f64) -> Reading {
+        Reading {
+            point: Point { name: "a".to_string(), x: 3 },
+            samples,
+            average: Some(average),
+        }
+    }
+
+    #[test]
+    fn timeout_from_deadline() {
+        use super::{Context, SendOpts};
+        use crate::Timestamp;
+        use std::time::Duration;
+
+        let in_5s = Timestamp::now().as_nanos() + 5_000_000_000;
+        let ctx = Context {
+            deadline: Some(Timestamp {
+                sec: (in_5s / 1_000_000_000) as i64,
+                nsec: (in_5s % 1_000_000_000) as u32,
+            }),
+            ..Default::default()
+        };
+        let opts = SendOpts::from_context_deadline(&ctx);
+        let timeout = opts.timeout.unwrap();
+        assert!(timeout <= Duration::from_secs(5) && timeout > Duration::from_secs(4));
+        // the request's timeout wins over the transport's
+        assert_eq!(
+            opts.effective_timeout(Some(Duration::from_secs(30))),
+            Some(timeout)
+        );
+
+        // a passed deadline leaves no time
+        let ctx = Context {
+            deadline: Some(Timestamp { sec: 1, nsec: 0 }),
+            ..Default::default()
+        };
+        assert_eq!(
+            SendOpts::from_context_deadline(&ctx).timeout,
+            Some(Duration::ZERO)
+        );
+
+        // without a deadline, the transport's timeout is used
+        let opts = SendOpts::from_context_deadline(&Context::default());
+        assert_eq!(opts.timeout, None);
+        assert_eq!(
+            opts.effective_timeout(Some(Duration::from_secs(2))),
+            Some(Duration::from_secs(2))
+        );
+    }
+
+    #[test]
+    fn non_finite_floats() {
+        let strict = SerializeOptions::default().strict_floats(true);
+        let lenient = SerializeOptions::default();
+        for bad in [
+            reading(vec![1.0, f32::NAN], 1.0),
+            reading(vec![1.0], f64::INFINITY),
+            reading(vec![f32::NEG_INFINITY], 1.0),
+        ] {
+            let err = serialize_with(&bad, &strict).unwrap_err();
+            assert!(matches!(err, RpcError::Ser(m) if m.starts_with("non-finite float in field")));
+
+            // the lenient path encodes special values, as serialize does
+            let buf = serialize_with(&bad, &lenient).unwrap();
+            assert_eq!(buf, serialize(&bad).unwrap());
+            let decoded: Reading = deserialize(&buf).unwrap();
+            let average = decoded.average.unwrap();
+            assert!(average.is_infinite() || decoded.samples.iter().any(|f| !f.is_finite()));
+        }
+
+        let err = serialize_with(&reading(vec![], f64::NAN), &strict).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "serialization: non-finite float in field 'average'"
+        );
+        let err = serialize_with(&reading(vec![1.0, f32::NAN], 1.0), &strict).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "serialization: non-finite float in field 'samples[1]'"
+        );
+
+        // finite values are unaffected
+        let good = reading(vec![0.5, -2.0], 1e300);
+        assert_eq!(
+            serialize_with(&good, &strict).unwrap(),
+            serialize(&good).unwrap()
+        );
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Labels {
+        name: String,
+        labels: std::collections::HashMap<String, Vec<std::collections::HashMap<u32, String>>>,
+    }
+
+    fn labels() -> Labels {
+        let inner = |n: u32| (0..n).map(|i| (i * 7919 % 101, format!("v{i}"))).collect();
+        Labels {
+            name: "a".to_string(),
+            labels: (0..40).map(|i| (format!("key{i}"), vec![inner(i), inner(3)])).collect(),
+        }
+    }
+
+    #[test]
+    fn stable_map_order() {
+        // separately built maps have different random hash seeds, so they usually iterate
+        // in different orders; stable serialization must not depend on that
+        let first = serialize_stable(&labels()).unwrap();
+        for _ in 0..5 {
+            assert_eq!(serialize_stable(&labels()).unwrap(), first);
+        }
+        assert_eq!(deserialize::<Labels>(&first).unwrap(), labels());
+        assert_eq!(first.len(), serialize(&labels()).unwrap().len());
+
+        // struct fields are map entries too
+        let point = Point { name: "b".to_string(), x: 70000 };
+        let buf = serialize_stable(&point).unwrap();
+        assert_eq!(&buf[..8], &[0x82, 0xa1, b'x', 0xce, 0, 1, 0x11, 0x70]);
+        assert_eq!(deserialize::<Point>(&buf).unwrap(), point);
+    }
+
+    #[test]
+    fn size_matches_serialize() {
+        let values = [
+            reading(vec![], 0.0),
+            reading(vec![1.5; 1000], -3.25),
+            reading((0..70000).map(|i| i as f32).collect(), 1e300),
+        ];
+        for value in values.iter() {
+            assert_eq!(
+                serialized_size(value).unwrap(),
+                serialize(value).unwrap().len()
+            );
+        }
+        assert_eq!(
+            serialized_size(&labels()).unwrap(),
+            serialize(&labels()).unwrap().len()
+        );
+        assert_eq!(serialized_size(&()).unwrap(), serialize(&()).unwrap().len());
+    }
+
+    /// Fails to serialize if it's odd
+    struct Even(u32);
+
+    impl serde::Serialize for Even {
+        fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+            if self.0 % 2 == 1 {
+                return Err(serde::ser::Error::custom(format!("{} is odd", self.0)));
+            }
+            s.serialize_u32(self.0)
+        }
+    }
+
+    #[test]
+    fn failing_element_path() {
+        let err = serialize(&vec![Even(0), Even(2), Even(5), Even(6)]).unwrap_err();
+        assert_eq!(err.to_string(), "serialization: 5 is odd (at [2])");
+
+        #[derive(serde::Serialize)]
+        struct Batch {
+            name: String,
+            groups: std::collections::BTreeMap<String, Vec<Even>>,
+        }
+        let batch = Batch {
+            name: "b".to_string(),
+            groups: [
+                ("a".to_string(), vec![Even(2)]),
+                ("b".to_string(), vec![Even(4), Even(8), Even(3)]),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let err = serialize(&batch).unwrap_err();
+        assert!(err.to_string().ends_with(r#"(at groups["b"][2])"#), "{err}");
+
+        // at the top level, there's no path to add
+        assert_eq!(
+            serialize(&Even(1)).unwrap_err().to_string(),
+            "serialization: 1 is odd"
+        );
+
+        // with options, and in other formats, the path is named the same way
+        let strict = SerializeOptions::default().strict_floats(true);
+        let err = serialize_with(&batch, &strict).unwrap_err();
+        assert!(err.to_string().ends_with(r#"(at groups["b"][2])"#), "{err}");
+        for format in [MessageFormat::Cbor, MessageFormat::Json] {
+            let err = serialize_as(&batch, format).unwrap_err();
+            assert!(err.to_string().ends_with(r#"(at groups["b"][2])"#), "{err}");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "legacy-decode")]
+    fn legacy_msgpack() {
+        // {"name": "a", "x": 3} as written by older actors: named msgpack map, no header
+        let legacy = [
+            0x82, 0xa4, b'n', b'a', b'm', b'e', 0xa1, b'a', 0xa1, b'x', 0x03,
+        ];
+        assert_eq!(deserialize::<Point>(&legacy).unwrap(), point());
+
+        // the same payload with the msgpack header
+        let mut prefixed = vec![0xc1];
+        prefixed.extend_from_slice(&legacy);
+        assert_eq!(deserialize::<Point>(&prefixed).unwrap(), point());
+    }
+
+    #[test]
+    #[cfg(feature = "legacy-decode")]
+    fn cbor_with_header() {
+        let mut buf = vec![0x7f];
+        buf.extend(minicbor_ser::to_vec(&point()).unwrap());
+        assert_eq!(deserialize::<Point>(&buf).unwrap(), point());
+
+        // falls back to msgpack: 0x7f is also the msgpack encoding of 127
+        assert_eq!(deserialize::<u8>(&[0x7f, 0xa1]).unwrap(), 127);
+
+        // neither valid cbor nor msgpack
+        assert!(deserialize::<Point>(&[0x7f, 0xff, 0xff]).is_err());
+    }
+
+    #[test]
+    fn decode_limits() {
+        fn exceeded<T: std::fmt::Debug>(res: Result<T, RpcError>) -> bool {
+            matches!(res, Err(RpcError::Deser(m)) if m.starts_with("decode limit exceeded"))
+        }
+        type Nested = Vec<Vec<Vec<String>>>;
+        let value: Nested = vec![vec![vec!["abcd".to_string()]], vec![]];
+        let buf = serialize(&value).unwrap();
+        let decode = |limits: DecodeLimits| deserialize_with_limits::<Nested>(&buf, &limits);
+        let defaults = DecodeLimits::default;
+
+        assert_eq!(decode(defaults()).unwrap(), value);
+        assert_eq!(deserialize::<Nested>(&buf).unwrap(), value);
+
+        // size
+        assert!(decode(defaults().max_size(buf.len())).is_ok());
+        assert!(exceeded(decode(defaults().max_size(buf.len() - 1))));
+        // depth: three nested arrays
+        assert!(decode(defaults().max_depth(3)).is_ok());
+        assert!(exceeded(decode(defaults().max_depth(2))));
+        // string length
+        assert!(decode(defaults().max_string_len(4)).is_ok());
+        assert!(exceeded(decode(defaults().max_string_len(3))));
+        // byte arrays count as strings
+        let blob = serialize(&serde_bytes::ByteBuf::from(vec![0u8; 10])).unwrap();
+        assert!(exceeded(deserialize_with_limits::<serde_bytes::ByteBuf>(
+            &blob,
+            &defaults().max_string_len(9)
+        )));
+
+        // a declared length larger than the message, and deep nesting,
+        // are rejected without allocating or recursing
+        let huge_array = [0xdd, 0xff, 0xff, 0xff, 0xff];
+        assert!(exceeded(deserialize_with_limits::<Vec<u8>>(
+            &[0x91; 300],
+            &defaults()
+        )));
+        assert!(deserialize::<Vec<u32>>(&huge_array).is_err());
+        // truncated input is reported by the decoder
+        let res = deserialize::<Nested>(&buf[..buf.len() - 2]);
+        assert!(matches!(res, Err(RpcError::Deser(m)) if !m.starts_with("decode limit")));
+    }
+
+    #[test]
+    fn forwarded_context() {
+        use super::{Context, Priority};
+        use crate::{core::WasmCloudEntity, Timestamp};
+
+        let ctx = Context {
+            actor: Some("MOLD".to_string()),
+            idempotency_key: Some("key".to_string()),
+            deadline: Some(Timestamp { sec: 100, nsec: 0 }),
+            priority: Priority::High,
+            ..Default::default()
+        };
+        let provider = WasmCloudEntity {
+            public_key: "VPROVIDER".to_string(),
+            link_name: "default".to_string(),
+            contract_id: "wasmcloud:test".to_string(),
+        };
+        let fwd = ctx
+            .with_origin(WasmCloudEntity::new_actor("MNEW").unwrap())
+            .with_target(provider.clone());
+        assert_eq!(fwd.actor.as_deref(), Some("MNEW"));
+        assert_eq!(fwd.target, Some(provider));
+        assert_eq!(fwd.idempotency_key, ctx.idempotency_key);
+        assert_eq!(fwd.deadline, ctx.deadline);
+        assert_eq!(fwd.priority, Priority::High);
+        assert!(fwd.claims().is_none());
+        // the original is unchanged
+        assert_eq!(ctx.actor.as_deref(), Some("MOLD"));
+        assert!(ctx.target.is_none());
+    }
+}
@@ -0,0 +1,135 @@
+//! Checks a msgpack message against [DecodeLimits](crate::common::DecodeLimits)
+//! before it is deserialized. Used by [deserialize_with_limits](crate::common::deserialize_with_limits)
+
+use crate::{
+    common::DecodeLimits,
+    error::{RpcError, RpcResult},
+};
+
+/// Returns an error if the message is larger than the limits allow.
+/// If `msgpack` is true, the nesting depth and string lengths of the first
+/// value are checked too. Malformed input is left for the decoder to report.
+pub(crate) fn check_limits(buf: &[u8], msgpack: bool, limits: &DecodeLimits) -> RpcResult<()> {
+    if buf.len() > limits.max_size {
+        return Err(exceeded(format!(
+            "message size {} > {}",
+            buf.len(),
+            limits.max_size
+        )));
+    }
+    if msgpack {
+        let mut reader = Reader { input: buf, pos: 0 };
+        // returns None if the input ended early
+        let _ = reader.check_value(limits).transpose()?;
+    }
+    Ok(())
+}
+
+fn exceeded(what: String) -> RpcError {
+    RpcError::Deser(format!("decode limit exceeded: {what}"))
+}
+
+struct Reader<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Advances past `len` bytes, or returns None if there are fewer left
+    fn skip(&mut self, len: usize) -> Option<()> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.input.len())?;
+        self.pos = end;
+        Some(())
+    }
+
+    /// Reads a big-endian unsigned length of `width` bytes
+    fn read_len(&mut self, width: usize) -> Option<usize> {
+        let start = self.pos;
+        self.skip(width)?;
+        Some(
+            self.input[start..self.pos]
+                .iter()
+                .fold(0usize, |n, b| (n << 8) | *b as usize),
+        )
+    }
+
+    /// Walks one value and its nested values, without recursion.
+    /// Returns None if the input ended early.
+    fn check_value(&mut self, limits: &DecodeLimits) -> Option<RpcResult<()>> {
+        // number of values still to read in each enclosing array or map
+        let mut open: Vec<usize> = Vec::new();
+        loop {
+            let marker = self.input.get(self.pos).copied()?;
+            self.pos += 1;
+            // (number of nested values), (length of a string, binary, or ext)
+            let (nested, string) = match marker {
+                0x80..=0x8f => (2 * (marker & 0x0f) as usize, None),
+                0x90..=0x9f => ((marker & 0x0f) as usize, None),
+                0xa0..=0xbf => (0, Some((marker & 0x1f) as usize)),
+                0xc4 | 0xd9 => (0, Some(self.read_len(1)?)),
+                0xc5 | 0xda => (0, Some(self.read_len(2)?)),
+                0xc6 | 0xdb => (0, Some(self.read_len(4)?)),
+                // ext: length, then a type byte
+                0xc7 => (0, Some(self.read_len(1)? + 1)),
+                0xc8 => (0, Some(self.read_len(2)? + 1)),
+                0xc9 => (0, Some(self.read_len(4)? + 1)),
+                0xca | 0xce | 0xd2 => {
+                    self.skip(4)?;
+                    (0, None)
+                }
+                0xcb | 0xcf | 0xd3 => {
+                    self.skip(8)?;
+                    (0, None)
+                }
+                0xcc | 0xd0 => {
+                    self.skip(1)?;
+                    (0, None)
+                }
+                0xcd | 0xd1 => {
+                    self.skip(2)?;
+                    (0, None)
+                }
+                // fixext: type byte, then 1, 2, 4, 8, or 16 bytes
+                0xd4..=0xd8 => (0, Some(1 + (1 << (marker - 0xd4)))),
+                0xdc => (self.read_len(2)?, None),
+                0xdd => (self.read_len(4)?, None),
+                0xde => (2 * self.read_len(2)?, None),
+                0xdf => (2usize.checked_mul(self.read_len(4)?)?, None),
+                // ints, nil, bool, and the unused marker 0xc1, which the decoder rejects
+                _ => (0, None),
+            };
+            if let Some(len) = string {
+                if len > limits.max_string_len {
+                    return Some(Err(exceeded(format!(
+                        "string length {len} > {}",
+                        limits.max_string_len
+                    ))));
+                }
+                self.skip(len)?;
+            }
+            if nested > 0 {
+                if open.len() >= limits.max_depth {
+                    return Some(Err(exceeded(format!(
+                        "nesting depth > {}",
+                        limits.max_depth
+                    ))));
+                }
+                open.push(nested);
+                continue;
+            }
+            // a value is complete, which may complete its enclosing arrays and maps
+            loop {
+                match open.last_mut() {
+                    None => return Some(Ok(())),
+                    Some(left) => {
+                        *left -= 1;
+                        if *left > 0 {
+                            break;
+                        }
+                        open.pop();
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,1253 @@
+//! Composable wrappers around [MessageDispatch](crate::common::MessageDispatch)
+//!
+//! Each wrapper implements `MessageDispatch` itself, so wrappers can be nested,
+//! and a provider can delegate its own `dispatch` to a wrapped receiver.
+//! Wrappers forward `dispatch_with_metadata`, so reply headers set by the wrapped
+//! dispatcher reach the reply.
+//!
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use aliases::OperationAliases;
+#[cfg(not(target_arch = "wasm32"))]
+pub use chain::ChainDispatch;
+#[cfg(not(target_arch = "wasm32"))]
+pub use cooperative::{YieldEvery, DEFAULT_YIELD_INTERVAL};
+#[cfg(not(target_arch = "wasm32"))]
+pub use idempotency::IdempotencyCache;
+#[cfg(not(target_arch = "wasm32"))]
+pub use schema::{schema_hash, SchemaCheck, SchemaRegistry, SCHEMA_HASH_HEADER};
+#[cfg(not(target_arch = "wasm32"))]
+pub use single_flight::{SingleFlight, SingleFlightKeyFn};
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use streaming::{parse_frame, reply_items};
+#[cfg(not(target_arch = "wasm32"))]
+pub use streaming::{serve_stream, ReplyStream, StreamDispatch, STREAM_FRAME_HEADER};
+
+#[cfg(not(target_arch = "wasm32"))]
+mod single_flight {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use async_trait::async_trait;
+    use tokio::sync::oneshot;
+
+    use crate::{
+        common::{Context, DispatchResult, Message, MessageDispatch, OperationInfo},
+        error::{RpcError, RpcResult},
+    };
+
+    /// Computes the coalescing key for a request. Requests with equal keys that are
+    /// in flight at the same time share a single execution.
+    /// Returning None means the request is never coalesced.
+    pub type SingleFlightKeyFn = dyn Fn(&Context, &Message<'_>) -> Option<Vec<u8>> + Send + Sync;
+
+    pub(super) type Waiters = HashMap<Vec<u8>, Vec<oneshot::Sender<RpcResult<DispatchResult>>>>;
+
+    /// Dispatch wrapper that deduplicates concurrent identical requests.
+    ///
+    /// The first request for a key runs the inner dispatcher; requests with the same key
+    /// that arrive before it completes wait for, and receive a copy of, that result.
+    /// Once the result is delivered the key is forgotten, so this is not a cache:
+    /// a request arriving after completion runs the handler again.
+    ///
+    /// By default the key is the operation name plus the serialized argument.
+    /// Only wrap operations that are safe to share, such as reads.
+    pub struct SingleFlight<D> {
+        inner: D,
+        key_fn: Arc<SingleFlightKeyFn>,
+        in_flight: Arc<Mutex<Waiters>>,
+    }
+
+    impl<D: Clone> Clone for SingleFlight<D> {
+        fn clone(&self) -> Self {
+            SingleFlight {
+                inner: self.inner.clone(),
+                key_fn: self.key_fn.clone(),
+                in_flight: self.in_flight.clone(),
+            }
+        }
+    }
+
+    impl<D> SingleFlight<D> {
+        /// Wraps the dispatcher, using the default key of (operation, body)
+        pub fn new(inner: D) -> Self {
+            SingleFlight {
+                inner,
+                key_fn: Arc::new(default_key),
+                in_flight: Arc::new(Mutex::new(HashMap::new())),
+            }
+        }
+
+        /// Replaces the function used to compute the coalescing key
+        #[must_use]
+        pub fn with_key_fn<F>(mut self, key_fn: F) -> Self
+        where
+            F: Fn(&Context, &Message<'_>) -> Option<Vec<u8>> + Send + Sync + 'static,
+        {
+            self.key_fn = Arc::new(key_fn);
+            self
+        }
+
+        /// Returns a reference to the wrapped dispatcher
+        pub fn inner(&self) -> &D {
+            &self.inner
+        }
+    }
+
+    fn default_key(_ctx: &Context, message: &Message<'_>) -> Option<Vec<u8>> {
+        let mut key = Vec::with_capacity(message.method.len() + 1 + message.arg.len());
+        key.extend_from_slice(message.method.as_bytes());
+        key.push(0);
+        key.extend_from_slice(&message.arg);
+        Some(key)
+    }
+
+    /// Removes the key when the leading request finishes, or is dropped before finishing.
+    /// In the latter case, dropping the senders wakes the waiters with an error.
+    pub(super) struct InFlightGuard<'a> {
+        pub(super) in_flight: &'a Mutex<Waiters>,
+        pub(super) key: Vec<u8>,
+        /// set once the waiters are taken; the key may then belong to a new leader
+        pub(super) done: bool,
+    }
+
+    impl<'a> InFlightGuard<'a> {
+        pub(super) fn take_waiters(&mut self) -> Vec<oneshot::Sender<RpcResult<DispatchResult>>> {
+            self.done = true;
+            self.in_flight.lock().unwrap().remove(&self.key).unwrap_or_default()
+        }
+    }
+
+    impl<'a> Drop for InFlightGuard<'a> {
+        fn drop(&mut self) {
+            if self.done {
+                return;
+            }
+            if let Ok(mut map) = self.in_flight.lock() {
+                map.remove(&self.key);
+            }
+        }
+    }
+
+    #[async_trait]
+    impl<D: MessageDispatch + Send + Sync> MessageDispatch for SingleFlight<D> {
+        fn operation_info(&self) -> Vec<OperationInfo> {
+            self.inner.operation_info()
+        }
+
+        async fn dispatch(&self, ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+            Ok(self.dispatch_with_metadata(ctx, message).await?.body)
+        }
+
+        async fn dispatch_with_metadata(
+            &self,
+            ctx: &Context,
+            message: Message<'_>,
+        ) -> RpcResult<DispatchResult> {
+            let key = match (self.key_fn)(ctx, &message) {
+                Some(key) => key,
+                None => return self.inner.dispatch_with_metadata(ctx, message).await,
+            };
+            let receiver = {
+                let mut map = self.in_flight.lock().unwrap();
+                match map.get_mut(&key) {
+                    Some(waiters) => {
+                        let (tx, rx) = oneshot::channel();
+                        waiters.push(tx);
+                        Some(rx)
+                    }
+                    None => {
+                        map.insert(key.clone(), Vec::new());
+                        None
+                    }
+                }
+            };
+            if let Some(rx) = receiver {
+                return rx.await.unwrap_or_else(|_| {
+                    Err(RpcError::Other(
+                        "coalesced request was cancelled before completing".to_string(),
+                    ))
+                });
+            }
+
+            let mut guard = InFlightGuard {
+                in_flight: &self.in_flight,
+                key,
+                done: false,
+            };
+            let result = self.inner.dispatch_with_metadata(ctx, message).await;
+            for waiter in guard.take_waiters() {
+                // receiver may have been dropped; that's ok
+                let _ = waiter.send(result.clone());
+            }
+            result
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod aliases {
+    use std::{
+        collections::{HashMap, HashSet},
+        sync::{Arc, Mutex},
+    };
+
+    use async_trait::async_trait;
+
+    use crate::{
+        common::{Context, DispatchResult, Message, MessageDispatch, OperationInfo},
+        error::RpcResult,
+    };
+
+    /// Dispatch wrapper that accepts old operation names during an interface rename.
+    /// A request for an aliased name is passed to the inner dispatcher
+    /// with the new name. The first use of each alias is logged as deprecated.
+    #[derive(Clone)]
+    pub struct OperationAliases<D> {
+        inner: D,
+        aliases: HashMap<String, String>,
+        warned: Arc<Mutex<HashSet<String>>>,
+    }
+
+    impl<D> OperationAliases<D> {
+        pub fn new(inner: D) -> Self {
+            OperationAliases {
+                inner,
+                aliases: HashMap::new(),
+                warned: Arc::new(Mutex::new(HashSet::new())),
+            }
+        }
+
+        /// Routes requests for operation `old` to operation `new`.
+        /// Both are full method names, such as "Service.Method"
+        #[must_use]
+        pub fn register_alias<S: Into<String>>(mut self, old: S, new: S) -> Self {
+            self.aliases.insert(old.into(), new.into());
+            self
+        }
+
+        /// Returns a reference to the wrapped dispatcher
+        pub fn inner(&self) -> &D {
+            &self.inner
+        }
+    }
+
+    #[async_trait]
+    impl<D: MessageDispatch + Send + Sync> MessageDispatch for OperationAliases<D> {
+        fn operation_info(&self) -> Vec<OperationInfo> {
+            self.inner.operation_info()
+        }
+
+        async fn dispatch(&self, ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+            Ok(self.dispatch_with_metadata(ctx, message).await?.body)
+        }
+
+        async fn dispatch_with_metadata(
+            &self,
+            ctx: &Context,
+            message: Message<'_>,
+        ) -> RpcResult<DispatchResult> {
+            let new = match self.aliases.get(message.method) {
+                Some(new) => new,
+                None => return self.inner.dispatch_with_metadata(ctx, message).await,
+            };
+            if self.warned.lock().unwrap().insert(message.method.to_string()) {
+                tracing::warn!(
+                    operation = message.method,
+                    replacement = %new,
+                    "deprecated operation name; use the replacement"
+                );
+            }
+            let message = Message { method: new, arg: message.arg };
+            self.inner.dispatch_with_metadata(ctx, message).await
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod chain {
+    use std::{borrow::Cow, sync::Arc};
+
+    use async_trait::async_trait;
+
+    use crate::{
+        common::{Context, DispatchResult, Message, MessageDispatch, OperationInfo},
+        error::{RpcError, RpcResult},
+    };
+
+    /// Dispatcher that tries each of its dispatchers in order, until one handles the message.
+    /// It falls through to the next only on `RpcError::MethodNotHandled`;
+    /// any other error is returned to the caller. If no dispatcher handles the
+    /// message, the result is the last one's `MethodNotHandled`.
+    #[derive(Clone, Default)]
+    pub struct ChainDispatch {
+        dispatchers: Vec<Arc<dyn MessageDispatch + Send + Sync>>,
+    }
+
+    impl ChainDispatch {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Adds a dispatcher, tried after those already added
+        #[must_use]
+        pub fn then<D: MessageDispatch + Send + Sync + 'static>(mut self, dispatcher: D) -> Self {
+            self.dispatchers.push(Arc::new(dispatcher));
+            self
+        }
+    }
+
+    #[async_trait]
+    impl MessageDispatch for ChainDispatch {
+        fn operation_info(&self) -> Vec<OperationInfo> {
+            self.dispatchers.iter().flat_map(|d| d.operation_info()).collect()
+        }
+
+        async fn dispatch(&self, ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+            Ok(self.dispatch_with_metadata(ctx, message).await?.body)
+        }
+
+        async fn dispatch_with_metadata(
+            &self,
+            ctx: &Context,
+            message: Message<'_>,
+        ) -> RpcResult<DispatchResult> {
+            let mut result = Err(RpcError::MethodNotHandled(message.method.to_string()));
+            for dispatcher in self.dispatchers.iter() {
+                let attempt = Message {
+                    method: message.method,
+                    arg: Cow::Borrowed(&message.arg),
+                };
+                result = dispatcher.dispatch_with_metadata(ctx, attempt).await;
+                if !matches!(result, Err(RpcError::MethodNotHandled(_))) {
+                    break;
+                }
+            }
+            result
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod idempotency {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use async_trait::async_trait;
+
+    use crate::{
+        common::{Context, DispatchResult, Message, MessageDispatch, OperationInfo},
+        error::RpcResult,
+    };
+
+    /// Default limit on the number of remembered keys
+    const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+    /// (sending actor, idempotency key)
+    type CacheKey = (Option<String>, String);
+
+    type Entries = HashMap<CacheKey, (Instant, DispatchResult)>;
+
+    /// Dispatch wrapper that gives at-most-once semantics to requests carrying an
+    /// idempotency key (see [SendOpts::idempotency_key](crate::common::SendOpts::idempotency_key)).
+    ///
+    /// After a request with a key completes successfully, its response, with its headers, is remembered
+    /// for `ttl`. A request from the same actor with the same key that arrives within
+    /// that window gets the remembered response, and the inner dispatcher is not called.
+    /// Errors are not remembered, so a failed request can be retried.
+    /// Requests without a key are passed through unchanged.
+    ///
+    /// This does not coalesce duplicates that arrive while the first is still running;
+    /// wrap the inner dispatcher with [SingleFlight](super::SingleFlight) for that.
+    pub struct IdempotencyCache<D> {
+        inner: D,
+        ttl: Duration,
+        max_entries: usize,
+        entries: Arc<Mutex<Entries>>,
+    }
+
+    impl<D: Clone> Clone for IdempotencyCache<D> {
+        fn clone(&self) -> Self {
+            IdempotencyCache {
+                inner: self.inner.clone(),
+                ttl: self.ttl,
+                max_entries: self.max_entries,
+                entries: self.entries.clone(),
+            }
+        }
+    }
+
+    impl<D> IdempotencyCache<D> {
+        /// Wraps the dispatcher, remembering responses for `ttl`
+        pub fn new(inner: D, ttl: Duration) -> Self {
+            IdempotencyCache {
+                inner,
+                ttl,
+                max_entries: DEFAULT_MAX_ENTRIES,
+                entries: Arc::new(Mutex::new(HashMap::new())),
+            }
+        }
+
+        /// Sets the maximum number of remembered keys. When the cache is full,
+        /// the oldest response is forgotten to make room.
+        #[must_use]
+        pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+            self.max_entries = max_entries.max(1);
+            self
+        }
+
+        /// Returns a reference to the wrapped dispatcher
+        pub fn inner(&self) -> &D {
+            &self.inner
+        }
+
+        fn lookup(&self, key: &CacheKey) -> Option<DispatchResult> {
+            let mut entries = self.entries.lock().unwrap();
+            match entries.get(key) {
+                Some((stored, resp)) if stored.elapsed() < self.ttl => Some(resp.clone()),
+                Some(_) => {
+                    entries.remove(key);
+                    None
+                }
+                None => None,
+            }
+        }
+
+        fn store(&self, key: CacheKey, resp: DispatchResult) {
+            let mut entries = self.entries.lock().unwrap();
+            let ttl = self.ttl;
+            entries.retain(|_, (stored, _)| stored.elapsed() < ttl);
+            if entries.len() >= self.max_entries {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, (stored, _))| *stored)
+                    .map(|(k, _)| k.clone());
+                if let Some(oldest) = oldest {
+                    entries.remove(&oldest);
+                }
+            }
+            entries.insert(key, (Instant::now(), resp));
+        }
+    }
+
+    #[async_trait]
+    impl<D: MessageDispatch + Send + Sync> MessageDispatch for IdempotencyCache<D> {
+        fn operation_info(&self) -> Vec<OperationInfo> {
+            self.inner.operation_info()
+        }
+
+        async fn dispatch(&self, ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+            Ok(self.dispatch_with_metadata(ctx, message).await?.body)
+        }
+
+        async fn dispatch_with_metadata(
+            &self,
+            ctx: &Context,
+            message: Message<'_>,
+        ) -> RpcResult<DispatchResult> {
+            let key = match &ctx.idempotency_key {
+                Some(key) => (ctx.actor.clone(), key.clone()),
+                None => return self.inner.dispatch_with_metadata(ctx, message).await,
+            };
+            if let Some(resp) = self.lookup(&key) {
+                return Ok(resp);
+            }
+            let resp = self.inner.dispatch_with_metadata(ctx, message).await?;
+            self.store(key, resp.clone());
+            Ok(resp)
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod schema {
+    use std::collections::HashMap;
+
+    use async_trait::async_trait;
+
+    use crate::{
+        common::{Context, DispatchResult, Message, MessageDispatch, OperationInfo},
+        error::{RpcError, RpcResult},
+    };
+
+    /// Nats header carrying the schema hash of the request's operation, as 16 hex digits
+    pub const SCHEMA_HASH_HEADER: &str = "Wasmbus-Schema-Hash";
+
+    /// Returns a stable 64-bit fingerprint (FNV-1a) of a schema description,
+    /// such as the operation's input and output shapes from the model.
+    /// The same description produces the same hash on every platform and release.
+    pub fn schema_hash(schema: &str) -> u64 {
+        schema.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+    }
+
+    /// Schema hashes of operations, keyed by method name ("Service.Method").
+    /// A sender attaches the hash to requests with
+    /// [SendOpts::schema_hash](crate::common::SendOpts::schema_hash),
+    /// and a receiver checks it with [SchemaCheck].
+    #[derive(Clone, Debug, Default)]
+    pub struct SchemaRegistry {
+        hashes: HashMap<String, u64>,
+    }
+
+    impl SchemaRegistry {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Registers the hash of an operation's schema
+        #[must_use]
+        pub fn register<S: Into<String>>(mut self, operation: S, hash: u64) -> Self {
+            self.hashes.insert(operation.into(), hash);
+            self
+        }
+
+        /// Returns the schema hash of the operation, if registered
+        pub fn operation_schema_hash(&self, operation: &str) -> Option<u64> {
+            self.hashes.get(operation).copied()
+        }
+    }
+
+    /// Dispatch wrapper that compares the schema hash sent with a request
+    /// to the hash registered for its operation, to detect an actor and provider
+    /// generated from different versions of a model.
+    ///
+    /// In strict mode a mismatch fails the request with `RpcError::Rpc("schema mismatch ..")`;
+    /// otherwise it is logged and the request is dispatched.
+    /// Requests without a hash, and operations that aren't registered, are not checked.
+    #[derive(Clone)]
+    pub struct SchemaCheck<D> {
+        inner: D,
+        registry: SchemaRegistry,
+        strict: bool,
+    }
+
+    impl<D> SchemaCheck<D> {
+        /// Wraps the dispatcher. Mismatches are logged, but not rejected, unless [strict](Self::strict) is set.
+        pub fn new(inner: D, registry: SchemaRegistry) -> Self {
+            SchemaCheck { inner, registry, strict: false }
+        }
+
+        /// If true, requests whose schema hash doesn't match are rejected
+        #[must_use]
+        pub fn strict(mut self, val: bool) -> Self {
+            self.strict = val;
+            self
+        }
+
+        /// Returns a reference to the wrapped dispatcher
+        pub fn inner(&self) -> &D {
+            &self.inner
+        }
+    }
+
+    #[async_trait]
+    impl<D: MessageDispatch + Send + Sync> MessageDispatch for SchemaCheck<D> {
+        fn operation_info(&self) -> Vec<OperationInfo> {
+            self.inner.operation_info()
+        }
+
+        async fn dispatch(&self, ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+            Ok(self.dispatch_with_metadata(ctx, message).await?.body)
+        }
+
+        async fn dispatch_with_metadata(
+            &self,
+            ctx: &Context,
+            message: Message<'_>,
+        ) -> RpcResult<DispatchResult> {
+            if let (Some(sent), Some(expected)) = (
+                ctx.schema_hash,
+                self.registry.operation_schema_hash(message.method),
+            ) {
+                if sent != expected {
+                    if self.strict {
+                        return Err(RpcError::Rpc(format!(
+                            "schema mismatch for {}: request has {sent:016x}, expected {expected:016x}",
+                            message.method
+                        )));
+                    }
+                    tracing::warn!(
+                        operation = message.method,
+                        sent = %format!("{sent:016x}"),
+                        expected = %format!("{expected:016x}"),
+                        "schema mismatch"
+                    );
+                }
+            }
+            self.inner.dispatch_with_metadata(ctx, message).await
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod cooperative {
+    /// Default number of items processed between yields by [serve_stream](super::serve_stream)
+    /// and the [BatchingPublisher](crate::batch_publish::BatchingPublisher)
+    pub const DEFAULT_YIELD_INTERVAL: usize = 64;
+
+    /// Yields to the runtime once every `interval` items, so a loop whose awaits are
+    /// always ready, such as publishing a long stream, doesn't starve other tasks
+    /// on the same thread.
+    #[derive(Debug)]
+    pub struct YieldEvery {
+        interval: usize,
+        count: usize,
+    }
+
+    impl YieldEvery {
+        /// Constructs a yield point. An interval of 0 never yields.
+        pub fn new(interval: usize) -> Self {
+            YieldEvery { interval, count: 0 }
+        }
+
+        /// Counts one item, and yields if `interval` items have been counted since the last yield
+        pub async fn tick(&mut self) {
+            if self.interval == 0 {
+                return;
+            }
+            self.count += 1;
+            if self.count >= self.interval {
+                self.count = 0;
+                tokio::task::yield_now().await;
+            }
+        }
+    }
+
+    impl Default for YieldEvery {
+        /// Yields every [DEFAULT_YIELD_INTERVAL] items
+        fn default() -> Self {
+            YieldEvery::new(DEFAULT_YIELD_INTERVAL)
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod streaming {
+    use async_trait::async_trait;
+    use futures::{stream::BoxStream, Stream, StreamExt};
+
+    use crate::{
+        async_nats::HeaderMap,
+        common::{Context, Message},
+        error::{RpcError, RpcResult},
+        rpc_client::RpcClient,
+    };
+
+    use super::YieldEvery;
+
+    /// Nats header that marks each message of a streamed reply as an item, an error, or the end
+    pub const STREAM_FRAME_HEADER: &str = "Wasmbus-Stream";
+
+    /// Sequence of replies to a single request
+    pub type ReplyStream = BoxStream<'static, RpcResult<Vec<u8>>>;
+
+    /// Dispatch variant for operations that reply with a sequence of updates,
+    /// such as tailing a log.
+    /// Items of the returned stream are published, in order, to the caller's reply subject
+    /// by [serve_stream], and consumed with [RpcClient::subscribe_replies].
+    #[async_trait]
+    pub trait StreamDispatch {
+        async fn dispatch_stream(
+            &self,
+            ctx: &Context,
+            message: Message<'_>,
+        ) -> RpcResult<ReplyStream>;
+    }
+
+    /// One message of a streamed reply.
+    /// A stream is any number of items, then either an end marker or an error.
+    #[derive(Debug)]
+    pub(crate) enum Frame {
+        Item(Vec<u8>),
+        /// The stream failed. No more frames follow.
+        /// The payload is the error as an error response
+        /// (see [RpcError::to_response_bytes]), so the client receives the same variant.
+        Error(RpcError),
+        End,
+    }
+
+    pub(crate) fn frame_message(frame: Frame) -> (HeaderMap, Vec<u8>) {
+        let mut headers = HeaderMap::new();
+        let (kind, payload) = match frame {
+            Frame::Item(bytes) => ("item", bytes),
+            Frame::Error(e) => (
+                "error",
+                e.to_response_bytes().unwrap_or_else(|_| e.to_string().into_bytes()),
+            ),
+            Frame::End => ("end", Vec::new()),
+        };
+        headers.insert(STREAM_FRAME_HEADER, kind);
+        (headers, payload)
+    }
+
+    pub(crate) fn parse_frame(headers: Option<&HeaderMap>, payload: &[u8]) -> RpcResult<Frame> {
+        let kind = headers
+            .and_then(|h| h.get(STREAM_FRAME_HEADER))
+            .map(|v| v.as_str())
+            .unwrap_or_default();
+        match kind {
+            "item" => Ok(Frame::Item(payload.to_vec())),
+            // senders built before errors were framed as responses send the error text
+            "error" => Ok(Frame::Error(
+                RpcError::from_response_bytes(payload).unwrap_or_else(|_| {
+                    RpcError::Rpc(String::from_utf8_lossy(payload).to_string())
+                }),
+            )),
+            "end" => Ok(Frame::End),
+            _ => Err(RpcError::Deser(format!(
+                "streamed reply is missing a valid {STREAM_FRAME_HEADER} header"
+            ))),
+        }
+    }
+
+    /// Converts a handler's stream to the frames that are published:
+    /// one per item, then an end marker, or an error frame if an item fails.
+    pub(crate) fn reply_frames(stream: ReplyStream) -> BoxStream<'static, Frame> {
+        futures::stream::unfold(Some(stream), |state| async move {
+            let mut stream = state?;
+            match stream.next().await {
+                Some(Ok(bytes)) => Some((Frame::Item(bytes), Some(stream))),
+                Some(Err(e)) => Some((Frame::Error(e), None)),
+                None => Some((Frame::End, None)),
+            }
+        })
+        .boxed()
+    }
+
+    /// Converts received frames back to the handler's items. The stream ends
+    /// at the end marker, or after returning an error: the sender's error,
+    /// or `RpcError::Rpc` if the frames stop before the end marker,
+    /// so a broken stream is not mistaken for a complete one.
+    pub(crate) fn reply_items<S>(frames: S) -> ReplyStream
+    where
+        S: Stream<Item = RpcResult<Frame>> + Send + Unpin + 'static,
+    {
+        futures::stream::unfold(Some(frames), |state| async move {
+            let mut frames = state?;
+            match frames.next().await {
+                Some(Ok(Frame::Item(bytes))) => Some((Ok(bytes), Some(frames))),
+                Some(Ok(Frame::End)) => None,
+                Some(Ok(Frame::Error(e))) | Some(Err(e)) => Some((Err(e), None)),
+                None => Some((
+                    Err(RpcError::Rpc(
+                        "streamed reply ended without an end marker".to_string(),
+                    )),
+                    None,
+                )),
+            }
+        })
+        .boxed()
+    }
+
+    /// Dispatches the message to a streaming handler, and publishes each item of its
+    /// stream to `reply_to`, followed by an end marker.
+    /// If the handler, or an item of the stream, fails, the error is published
+    /// and the stream ends.
+    /// Every `yield_interval` items, such as [DEFAULT_YIELD_INTERVAL](super::DEFAULT_YIELD_INTERVAL),
+    /// the task yields so a stream that is always ready doesn't starve other tasks.
+    /// 0 never yields.
+    /// Returns an error only if publishing fails.
+    pub async fn serve_stream<D>(
+        client: &RpcClient,
+        dispatcher: &D,
+        ctx: &Context,
+        message: Message<'_>,
+        reply_to: String,
+        yield_interval: usize,
+    ) -> RpcResult<()>
+    where
+        D: StreamDispatch + Sync + ?Sized,
+    {
+        let mut frames = match dispatcher.dispatch_stream(ctx, message).await {
+            Ok(stream) => reply_frames(stream),
+            Err(e) => futures::stream::iter([Frame::Error(e)]).boxed(),
+        };
+        let nc = client.client();
+        let mut yielder = YieldEvery::new(yield_interval);
+        while let Some(frame) = frames.next().await {
+            let (headers, payload) = frame_message(frame);
+            nc.publish_with_headers(reply_to.clone(), headers, payload.into())
+                .await
+                .map_err(|e| RpcError::Nats(e.to_string()))?;
+            yielder.tick().await;
+        }
+        nc.flush().await.map_err(|e| RpcError::Nats(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(target_arch = "wasm32"))]
+mod test {
+    use std::{
+        borrow::Cow,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+
+    use futures::StreamExt;
+
+    use super::{
+        schema_hash,
+        streaming::{frame_message, parse_frame, reply_frames, reply_items, Frame},
+        ChainDispatch, IdempotencyCache, OperationAliases, ReplyStream, SchemaCheck,
+        SchemaRegistry, SingleFlight, StreamDispatch, YieldEvery, DEFAULT_YIELD_INTERVAL,
+    };
+    use crate::{
+        common::{Context, DispatchResult, Message, MessageDispatch},
+        error::{RpcError, RpcResult},
+    };
+
+    #[derive(Clone, Default)]
+    struct SlowCounter {
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl MessageDispatch for SlowCounter {
+        async fn dispatch(&self, _ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(message.arg.to_vec())
+        }
+    }
+
+    fn msg(arg: &'static [u8]) -> Message<'static> {
+        Message {
+            method: "Cache.Get",
+            arg: Cow::Borrowed(arg),
+        }
+    }
+
+    #[tokio::test]
+    async fn coalesce_identical() {
+        let handler = SlowCounter::default();
+        let sf = SingleFlight::new(handler.clone());
+        let ctx = Context::default();
+
+        let results =
+            futures::future::join_all((0..10).map(|_| sf.dispatch(&ctx, msg(b"key1")))).await;
+
+        assert_eq!(handler.calls.load(Ordering::SeqCst), 1);
+        for r in results {
+            assert_eq!(r.unwrap(), b"key1".to_vec());
+        }
+
+        // not a cache: once completed, a new request runs again
+        sf.dispatch(&ctx, msg(b"key1")).await.unwrap();
+        assert_eq!(handler.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn distinct_keys() {
+        let handler = SlowCounter::default();
+        let sf = SingleFlight::new(handler.clone());
+        let ctx = Context::default();
+
+        let (a, b) = tokio::join!(sf.dispatch(&ctx, msg(b"a")), sf.dispatch(&ctx, msg(b"b")));
+        assert_eq!(a.unwrap(), b"a".to_vec());
+        assert_eq!(b.unwrap(), b"b".to_vec());
+        assert_eq!(handler.calls.load(Ordering::SeqCst), 2);
+
+        // key fn that opts out of coalescing
+        let sf = SingleFlight::new(handler.clone()).with_key_fn(|_, _| None);
+        let _ = tokio::join!(sf.dispatch(&ctx, msg(b"a")), sf.dispatch(&ctx, msg(b"a")));
+        assert_eq!(handler.calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn finished_leader_keeps_new_leader() {
+        use super::single_flight::{InFlightGuard, Waiters};
+
+        let in_flight = std::sync::Mutex::new(Waiters::new());
+        in_flight.lock().unwrap().insert(b"k".to_vec(), Vec::new());
+        let mut guard = InFlightGuard {
+            in_flight: &in_flight,
+            key: b"k".to_vec(),
+            done: false,
+        };
+        assert!(guard.take_waiters().is_empty());
+
+        // a new leader registers the key before the old guard is dropped
+        let (tx, _rx) = tokio::sync::oneshot::channel();
+        in_flight.lock().unwrap().insert(b"k".to_vec(), vec![tx]);
+        drop(guard);
+        assert_eq!(
+            in_flight.lock().unwrap().get(b"k".as_ref()).map(Vec::len),
+            Some(1)
+        );
+
+        // a leader dropped before finishing removes its key
+        let guard = InFlightGuard {
+            in_flight: &in_flight,
+            key: b"k".to_vec(),
+            done: false,
+        };
+        drop(guard);
+        assert!(in_flight.lock().unwrap().is_empty());
+    }
+
+    fn keyed_ctx(actor: &str, key: &str) -> Context {
+        Context {
+            actor: Some(actor.to_string()),
+            idempotency_key: Some(key.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn idempotent_repeat_returns_cached() {
+        let handler = SlowCounter::default();
+        let cache = IdempotencyCache::new(handler.clone(), Duration::from_secs(60));
+        let ctx = keyed_ctx("MA", "req-1");
+
+        assert_eq!(
+            cache.dispatch(&ctx, msg(b"first")).await.unwrap(),
+            b"first".to_vec()
+        );
+        // a retry with the same key gets the first response, even if the body differs
+        assert_eq!(
+            cache.dispatch(&ctx, msg(b"second")).await.unwrap(),
+            b"first".to_vec()
+        );
+        assert_eq!(handler.calls.load(Ordering::SeqCst), 1);
+
+        // another key, or the same key from another actor, runs the handler
+        cache.dispatch(&keyed_ctx("MA", "req-2"), msg(b"x")).await.unwrap();
+        cache.dispatch(&keyed_ctx("MB", "req-1"), msg(b"x")).await.unwrap();
+        assert_eq!(handler.calls.load(Ordering::SeqCst), 3);
+
+        // requests without a key are never cached
+        let ctx = Context::default();
+        cache.dispatch(&ctx, msg(b"x")).await.unwrap();
+        cache.dispatch(&ctx, msg(b"x")).await.unwrap();
+        assert_eq!(handler.calls.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test]
+    async fn idempotent_entry_expires() {
+        let handler = SlowCounter::default();
+        let cache = IdempotencyCache::new(handler.clone(), Duration::from_millis(20));
+        let ctx = keyed_ctx("MA", "req-1");
+
+        cache.dispatch(&ctx, msg(b"a")).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(
+            cache.dispatch(&ctx, msg(b"b")).await.unwrap(),
+            b"b".to_vec()
+        );
+        assert_eq!(handler.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn idempotent_max_entries() {
+        let handler = SlowCounter::default();
+        let cache =
+            IdempotencyCache::new(handler.clone(), Duration::from_secs(60)).with_max_entries(1);
+
+        cache.dispatch(&keyed_ctx("MA", "k1"), msg(b"a")).await.unwrap();
+        cache.dispatch(&keyed_ctx("MA", "k2"), msg(b"b")).await.unwrap();
+        // k1 was evicted to make room for k2
+        cache.dispatch(&keyed_ctx("MA", "k1"), msg(b"c")).await.unwrap();
+        assert_eq!(handler.calls.load(Ordering::SeqCst), 3);
+    }
+
+    /// Streams each line of the argument, failing at a line "!"
+    struct TailLog;
+
+    #[async_trait]
+    impl StreamDispatch for TailLog {
+        async fn dispatch_stream(
+            &self,
+            _ctx: &Context,
+            message: Message<'_>,
+        ) -> RpcResult<ReplyStream> {
+            let lines: Vec<RpcResult<Vec<u8>>> = String::from_utf8_lossy(&message.arg)
+                .lines()
+                .map(|line| match line {
+                    "!" => Err(RpcError::Other("log rotated".to_string())),
+                    line => Ok(line.as_bytes().to_vec()),
+                })
+                .collect();
+            Ok(futures::stream::iter(lines).boxed())
+        }
+    }
+
+    /// Runs the handler's stream through the framing used on the wire
+    async fn round_trip(arg: &'static [u8]) -> Vec<RpcResult<Vec<u8>>> {
+        let stream = TailLog.dispatch_stream(&Context::default(), msg(arg)).await.unwrap();
+        let frames = reply_frames(stream).map(|frame| {
+            let (headers, payload) = frame_message(frame);
+            parse_frame(Some(&headers), &payload)
+        });
+        reply_items(frames).collect().await
+    }
+
+    #[tokio::test]
+    async fn stream_in_order() {
+        let items = round_trip(b"one\ntwo\nthree").await;
+        let items: Vec<Vec<u8>> = items.into_iter().map(|r| r.unwrap()).collect();
+        assert_eq!(
+            items,
+            vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]
+        );
+
+        assert!(round_trip(b"").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stream_error_ends() {
+        let items = round_trip(b"one\n!\nthree").await;
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].as_ref().unwrap(), b"one");
+        assert!(matches!(&items[1], Err(RpcError::Other(m)) if m == "log rotated"));
+
+        // a message without frame header
+        assert!(parse_frame(None, b"x").is_err());
+    }
+
+    #[tokio::test]
+    async fn stream_error_after_items() {
+        // items delivered before the error are kept, and the error keeps its variant
+        let items = round_trip(b"one\ntwo\n!").await;
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0].as_ref().unwrap(), b"one");
+        assert_eq!(items[1].as_ref().unwrap(), b"two");
+        assert!(matches!(&items[2], Err(RpcError::Other(m)) if m == "log rotated"));
+
+        // frames that stop without an end marker are an error, not a clean end
+        let (headers, payload) = frame_message(Frame::Item(b"one".to_vec()));
+        let frames = futures::stream::iter([parse_frame(Some(&headers), &payload)]);
+        let items: Vec<_> = reply_items(frames).collect().await;
+        assert_eq!(items.len(), 2);
+        assert!(matches!(&items[1], Err(RpcError::Rpc(m)) if m.contains("end marker")));
+
+        // an error frame from an older sender holds just the text
+        let (headers, _) = frame_message(Frame::End);
+        let mut headers = headers;
+        headers.insert(super::streaming::STREAM_FRAME_HEADER, "error");
+        let frame = parse_frame(Some(&headers), b"disk full").unwrap();
+        assert!(matches!(frame, Frame::Error(RpcError::Rpc(m)) if m == "disk full"));
+    }
+
+    fn hashed_ctx(hash: u64) -> Context {
+        Context {
+            schema_hash: Some(hash),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn schema_hash_check() {
+        let hash = schema_hash("Cache.Get(String) -> String");
+        assert_eq!(hash, schema_hash("Cache.Get(String) -> String"));
+        assert_ne!(hash, schema_hash("Cache.Get(String) -> Blob"));
+        // FNV-1a of an empty description is the offset basis
+        assert_eq!(schema_hash(""), 0xcbf29ce484222325);
+
+        let registry = SchemaRegistry::new().register("Cache.Get", hash);
+        assert_eq!(registry.operation_schema_hash("Cache.Get"), Some(hash));
+        assert_eq!(registry.operation_schema_hash("Cache.Put"), None);
+
+        let handler = SlowCounter::default();
+        let strict = SchemaCheck::new(handler.clone(), registry.clone()).strict(true);
+        let resp = strict.dispatch(&hashed_ctx(hash), msg(b"a")).await.unwrap();
+        assert_eq!(resp, b"a");
+
+        let err = strict.dispatch(&hashed_ctx(hash ^ 1), msg(b"b")).await.unwrap_err();
+        assert!(matches!(err, RpcError::Rpc(m) if m.starts_with("schema mismatch")));
+        assert_eq!(handler.calls.load(Ordering::SeqCst), 1);
+
+        // requests without a hash aren't checked
+        strict.dispatch(&Context::default(), msg(b"c")).await.unwrap();
+
+        // lenient mode dispatches despite the mismatch
+        let lenient = SchemaCheck::new(handler.clone(), registry);
+        lenient.dispatch(&hashed_ctx(hash ^ 1), msg(b"d")).await.unwrap();
+        assert_eq!(handler.calls.load(Ordering::SeqCst), 3);
+    }
+
+    /// Replies with the method name it was called with
+    #[derive(Clone, Default)]
+    struct MethodEcho {}
+
+    #[async_trait]
+    impl MessageDispatch for MethodEcho {
+        async fn dispatch(&self, _ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+            match message.method {
+                "Store.Get" | "Store.Put" => Ok(message.method.as_bytes().to_vec()),
+                _ => Err(RpcError::MethodNotHandled(message.method.to_string())),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn operation_aliases() {
+        let aliases = OperationAliases::new(MethodEcho::default())
+            .register_alias("KeyValue.Get", "Store.Get");
+        let ctx = Context::default();
+        let call = |method: &'static str| Message { method, arg: Cow::Borrowed(b"") };
+
+        assert_eq!(
+            aliases.dispatch(&ctx, call("Store.Get")).await.unwrap(),
+            b"Store.Get"
+        );
+        // the old name reaches the same handler, every time
+        for _ in 0..2 {
+            assert_eq!(
+                aliases.dispatch(&ctx, call("KeyValue.Get")).await.unwrap(),
+                b"Store.Get"
+            );
+        }
+        assert_eq!(
+            aliases.dispatch(&ctx, call("Store.Put")).await.unwrap(),
+            b"Store.Put"
+        );
+        let err = aliases.dispatch(&ctx, call("KeyValue.Put")).await.unwrap_err();
+        assert!(matches!(err, RpcError::MethodNotHandled(m) if m == "KeyValue.Put"));
+    }
+
+    /// Handles one method, replying with its name, and declines the rest
+    struct Only(&'static str);
+
+    #[async_trait]
+    impl MessageDispatch for Only {
+        async fn dispatch(&self, _ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+            if message.method == self.0 {
+                Ok(message.method.as_bytes().to_vec())
+            } else {
+                Err(RpcError::MethodNotHandled(message.method.to_string()))
+            }
+        }
+    }
+
+    /// Fails every call with a real error
+    struct Broken {}
+
+    #[async_trait]
+    impl MessageDispatch for Broken {
+        async fn dispatch(&self, _ctx: &Context, _message: Message<'_>) -> RpcResult<Vec<u8>> {
+            Err(RpcError::Other("broken".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn chain_falls_through() {
+        let chain = ChainDispatch::new().then(Only("Store.Get")).then(Only("Store.Put"));
+        let ctx = Context::default();
+        let call = |method: &'static str| Message { method, arg: Cow::Borrowed(b"") };
+
+        // the first dispatcher declines, and the second handles it
+        assert_eq!(
+            chain.dispatch(&ctx, call("Store.Put")).await.unwrap(),
+            b"Store.Put"
+        );
+        assert_eq!(
+            chain.dispatch(&ctx, call("Store.Get")).await.unwrap(),
+            b"Store.Get"
+        );
+        let err = chain.dispatch(&ctx, call("Store.Delete")).await.unwrap_err();
+        assert!(matches!(err, RpcError::MethodNotHandled(m) if m == "Store.Delete"));
+
+        // other errors don't fall through
+        let chain = ChainDispatch::new().then(Broken {}).then(Only("Store.Get"));
+        let err = chain.dispatch(&ctx, call("Store.Get")).await.unwrap_err();
+        assert!(matches!(err, RpcError::Other(_)), "{err}");
+
+        let err = ChainDispatch::new().dispatch(&ctx, call("Store.Get")).await.unwrap_err();
+        assert!(matches!(err, RpcError::MethodNotHandled(_)), "{err}");
+    }
+
+    /// Replies with the argument, and a header naming the reply's cache lifetime
+    #[derive(Clone, Default)]
+    struct Tagged {
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl MessageDispatch for Tagged {
+        async fn dispatch(&self, ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+            Ok(self.dispatch_with_metadata(ctx, message).await?.body)
+        }
+
+        async fn dispatch_with_metadata(
+            &self,
+            _ctx: &Context,
+            message: Message<'_>,
+        ) -> RpcResult<DispatchResult> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(DispatchResult::from(message.arg.to_vec())
+                .with_header("Cache-Control", "max-age=60"))
+        }
+    }
+
+    #[tokio::test]
+    async fn wrappers_forward_reply_headers() {
+        let tagged = Tagged::default();
+        let wrapped: Vec<Box<dyn MessageDispatch + Send + Sync>> = vec![
+            Box::new(SingleFlight::new(tagged.clone())),
+            Box::new(OperationAliases::new(tagged.clone()).register_alias("Old.Get", "Cache.Get")),
+            Box::new(ChainDispatch::new().then(Only("Store.Get")).then(tagged.clone())),
+            Box::new(IdempotencyCache::new(
+                tagged.clone(),
+                Duration::from_secs(60),
+            )),
+            Box::new(SchemaCheck::new(tagged.clone(), SchemaRegistry::new())),
+        ];
+        let expected =
+            DispatchResult::from(b"a".to_vec()).with_header("Cache-Control", "max-age=60");
+        for dispatcher in wrapped.iter() {
+            let result = dispatcher.dispatch_with_metadata(&Context::default(), msg(b"a")).await;
+            assert_eq!(result.unwrap(), expected);
+        }
+
+        // a remembered response is replayed with its headers
+        let cache = IdempotencyCache::new(tagged.clone(), Duration::from_secs(60));
+        let ctx = keyed_ctx("MA", "req-1");
+        for _ in 0..2 {
+            let result = cache.dispatch_with_metadata(&ctx, msg(b"a")).await;
+            assert_eq!(result.unwrap(), expected);
+        }
+        assert_eq!(tagged.calls.load(Ordering::SeqCst), wrapped.len() + 1);
+    }
+
+    #[tokio::test]
+    async fn large_batch_yields() {
+        // returns the number of items processed before a task spawned
+        // on the same (single) thread got to run
+        async fn first_interleave(mut yielder: YieldEvery) -> Option<usize> {
+            let ran = Arc::new(AtomicUsize::new(0));
+            let flag = ran.clone();
+            let task = tokio::spawn(async move { flag.store(1, Ordering::SeqCst) });
+            let mut items = futures::stream::iter(0..1000usize);
+            let mut first = None;
+            while let Some(i) = items.next().await {
+                if first.is_none() && ran.load(Ordering::SeqCst) == 1 {
+                    first = Some(i);
+                }
+                yielder.tick().await;
+            }
+            task.await.unwrap();
+            first
+        }
+
+        assert_eq!(first_interleave(YieldEvery::new(100)).await, Some(100));
+        assert_eq!(
+            first_interleave(YieldEvery::default()).await,
+            Some(DEFAULT_YIELD_INTERVAL)
+        );
+        // without yield points, the other task waits for the whole batch
+        assert_eq!(first_interleave(YieldEvery::new(0)).await, None);
+    }
+}
@@ -803,7 +803,8 @@ pub fn decode_document(d: &mut crate::cbor::Decoder<'_>) -> RpcResult<Document>
         0 => {
             // Object
             let map_len = d.fixed_map()? as usize;
-            let mut map = HashMap::with_capacity(map_len);
+            // don't trust the declared length for preallocation: each entry needs at least one byte
+            let mut map = HashMap::with_capacity(map_len.min(d.remaining()));
             for _ in 0..map_len {
                 let k = d.str()?.to_string();
                 let v = decode_document(d)?;
@@ -818,7 +819,7 @@ pub fn decode_document(d: &mut crate::cbor::Decoder<'_>) -> RpcResult<Document>
         2 => {
             // Array
             let arr_len = d.fixed_array()? as usize;
-            let mut arr = Vec::with_capacity(arr_len);
+            let mut arr = Vec::with_capacity(arr_len.min(d.remaining()));
             for _ in 0..arr_len {
                 arr.push(decode_document(d)?);
             }
@@ -1,9 +1,10 @@
 /// An error that can occur in the processing of an RPC. This is not request-specific errors but
 /// rather cross-cutting errors that can always occur.
-#[derive(thiserror::Error, Debug)]
+#[derive(thiserror::Error, Clone, Debug)]
 #[non_exhaustive]
 pub enum RpcError {
     /// The request exceeded its deadline.
+    /// From a provider, this is sent back to the caller, which receives this variant too.
     #[error("the request exceeded its deadline: {0}")]
     DeadlineExceeded(String),
 
@@ -49,6 +50,10 @@ pub enum RpcError {
     #[error("timeout: {0}")]
     Timeout(String),
 
+    /// Domain error returned by a provider's handler
+    #[error("provider: {0}")]
+    Provider(ProviderError),
+
     /// Anything else
     #[error("{0}")]
     Other(String),
@@ -56,6 +61,244 @@ pub enum RpcError {
 
 pub type RpcResult<T> = std::result::Result<T, RpcError>;
 
+impl RpcError {
+    /// A short, stable name for the variant, such as "timeout", for metrics and logs
+    pub fn code(&self) -> &'static str {
+        match self {
+            RpcError::DeadlineExceeded(_) => "deadline_exceeded",
+            RpcError::NotInitialized(_) => "not_initialized",
+            RpcError::MethodNotHandled(_) => "method_not_handled",
+            RpcError::NotImplemented => "not_implemented",
+            RpcError::HostError(_) => "host_error",
+            RpcError::Deser(_) => "deser",
+            RpcError::Ser(_) => "ser",
+            RpcError::Rpc(_) => "rpc",
+            RpcError::Nats(_) => "nats",
+            RpcError::InvalidParameter(_) => "invalid_parameter",
+            RpcError::ActorHandler(_) => "actor_handler",
+            RpcError::ProviderInit(_) => "provider_init",
+            RpcError::Timeout(_) => "timeout",
+            RpcError::Provider(_) => "provider",
+            RpcError::Other(_) => "other",
+        }
+    }
+
+    /// Returns true if the request might succeed if sent again: it timed out,
+    /// or the message couldn't be delivered. Other errors would recur on retry.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self.code(), "timeout" | "nats" | "host_error")
+    }
+
+    /// Returns the domain error, if the handler rejected the request with a [ProviderError]
+    pub fn provider_error(&self) -> Option<&ProviderError> {
+        match self {
+            RpcError::Provider(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Prefixes the error message with the name of the operation that failed,
+    /// keeping the variant, so `Timeout("slow")` becomes `Timeout("Bank.Withdraw: slow")`.
+    /// The send and dispatch paths call this, so errors say which operation failed.
+    ///
+    /// An error whose message already contains the prefix is unchanged, as are
+    /// `NotImplemented`, which has no message, `Provider`, whose message
+    /// belongs to the interface that defines it, and a `MethodNotHandled` that already
+    /// names the operation.
+    #[must_use]
+    pub fn with_context(self, op: &str) -> RpcError {
+        let prefix = format!("{op}: ");
+        let tag = |msg: String| {
+            if msg.contains(&prefix) {
+                msg
+            } else {
+                format!("{prefix}{msg}")
+            }
+        };
+        match self {
+            RpcError::DeadlineExceeded(s) => RpcError::DeadlineExceeded(tag(s)),
+            RpcError::NotInitialized(s) => RpcError::NotInitialized(tag(s)),
+            RpcError::MethodNotHandled(s) if s.contains(op) => RpcError::MethodNotHandled(s),
+            RpcError::MethodNotHandled(s) => RpcError::MethodNotHandled(tag(s)),
+            RpcError::HostError(s) => RpcError::HostError(tag(s)),
+            RpcError::Deser(s) => RpcError::Deser(tag(s)),
+            RpcError::Ser(s) => RpcError::Ser(tag(s)),
+            RpcError::Rpc(s) => RpcError::Rpc(tag(s)),
+            RpcError::Nats(s) => RpcError::Nats(tag(s)),
+            RpcError::InvalidParameter(s) => RpcError::InvalidParameter(tag(s)),
+            RpcError::ActorHandler(s) => RpcError::ActorHandler(tag(s)),
+            RpcError::ProviderInit(s) => RpcError::ProviderInit(tag(s)),
+            RpcError::Timeout(s) => RpcError::Timeout(tag(s)),
+            RpcError::Other(s) => RpcError::Other(tag(s)),
+            e @ (RpcError::NotImplemented | RpcError::Provider(_)) => e,
+        }
+    }
+
+    /// Serializes the error as a provider does when a request fails:
+    /// an `InvocationResponse` with the error text, and the domain error, if any, in `msg`.
+    pub fn to_response_bytes(&self) -> RpcResult<Vec<u8>> {
+        crate::common::serialize(&crate::core::InvocationResponse {
+            error: Some(self.to_string()),
+            msg: error_response_msg(self),
+            ..Default::default()
+        })
+    }
+
+    /// Reconstructs an error from a serialized error response,
+    /// such as one made by [to_response_bytes](RpcError::to_response_bytes).
+    /// The variant is recovered from the error text, so text that doesn't start with
+    /// the message of a known variant becomes `RpcError::Other`.
+    /// Returns an error if the bytes are not an `InvocationResponse` containing an error.
+    pub fn from_response_bytes(bytes: &[u8]) -> RpcResult<RpcError> {
+        let resp: crate::core::InvocationResponse = crate::common::deserialize(bytes)?;
+        let error = resp
+            .error
+            .ok_or_else(|| RpcError::Deser("response does not contain an error".to_string()))?;
+        Ok(match error_from_response(error, &resp.msg) {
+            RpcError::Rpc(text) => parse_error_text(text),
+            e => e,
+        })
+    }
+}
+
+/// Constructor of an RpcError variant that holds a String
+type Variant = fn(String) -> RpcError;
+
+/// Display prefix of `RpcError::DeadlineExceeded`
+const DEADLINE_EXCEEDED_PREFIX: &str = "the request exceeded its deadline: ";
+
+/// Display prefix of each variant that holds a String
+const ERROR_PREFIXES: &[(&str, Variant)] = &[
+    (DEADLINE_EXCEEDED_PREFIX, RpcError::DeadlineExceeded),
+    (
+        "the capability provider has not been initialized: ",
+        RpcError::NotInitialized,
+    ),
+    ("method not handled ", RpcError::MethodNotHandled),
+    ("Host send error ", RpcError::HostError),
+    ("deserialization: ", RpcError::Deser),
+    ("serialization: ", RpcError::Ser),
+    ("rpc: ", RpcError::Rpc),
+    ("nats: ", RpcError::Nats),
+    ("invalid parameter: ", RpcError::InvalidParameter),
+    ("actor: ", RpcError::ActorHandler),
+    (
+        "provider initialization or put-link: ",
+        RpcError::ProviderInit,
+    ),
+    ("timeout: ", RpcError::Timeout),
+];
+
+/// Inverse of the Display implementation of RpcError
+fn parse_error_text(text: String) -> RpcError {
+    if text == RpcError::NotImplemented.to_string() {
+        return RpcError::NotImplemented;
+    }
+    for (prefix, variant) in ERROR_PREFIXES {
+        if let Some(rest) = text.strip_prefix(prefix) {
+            return variant(rest.to_string());
+        }
+    }
+    RpcError::Other(text)
+}
+
+/// A domain error from a provider's handler, such as a failed business rule,
+/// as opposed to a failure to deliver or process the request.
+///
+/// A handler returns one with `Err(ProviderError::new(..).into())`.
+/// The response envelope carries it apart from the error text,
+/// so the caller receives it as [RpcError::Provider] with the same code, message, and details.
+/// Callers built before this was added see it as `RpcError::Rpc`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ProviderError {
+    /// Machine-readable error code, defined by the interface
+    pub code: String,
+    /// Human-readable description
+    pub message: String,
+    /// Optional serialized details; see [with_details](ProviderError::with_details)
+    #[serde(with = "serde_bytes", default, skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<u8>,
+}
+
+impl ProviderError {
+    pub fn new(code: impl Into<String>, message: impl Into<String>) -> Self {
+        ProviderError {
+            code: code.into(),
+            message: message.into(),
+            details: Vec::new(),
+        }
+    }
+
+    /// Attaches a typed value, serialized with the crate's standard serialization
+    pub fn with_details<T: serde::Serialize>(mut self, details: &T) -> RpcResult<Self> {
+        self.details = crate::common::serialize(details)?;
+        Ok(self)
+    }
+
+    /// Deserializes the details attached with [with_details](ProviderError::with_details)
+    pub fn details<T: serde::de::DeserializeOwned>(&self) -> RpcResult<T> {
+        crate::common::deserialize(&self.details)
+    }
+}
+
+impl std::fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+impl From<ProviderError> for RpcError {
+    fn from(e: ProviderError) -> RpcError {
+        RpcError::Provider(e)
+    }
+}
+
+/// The `msg` of an error response: the serialized domain error, if any.
+/// Responses with an error have no other use for `msg`.
+pub(crate) fn error_response_msg(e: &RpcError) -> Vec<u8> {
+    match e {
+        RpcError::Provider(pe) => crate::common::serialize(pe).unwrap_or_default(),
+        _ => Vec::new(),
+    }
+}
+
+/// Reconstructs the error from the `error` and `msg` fields of a response.
+/// A deadline the receiver enforced is `DeadlineExceeded`, so the caller can tell it from
+/// its own timeout; other errors are `Rpc`.
+pub(crate) fn error_from_response(error: String, msg: &[u8]) -> RpcError {
+    if !msg.is_empty() {
+        if let Ok(pe) = crate::common::deserialize::<ProviderError>(msg) {
+            return RpcError::Provider(pe);
+        }
+    }
+    match error.strip_prefix(DEADLINE_EXCEEDED_PREFIX) {
+        Some(rest) => RpcError::DeadlineExceeded(rest.to_string()),
+        None => RpcError::Rpc(error),
+    }
+}
+
+/// Converts the boxed error of a [HandlerResult](crate::HandlerResult) to an RpcError.
+/// If the boxed error is an RpcError, it is returned unchanged;
+/// a [ProviderError] becomes `RpcError::Provider`;
+/// any other error is wrapped in `RpcError::ActorHandler`.
+pub fn handler_err_to_rpc(e: Box<dyn std::error::Error + Send + Sync>) -> RpcError {
+    match e.downcast::<RpcError>() {
+        Ok(rpc_err) => *rpc_err,
+        Err(e) => match e.downcast::<ProviderError>() {
+            Ok(pe) => RpcError::Provider(*pe),
+            Err(e) => RpcError::ActorHandler(e.to_string()),
+        },
+    }
+}
+
+impl From<Box<dyn std::error::Error + Send + Sync>> for RpcError {
+    fn from(e: Box<dyn std::error::Error + Send + Sync>) -> RpcError {
+        handler_err_to_rpc(e)
+    }
+}
+
 impl From<String> for RpcError {
     fn from(s: String) -> RpcError {
         RpcError::Other(s)
@@ -85,3 +328,183 @@ impl From<minicbor::decode::Error> for RpcError {
         RpcError::Other(format!("decode: {e}"))
     }
 }
+
+/// Converts an [RpcResult] into an `anyhow::Result`, with the `anyhow` feature.
+/// (`?` already converts an [RpcError] to an `anyhow::Error`, but without naming the variant)
+#[cfg(feature = "anyhow")]
+pub trait RpcResultExt<T> {
+    /// Converts the error into an `anyhow::Error` with its [code](RpcError::code) as context.
+    /// The original error is the source, and can be recovered with `downcast_ref::<RpcError>()`.
+    fn into_anyhow(self) -> anyhow::Result<T>;
+}
+
+#[cfg(feature = "anyhow")]
+impl<T> RpcResultExt<T> for RpcResult<T> {
+    fn into_anyhow(self) -> anyhow::Result<T> {
+        self.map_err(|e| {
+            let code = e.code();
+            anyhow::Error::new(e).context(format!("rpc error: {code}"))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{handler_err_to_rpc, ProviderError, RpcError};
+    use crate::HandlerResult;
+
+    fn fails_with_rpc() -> HandlerResult<()> {
+        Err(Box::new(RpcError::Timeout("slow".to_string())))
+    }
+
+    fn fails_with_io() -> HandlerResult<()> {
+        Err(Box::new(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "disk full",
+        )))
+    }
+
+    #[test]
+    fn handler_errors() {
+        let e = handler_err_to_rpc(fails_with_rpc().unwrap_err());
+        assert!(matches!(e, RpcError::Timeout(s) if s == "slow"));
+
+        let e = handler_err_to_rpc(fails_with_io().unwrap_err());
+        assert!(matches!(e, RpcError::ActorHandler(s) if s == "disk full"));
+
+        // same conversion with `?`
+        fn handler() -> Result<(), RpcError> {
+            fails_with_rpc()?;
+            Ok(())
+        }
+        assert!(matches!(handler(), Err(RpcError::Timeout(_))));
+    }
+
+    #[cfg(feature = "anyhow")]
+    #[test]
+    fn anyhow_conversion() {
+        use super::{RpcResult, RpcResultExt};
+
+        let result: RpcResult<()> = Err(RpcError::Timeout("slow".to_string()));
+        let err = result.into_anyhow().unwrap_err();
+        let chain: Vec<String> = err.chain().map(|e| e.to_string()).collect();
+        assert_eq!(chain, vec!["rpc error: timeout", "timeout: slow"]);
+        let original = err.downcast_ref::<RpcError>().unwrap();
+        assert!(matches!(original, RpcError::Timeout(s) if s == "slow"));
+
+        // plain `?` keeps the error, without the context
+        fn send() -> anyhow::Result<()> {
+            Err(RpcError::Nats("closed".to_string()))?;
+            Ok(())
+        }
+        let err = send().unwrap_err();
+        assert_eq!(err.downcast_ref::<RpcError>().unwrap().code(), "nats");
+    }
+
+    #[test]
+    fn error_context() {
+        let e = RpcError::Timeout("slow".to_string()).with_context("Bank.Withdraw");
+        assert!(matches!(&e, RpcError::Timeout(s) if s == "Bank.Withdraw: slow"));
+        assert_eq!(e.to_string(), "timeout: Bank.Withdraw: slow");
+
+        // applied at several levels, the operation is named once
+        let e = e.with_context("Bank.Withdraw");
+        assert_eq!(e.to_string(), "timeout: Bank.Withdraw: slow");
+
+        let e = RpcError::Other("disk full".to_string()).with_context("Store.Put");
+        assert_eq!(e.to_string(), "Store.Put: disk full");
+
+        // an unhandled method isn't named twice
+        let e = RpcError::MethodNotHandled("Store.Put".to_string()).with_context("Store.Put");
+        assert_eq!(e.to_string(), "method not handled Store.Put");
+        let e = RpcError::MethodNotHandled("Store.Get".to_string()).with_context("Store.Put");
+        assert_eq!(e.to_string(), "method not handled Store.Put: Store.Get");
+
+        let pe = ProviderError::new("insufficient_funds", "balance too low");
+        let e = RpcError::from(pe.clone()).with_context("Bank.Withdraw");
+        assert_eq!(e.provider_error(), Some(&pe));
+    }
+
+    #[test]
+    fn provider_error_in_response() {
+        use super::{error_from_response, error_response_msg};
+        use crate::core::InvocationResponse;
+
+        #[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug)]
+        struct Shortfall {
+            needed: u64,
+        }
+
+        let pe = ProviderError::new("insufficient_funds", "balance too low")
+            .with_details(&Shortfall { needed: 42 })
+            .unwrap();
+        let sent: RpcError = pe.clone().into();
+        let resp = InvocationResponse {
+            error: Some(sent.to_string()),
+            msg: error_response_msg(&sent),
+            ..Default::default()
+        };
+        let resp: InvocationResponse =
+            crate::common::deserialize(&crate::common::serialize(&resp).unwrap()).unwrap();
+
+        let received = error_from_response(resp.error.unwrap(), &resp.msg);
+        assert_eq!(received.provider_error(), Some(&pe));
+        assert_eq!(
+            received.provider_error().unwrap().details::<Shortfall>().unwrap(),
+            Shortfall { needed: 42 }
+        );
+
+        // transport errors are unchanged
+        let sent = RpcError::Timeout("slow".to_string());
+        let received = error_from_response(sent.to_string(), &error_response_msg(&sent));
+        assert!(matches!(&received, RpcError::Rpc(s) if s == "timeout: slow"));
+        assert!(received.provider_error().is_none());
+
+        // except a deadline the provider enforced
+        let sent = RpcError::DeadlineExceeded("Bank.Audit: server-side timeout".to_string());
+        let received = error_from_response(sent.to_string(), &error_response_msg(&sent));
+        assert!(
+            matches!(&received, RpcError::DeadlineExceeded(s) if s == "Bank.Audit: server-side timeout"),
+            "{received}"
+        );
+
+        // a handler can return it as a boxed error
+        let boxed: Box<dyn std::error::Error + Send + Sync> = Box::new(pe.clone());
+        assert_eq!(handler_err_to_rpc(boxed).provider_error(), Some(&pe));
+    }
+
+    #[test]
+    fn response_bytes_round_trip() {
+        let text = || "some detail".to_string();
+        let errors = vec![
+            RpcError::DeadlineExceeded(text()),
+            RpcError::NotInitialized(text()),
+            RpcError::MethodNotHandled(text()),
+            RpcError::NotImplemented,
+            RpcError::HostError(text()),
+            RpcError::Deser(text()),
+            RpcError::Ser(text()),
+            RpcError::Rpc(text()),
+            RpcError::Nats(text()),
+            RpcError::InvalidParameter(text()),
+            RpcError::ActorHandler(text()),
+            RpcError::ProviderInit(text()),
+            RpcError::Timeout(text()),
+            RpcError::Provider(ProviderError::new("code", text())),
+            RpcError::Other(text()),
+        ];
+        for sent in errors {
+            let bytes = sent.to_response_bytes().unwrap();
+            let received = RpcError::from_response_bytes(&bytes).unwrap();
+            assert_eq!(format!("{received:?}"), format!("{sent:?}"));
+        }
+
+        // a response without an error
+        let ok = crate::common::serialize(&crate::core::InvocationResponse::default()).unwrap();
+        assert!(matches!(
+            RpcError::from_response_bytes(&ok),
+            Err(RpcError::Deser(_))
+        ));
+        assert!(RpcError::from_response_bytes(b"not msgpack").is_err());
+    }
+}
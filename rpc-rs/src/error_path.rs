@@ -0,0 +1,488 @@
+//! A serde Serializer that writes nothing, and walks a value keeping the path to the
+//! current element. Used by [serialize](crate::common::serialize) to name the failing
+//! element in its error, and by strict mode in [serialize_with](crate::common::serialize_with)
+//! to find NaN and infinite floats.
+
+use std::fmt;
+
+use serde::{ser, Serialize};
+
+use crate::error::{RpcError, RpcResult};
+
+/// Returns the path to the element of the value that fails to serialize, such as
+/// `items[3].name`, or None if the value serializes without error, or fails at the top level
+pub(crate) fn locate<T: Serialize + ?Sized>(data: &T) -> Option<String> {
+    data.serialize(Walk { check_floats: false }).err()?.path()
+}
+
+/// Returns an error if any float in the value is NaN or infinite
+pub(crate) fn check_finite<T: Serialize + ?Sized>(data: &T) -> RpcResult<()> {
+    data.serialize(Walk { check_floats: true }).map_err(|e| {
+        let path = e.path();
+        match (e.msg, path) {
+            (None, Some(path)) => RpcError::Ser(format!("non-finite float in field '{path}'")),
+            (None, None) => RpcError::Ser("non-finite float in field".to_string()),
+            (Some(msg), Some(path)) => RpcError::Ser(format!("{msg} (at {path})")),
+            (Some(msg), None) => RpcError::Ser(msg),
+        }
+    })
+}
+
+#[derive(Debug)]
+struct PathError {
+    /// the value's own error, or None for a non-finite float
+    msg: Option<String>,
+    path: Vec<String>,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.msg.as_deref().unwrap_or("non-finite float"))
+    }
+}
+
+impl std::error::Error for PathError {}
+
+impl ser::Error for PathError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        PathError {
+            msg: Some(msg.to_string()),
+            path: Vec::new(),
+        }
+    }
+}
+
+impl PathError {
+    fn within(mut self, segment: String) -> Self {
+        self.path.push(segment);
+        self
+    }
+
+    /// Returns the path as text, or None at the top level
+    fn path(&self) -> Option<String> {
+        if self.path.is_empty() {
+            return None;
+        }
+        // segments were added as the error returned, innermost first
+        let path: String = self.path.iter().rev().map(String::as_str).collect();
+        Some(path.strip_prefix('.').map(str::to_string).unwrap_or(path))
+    }
+}
+
+/// Walks the value; with `check_floats`, a NaN or infinite float is an error
+#[derive(Clone, Copy)]
+struct Walk {
+    check_floats: bool,
+}
+
+impl Walk {
+    fn float(self, finite: bool) -> Result<(), PathError> {
+        if finite || !self.check_floats {
+            Ok(())
+        } else {
+            Err(PathError { msg: None, path: Vec::new() })
+        }
+    }
+
+    fn compound(self) -> Compound {
+        Compound { walk: self, index: 0, key: None }
+    }
+}
+
+/// Walks the elements of a sequence, map, or struct, counting them
+struct Compound {
+    walk: Walk,
+    index: usize,
+    key: Option<String>,
+}
+
+impl Compound {
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PathError> {
+        let rc = value
+            .serialize(self.walk)
+            .map_err(|e| e.within(format!("[{}]", self.index)));
+        self.index += 1;
+        rc
+    }
+
+    fn field<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<(), PathError> {
+        value.serialize(self.walk).map_err(|e| e.within(format!(".{key}")))
+    }
+}
+
+/// Returns the key of a map entry as text, if it's a string, char, or integer
+fn key_name<T: Serialize + ?Sized>(key: &T) -> Option<String> {
+    key.serialize(KeyName).ok()
+}
+
+struct KeyName;
+
+impl ser::Serializer for KeyName {
+    type Ok = String;
+    type Error = fmt::Error;
+    type SerializeSeq = ser::Impossible<String, fmt::Error>;
+    type SerializeTuple = ser::Impossible<String, fmt::Error>;
+    type SerializeTupleStruct = ser::Impossible<String, fmt::Error>;
+    type SerializeTupleVariant = ser::Impossible<String, fmt::Error>;
+    type SerializeMap = ser::Impossible<String, fmt::Error>;
+    type SerializeStruct = ser::Impossible<String, fmt::Error>;
+    type SerializeStructVariant = ser::Impossible<String, fmt::Error>;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn serialize_bool(self, _: bool) -> Result<String, fmt::Error> {
+        Err(fmt::Error)
+    }
+    fn serialize_i8(self, v: i8) -> Result<String, fmt::Error> {
+        Ok(v.to_string())
+    }
+    fn serialize_i16(self, v: i16) -> Result<String, fmt::Error> {
+        Ok(v.to_string())
+    }
+    fn serialize_i32(self, v: i32) -> Result<String, fmt::Error> {
+        Ok(v.to_string())
+    }
+    fn serialize_i64(self, v: i64) -> Result<String, fmt::Error> {
+        Ok(v.to_string())
+    }
+    fn serialize_u8(self, v: u8) -> Result<String, fmt::Error> {
+        Ok(v.to_string())
+    }
+    fn serialize_u16(self, v: u16) -> Result<String, fmt::Error> {
+        Ok(v.to_string())
+    }
+    fn serialize_u32(self, v: u32) -> Result<String, fmt::Error> {
+        Ok(v.to_string())
+    }
+    fn serialize_u64(self, v: u64) -> Result<String, fmt::Error> {
+        Ok(v.to_string())
+    }
+    fn serialize_f32(self, _: f32) -> Result<String, fmt::Error> {
+        Err(fmt::Error)
+    }
+    fn serialize_f64(self, _: f64) -> Result<String, fmt::Error> {
+        Err(fmt::Error)
+    }
+    fn serialize_char(self, v: char) -> Result<String, fmt::Error> {
+        Ok(format!("{v:?}"))
+    }
+    fn serialize_str(self, v: &str) -> Result<String, fmt::Error> {
+        Ok(format!("{v:?}"))
+    }
+    fn serialize_bytes(self, _: &[u8]) -> Result<String, fmt::Error> {
+        Err(fmt::Error)
+    }
+    fn serialize_none(self) -> Result<String, fmt::Error> {
+        Err(fmt::Error)
+    }
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<String, fmt::Error> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<String, fmt::Error> {
+        Err(fmt::Error)
+    }
+    fn serialize_unit_struct(self, _: &'static str) -> Result<String, fmt::Error> {
+        Err(fmt::Error)
+    }
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+    ) -> Result<String, fmt::Error> {
+        Ok(variant.to_string())
+    }
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<String, fmt::Error> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<String, fmt::Error> {
+        Err(fmt::Error)
+    }
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, fmt::Error> {
+        Err(fmt::Error)
+    }
+    fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, fmt::Error> {
+        Err(fmt::Error)
+    }
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleStruct, fmt::Error> {
+        Err(fmt::Error)
+    }
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleVariant, fmt::Error> {
+        Err(fmt::Error)
+    }
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, fmt::Error> {
+        Err(fmt::Error)
+    }
+    fn serialize_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStruct, fmt::Error> {
+        Err(fmt::Error)
+    }
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStructVariant, fmt::Error> {
+        Err(fmt::Error)
+    }
+}
+
+impl ser::Serializer for Walk {
+    type Ok = ();
+    type Error = PathError;
+    type SerializeSeq = Compound;
+    type SerializeTuple = Compound;
+    type SerializeTupleStruct = Compound;
+    type SerializeTupleVariant = Compound;
+    type SerializeMap = Compound;
+    type SerializeStruct = Compound;
+    type SerializeStructVariant = Compound;
+
+    // match the msgpack serializer, so types serialize the same way
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn serialize_bool(self, _: bool) -> Result<(), PathError> {
+        Ok(())
+    }
+    fn serialize_i8(self, _: i8) -> Result<(), PathError> {
+        Ok(())
+    }
+    fn serialize_i16(self, _: i16) -> Result<(), PathError> {
+        Ok(())
+    }
+    fn serialize_i32(self, _: i32) -> Result<(), PathError> {
+        Ok(())
+    }
+    fn serialize_i64(self, _: i64) -> Result<(), PathError> {
+        Ok(())
+    }
+    fn serialize_i128(self, _: i128) -> Result<(), PathError> {
+        Ok(())
+    }
+    fn serialize_u8(self, _: u8) -> Result<(), PathError> {
+        Ok(())
+    }
+    fn serialize_u16(self, _: u16) -> Result<(), PathError> {
+        Ok(())
+    }
+    fn serialize_u32(self, _: u32) -> Result<(), PathError> {
+        Ok(())
+    }
+    fn serialize_u64(self, _: u64) -> Result<(), PathError> {
+        Ok(())
+    }
+    fn serialize_u128(self, _: u128) -> Result<(), PathError> {
+        Ok(())
+    }
+    fn serialize_f32(self, v: f32) -> Result<(), PathError> {
+        self.float(v.is_finite())
+    }
+    fn serialize_f64(self, v: f64) -> Result<(), PathError> {
+        self.float(v.is_finite())
+    }
+    fn serialize_char(self, _: char) -> Result<(), PathError> {
+        Ok(())
+    }
+    fn serialize_str(self, _: &str) -> Result<(), PathError> {
+        Ok(())
+    }
+    fn serialize_bytes(self, _: &[u8]) -> Result<(), PathError> {
+        Ok(())
+    }
+    fn serialize_none(self) -> Result<(), PathError> {
+        Ok(())
+    }
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), PathError> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<(), PathError> {
+        Ok(())
+    }
+    fn serialize_unit_struct(self, _: &'static str) -> Result<(), PathError> {
+        Ok(())
+    }
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+    ) -> Result<(), PathError> {
+        Ok(())
+    }
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<(), PathError> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), PathError> {
+        self.compound().field(variant, value)
+    }
+    fn serialize_seq(self, _: Option<usize>) -> Result<Compound, PathError> {
+        Ok(self.compound())
+    }
+    fn serialize_tuple(self, _: usize) -> Result<Compound, PathError> {
+        Ok(self.compound())
+    }
+    fn serialize_tuple_struct(self, _: &'static str, _: usize) -> Result<Compound, PathError> {
+        Ok(self.compound())
+    }
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Compound, PathError> {
+        Ok(self.compound())
+    }
+    fn serialize_map(self, _: Option<usize>) -> Result<Compound, PathError> {
+        Ok(self.compound())
+    }
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Compound, PathError> {
+        Ok(self.compound())
+    }
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Compound, PathError> {
+        Ok(self.compound())
+    }
+}
+
+impl ser::SerializeSeq for Compound {
+    type Ok = ();
+    type Error = PathError;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PathError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), PathError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for Compound {
+    type Ok = ();
+    type Error = PathError;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PathError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), PathError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for Compound {
+    type Ok = ();
+    type Error = PathError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PathError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), PathError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleVariant for Compound {
+    type Ok = ();
+    type Error = PathError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PathError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), PathError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for Compound {
+    type Ok = ();
+    type Error = PathError;
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), PathError> {
+        let name = key_name(key);
+        key.serialize(self.walk)
+            .map_err(|e| e.within(format!("[key {}]", self.index)))?;
+        self.key = name;
+        Ok(())
+    }
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PathError> {
+        match self.key.take() {
+            Some(key) => {
+                let rc = value.serialize(self.walk).map_err(|e| e.within(format!("[{key}]")));
+                self.index += 1;
+                rc
+            }
+            None => self.element(value),
+        }
+    }
+    fn end(self) -> Result<(), PathError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for Compound {
+    type Ok = ();
+    type Error = PathError;
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), PathError> {
+        self.field(key, value)
+    }
+    fn end(self) -> Result<(), PathError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStructVariant for Compound {
+    type Ok = ();
+    type Error = PathError;
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), PathError> {
+        self.field(key, value)
+    }
+    fn end(self) -> Result<(), PathError> {
+        Ok(())
+    }
+}
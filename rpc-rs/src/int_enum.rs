@@ -0,0 +1,338 @@
+//! Enums that are sent as integers.
+//!
+//! A derived `Serialize` writes a unit enum as its variant name, so renaming a variant
+//! changes the wire format. An enum declared with [int_enum](crate::int_enum) is sent
+//! as its integer value instead, in both msgpack and cbor, and an integer without
+//! a variant fails to decode with `RpcError::Deser`.
+//!
+//! Consumers that want variant names can ask for them per call: with
+//! [Encoder::with_enum_repr](crate::cbor::Encoder::with_enum_repr) for cbor, or the
+//! [enum_repr](crate::common::SerializeOptions::enum_repr) option of
+//! [serialize_with](crate::common::serialize_with) for serde formats.
+//! Decoding accepts either form, whatever the setting.
+
+use std::cell::Cell;
+
+/// How enums declared with [int_enum](crate::int_enum) are encoded
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EnumRepr {
+    /// The variant's integer value. The default
+    #[default]
+    Int,
+    /// The variant's name, as a string
+    Name,
+}
+
+thread_local! {
+    /// The repr for serde, set only while [serialize_with](crate::common::serialize_with) runs
+    static SERDE_REPR: Cell<EnumRepr> = const { Cell::new(EnumRepr::Int) };
+}
+
+/// Runs `f` with the repr used by the serde implementations on this thread,
+/// restoring the previous repr afterwards, even if `f` panics
+pub(crate) fn with_serde_repr<R>(repr: EnumRepr, f: impl FnOnce() -> R) -> R {
+    struct Restore(EnumRepr);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            SERDE_REPR.with(|r| r.set(self.0));
+        }
+    }
+
+    let _restore = Restore(SERDE_REPR.with(|r| r.replace(repr)));
+    f()
+}
+
+/// The repr for a serde `Serialize` of an enum declared with [int_enum](crate::int_enum)
+#[doc(hidden)]
+pub fn serde_repr() -> EnumRepr {
+    SERDE_REPR.with(|r| r.get())
+}
+
+/// An encoded enum value, in either representation
+#[doc(hidden)]
+pub enum IntOrName {
+    Int(i64),
+    Name(String),
+}
+
+#[doc(hidden)]
+pub fn deserialize_int_or_name<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<IntOrName, D::Error> {
+    struct Visitor;
+
+    impl<'de> serde::de::Visitor<'de> for Visitor {
+        type Value = IntOrName;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("an enum integer or variant name")
+        }
+
+        fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<IntOrName, E> {
+            Ok(IntOrName::Int(v))
+        }
+
+        fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<IntOrName, E> {
+            i64::try_from(v)
+                .map(IntOrName::Int)
+                .map_err(|_| E::custom(format!("enum value {v} out of range")))
+        }
+
+        fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<IntOrName, E> {
+            Ok(IntOrName::Name(v.to_string()))
+        }
+
+        fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<IntOrName, E> {
+            std::str::from_utf8(v)
+                .map(|v| IntOrName::Name(v.to_string()))
+                .map_err(|_| E::custom("enum name is not utf-8"))
+        }
+    }
+
+    deserializer.deserialize_any(Visitor)
+}
+
+/// Declares an enum whose variants have explicit `i32` values, with `TryFrom<i32>`,
+/// `as_i32`, `as_name` and `from_name`, serde implementations that use the integer
+/// (or the name, see [EnumRepr](crate::int_enum::EnumRepr)),
+/// and cbor `encode` and `decode`. The crate using it must depend on serde.
+///
+/// ```
+/// wasmbus_rpc::int_enum! {
+///     /// Size of a shirt
+///     pub enum Size {
+///         Small = 1,
+///         Medium = 2,
+///         Large = 3,
+///     }
+/// }
+/// assert_eq!(Size::Medium.as_i32(), 2);
+/// assert!(Size::try_from(4).is_err());
+/// ```
+#[macro_export]
+macro_rules! int_enum {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident {
+            $( $(#[$vmeta:meta])* $variant:ident = $value:literal ),+ $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        $vis enum $name {
+            $( $(#[$vmeta])* $variant = $value ),+
+        }
+
+        impl $name {
+            /// The value sent on the wire
+            pub fn as_i32(self) -> i32 {
+                self as i32
+            }
+
+            /// The variant's name, sent on the wire with `EnumRepr::Name`
+            pub fn as_name(self) -> &'static str {
+                match self {
+                    $( $name::$variant => stringify!($variant), )+
+                }
+            }
+
+            /// Returns the variant with the name, or `RpcError::Deser` if there is none
+            pub fn from_name(name: &str) -> $crate::error::RpcResult<Self> {
+                match name {
+                    $( stringify!($variant) => Ok($name::$variant), )+
+                    _ => Err($crate::error::RpcError::Deser(format!(
+                        "unknown {} name {}",
+                        stringify!($name),
+                        name
+                    ))),
+                }
+            }
+
+            /// Writes the value as a cbor integer, or string with `EnumRepr::Name`
+            pub fn encode<W: $crate::cbor::Write>(
+                &self,
+                e: &mut $crate::cbor::Encoder<W>,
+            ) -> $crate::error::RpcResult<()>
+            where
+                <W as $crate::cbor::Write>::Error: std::fmt::Display,
+            {
+                match e.enum_repr() {
+                    $crate::int_enum::EnumRepr::Int => e.i32(self.as_i32())?,
+                    $crate::int_enum::EnumRepr::Name => e.str(self.as_name())?,
+                };
+                Ok(())
+            }
+
+            /// Reads a cbor integer or name, returning `RpcError::Deser` if it has no variant
+            pub fn decode(d: &mut $crate::cbor::Decoder<'_>) -> $crate::error::RpcResult<Self> {
+                match d.datatype()? {
+                    $crate::cbor::Type::String => Self::from_name(d.str()?),
+                    _ => Self::try_from(d.i32()?),
+                }
+            }
+        }
+
+        impl std::convert::TryFrom<i32> for $name {
+            type Error = $crate::error::RpcError;
+
+            fn try_from(value: i32) -> Result<Self, Self::Error> {
+                match value {
+                    $( $value => Ok($name::$variant), )+
+                    _ => Err($crate::error::RpcError::Deser(format!(
+                        "unknown {} value {}",
+                        stringify!($name),
+                        value
+                    ))),
+                }
+            }
+        }
+
+        impl From<$name> for i32 {
+            fn from(value: $name) -> i32 {
+                value.as_i32()
+            }
+        }
+
+        impl ::serde::Serialize for $name {
+            fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                match $crate::int_enum::serde_repr() {
+                    $crate::int_enum::EnumRepr::Int => serializer.serialize_i32(self.as_i32()),
+                    $crate::int_enum::EnumRepr::Name => serializer.serialize_str(self.as_name()),
+                }
+            }
+        }
+
+        impl<'de> ::serde::Deserialize<'de> for $name {
+            fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                match $crate::int_enum::deserialize_int_or_name(deserializer)? {
+                    $crate::int_enum::IntOrName::Int(value) => i32::try_from(value)
+                        .map_err(|_| $crate::error::RpcError::Deser(format!(
+                            "unknown {} value {}",
+                            stringify!($name),
+                            value
+                        )))
+                        .and_then(Self::try_from),
+                    $crate::int_enum::IntOrName::Name(name) => Self::from_name(&name),
+                }
+                .map_err(::serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+
+    use super::EnumRepr;
+    use crate::{
+        cbor::{Decoder, Encoder},
+        common::{deserialize, serialize, serialize_with, SerializeOptions},
+        error::RpcError,
+    };
+
+    crate::int_enum! {
+        enum Status {
+            Active = 1,
+            Suspended = 2,
+            Closed = 10,
+        }
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+    struct Account {
+        status: Status,
+    }
+
+    #[test]
+    fn integer_wire_values() {
+        assert_eq!(Status::try_from(10).unwrap(), Status::Closed);
+        assert_eq!(Status::Suspended.as_i32(), 2);
+        assert_eq!(i32::from(Status::Active), 1);
+
+        // msgpack: the field holds the integer, not the variant name
+        let account = Account { status: Status::Closed };
+        let buf = serialize(&account).unwrap();
+        assert_eq!(
+            buf,
+            serialize(&serde_json::json!({ "status": 10 })).unwrap()
+        );
+        assert_eq!(deserialize::<Account>(&buf).unwrap(), account);
+
+        // cbor
+        let mut buf = Vec::new();
+        Status::Suspended.encode(&mut Encoder::new(&mut buf)).unwrap();
+        assert_eq!(buf, vec![0x02]);
+        assert_eq!(
+            Status::decode(&mut Decoder::new(&buf)).unwrap(),
+            Status::Suspended
+        );
+    }
+
+    #[test]
+    fn unknown_integer() {
+        let err = Status::try_from(3).unwrap_err();
+        assert!(
+            matches!(&err, RpcError::Deser(s) if s == "unknown Status value 3"),
+            "{err}"
+        );
+
+        let buf = serialize(&serde_json::json!({ "status": 3 })).unwrap();
+        let err = deserialize::<Account>(&buf).unwrap_err();
+        assert!(
+            matches!(&err, RpcError::Deser(s) if s.contains("unknown Status value 3")),
+            "{err}"
+        );
+
+        let buf = vec![0x03];
+        let err = Status::decode(&mut Decoder::new(&buf)).unwrap_err();
+        assert!(matches!(err, RpcError::Deser(_)), "{err}");
+    }
+
+    #[test]
+    fn name_repr_and_cross_decode() {
+        let account = Account { status: Status::Suspended };
+        let as_int = serialize(&account).unwrap();
+        let mut cbor_int = Vec::new();
+        Status::Suspended.encode(&mut Encoder::new(&mut cbor_int)).unwrap();
+
+        let names = SerializeOptions::default().enum_repr(EnumRepr::Name);
+        let as_name = serialize_with(&account, &names).unwrap();
+        let mut cbor_name = Vec::new();
+        let mut e = Encoder::new(&mut cbor_name).with_enum_repr(EnumRepr::Name);
+        Status::Suspended.encode(&mut e).unwrap();
+
+        assert_eq!(
+            as_name,
+            serialize(&serde_json::json!({ "status": "Suspended" })).unwrap()
+        );
+        assert_eq!(cbor_name, b"\x69Suspended");
+        // the option lasts only for its call
+        assert_eq!(serialize(&account).unwrap(), as_int);
+        assert_eq!(
+            serialize_with(&account, &SerializeOptions::default()).unwrap(),
+            as_int
+        );
+
+        // either form decodes
+        for buf in [&as_int, &as_name] {
+            assert_eq!(deserialize::<Account>(buf).unwrap(), account);
+        }
+        for buf in [&cbor_int, &cbor_name] {
+            assert_eq!(
+                Status::decode(&mut Decoder::new(buf)).unwrap(),
+                Status::Suspended
+            );
+        }
+
+        let buf = serialize(&serde_json::json!({ "status": "Open" })).unwrap();
+        let err = deserialize::<Account>(&buf).unwrap_err();
+        assert!(
+            err.to_string().contains("unknown Status name Open"),
+            "{err}"
+        );
+        assert_eq!(Status::Closed.as_name(), "Closed");
+        assert_eq!(Status::from_name("Active").unwrap(), Status::Active);
+    }
+}
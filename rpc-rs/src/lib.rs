@@ -4,7 +4,7 @@
 //! used by [wasmcloud](https://wasmcloud.dev) actors and capability providers.
 //!
 
-mod timestamp;
+pub mod timestamp;
 // re-export Timestamp
 pub use timestamp::Timestamp;
 // re-export wascap crate
@@ -23,12 +23,27 @@ pub use async_nats;
 pub mod otel;
 
 mod actor_wasm;
+#[cfg(all(not(target_arch = "wasm32"), feature = "alloc-tracking"))]
+pub mod alloc_tracking;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod batch_publish;
+pub mod buffer_pool;
+mod canonical;
 pub mod cbor;
 pub mod common;
+mod decode_limits;
+pub mod dispatch;
 pub(crate) mod document;
 pub mod error;
+mod error_path;
+pub mod int_enum;
 pub mod provider;
 pub(crate) mod provider_main;
+pub mod provider_transport;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod reply_mux;
+#[cfg(all(not(target_arch = "wasm32"), feature = "testing"))]
+pub mod testing;
 mod wasmbus_model;
 
 pub use minicbor;
@@ -56,7 +71,6 @@ pub(crate) mod chunkify;
 mod wasmbus_core;
 
 #[macro_use]
-
 pub mod model {
     // re-export model lib as "model"
     pub use crate::wasmbus_model::*;
@@ -74,32 +88,188 @@ pub mod core {
             // allow testing provider outside host
             const TEST_HARNESS: &str = "_TEST_";
 
+            /// Environment variable that may hold the base64-encoded host data,
+            /// read by [HostData::from_base64_env]
+            pub const HOST_DATA_ENV: &str = "WASMCLOUD_HOST_DATA";
+
             impl HostData {
+                /// Decodes host data the way the host sends it: base64-encoded json.
+                /// Surrounding whitespace is ignored.
+                /// Returns `RpcError::InvalidParameter` if the text is not base64,
+                /// or `RpcError::Deser` if the decoded bytes are not valid host data.
+                pub fn from_base64(encoded: &str) -> RpcResult<HostData> {
+                    let encoded = encoded.trim();
+                    if encoded.is_empty() {
+                        return Err(RpcError::InvalidParameter("host data is empty".to_string()));
+                    }
+                    let bytes = base64::decode(encoded.as_bytes()).map_err(|e| {
+                        RpcError::InvalidParameter(format!(
+                            "host data has invalid encoding (expected base64): {e}"
+                        ))
+                    })?;
+                    serde_json::from_slice(&bytes).map_err(|e| {
+                        RpcError::Deser(format!(
+                            "parsing host data: {}:\n{}",
+                            e,
+                            String::from_utf8_lossy(&bytes)
+                        ))
+                    })
+                }
+
+                /// Decodes host data from the [HOST_DATA_ENV] environment variable
+                /// (see [from_base64](HostData::from_base64)).
+                /// Returns `RpcError::NotInitialized` if the variable is not set.
+                pub fn from_base64_env() -> RpcResult<HostData> {
+                    let encoded = std::env::var(HOST_DATA_ENV).map_err(|e| {
+                        RpcError::NotInitialized(format!("reading host data from {HOST_DATA_ENV}: {e}"))
+                    })?;
+                    HostData::from_base64(&encoded)
+                }
+
                 /// returns whether the provider is running under test
                 pub fn is_test(&self) -> bool {
                     self.host_id == TEST_HARNESS
                 }
 
-                /// Connect to nats using options provided by host
-                pub async fn nats_connect(&self) -> RpcResult<crate::async_nats::Client> {
-                    use std::str::FromStr as _;
-                    let nats_addr = if !self.lattice_rpc_url.is_empty() {
+                /// Returns the nats url provided by the host, or the default url
+                fn nats_url(&self) -> &str {
+                    if !self.lattice_rpc_url.is_empty() {
                         self.lattice_rpc_url.as_str()
                     } else {
                         crate::provider::DEFAULT_NATS_ADDR
-                    };
-                    let nats_server = crate::async_nats::ServerAddr::from_str(nats_addr).map_err(|e| {
+                    }
+                }
+
+                /// Returns the nats server address provided by the host, or the default address
+                pub(crate) fn nats_server_addr(&self) -> RpcResult<crate::async_nats::ServerAddr> {
+                    use std::str::FromStr as _;
+                    let nats_addr = self.nats_url();
+                    crate::async_nats::ServerAddr::from_str(nats_addr).map_err(|e| {
                         RpcError::InvalidParameter(format!("Invalid nats server url '{}': {}", nats_addr, e))
-                    })?;
+                    })
+                }
 
-                    // Connect to nats
-                    let nc = crate::async_nats::ConnectOptions::default()
-                        .connect(nats_server)
+                /// Returns the default nats connection name, "weld-provider:<provider_key>:<link_name>",
+                /// which identifies the provider's connection in nats monitoring tools
+                pub fn nats_connection_name(&self) -> String {
+                    format!("weld-provider:{}:{}", &self.provider_key, &self.link_name)
+                }
+
+                /// Returns the subject the provider receives rpc invocations on,
+                /// "wasmbus.rpc.<lattice_rpc_prefix>.<provider_key>.<link_name>".
+                /// The contract id is not part of the subject: a provider instance
+                /// is identified by its public key and link name.
+                /// An empty prefix is replaced by [DEFAULT_LATTICE_PREFIX].
+                pub fn rpc_subject(&self) -> String {
+                    format!(
+                        "wasmbus.rpc.{}.{}.{}",
+                        lattice_prefix_or_default(&self.lattice_rpc_prefix),
+                        sanitize_subject_token(&self.provider_key),
+                        sanitize_subject_token(&self.link_name)
+                    )
+                }
+
+                /// Returns the key pair for signing invocations: the invocation seed
+                /// provided by the host, or a new key when running under test
+                pub(crate) fn invocation_key_pair(&self) -> RpcResult<crate::wascap::prelude::KeyPair> {
+                    use crate::wascap::prelude::KeyPair;
+                    if self.is_test() {
+                        Ok(KeyPair::new_user())
+                    } else {
+                        KeyPair::from_seed(&self.invocation_seed)
+                            .map_err(|e| RpcError::NotInitialized(format!("key failure: {e}")))
+                    }
+                }
+
+                /// Returns nats connection options with the connection name,
+                /// the credentials provided by the host, if any,
+                /// jittered reconnect delays up to `max_backoff`, counted against `limit`,
+                /// and a limit of `connect_timeout` on each tcp connection attempt
+                pub(crate) fn nats_connect_options(
+                    &self,
+                    name: &str,
+                    max_backoff: std::time::Duration,
+                    limit: &crate::rpc_client::ReconnectLimit,
+                    connect_timeout: std::time::Duration,
+                ) -> RpcResult<crate::async_nats::ConnectOptions> {
+                    use crate::async_nats::{AuthError, ConnectOptions};
+                    let opts = match (self.lattice_rpc_user_jwt.trim(), self.lattice_rpc_user_seed.trim()) {
+                        ("", "") => ConnectOptions::default(),
+                        (rpc_jwt, rpc_seed) => {
+                            let key_pair = std::sync::Arc::new(nkeys::KeyPair::from_seed(rpc_seed).map_err(|e| {
+                                RpcError::InvalidParameter(format!("invalid nats user seed: {e}"))
+                            })?);
+                            ConnectOptions::with_jwt(rpc_jwt.to_owned(), move |nonce| {
+                                let key_pair = key_pair.clone();
+                                async move { key_pair.sign(&nonce).map_err(AuthError::new) }
+                            })
+                        }
+                    };
+                    let opts = limit.apply(opts.name(name).connection_timeout(connect_timeout), max_backoff);
+                    Ok(crate::rpc_client::with_connection_events(opts, limit))
+                }
+
+                /// Connect to nats using options provided by host,
+                /// with the default connection name from [HostData::nats_connection_name]
+                pub async fn nats_connect(&self) -> RpcResult<crate::async_nats::Client> {
+                    self.nats_connect_with_name(&self.nats_connection_name()).await
+                }
+
+                /// Connect to nats using options provided by host, and the connection name
+                pub async fn nats_connect_with_name(&self, name: &str) -> RpcResult<crate::async_nats::Client> {
+                    self.nats_connect_with_timeout(name, crate::rpc_client::DEFAULT_CONNECT_TIMEOUT)
                         .await
-                        .map_err(|e| {
-                            RpcError::ProviderInit(format!("nats connection to {nats_addr} failed: {e}"))
+                }
+
+                /// Connect to nats using options provided by host, and the connection name.
+                /// Returns `RpcError::Timeout` if the connection isn't established within `timeout`.
+                pub async fn nats_connect_with_timeout(
+                    &self,
+                    name: &str,
+                    timeout: std::time::Duration,
+                ) -> RpcResult<crate::async_nats::Client> {
+                    Ok(self
+                        .nats_connect_measured(
+                            name,
+                            crate::rpc_client::DEFAULT_RECONNECT_MAX_BACKOFF,
+                            &Default::default(),
+                            timeout,
+                        )
+                        .await?
+                        .0)
+                }
+
+                /// Connects to nats, and measures the round trip time to the server
+                pub(crate) async fn nats_connect_measured(
+                    &self,
+                    name: &str,
+                    max_backoff: std::time::Duration,
+                    limit: &crate::rpc_client::ReconnectLimit,
+                    connect_timeout: std::time::Duration,
+                ) -> RpcResult<(crate::async_nats::Client, std::time::Duration)> {
+                    let nats_server = self.nats_server_addr()?;
+                    let connect = self
+                        .nats_connect_options(name, max_backoff, limit, connect_timeout)?
+                        .connect(nats_server);
+                    let timed_out = || RpcError::Timeout("nats connect timed out".to_string());
+                    // the client's own timeout covers only the tcp connect, and this the handshake too
+                    let nc = tokio::time::timeout(connect_timeout, connect)
+                        .await
+                        .map_err(|_| timed_out())?
+                        .map_err(|e| match e.kind() {
+                            crate::async_nats::ConnectErrorKind::TimedOut => timed_out(),
+                            _ => RpcError::ProviderInit(format!(
+                                "nats connection to {} failed: {}",
+                                self.nats_url(),
+                                e
+                            )),
                         })?;
-                    Ok(nc)
+                    // flush waits for the server's reply to a ping
+                    let start = std::time::Instant::now();
+                    nc.flush().await.map_err(|e| RpcError::Nats(format!("nats flush failed: {e}")))?;
+                    let rtt = start.elapsed();
+                    tracing::debug!(?rtt, %name, "connected to nats");
+                    Ok((nc, rtt))
                 }
             }
         }
@@ -108,13 +278,248 @@ pub mod core {
     /// url scheme for wasmbus protocol messages
     pub const URL_SCHEME: &str = "wasmbus";
 
+    /// Makes a string safe to use as a single token of a nats subject.
+    ///
+    /// Characters with special meaning in subjects ('.' separates tokens, '*' and '>'
+    /// are wildcards), whitespace, and control characters are each replaced with '_'.
+    /// Other characters, including non-ascii, are unchanged. An empty string,
+    /// which would produce an empty token, becomes "_".
+    /// The lattice prefix is not a single token, and is not passed through this function.
+    pub fn sanitize_subject_token(token: &str) -> String {
+        if token.is_empty() {
+            return "_".to_string();
+        }
+        token
+            .chars()
+            .map(|c| match c {
+                '.' | '*' | '>' => '_',
+                c if c.is_whitespace() || c.is_control() => '_',
+                c => c,
+            })
+            .collect()
+    }
+
+    /// Lattice prefix used when the host doesn't provide one
+    pub const DEFAULT_LATTICE_PREFIX: &str = "default";
+
+    /// Returns the prefix to use in subjects: `prefix`, or [DEFAULT_LATTICE_PREFIX]
+    /// if it's empty or blank, which would produce a subject nats rejects
+    pub fn lattice_prefix_or_default(prefix: &str) -> &str {
+        if prefix.trim().is_empty() {
+            DEFAULT_LATTICE_PREFIX
+        } else {
+            prefix
+        }
+    }
+
+    /// A lattice prefix, the part of rpc subjects that separates lattices
+    /// sharing a nats server, as in `wasmbus.rpc.<prefix>.<public_key>`.
+    ///
+    /// The prefix is checked when constructed: it is one or more tokens separated
+    /// by '.', where no token is empty or contains a wildcard ('*' or '>'),
+    /// whitespace, or control characters.
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct LatticePrefix(String);
+
+    impl LatticePrefix {
+        /// Returns the prefix, or an error if it isn't valid in a nats subject
+        pub fn new<S: Into<String>>(prefix: S) -> RpcResult<LatticePrefix> {
+            let prefix = prefix.into();
+            let invalid = |reason: &str| {
+                Err(RpcError::InvalidParameter(format!(
+                    "invalid lattice prefix '{prefix}': {reason}"
+                )))
+            };
+            if prefix.is_empty() {
+                return invalid("empty");
+            }
+            for token in prefix.split('.') {
+                if token.is_empty() {
+                    return invalid("empty token");
+                }
+                if token.contains(['*', '>']) {
+                    return invalid("wildcards are not allowed");
+                }
+                if token.chars().any(|c| c.is_whitespace() || c.is_control()) {
+                    return invalid("whitespace and control characters are not allowed");
+                }
+            }
+            Ok(LatticePrefix(prefix))
+        }
+
+        pub fn as_str(&self) -> &str {
+            &self.0
+        }
+    }
+
+    impl std::fmt::Display for LatticePrefix {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str(&self.0)
+        }
+    }
+
+    impl AsRef<str> for LatticePrefix {
+        fn as_ref(&self) -> &str {
+            &self.0
+        }
+    }
+
+    impl std::str::FromStr for LatticePrefix {
+        type Err = RpcError;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            LatticePrefix::new(s)
+        }
+    }
+
+    /// The components of an rpc subject, as returned by [parse_rpc_subject]
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct RpcSubjectParts {
+        /// The lattice prefix, which may contain several tokens
+        pub lattice_prefix: String,
+        /// Public key of the actor or provider the subject addresses
+        pub public_key: String,
+        /// Link name, for a provider subject; None for an actor
+        pub link_name: Option<String>,
+    }
+
+    /// Splits an rpc subject, "wasmbus.rpc.<lattice_prefix>.<public_key>" for an actor,
+    /// or "wasmbus.rpc.<lattice_prefix>.<public_key>.<link_name>" for a provider,
+    /// as built by [HostData::rpc_subject] or `rpc_topic`. The contract id is not
+    /// part of the subject.
+    ///
+    /// Because the lattice prefix may contain '.', a subject is taken to address a
+    /// provider if its second-to-last token has the shape of a provider public key:
+    /// 56 base32 characters starting with 'V'.
+    /// Returns `RpcError::InvalidParameter` if the subject is not an rpc subject,
+    /// or has an empty token, wildcard, or whitespace.
+    pub fn parse_rpc_subject(subject: &str) -> RpcResult<RpcSubjectParts> {
+        let invalid = |reason: &str| {
+            RpcError::InvalidParameter(format!("invalid rpc subject '{subject}': {reason}"))
+        };
+        let rest = subject
+            .strip_prefix("wasmbus.rpc.")
+            .ok_or_else(|| invalid("expected 'wasmbus.rpc.' at the start"))?;
+        let tokens: Vec<&str> = rest.split('.').collect();
+        if tokens.len() < 2 {
+            return Err(invalid("expected a lattice prefix and public key"));
+        }
+        for token in tokens.iter() {
+            if token.is_empty() {
+                return Err(invalid("empty token"));
+            }
+            if token.contains(['*', '>']) {
+                return Err(invalid("wildcards are not allowed"));
+            }
+            if token.chars().any(|c| c.is_whitespace() || c.is_control()) {
+                return Err(invalid("whitespace and control characters are not allowed"));
+            }
+        }
+        let (key, link) = match tokens.len() {
+            n if n >= 3 && is_public_key(tokens[n - 2], 'V') => (n - 2, Some(tokens[n - 1])),
+            n => (n - 1, None),
+        };
+        Ok(RpcSubjectParts {
+            lattice_prefix: tokens[..key].join("."),
+            public_key: tokens[key].to_string(),
+            link_name: link.map(String::from),
+        })
+    }
+
+    /// Returns true if the token has the shape of a public key with the prefix letter
+    fn is_public_key(token: &str, prefix: char) -> bool {
+        token.len() == 56
+            && token.starts_with(prefix)
+            && token.bytes().all(|b| matches!(b, b'A'..=b'Z' | b'2'..=b'7'))
+    }
+
+    impl HostData {
+        /// Returns an `RpcError::ProviderInit` listing the required fields that are empty:
+        /// `host_id`, `lattice_rpc_prefix`, and `provider_key`.
+        /// Providers check this at startup, so a malformed handshake from the host
+        /// fails with a clear error instead of later in the provider.
+        pub fn validate(&self) -> RpcResult<()> {
+            let missing: Vec<&str> = [
+                ("host_id", &self.host_id),
+                ("lattice_rpc_prefix", &self.lattice_rpc_prefix),
+                ("provider_key", &self.provider_key),
+            ]
+            .into_iter()
+            .filter(|(_, value)| value.trim().is_empty())
+            .map(|(name, _)| name)
+            .collect();
+            if missing.is_empty() {
+                Ok(())
+            } else {
+                Err(RpcError::ProviderInit(format!(
+                    "host data is missing required fields: {}",
+                    missing.join(", ")
+                )))
+            }
+        }
+
+        /// Returns the validated lattice prefix provided by the host,
+        /// or [DEFAULT_LATTICE_PREFIX] if the host didn't provide one
+        pub fn lattice_prefix(&self) -> RpcResult<LatticePrefix> {
+            LatticePrefix::new(lattice_prefix_or_default(&self.lattice_rpc_prefix))
+        }
+    }
+
     impl std::fmt::Display for WasmCloudEntity {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
             write!(f, "{}", self.url())
         }
     }
 
+    /// Link setting keys that are treated as secrets by [LinkDefinition::redacted].
+    /// A key is sensitive if it contains any of these, ignoring case.
+    pub const SENSITIVE_LINK_KEYS: &[&str] = &[
+        "password",
+        "passwd",
+        "secret",
+        "token",
+        "credential",
+        "api_key",
+        "apikey",
+        "private_key",
+        "seed",
+        "jwt",
+    ];
+
     impl LinkDefinition {
+        /// Returns a copy that is safe to log, with the values of sensitive settings
+        /// (see [SENSITIVE_LINK_KEYS]) replaced by "***"
+        pub fn redacted(&self) -> LinkDefinition {
+            self.redacted_with(SENSITIVE_LINK_KEYS)
+        }
+
+        /// Returns a copy that is safe to log, with the value of each setting whose key
+        /// contains any of `sensitive`, ignoring case, replaced by "***"
+        pub fn redacted_with(&self, sensitive: &[&str]) -> LinkDefinition {
+            let sensitive: Vec<String> = sensitive.iter().map(|s| s.to_lowercase()).collect();
+            let mut ld = self.clone();
+            for (key, value) in ld.values.iter_mut() {
+                let key = key.to_lowercase();
+                if sensitive.iter().any(|s| key.contains(s.as_str())) {
+                    *value = "***".to_string();
+                }
+            }
+            ld
+        }
+
+        /// Checks that the link is for the contract, returning `RpcError::ProviderInit`
+        /// if the host linked the actor to a different one
+        pub fn matches_contract(&self, contract_id: &str) -> RpcResult<()> {
+            if self.contract_id == contract_id {
+                Ok(())
+            } else {
+                Err(RpcError::ProviderInit(format!(
+                    "link from actor {} is for contract '{}', not '{}'",
+                    self.actor_id, self.contract_id, contract_id
+                )))
+            }
+        }
+
         pub fn actor_entity(&self) -> WasmCloudEntity {
             WasmCloudEntity {
                 public_key: self.actor_id.clone(),
@@ -173,7 +578,10 @@ pub mod core {
             })
         }
 
-        /// Returns URL of the entity
+        /// Returns URL of the entity.
+        /// The url is signed into invocation claims and hashed, so its encoding must
+        /// not change; it is not a subject token, and is not passed through
+        /// [sanitize_subject_token].
         pub fn url(&self) -> String {
             if self.public_key.to_uppercase().starts_with('M') {
                 format!("{}://{}", URL_SCHEME, self.public_key)
@@ -188,6 +596,21 @@ pub mod core {
             }
         }
 
+        /// Returns a short form of the entity for log lines: the first 8 characters of an
+        /// actor's public key, or `contract/link/key8` for a provider, without the key if it's empty.
+        /// Short ids of different entities may be the same; use [url](WasmCloudEntity::url)
+        /// where the entity must be identified exactly.
+        pub fn short_id(&self) -> String {
+            let key: String = self.public_key.chars().take(8).collect();
+            if self.is_actor() {
+                key
+            } else if key.is_empty() {
+                format!("{}/{}", self.contract_id, self.link_name)
+            } else {
+                format!("{}/{}/{}", self.contract_id, self.link_name, key)
+            }
+        }
+
         /// Returns the unique (public) key of the entity
         pub fn public_key(&self) -> String {
             self.public_key.to_string()
@@ -202,6 +625,34 @@ pub mod core {
         pub fn is_provider(&self) -> bool {
             !self.is_actor()
         }
+
+        /// Checks that the fields describe one kind of entity: an actor has a public key
+        /// starting with 'M' and no contract_id or link_name; a provider has both
+        /// contract_id and link_name, and a public key, if any, that isn't an actor's.
+        /// Returns `RpcError::InvalidParameter` describing the first inconsistency.
+        pub fn validate(&self) -> RpcResult<()> {
+            let invalid = |msg: &str| {
+                Err(RpcError::InvalidParameter(format!(
+                    "invalid entity {}: {}",
+                    self.url(),
+                    msg
+                )))
+            };
+            let actor_key = self.public_key.starts_with('M');
+            match (self.contract_id.is_empty(), self.link_name.is_empty()) {
+                (true, true) if self.public_key.is_empty() => {
+                    invalid("public_key, or contract_id and link_name, are required")
+                }
+                (true, true) if !actor_key => invalid("actor public_key must start with 'M'"),
+                (true, true) => Ok(()),
+                (false, true) => invalid("provider has contract_id but no link_name"),
+                (true, false) => invalid("provider has link_name but no contract_id"),
+                (false, false) if actor_key => {
+                    invalid("actor public_key may not have contract_id or link_name")
+                }
+                (false, false) => Ok(()),
+            }
+        }
     }
 
     impl TryFrom<&str> for WasmCloudEntity {
@@ -224,9 +675,34 @@ pub mod core {
 }
 
 pub mod actor {
+    use crate::{
+        common::Context,
+        core::{HealthCheckRequest, HealthCheckResponse},
+        error::RpcResult,
+    };
+    use async_trait::async_trait;
+
+    /// Health check handler for an actor.
+    /// `#[derive(HealthResponder)]` implements [Actor::health_request](crate::core::Actor::health_request)
+    /// by calling `health`, and also implements this trait with the default method.
+    /// To supply your own `health`, add `#[health_responder(custom)]` next to the derive,
+    /// and implement this trait.
+    #[async_trait]
+    pub trait HealthResponder {
+        /// Returns the actor's health. The default implementation always returns healthy
+        #[allow(unused_variables)]
+        async fn health(
+            &self,
+            ctx: &Context,
+            arg: &HealthCheckRequest,
+        ) -> RpcResult<HealthCheckResponse> {
+            Ok(HealthCheckResponse { healthy: true, message: None })
+        }
+    }
 
     pub mod prelude {
         pub use crate::{
+            actor::HealthResponder,
             common::{Context, Message, MessageDispatch, SendOpts, Transport},
             core::{Actor, ActorReceiver},
             error::{RpcError, RpcResult},
@@ -234,7 +710,7 @@ pub mod actor {
 
         // re-export async_trait
         pub use async_trait::async_trait;
-        // derive macros
+        // derive macros. The HealthResponder derive shares its name with the trait it implements
         pub use wasmbus_macros::{Actor, ActorHealthResponder as HealthResponder};
 
         #[cfg(feature = "BigInteger")]
@@ -353,4 +829,411 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn subject_tokens() {
+        use crate::core::sanitize_subject_token;
+
+        assert_eq!(sanitize_subject_token("default"), "default");
+        assert_eq!(sanitize_subject_token("a.b.c"), "a_b_c");
+        assert_eq!(sanitize_subject_token("my link"), "my_link");
+        assert_eq!(sanitize_subject_token("tab\tnl\n"), "tab_nl_");
+        assert_eq!(sanitize_subject_token("*"), "_");
+        assert_eq!(sanitize_subject_token("a>"), "a_");
+        assert_eq!(sanitize_subject_token(""), "_");
+        assert_eq!(sanitize_subject_token("café:ü"), "café:ü");
+    }
+
+    #[test]
+    fn entity_urls() {
+        use crate::core::WasmCloudEntity;
+
+        let actor = WasmCloudEntity::new_actor("MABC").unwrap();
+        assert_eq!(actor.url(), "wasmbus://MABC");
+
+        let mut provider = WasmCloudEntity::new_provider("wasmcloud:keyvalue", "default").unwrap();
+        provider.public_key = "VXYZ".to_string();
+        assert_eq!(provider.url(), "wasmbus://wasmcloud/keyvalue/default/VXYZ");
+
+        // only spaces are replaced, as peers that sign and hash the url expect
+        let provider = WasmCloudEntity::new_provider("Acme:Key Value", "link.one*").unwrap();
+        assert_eq!(provider.url(), "wasmbus://acme/key_value/link.one*/");
+        let provider = WasmCloudEntity {
+            public_key: "VXYZ".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(provider.url(), "wasmbus:////VXYZ");
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn rpc_topics() {
+        use crate::{core::WasmCloudEntity, rpc_client::rpc_topic};
+
+        let actor = WasmCloudEntity::new_actor("MABC").unwrap();
+        assert_eq!(rpc_topic(&actor, "default"), "wasmbus.rpc.default.MABC");
+
+        let mut provider = WasmCloudEntity::new_provider("wasmcloud:keyvalue", "a.b >").unwrap();
+        provider.public_key = "VXYZ".to_string();
+        assert_eq!(
+            rpc_topic(&provider, "default"),
+            "wasmbus.rpc.default.VXYZ.a_b__"
+        );
+    }
+
+    #[test]
+    fn lattice_prefixes() {
+        use crate::{
+            core::{HostData, LatticePrefix},
+            error::RpcError,
+        };
+
+        for valid in ["default", "prod.us-east", "lattice_1", "ünïcode"] {
+            let prefix = LatticePrefix::new(valid).unwrap();
+            assert_eq!(prefix.to_string(), valid);
+            assert_eq!(valid.parse::<LatticePrefix>().unwrap(), prefix);
+        }
+        for invalid in [
+            "",
+            ".",
+            "a.",
+            ".a",
+            "a..b",
+            "a*",
+            "a.>",
+            "with space",
+            "tab\t",
+            "nl\n",
+        ] {
+            let err = LatticePrefix::new(invalid).unwrap_err();
+            assert!(matches!(err, RpcError::InvalidParameter(_)), "{invalid:?}");
+        }
+
+        let mut host_data = HostData::default();
+        assert_eq!(host_data.lattice_prefix().unwrap().as_str(), "default");
+        host_data.lattice_rpc_prefix = "prod".to_string();
+        assert_eq!(host_data.lattice_prefix().unwrap().as_str(), "prod");
+        host_data.lattice_rpc_prefix = "prod.*".to_string();
+        assert!(host_data.lattice_prefix().is_err());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn host_data_from_base64() {
+        use crate::{
+            core::{HostData, HOST_DATA_ENV},
+            error::RpcError,
+        };
+
+        let host_data = HostData {
+            host_id: "NHOST".to_string(),
+            lattice_rpc_prefix: "default".to_string(),
+            provider_key: "VPROVIDER".to_string(),
+            ..Default::default()
+        };
+        let encoded = base64::encode(serde_json::to_vec(&host_data).unwrap());
+        let decoded = HostData::from_base64(&format!("{encoded}\n")).unwrap();
+        assert_eq!(decoded.host_id, "NHOST");
+        assert_eq!(decoded.provider_key, "VPROVIDER");
+
+        let err = HostData::from_base64("not base64!").unwrap_err();
+        assert!(matches!(err, RpcError::InvalidParameter(_)), "{err}");
+
+        let err = HostData::from_base64(&base64::encode(b"{\"host_id\": 42}")).unwrap_err();
+        assert!(matches!(err, RpcError::Deser(_)), "{err}");
+
+        std::env::set_var(HOST_DATA_ENV, &encoded);
+        assert_eq!(HostData::from_base64_env().unwrap().host_id, "NHOST");
+        std::env::remove_var(HOST_DATA_ENV);
+        let err = HostData::from_base64_env().unwrap_err();
+        assert!(matches!(err, RpcError::NotInitialized(_)), "{err}");
+    }
+
+    #[test]
+    fn host_data_required_fields() {
+        use crate::{core::HostData, error::RpcError};
+
+        let mut host_data = HostData {
+            lattice_rpc_prefix: "default".to_string(),
+            ..Default::default()
+        };
+        let err = host_data.validate().unwrap_err();
+        assert!(matches!(err, RpcError::ProviderInit(_)));
+        assert_eq!(
+            err.to_string(),
+            "provider initialization or put-link: host data is missing required fields: \
+             host_id, provider_key"
+        );
+
+        host_data.host_id = "NHOST".to_string();
+        host_data.provider_key = "VPROVIDER".to_string();
+        assert!(host_data.validate().is_ok());
+
+        host_data.lattice_rpc_prefix = " ".to_string();
+        let err = host_data.validate().unwrap_err();
+        assert!(err.to_string().ends_with("missing required fields: lattice_rpc_prefix"));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn redacted_link() {
+        use crate::core::LinkDefinition;
+
+        let ld = LinkDefinition {
+            actor_id: "MACTOR".to_string(),
+            values: [
+                ("password", "hunter2"),
+                ("DB_Password", "x"),
+                ("url", "postgres://db"),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+            ..Default::default()
+        };
+        let redacted = ld.redacted();
+        assert_eq!(redacted.values["password"], "***");
+        assert_eq!(redacted.values["DB_Password"], "***");
+        assert_eq!(redacted.values["url"], "postgres://db");
+        assert_eq!(redacted.actor_id, "MACTOR");
+        assert!(!format!("{redacted:?}").contains("hunter2"));
+
+        let redacted = ld.redacted_with(&["URL"]);
+        assert_eq!(redacted.values["url"], "***");
+        assert_eq!(redacted.values["password"], "hunter2");
+    }
+
+    #[test]
+    fn entity_validate() {
+        use crate::{core::WasmCloudEntity, error::RpcError};
+
+        let entity = |public_key: &str, contract_id: &str, link_name: &str| WasmCloudEntity {
+            public_key: public_key.to_string(),
+            contract_id: contract_id.to_string(),
+            link_name: link_name.to_string(),
+        };
+        // valid
+        assert!(WasmCloudEntity::new_actor("MACTOR").unwrap().validate().is_ok());
+        assert!(WasmCloudEntity::new_provider("wasmcloud:kv", "default")
+            .unwrap()
+            .validate()
+            .is_ok());
+        assert!(entity("VPROVIDER", "wasmcloud:kv", "default").validate().is_ok());
+
+        // malformed
+        for (e, why) in [
+            (entity("", "", ""), "are required"),
+            (entity("VPROVIDER", "", ""), "must start with 'M'"),
+            (entity("MACTOR", "wasmcloud:kv", ""), "no link_name"),
+            (entity("", "", "default"), "no contract_id"),
+            (
+                entity("MACTOR", "wasmcloud:kv", "default"),
+                "may not have contract_id",
+            ),
+        ] {
+            let err = e.validate().unwrap_err();
+            assert!(
+                matches!(&err, RpcError::InvalidParameter(m) if m.contains(why)),
+                "{e:?}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn rpc_subject() {
+        let host_data = crate::core::HostData {
+            lattice_rpc_prefix: "default".to_string(),
+            provider_key: "VAHNM37GORAMRA6ZTKZJZ3QXVATFH7YRJUWNBNFH3JTEOWSPVQJZA5HO".to_string(),
+            link_name: "backup.east".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            host_data.rpc_subject(),
+            "wasmbus.rpc.default.VAHNM37GORAMRA6ZTKZJZ3QXVATFH7YRJUWNBNFH3JTEOWSPVQJZA5HO.backup_east"
+        );
+    }
+
+    #[test]
+    fn link_contract() {
+        use crate::{core::LinkDefinition, error::RpcError};
+
+        let ld = LinkDefinition {
+            actor_id: "MACTOR".to_string(),
+            contract_id: "wasmcloud:keyvalue".to_string(),
+            ..Default::default()
+        };
+        assert!(ld.matches_contract("wasmcloud:keyvalue").is_ok());
+        let err = ld.matches_contract("wasmcloud:httpserver").unwrap_err();
+        assert!(
+            matches!(&err, RpcError::ProviderInit(s) if s.contains("'wasmcloud:keyvalue', not 'wasmcloud:httpserver'")),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn entity_short_id() {
+        use crate::core::WasmCloudEntity;
+
+        let actor = WasmCloudEntity {
+            public_key: "MBCFOPM6JW2APJLXJD3Z5O4CN7CPYJ2B4FTKLJUR5YR5MITIU7HD3WD5".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(actor.short_id(), "MBCFOPM6");
+        let provider = WasmCloudEntity {
+            public_key: "VAHNM37GORAMRA6ZTKZJZ3QXVATFH7YRJUWNBNFH3JTEOWSPVQJZA5HO".to_string(),
+            contract_id: "wasmcloud:keyvalue".to_string(),
+            link_name: "default".to_string(),
+        };
+        assert_eq!(provider.short_id(), "wasmcloud:keyvalue/default/VAHNM37G");
+        let unkeyed = WasmCloudEntity { public_key: String::new(), ..provider };
+        assert_eq!(unkeyed.short_id(), "wasmcloud:keyvalue/default");
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[tokio::test]
+    async fn nats_connect_timeout() {
+        use crate::error::RpcError;
+        use std::time::{Duration, Instant};
+
+        // the server accepts the tcp connection, but never sends its INFO
+        let stalled = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let host_data = crate::core::HostData {
+            lattice_rpc_url: format!("nats://{}", stalled.local_addr().unwrap()),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let err = host_data
+            .nats_connect_with_timeout("test", Duration::from_millis(200))
+            .await
+            .unwrap_err();
+        let elapsed = start.elapsed();
+        assert!(
+            matches!(&err, RpcError::Timeout(s) if s == "nats connect timed out"),
+            "{err}"
+        );
+        assert!(elapsed >= Duration::from_millis(200), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn parse_rpc_subjects() {
+        use crate::{
+            core::{parse_rpc_subject, HostData, RpcSubjectParts, WasmCloudEntity},
+            error::RpcError,
+            rpc_client::rpc_topic,
+            wascap::prelude::KeyPair,
+        };
+
+        let provider_key = KeyPair::new_service().public_key();
+        let actor_key = KeyPair::new_module().public_key();
+        let host_data = HostData {
+            lattice_rpc_prefix: "acme.east".to_string(),
+            provider_key: provider_key.clone(),
+            link_name: "backup.east".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            parse_rpc_subject(&host_data.rpc_subject()).unwrap(),
+            RpcSubjectParts {
+                lattice_prefix: "acme.east".to_string(),
+                public_key: provider_key.clone(),
+                link_name: Some("backup_east".to_string()),
+            }
+        );
+
+        let mut provider = WasmCloudEntity::new_provider("wasmcloud:keyvalue", "default").unwrap();
+        provider.public_key = provider_key.clone();
+        let parts = parse_rpc_subject(&rpc_topic(&provider, "default")).unwrap();
+        assert_eq!(parts.lattice_prefix, "default");
+        assert_eq!(parts.public_key, provider_key);
+        assert_eq!(parts.link_name.as_deref(), Some("default"));
+
+        // an actor, with a prefix of several tokens
+        let actor = WasmCloudEntity::new_actor(&actor_key).unwrap();
+        let parts = parse_rpc_subject(&rpc_topic(&actor, "a.b")).unwrap();
+        assert_eq!(parts.lattice_prefix, "a.b");
+        assert_eq!(parts.public_key, actor_key);
+        assert_eq!(parts.link_name, None);
+
+        // a prefix token that starts with 'V' is not a provider key
+        let parts = parse_rpc_subject(&rpc_topic(&actor, "acme.Vendor")).unwrap();
+        assert_eq!(parts.lattice_prefix, "acme.Vendor");
+        assert_eq!(parts.public_key, actor_key);
+        assert_eq!(parts.link_name, None);
+
+        for bad in [
+            "wasmcloud.rpc.default.MABC",
+            "wasmbus.rpc.MABC",
+            "wasmbus.rpc.default..MABC",
+            "wasmbus.rpc.*.MABC",
+            "wasmbus.rpc.default.VXYZ.>",
+        ] {
+            let err = parse_rpc_subject(bad).unwrap_err();
+            assert!(
+                matches!(&err, RpcError::InvalidParameter(s) if s.contains(bad)),
+                "{err}"
+            );
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn empty_lattice_prefix() {
+        use crate::{
+            core::{HostData, WasmCloudEntity},
+            rpc_client::rpc_topic,
+        };
+
+        let mut host_data = HostData {
+            host_id: "NHOST".to_string(),
+            provider_key: "VPROVIDER".to_string(),
+            link_name: "default".to_string(),
+            ..Default::default()
+        };
+        for blank in ["", " "] {
+            host_data.lattice_rpc_prefix = blank.to_string();
+            assert_eq!(
+                host_data.rpc_subject(),
+                "wasmbus.rpc.default.VPROVIDER.default"
+            );
+            assert_eq!(host_data.lattice_prefix().unwrap().as_str(), "default");
+            // a provider still refuses to start without a prefix from the host
+            assert!(host_data.validate().is_err());
+
+            let actor = WasmCloudEntity::new_actor("MABC").unwrap();
+            assert_eq!(rpc_topic(&actor, blank), "wasmbus.rpc.default.MABC");
+        }
+    }
+
+    #[test]
+    fn nats_connection_name() {
+        let host_data = crate::core::HostData {
+            provider_key: "VPROVIDER".to_string(),
+            link_name: "default".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            host_data.nats_connection_name(),
+            "weld-provider:VPROVIDER:default"
+        );
+
+        let opts = host_data
+            .nats_connect_options(
+                "my-name",
+                crate::rpc_client::DEFAULT_RECONNECT_MAX_BACKOFF,
+                &Default::default(),
+                std::time::Duration::from_secs(12),
+            )
+            .unwrap();
+        assert!(
+            format!("{opts:?}").contains(r#""name": Some("my-name")"#),
+            "{opts:?}"
+        );
+        // a stalled tcp connect fails with the caller's timeout, not the client's default
+        assert!(
+            format!("{opts:?}").contains(r#""connection_timeout": 12s"#),
+            "{opts:?}"
+        );
+    }
 }
@@ -8,16 +8,14 @@ use std::{
     convert::Infallible,
     fmt::Formatter,
     ops::Deref,
+    panic::AssertUnwindSafe,
     sync::{atomic::AtomicU64, atomic::Ordering, Arc},
     time::Duration,
 };
 
-use crate::wascap::{
-    jwt,
-    prelude::{Claims, KeyPair},
-};
+use crate::wascap::{jwt, prelude::Claims};
 use async_trait::async_trait;
-use futures::{future::JoinAll, StreamExt};
+use futures::{future::JoinAll, FutureExt, StreamExt};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tokio::sync::RwLock;
 use tracing::{debug, error, info, instrument, trace, warn};
@@ -25,13 +23,23 @@ use tracing_futures::Instrument;
 
 pub use crate::rpc_client::make_uuid;
 use crate::{
-    common::{deserialize, serialize, Context, Message, MessageDispatch, SendOpts, Transport},
+    common::{
+        deserialize, serialize, Context, DispatchResult, Message, MessageDispatch, MessageFormat,
+        Priority, SendOpts, Transport,
+    },
     core::{
         HealthCheckRequest, HealthCheckResponse, HostData, Invocation, InvocationResponse,
-        LinkDefinition,
+        LatticePrefix, LinkDefinition,
     },
+    dispatch::SCHEMA_HASH_HEADER,
     error::{RpcError, RpcResult},
-    rpc_client::{RpcClient, DEFAULT_RPC_TIMEOUT_MILLIS},
+    provider_transport::NatsTransport,
+    rpc_client::{
+        parse_expires_header, ReconnectLimit, RpcClient, WireTap, ACCEPT_ENCODING_HEADER,
+        ACCEPT_FORMATS_HEADER, CHECKSUM_HEADER, DEFAULT_RPC_TIMEOUT_MILLIS, DEFLATE_ENCODING,
+        ENCODING_HEADER, EXPIRES_HEADER, FORMAT_HEADER, IDEMPOTENCY_KEY_HEADER, PRIORITY_HEADER,
+        PROTOCOL_VERSION_HEADER, SENT_AT_HEADER,
+    },
 };
 
 // name of nats queue group for rpc subscription
@@ -42,17 +50,50 @@ pub(crate) const DEFAULT_NATS_ADDR: &str = "nats://127.0.0.1:4222";
 
 pub type HostShutdownEvent = String;
 
+/// Operation that every provider answers, without a handler, with its [VersionInfo]
+pub const VERSION_OPERATION: &str = "_version";
+
+/// Message of the `RpcError::DeadlineExceeded` a provider replies with when
+/// a handler runs past [ProviderOptions::handler_timeout]
+pub const SERVER_TIMEOUT: &str = "server-side timeout";
+
+/// Response to [VERSION_OPERATION]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionInfo {
+    /// Version of this crate the provider was built with, [WELD_CRATE_VERSION](crate::WELD_CRATE_VERSION)
+    pub weld_version: String,
+    /// The provider's own version, from [ProviderHandler::provider_version]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider_version: Option<String>,
+}
+
+/// Change in the state of a running provider, received from [HostBridge::subscribe_events]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ProviderEvent {
+    /// The provider was initialized and subscribed to all its host subjects,
+    /// so it receives rpc, link, health, and shutdown messages. Sent once,
+    /// after the provider's [ready](ProviderHandler::ready) hook returns.
+    Ready,
+    /// The provider's nats connection used up its [max_reconnects](ProviderOptions::max_reconnects).
+    /// No more messages are received, and sends fail. This is the last event.
+    ConnectionLost,
+}
+
 pub trait ProviderDispatch: MessageDispatch + ProviderHandler {}
-trait ProviderImpl: ProviderDispatch + Send + Sync + 'static {}
 
 pub mod prelude {
     pub use crate::{
-        common::{Context, Message, MessageDispatch, SendOpts},
+        common::{Context, DispatchResult, Message, MessageDispatch, SendOpts},
         core::LinkDefinition,
         error::{RpcError, RpcResult},
-        provider::{HostBridge, ProviderDispatch, ProviderHandler},
+        provider::{
+            CompressionPolicy, ConnectionStrategy, HostBridge, ProviderConfig, ProviderDispatch,
+            ProviderEvent, ProviderHandler, ProviderOptions, VersionInfo, VERSION_OPERATION,
+        },
         provider_main::{
             get_host_bridge, load_host_data, provider_main, provider_run, provider_start,
+            run_provider_with_connection, run_provider_with_transport, ProviderHost,
         },
     };
 
@@ -70,6 +111,15 @@ pub mod prelude {
 /// The HostBridge handles most messages and forwards the remainder to this handler
 #[async_trait]
 pub trait ProviderHandler: Sync {
+    /// Performs async setup, such as opening a database pool, before the provider
+    /// receives any messages. Called once at startup, before the initial links are
+    /// added and before any nats subscriptions are made, so no rpc can be dispatched
+    /// until this returns. An error stops the provider.
+    #[allow(unused_variables)]
+    async fn init(&self, host_data: &HostData) -> RpcResult<()> {
+        Ok(())
+    }
+
     /// Provider should perform any operations needed for a new link,
     /// including setting up per-actor resources, and checking authorization.
     /// If the link is allowed, return true, otherwise return false to deny the link.
@@ -80,6 +130,18 @@ pub trait ProviderHandler: Sync {
         Ok(true)
     }
 
+    /// Called instead of [put_link](ProviderHandler::put_link) when the host sends a link
+    /// for an actor that is already linked, with the new link definition, such as new values.
+    /// Return true to replace the link. If this returns false or an error, the actor is
+    /// no longer linked: the default has deleted the previous link by then, so an
+    /// override should release the actor's resources before failing, too.
+    /// The default calls [delete_link](ProviderHandler::delete_link) and then `put_link`,
+    /// as if the host had deleted the link first, so `put_link` never sees an actor twice.
+    async fn update_config(&self, ld: &LinkDefinition) -> RpcResult<bool> {
+        self.delete_link(&ld.actor_id).await;
+        self.put_link(ld).await
+    }
+
     /// Notify the provider that the link is dropped
     #[allow(unused_variables)]
     async fn delete_link(&self, actor_id: &str) {}
@@ -91,10 +153,373 @@ pub trait ProviderHandler: Sync {
         Ok(HealthCheckResponse { healthy: true, message: None })
     }
 
+    /// Perform health check for a single link. Used by the aggregate health check,
+    /// which combines the provider's health with the health of each of its links.
+    /// Default implementation always returns healthy
+    #[allow(unused_variables)]
+    async fn link_health_request(
+        &self,
+        ld: &LinkDefinition,
+        arg: &HealthCheckRequest,
+    ) -> RpcResult<HealthCheckResponse> {
+        Ok(HealthCheckResponse { healthy: true, message: None })
+    }
+
     /// Handle system shutdown message
     async fn shutdown(&self) -> Result<(), Infallible> {
         Ok(())
     }
+
+    /// Called when the provider's nats connection is lost (see [ProviderEvent::ConnectionLost]),
+    /// before [provider_main](crate::provider::prelude::provider_main) returns an error,
+    /// so the process exits with a non-zero status and the host or supervisor can restart it.
+    async fn connection_lost(&self) {}
+
+    /// Called once, after the provider was initialized and subscribed to all its host subjects.
+    /// From here on it receives rpc messages, so an orchestrator that gates traffic on
+    /// readiness can be told here. Not called if a subscription fails.
+    async fn ready(&self) {}
+
+    /// Options for the HostBridge running this provider.
+    /// This is called before the bridge subscribes to rpc messages.
+    fn provider_options(&self) -> ProviderOptions {
+        ProviderOptions::default()
+    }
+
+    /// The provider's version, reported by [VERSION_OPERATION],
+    /// such as `Some(env!("CARGO_PKG_VERSION").to_string())`. The default is None.
+    fn provider_version(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Settings that control how the HostBridge runs a provider.
+/// Returned from [ProviderHandler::provider_options]
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ProviderOptions {
+    /// If true, a panic in the provider's message handler is caught and returned
+    /// to the caller as an error, instead of leaving the caller to time out.
+    /// Default is true.
+    pub catch_panics: bool,
+
+    /// Whether links share the provider's nats connection. Default is Shared.
+    pub connection_strategy: ConnectionStrategy,
+
+    /// Name of the provider's nats connection, shown by nats monitoring tools.
+    /// Per-link connections append ":<actor_id>".
+    /// Default is [HostData::nats_connection_name]
+    pub connection_name: Option<String>,
+
+    /// Observer of the exact bytes of rpc messages the provider sends and receives,
+    /// on the provider's connection and any per-link connections. Default is None.
+    pub wire_tap: Option<Arc<dyn WireTap>>,
+
+    /// Upper limit on the delay between nats reconnect attempts.
+    /// Delays grow from 100ms up to this limit, with random jitter,
+    /// so providers that restart together don't reconnect at the same moment.
+    /// Default is [DEFAULT_RECONNECT_MAX_BACKOFF](crate::rpc_client::DEFAULT_RECONNECT_MAX_BACKOFF)
+    pub reconnect_max_backoff: Duration,
+
+    /// If set, each rpc the provider handles or sends is logged with up to this many
+    /// bytes of its payload. See [RpcClient::set_debug_logging]. Default is None.
+    pub debug_logging: Option<usize>,
+
+    /// Number of consecutive failed reconnect attempts after which the nats connection
+    /// is considered lost, and the provider stops with an error. See [ReconnectLimit].
+    /// Default is None: reconnect forever.
+    pub max_reconnects: Option<usize>,
+
+    /// Limit on the time to establish the provider's nats connections, after which
+    /// the provider fails to start with `RpcError::Timeout`.
+    /// Default is [DEFAULT_CONNECT_TIMEOUT](crate::rpc_client::DEFAULT_CONNECT_TIMEOUT)
+    pub connect_timeout: Duration,
+
+    /// If set, an invocation whose id was received within this window is a redelivery,
+    /// as with at-least-once delivery, and is dropped without calling the handler or replying.
+    /// Unlike [IdempotencyCache](crate::dispatch::IdempotencyCache), this uses the id the
+    /// sender's client assigns to each invocation, not a key chosen by the caller.
+    /// Default is None: every delivery is handled.
+    pub dedup_window: Option<Duration>,
+
+    /// If set, a warning is logged when the [apparent skew](Context::apparent_skew) between
+    /// the sender's clock and ours exceeds this. Only senders that set
+    /// [SendOpts::send_time](crate::common::SendOpts::send_time) are checked.
+    /// Default is [DEFAULT_CLOCK_SKEW_WARNING]
+    pub clock_skew_warning: Option<Duration>,
+
+    /// Formats the provider can encode responses in, chosen from by senders that set
+    /// [SendOpts::accept_formats](crate::common::SendOpts::accept_formats).
+    /// A sender that sets neither is replied to in its request's format, if it's one of these.
+    /// The chosen format is in [Context::reply_format]. It's named in the reply's
+    /// [FORMAT_HEADER] header only if the handler wrote the reply in it, with
+    /// [DispatchResult::serialize_reply] or [DispatchResult::with_format].
+    /// Default is Cbor and Msgpack.
+    pub message_formats: Vec<MessageFormat>,
+
+    /// If set, responses larger than this many bytes are compressed for senders that set
+    /// [SendOpts::accept_compression](crate::common::SendOpts::accept_compression),
+    /// except for operations with a [compression policy](ProviderOptions::compression_policy).
+    /// Default is None: only operations with a policy are compressed.
+    pub compression_threshold: Option<usize>,
+
+    /// Compression of the responses to particular operations, overriding `compression_threshold`
+    pub compression_policies: HashMap<String, CompressionPolicy>,
+
+    /// If set, a handler that runs longer than this is cancelled, and the caller is sent
+    /// `RpcError::DeadlineExceeded` with the message [SERVER_TIMEOUT], so it can tell
+    /// the provider gave up from its own timeout. Should be shorter than callers' timeouts.
+    /// Default is None: handlers run until they finish.
+    pub handler_timeout: Option<Duration>,
+
+    /// Contract the provider implements. If set, links for other contracts are rejected
+    /// without calling [ProviderHandler::put_link]. Default is None: links aren't checked.
+    pub contract_id: Option<String>,
+
+    /// If set, the peak memory allocated by each dispatch is reported to the sink.
+    /// Measurements need [TrackingAllocator](crate::alloc_tracking::TrackingAllocator)
+    /// installed as the global allocator. Default is None.
+    #[cfg(feature = "alloc-tracking")]
+    pub metrics_sink: Option<Arc<dyn crate::alloc_tracking::MetricsSink>>,
+}
+
+/// When a provider compresses its responses, for senders that accept compressed responses
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressionPolicy {
+    /// Responses are not compressed
+    Never,
+    /// Every response is compressed
+    Always,
+    /// Responses larger than this many bytes are compressed
+    Above(usize),
+}
+
+impl CompressionPolicy {
+    /// Returns true if a response of this size is compressed
+    pub fn applies(&self, len: usize) -> bool {
+        match self {
+            CompressionPolicy::Never => false,
+            CompressionPolicy::Always => true,
+            CompressionPolicy::Above(threshold) => len > *threshold,
+        }
+    }
+}
+
+/// Number of host subjects a provider subscribes to in [HostBridge::connect]:
+/// rpc, link put and del, shutdown, health, and aggregate health
+const HOST_SUBSCRIPTIONS: usize = 6;
+
+/// Default [ProviderOptions::clock_skew_warning]
+pub const DEFAULT_CLOCK_SKEW_WARNING: Duration = Duration::from_secs(2);
+
+impl Default for ProviderOptions {
+    fn default() -> Self {
+        ProviderOptions {
+            catch_panics: true,
+            connection_strategy: ConnectionStrategy::default(),
+            connection_name: None,
+            wire_tap: None,
+            reconnect_max_backoff: crate::rpc_client::DEFAULT_RECONNECT_MAX_BACKOFF,
+            debug_logging: None,
+            max_reconnects: None,
+            connect_timeout: crate::rpc_client::DEFAULT_CONNECT_TIMEOUT,
+            dedup_window: None,
+            clock_skew_warning: Some(DEFAULT_CLOCK_SKEW_WARNING),
+            message_formats: vec![MessageFormat::Cbor, MessageFormat::Msgpack],
+            compression_threshold: None,
+            compression_policies: HashMap::new(),
+            handler_timeout: None,
+            contract_id: None,
+            #[cfg(feature = "alloc-tracking")]
+            metrics_sink: None,
+        }
+    }
+}
+
+impl ProviderOptions {
+    #[must_use]
+    pub fn catch_panics(mut self, val: bool) -> ProviderOptions {
+        self.catch_panics = val;
+        self
+    }
+
+    #[must_use]
+    pub fn connection_strategy(mut self, val: ConnectionStrategy) -> ProviderOptions {
+        self.connection_strategy = val;
+        self
+    }
+
+    #[must_use]
+    pub fn connection_name(mut self, val: String) -> ProviderOptions {
+        self.connection_name = Some(val);
+        self
+    }
+
+    #[must_use]
+    pub fn wire_tap(mut self, val: Arc<dyn WireTap>) -> ProviderOptions {
+        self.wire_tap = Some(val);
+        self
+    }
+
+    #[must_use]
+    pub fn reconnect_max_backoff(mut self, val: Duration) -> ProviderOptions {
+        self.reconnect_max_backoff = val;
+        self
+    }
+
+    #[must_use]
+    pub fn debug_logging(mut self, max_preview: Option<usize>) -> ProviderOptions {
+        self.debug_logging = max_preview;
+        self
+    }
+
+    #[must_use]
+    pub fn max_reconnects(mut self, val: Option<usize>) -> ProviderOptions {
+        self.max_reconnects = val;
+        self
+    }
+
+    #[must_use]
+    pub fn connect_timeout(mut self, val: Duration) -> ProviderOptions {
+        self.connect_timeout = val;
+        self
+    }
+
+    #[must_use]
+    pub fn dedup_window(mut self, val: Option<Duration>) -> ProviderOptions {
+        self.dedup_window = val;
+        self
+    }
+
+    #[must_use]
+    pub fn clock_skew_warning(mut self, val: Option<Duration>) -> ProviderOptions {
+        self.clock_skew_warning = val;
+        self
+    }
+
+    #[must_use]
+    pub fn message_formats(mut self, val: Vec<MessageFormat>) -> ProviderOptions {
+        self.message_formats = val;
+        self
+    }
+
+    #[must_use]
+    pub fn compression_threshold(mut self, val: Option<usize>) -> ProviderOptions {
+        self.compression_threshold = val;
+        self
+    }
+
+    /// Sets the compression of responses to the operation, such as "KeyValue.Get",
+    /// in place of the [compression_threshold](ProviderOptions::compression_threshold)
+    #[must_use]
+    pub fn compression_policy(
+        mut self,
+        operation: &str,
+        policy: CompressionPolicy,
+    ) -> ProviderOptions {
+        self.compression_policies.insert(operation.to_string(), policy);
+        self
+    }
+
+    /// Returns the compression of responses to the operation
+    pub fn response_compression(&self, operation: &str) -> CompressionPolicy {
+        match self.compression_policies.get(operation) {
+            Some(policy) => *policy,
+            None => self
+                .compression_threshold
+                .map_or(CompressionPolicy::Never, CompressionPolicy::Above),
+        }
+    }
+
+    #[must_use]
+    pub fn handler_timeout(mut self, val: Option<Duration>) -> ProviderOptions {
+        self.handler_timeout = val;
+        self
+    }
+
+    #[must_use]
+    pub fn contract_id(mut self, val: String) -> ProviderOptions {
+        self.contract_id = Some(val);
+        self
+    }
+
+    #[cfg(feature = "alloc-tracking")]
+    #[must_use]
+    pub fn metrics_sink(
+        mut self,
+        val: Arc<dyn crate::alloc_tracking::MetricsSink>,
+    ) -> ProviderOptions {
+        self.metrics_sink = Some(val);
+        self
+    }
+
+    /// Returns the configured connection name, or the default name for the provider
+    pub(crate) fn connection_name_or_default(&self, host_data: &HostData) -> String {
+        self.connection_name
+            .clone()
+            .unwrap_or_else(|| host_data.nats_connection_name())
+    }
+}
+
+/// Holder for provider configuration that can be replaced while the provider runs,
+/// for example when settings are reloaded by a background task.
+///
+/// Readers get an `Arc` snapshot of the whole config, so a value read once stays
+/// consistent even if the config is replaced while it's in use. Replacing the config
+/// swaps the `Arc`; readers only wait for the pointer swap, never for a writer
+/// building a new config. Clones share the same config.
+#[derive(Debug)]
+pub struct ProviderConfig<T> {
+    current: Arc<std::sync::RwLock<Arc<T>>>,
+}
+
+impl<T> Clone for ProviderConfig<T> {
+    fn clone(&self) -> Self {
+        ProviderConfig { current: self.current.clone() }
+    }
+}
+
+impl<T: Default> Default for ProviderConfig<T> {
+    fn default() -> Self {
+        ProviderConfig::new(T::default())
+    }
+}
+
+impl<T> ProviderConfig<T> {
+    pub fn new(config: T) -> Self {
+        ProviderConfig {
+            current: Arc::new(std::sync::RwLock::new(Arc::new(config))),
+        }
+    }
+
+    /// Returns the current config
+    pub fn config(&self) -> Arc<T> {
+        // nothing that can panic runs while the lock is held, so it can't be poisoned
+        self.current.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Replaces the config. Readers that already have the previous config keep it;
+    /// later calls to [config](Self::config) return the new one.
+    pub fn set_config(&self, config: T) {
+        let config = Arc::new(config);
+        let mut current = self.current.write().unwrap_or_else(|e| e.into_inner());
+        let previous = std::mem::replace(&mut *current, config);
+        drop(current);
+        // the previous config is dropped outside the lock
+        drop(previous);
+    }
+}
+
+/// Selects the nats connection used for messages a provider sends to linked actors
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConnectionStrategy {
+    /// All links use the provider's connection
+    #[default]
+    Shared,
+    /// Each link gets its own connection, opened when the link is added
+    /// and closed when it is deleted, so a busy link can't delay messages on other links.
+    /// Rpc messages received by the provider still arrive on the provider's connection.
+    PerLink,
 }
 
 /// format of log message sent to main thread for output to logger
@@ -110,7 +535,7 @@ struct ShutdownMessage {
 
 #[doc(hidden)]
 /// Process subscription, until closed or exhausted, or value is received on the channel.
-/// `sub` is a mutable Subscriber (regular or queue subscription)
+/// `sub` is a mutable [Subscription](crate::provider_transport::Subscription), which ends when it is dropped
 /// `channel` may be either tokio mpsc::Receiver or broadcast::Receiver, and is considered signaled
 /// when a value is sent or the chanel is closed.
 /// `msg` is the variable name to be used in the handler
@@ -120,10 +545,7 @@ macro_rules! process_until_quit {
         tokio::spawn(async move {
             loop {
                 tokio::select! {
-                    _ = $channel.recv() => {
-                        let _ = $sub.unsubscribe().await;
-                        break;
-                    },
+                    _ = $channel.recv() => break,
                     __msg = $sub.next() => {
                         match __msg {
                             None => break,
@@ -145,7 +567,6 @@ macro_rules! process_until_quit {
 #[derive(Clone)]
 pub struct HostBridge {
     inner: Arc<HostBridgeInner>,
-    #[allow(dead_code)]
     key: Arc<wascap::prelude::KeyPair>,
     host_data: HostData,
 }
@@ -156,25 +577,70 @@ impl HostBridge {
         nats: crate::async_nats::Client,
         host_data: &HostData,
     ) -> RpcResult<HostBridge> {
-        let key = Arc::new(if host_data.is_test() {
-            KeyPair::new_user()
-        } else {
-            KeyPair::from_seed(&host_data.invocation_seed)
-                .map_err(|e| RpcError::NotInitialized(format!("key failure: {e}")))?
-        });
+        Self::new_with_options(nats, host_data, ProviderOptions::default())
+    }
+
+    #[doc(hidden)]
+    pub fn new_with_options(
+        nats: crate::async_nats::Client,
+        host_data: &HostData,
+        options: ProviderOptions,
+    ) -> RpcResult<HostBridge> {
+        Self::new_bridge(
+            nats,
+            None,
+            ReconnectLimit::default(),
+            None,
+            host_data,
+            options,
+        )
+    }
+
+    /// Constructs a bridge whose rpc client fails, and which sends [ProviderEvent::ConnectionLost],
+    /// when the connection exceeds `reconnect_limit`.
+    /// Host messages use `transport`, or the nats connection if it's None.
+    pub(crate) fn new_bridge(
+        nats: crate::async_nats::Client,
+        connect_rtt: Option<Duration>,
+        reconnect_limit: ReconnectLimit,
+        transport: Option<Arc<dyn crate::provider_transport::HostTransport>>,
+        host_data: &HostData,
+        options: ProviderOptions,
+    ) -> RpcResult<HostBridge> {
+        let key = Arc::new(host_data.invocation_key_pair()?);
 
-        let rpc_client = RpcClient::new_client(
+        let mut rpc_client = RpcClient::new_client(
             nats,
             host_data.host_id.clone(),
             host_data.default_rpc_timeout_ms.map(Duration::from_millis),
             key.clone(),
         );
+        rpc_client.set_connect_rtt(connect_rtt);
+        if let Some(tap) = &options.wire_tap {
+            rpc_client.set_wire_tap(tap.clone());
+        }
+        rpc_client.set_debug_logging(options.debug_logging);
+        rpc_client.set_reconnect_limit(reconnect_limit.clone());
+        let (events, _) = tokio::sync::broadcast::channel(4);
+        let events_tx = events.clone();
+        reconnect_limit.on_lost(move || {
+            let _ = events_tx.send(ProviderEvent::ConnectionLost);
+        });
+        let transport =
+            transport.unwrap_or_else(|| Arc::new(NatsTransport::new(rpc_client.clone())));
 
         Ok(HostBridge {
             inner: Arc::new(HostBridgeInner {
                 links: RwLock::new(HashMap::new()),
+                link_clients: RwLock::new(HashMap::new()),
                 rpc_client,
-                lattice_prefix: host_data.lattice_rpc_prefix.clone(),
+                transport,
+                reconnect_limit,
+                events,
+                lattice_prefix: host_data.lattice_prefix()?,
+                recent_invocations: options.dedup_window.map(RecentInvocations::new),
+                host_subscriptions: tokio::sync::watch::channel((0, 0)).0,
+                options,
             }),
             key,
             host_data: host_data.clone(),
@@ -198,7 +664,7 @@ impl HostBridge {
 
     /// returns the lattice id
     pub fn lattice_prefix(&self) -> &str {
-        &self.host_data.lattice_rpc_prefix
+        self.inner.lattice_prefix.as_str()
     }
 
     pub fn log_level(&self) -> Option<&str> {
@@ -255,8 +721,59 @@ pub struct HostBridgeInner {
     /// Table of actors that are bound to this provider
     /// Key is actor_id / actor public key
     links: RwLock<HashMap<String, LinkDefinition>>,
+    /// Dedicated clients for links, if the connection strategy is PerLink
+    /// Key is actor_id / actor public key
+    link_clients: RwLock<HashMap<String, RpcClient>>,
     rpc_client: RpcClient,
-    lattice_prefix: String,
+    /// subscriptions and replies for host messages
+    transport: Arc<dyn crate::provider_transport::HostTransport>,
+    /// reconnect limit of the provider's connection
+    reconnect_limit: ReconnectLimit,
+    events: tokio::sync::broadcast::Sender<ProviderEvent>,
+    lattice_prefix: LatticePrefix,
+    /// ids of invocations received within the dedup window, if enabled
+    recent_invocations: Option<RecentInvocations>,
+    /// number of host subjects subscribed to, and of subscriptions that failed
+    host_subscriptions: tokio::sync::watch::Sender<(usize, usize)>,
+    options: ProviderOptions,
+}
+
+/// Ids of the invocations received within a sliding window
+struct RecentInvocations {
+    window: Duration,
+    seen: std::sync::Mutex<SeenIds>,
+}
+
+#[derive(Default)]
+struct SeenIds {
+    ids: std::collections::HashSet<String>,
+    /// ids in the order received
+    order: std::collections::VecDeque<(std::time::Instant, String)>,
+}
+
+impl RecentInvocations {
+    fn new(window: Duration) -> Self {
+        RecentInvocations { window, seen: Default::default() }
+    }
+
+    /// Records the id, returning false if it was already received within the window
+    fn first_delivery(&self, id: &str) -> bool {
+        let now = std::time::Instant::now();
+        let mut seen = self.seen.lock().unwrap();
+        while let Some((received, _)) = seen.order.front() {
+            if now.duration_since(*received) < self.window {
+                break;
+            }
+            if let Some((_, expired)) = seen.order.pop_front() {
+                seen.ids.remove(&expired);
+            }
+        }
+        if !seen.ids.insert(id.to_string()) {
+            return false;
+        }
+        seen.order.push_back((now, id.to_string()));
+        true
+    }
 }
 
 impl std::fmt::Debug for HostBridge {
@@ -276,6 +793,35 @@ impl HostBridge {
         &self.rpc_client
     }
 
+    /// Returns a receiver of events sent after this call
+    pub fn subscribe_events(&self) -> tokio::sync::broadcast::Receiver<ProviderEvent> {
+        self.events.subscribe()
+    }
+
+    /// Returns true if the provider's nats connection has used up its reconnect attempts
+    pub fn is_connection_lost(&self) -> bool {
+        self.reconnect_limit.is_lost()
+    }
+
+    /// Waits until the provider's nats connection is lost.
+    /// Never returns if the connection has no reconnect limit.
+    pub(crate) async fn connection_lost(&self) {
+        let mut events = self.subscribe_events();
+        if self.is_connection_lost() {
+            return;
+        }
+        loop {
+            match events.recv().await {
+                Ok(ProviderEvent::ConnectionLost) => return,
+                Ok(_) => {}
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => {
+                    futures::future::pending::<()>().await
+                }
+            }
+        }
+    }
+
     // parse incoming subscription message
     // if it fails deserialization, we can't really respond;
     // so log the error
@@ -297,16 +843,85 @@ impl HostBridge {
         }
     }
 
-    /// Stores actor with link definition
+    /// Stores actor with link definition, replacing any previous link for the actor.
+    /// With [ConnectionStrategy::PerLink], this also opens the link's nats connection,
+    /// if it doesn't have one yet.
+    /// If that fails, the error is logged and the link uses the provider's connection.
     pub async fn put_link(&self, ld: LinkDefinition) {
+        if self.options.connection_strategy == ConnectionStrategy::PerLink
+            && !self.link_clients.read().await.contains_key(&ld.actor_id)
+        {
+            match self.connect_link_client(&ld.actor_id).await {
+                Ok(client) => {
+                    let mut clients = self.link_clients.write().await;
+                    clients.insert(ld.actor_id.to_string(), client);
+                }
+                Err(error) => {
+                    error!(%error, actor_id = %ld.actor_id, "nats connection for link failed, link will use shared connection");
+                }
+            }
+        }
         let mut update = self.links.write().await;
         update.insert(ld.actor_id.to_string(), ld);
     }
 
-    /// Deletes link
+    /// Checks that the link is for the provider's [contract](ProviderOptions::contract_id), if it declared one
+    pub(crate) fn check_link_contract(&self, ld: &LinkDefinition) -> RpcResult<()> {
+        match &self.options.contract_id {
+            Some(contract_id) => ld.matches_contract(contract_id),
+            None => Ok(()),
+        }
+    }
+
+    /// Deletes link, and closes its nats connection if it had one
     pub async fn delete_link(&self, actor_id: &str) {
         let mut update = self.links.write().await;
         update.remove(actor_id);
+        drop(update);
+        let client = self.link_clients.write().await.remove(actor_id);
+        if let Some(client) = client {
+            // the connection closes when the last handle is dropped; flush pending messages first
+            if let Err(error) = client.client().flush().await {
+                warn!(%error, %actor_id, "flushing link connection");
+            }
+        }
+    }
+
+    /// Returns the dedicated rpc client for the link, if there is one.
+    /// Links only have their own client with [ConnectionStrategy::PerLink]
+    pub async fn link_rpc_client(&self, actor_id: &str) -> Option<RpcClient> {
+        self.link_clients.read().await.get(actor_id).cloned()
+    }
+
+    async fn connect_link_client(&self, actor_id: &str) -> RpcResult<RpcClient> {
+        let name = format!(
+            "{}:{}",
+            self.options.connection_name_or_default(&self.host_data),
+            actor_id
+        );
+        let limit = ReconnectLimit::new(self.options.max_reconnects);
+        let (nc, rtt) = self
+            .host_data
+            .nats_connect_measured(
+                &name,
+                self.options.reconnect_max_backoff,
+                &limit,
+                self.options.connect_timeout,
+            )
+            .await?;
+        let mut client = RpcClient::new_client(
+            nc,
+            self.host_data.host_id.clone(),
+            self.host_data.default_rpc_timeout_ms.map(Duration::from_millis),
+            self.key.clone(),
+        );
+        client.set_connect_rtt(Some(rtt));
+        if let Some(tap) = &self.options.wire_tap {
+            client.set_wire_tap(tap.clone());
+        }
+        client.set_debug_logging(self.options.debug_logging);
+        client.set_reconnect_limit(limit);
+        Ok(client)
     }
 
     /// Returns true if the actor is linked
@@ -342,23 +957,105 @@ impl HostBridge {
             tokio::task::spawn(self.subscribe_link_del(provider.clone(), shutdown_tx.subscribe())),
             tokio::task::spawn(self.subscribe_shutdown(provider.clone(), shutdown_tx.clone())),
             // subscribe to health last, after receivers are set up
+            tokio::task::spawn(
+                self.subscribe_aggregate_health(provider.clone(), shutdown_tx.subscribe()),
+            ),
             tokio::task::spawn(self.subscribe_health(provider, shutdown_tx.subscribe())),
         ])
     }
 
+    /// Subscribes to the provider's host subjects, as [connect](HostBridge::connect) does.
+    /// Once all the subscriptions are made, calls the provider's [ready](ProviderHandler::ready)
+    /// hook and sends [ProviderEvent::Ready]. If a subscription fails, the provider isn't signalled.
+    pub(crate) async fn connect_and_signal_ready<P>(
+        &'static self,
+        provider: P,
+        shutdown_tx: &tokio::sync::broadcast::Sender<bool>,
+        lattice: &str,
+    ) -> JoinAll<tokio::task::JoinHandle<RpcResult<()>>>
+    where
+        P: ProviderDispatch + Send + Sync + Clone + 'static,
+    {
+        let mut progress = self.host_subscriptions.subscribe();
+        let join = self.connect(provider.clone(), shutdown_tx, lattice).await;
+        loop {
+            let (subscribed, failed) = *progress.borrow_and_update();
+            if failed > 0 {
+                error!("not signalling ready: a host subscription failed");
+                return join;
+            }
+            if subscribed == HOST_SUBSCRIPTIONS {
+                break;
+            }
+            if progress.changed().await.is_err() {
+                return join;
+            }
+        }
+        provider.ready().await;
+        let _ = self.events.send(ProviderEvent::Ready);
+        join
+    }
+
+    /// Subscribes to a host subject, counting the subscription for
+    /// [connect_and_signal_ready](HostBridge::connect_and_signal_ready)
+    async fn subscribe_host(
+        &self,
+        subject: String,
+        queue_group: Option<String>,
+    ) -> RpcResult<crate::provider_transport::Subscription> {
+        let sub = self.transport.subscribe(subject, queue_group).await;
+        self.host_subscriptions.send_modify(|(subscribed, failed)| match &sub {
+            Ok(_) => *subscribed += 1,
+            Err(_) => *failed += 1,
+        });
+        sub
+    }
+
+    /// Cancels outstanding requests of the bridge's rpc clients - called at shutdown
+    pub(crate) async fn abort_requests(&self) {
+        self.rpc_client().abort_all();
+        for client in self.link_clients.read().await.values() {
+            client.abort_all();
+        }
+    }
+
     /// flush nats - called before main process exits
     pub(crate) async fn flush(&self) {
-        if let Err(error) = self.inner.rpc_client.client().flush().await {
+        if let Err(error) = self.inner.transport.flush().await {
             error!(%error, "flushing nats connection");
         }
     }
 
+    /// Sends the response to an rpc message, compressed if the policy applies to its size,
+    /// and with a checksum of the bytes sent if `checksum` is true
+    async fn reply_invocation(
+        &self,
+        reply_to: String,
+        response: InvocationResponse,
+        lattice: &str,
+        mut headers: Option<crate::async_nats::HeaderMap>,
+        checksum: bool,
+        compression: CompressionPolicy,
+    ) -> RpcResult<()> {
+        let mut buf = self.rpc_client.invocation_response_bytes(response, lattice).await?;
+        if compression.applies(buf.len()) {
+            buf = crate::rpc_client::compress_payload(&buf);
+            headers
+                .get_or_insert_with(crate::async_nats::HeaderMap::new)
+                .insert(ENCODING_HEADER, DEFLATE_ENCODING);
+        }
+        if checksum {
+            headers.get_or_insert_with(crate::async_nats::HeaderMap::new).insert(
+                CHECKSUM_HEADER,
+                crate::rpc_client::payload_checksum(&buf).as_str(),
+            );
+        }
+        self.transport.reply(reply_to, headers, buf).await
+    }
+
     /// Returns the nats rpc topic for capability providers
     pub fn provider_rpc_topic(&self) -> String {
-        format!(
-            "wasmbus.rpc.{}.{}.{}",
-            &self.lattice_prefix, &self.host_data.provider_key, self.host_data.link_name
-        )
+        self.host_data.rpc_subject()
     }
 
     /// Subscribe to a nats topic for rpc messages.
@@ -374,27 +1071,32 @@ impl HostBridge {
         P: ProviderDispatch + Send + Sync + Clone + 'static,
     {
         let mut sub = self
-            .rpc_client
-            .client()
-            .queue_subscribe(
+            .subscribe_host(
                 self.provider_rpc_topic(),
-                RPC_SUBSCRIPTION_QUEUE_GROUP.to_string(),
+                Some(RPC_SUBSCRIPTION_QUEUE_GROUP.to_string()),
             )
-            .await
-            .map_err(|e| RpcError::Nats(e.to_string()))?;
+            .await?;
         let this = self.clone();
+        let catch_panics = self.options.catch_panics;
+        // one-way operations get no reply, even if the sender asked for one
+        let one_way: Arc<std::collections::HashSet<&'static str>> = Arc::new(
+            provider
+                .operation_info()
+                .into_iter()
+                .filter(|op| op.one_way)
+                .map(|op| op.name)
+                .collect(),
+        );
         tokio::spawn(async move {
             loop {
                 tokio::select! {
-                    _ = quit.recv() => {
-                        let _ = sub.unsubscribe().await;
-                        break;
-                    },
+                    _ = quit.recv() => break,
                     nats_msg = sub.next() => {
                         let msg = if let Some(msg) = nats_msg { msg } else { break; };
                         let this = this.clone();
                         let provider = provider.clone();
                         let lattice = lattice.clone();
+                        let one_way = one_way.clone();
                         let span = tracing::debug_span!("rpc",
                             operation = tracing::field::Empty,
                             lattice_id = tracing::field::Empty,
@@ -409,18 +1111,22 @@ impl HostBridge {
                         tokio::spawn( async move {
                             #[cfg(feature = "otel")]
                             crate::otel::attach_span_context(&msg);
-                            match crate::common::deserialize::<Invocation>(&msg.payload) {
+                            this.rpc_client().tap_recv(&msg.subject, &msg.payload);
+                            // a sender that checksums its request gets a checksummed response
+                            let checksum = msg.headers.as_ref().map_or(false, |h| h.get(CHECKSUM_HEADER).is_some());
+                            let checked = crate::rpc_client::verify_checksum(msg.headers.as_ref(), &msg.payload);
+                            match checked.and_then(|_| crate::common::deserialize::<Invocation>(&msg.payload)) {
                                 Ok(inv) => {
                                     let current = tracing::Span::current();
-                                    current.record("operation", &tracing::field::display(&inv.operation));
-                                    current.record("lattice_id", &tracing::field::display(&lattice));
-                                    current.record("actor_id", &tracing::field::display(&inv.origin));
-                                    current.record("inv_id", &tracing::field::display(&inv.id));
-                                    current.record("host_id", &tracing::field::display(&inv.host_id));
-                                    current.record("provider_id", &tracing::field::display(&inv.target.public_key));
-                                    current.record("contract_id", &tracing::field::display(&inv.target.contract_id));
-                                    current.record("link_name", &tracing::field::display(&inv.target.link_name));
-                                    current.record("payload_size", &tracing::field::display(&inv.content_length.unwrap_or_default()));
+                                    current.record("operation", tracing::field::display(&inv.operation));
+                                    current.record("lattice_id", tracing::field::display(&lattice));
+                                    current.record("actor_id", tracing::field::display(&inv.origin));
+                                    current.record("inv_id", tracing::field::display(&inv.id));
+                                    current.record("host_id", tracing::field::display(&inv.host_id));
+                                    current.record("provider_id", tracing::field::display(&inv.target.public_key));
+                                    current.record("contract_id", tracing::field::display(&inv.target.contract_id));
+                                    current.record("link_name", tracing::field::display(&inv.target.link_name));
+                                    current.record("payload_size", tracing::field::display(&inv.content_length.unwrap_or_default()));
                                     #[cfg(feature = "prometheus")]
                                     {
                                         if let Some(len) = inv.content_length {
@@ -429,18 +1135,34 @@ impl HostBridge {
                                         this.rpc_client.stats.rpc_recv.inc();
                                     }
                                     let inv_id = inv.id.clone();
-                                    let resp = match this.handle_rpc(provider.clone(), inv).in_current_span().await {
+                                    let reply_to = if one_way.contains(inv.operation.as_str()) { None } else { msg.reply };
+                                    let accepts_compression = msg.headers.as_ref()
+                                        .and_then(|h| h.get(ACCEPT_ENCODING_HEADER))
+                                        .map_or(false, |v| v.as_str().split(',').any(|e| e.trim() == DEFLATE_ENCODING));
+                                    let compression = if accepts_compression {
+                                        this.options.response_compression(&inv.operation)
+                                    } else {
+                                        CompressionPolicy::Never
+                                    };
+                                    let mut headers = None;
+                                    let resp = match this.handle_rpc(provider.clone(), inv, msg.headers.as_ref(), catch_panics).in_current_span().await {
                                         Err(error) => {
                                             error!(%error, "Invocation failed");
                                             #[cfg(feature = "prometheus")]
                                             this.rpc_client.stats.rpc_recv_err.inc();
                                             InvocationResponse{
                                                 invocation_id: inv_id,
+                                                msg: crate::error::error_response_msg(&error),
                                                 error: Some(error.to_string()),
                                                 ..Default::default()
                                             }
                                         },
-                                        Ok(bytes) => {
+                                        Ok(None) => {
+                                            debug!(%inv_id, "dropping redelivered invocation");
+                                            return;
+                                        }
+                                        Ok(Some(DispatchResult { body: bytes, headers: reply_headers })) => {
+                                            headers = to_header_map(&reply_headers);
                                             #[cfg(feature = "prometheus")]
                                             this.rpc_client.stats.rpc_recv_resp_bytes.inc_by(bytes.len() as u64);
                                             InvocationResponse{
@@ -451,10 +1173,10 @@ impl HostBridge {
                                             }
                                         }
                                     };
-                                    if let Some(reply) = msg.reply {
+                                    if let Some(reply) = reply_to {
                                         // send reply
-                                        if let Err(error) = this.rpc_client()
-                                            .publish_invocation_response(reply, resp, &lattice).in_current_span().await {
+                                        if let Err(error) = this
+                                            .reply_invocation(reply, resp, &lattice, headers, checksum, compression).in_current_span().await {
                                             error!(%error, "rpc sending response");
                                         }
                                     }
@@ -462,12 +1184,15 @@ impl HostBridge {
                                 Err(error) => {
                                     error!(%error, "invalid rpc message received (not deserializable)");
                                     if let Some(reply) = msg.reply {
-                                        if let Err(e) = this.rpc_client().publish_invocation_response(reply,
+                                        if let Err(e) = this.reply_invocation(reply,
                                             InvocationResponse{
                                                 error: Some(format!("deser error: {error}")),
                                                 ..Default::default()
                                             },
-                                            &lattice
+                                            &lattice,
+                                            None,
+                                            checksum,
+                                            CompressionPolicy::Never,
                                         ).in_current_span().await {
                                             error!(error = %e, "unable to publish invocation response error");
                                         }
@@ -482,10 +1207,17 @@ impl HostBridge {
         Ok(())
     }
 
-    async fn handle_rpc<P>(&self, provider: P, inv: Invocation) -> Result<Vec<u8>, RpcError>
+    async fn handle_rpc<P>(
+        &self,
+        provider: P,
+        inv: Invocation,
+        headers: Option<&crate::async_nats::HeaderMap>,
+        catch_panics: bool,
+    ) -> RpcResult<Option<DispatchResult>>
     where
         P: ProviderDispatch + Send + Sync + Clone + 'static,
     {
+        let received_at = crate::Timestamp::now();
         let lattice = &self.host_data.lattice_rpc_prefix;
         #[cfg(feature = "prometheus")]
         {
@@ -494,22 +1226,113 @@ impl HostBridge {
             }
             self.rpc_client.stats.rpc_recv.inc();
         }
+        let header = |name: &str| headers.and_then(|h| h.get(name)).map(|v| v.as_str().to_string());
+        crate::rpc_client::check_protocol_version(
+            headers.and_then(|h| h.get(PROTOCOL_VERSION_HEADER)).map(|v| v.as_str()),
+        )?;
+        // a request that queued past its expiry has no one waiting for the response
+        let deadline = header(EXPIRES_HEADER).and_then(|v| parse_expires_header(&v));
+        if let Some(deadline) = deadline {
+            let now = crate::Timestamp::now();
+            if deadline < now {
+                let late = Duration::from_nanos((now.as_nanos() - deadline.as_nanos()) as u64);
+                return Err(RpcError::DeadlineExceeded(format!(
+                    "{} received {:?} after it expired",
+                    &inv.operation, late
+                )));
+            }
+        }
         let inv = self.rpc_client().dechunk(inv, lattice).await?;
         let (inv, claims) = self.rpc_client.validate_invocation(inv).await?;
         self.validate_provider_invocation(&inv, &claims).await?;
-        provider
-            .dispatch(
-                &Context {
-                    actor: Some(inv.origin.public_key.clone()),
-                    ..Default::default()
-                },
-                Message {
-                    method: &inv.operation,
-                    arg: Cow::from(inv.msg),
-                },
-            )
-            .instrument(tracing::debug_span!("dispatch", public_key = %inv.origin.public_key, operation = %inv.operation))
-            .await
+        // only a validated invocation is recorded, so a forged one can't shadow the real one
+        if let Some(recent) = &self.recent_invocations {
+            if !inv.id.is_empty() && !recent.first_delivery(&inv.id) {
+                return Ok(None);
+            }
+        }
+        let mut ctx = invocation_context(&inv, claims);
+        ctx.idempotency_key = header(IDEMPOTENCY_KEY_HEADER);
+        ctx.deadline = deadline;
+        ctx.priority = header(PRIORITY_HEADER)
+            .map(|v| Priority::from_header(&v))
+            .unwrap_or_default();
+        ctx.schema_hash = header(SCHEMA_HASH_HEADER).and_then(|v| u64::from_str_radix(&v, 16).ok());
+        ctx.sent_at = header(SENT_AT_HEADER).and_then(|v| parse_expires_header(&v));
+        ctx.received_at = Some(received_at);
+        if let Some(headers) = headers {
+            ctx.headers = headers
+                .iter()
+                .flat_map(|(name, values)| {
+                    values.iter().map(move |v| (name.to_string(), v.to_string()))
+                })
+                .collect();
+            ctx.headers.sort();
+        }
+        ctx.request_format = header(FORMAT_HEADER).and_then(|v| MessageFormat::from_name(&v));
+        ctx.reply_format = match header(ACCEPT_FORMATS_HEADER) {
+            Some(v) => Some(crate::common::negotiate_format(
+                &v,
+                &self.options.message_formats,
+            )),
+            None => ctx
+                .request_format
+                .filter(|format| self.options.message_formats.contains(format)),
+        };
+        if let (Some(skew), Some(limit)) = (ctx.apparent_skew(), self.options.clock_skew_warning) {
+            if skew > limit {
+                warn!(origin = %inv.origin.public_key, ?skew, "clock skew with {} exceeds {:?}", inv.origin.short_id(), limit);
+            }
+        }
+        let debug_logging = self.rpc_client.debug_logging();
+        if let Some(max) = debug_logging {
+            crate::rpc_client::log_rpc("received", &inv.operation, None, &inv.msg, max);
+        }
+        if inv.operation == VERSION_OPERATION {
+            let info = VersionInfo {
+                weld_version: crate::WELD_CRATE_VERSION.to_string(),
+                provider_version: provider.provider_version(),
+            };
+            return serialize(&info).map(|body| Some(DispatchResult::from(body)));
+        }
+        let started = std::time::Instant::now();
+        let message = Message {
+            method: &inv.operation,
+            arg: Cow::from(inv.msg),
+        };
+        let span = tracing::debug_span!("dispatch", public_key = %inv.origin.public_key, operation = %inv.operation);
+        let dispatch = async {
+            if catch_panics {
+                dispatch_catching_panics(&provider, &ctx, message).instrument(span).await
+            } else {
+                provider.dispatch_with_metadata(&ctx, message).instrument(span).await
+            }
+        };
+        let dispatch = async {
+            match self.options.handler_timeout {
+                Some(limit) => tokio::time::timeout(limit, dispatch).await.unwrap_or_else(|_| {
+                    Err(RpcError::DeadlineExceeded(SERVER_TIMEOUT.to_string()))
+                }),
+                None => dispatch.await,
+            }
+        };
+        #[cfg(feature = "alloc-tracking")]
+        let rc = match &self.options.metrics_sink {
+            Some(sink) => {
+                let (rc, peak) = crate::alloc_tracking::track_allocations(dispatch).await;
+                sink.record_allocation(&inv.operation, peak);
+                rc
+            }
+            None => dispatch.await,
+        };
+        #[cfg(not(feature = "alloc-tracking"))]
+        let rc = dispatch.await;
+        let rc = rc.and_then(|result| check_reply_headers(&result.headers).map(|_| result));
+        if let (Some(max), Ok(result)) = (debug_logging, &rc) {
+            let latency = Some(started.elapsed());
+            crate::rpc_client::log_rpc("replied", &inv.operation, latency, &result.body, max);
+        }
+        rc.map(Some).map_err(|e| e.with_context(&inv.operation))
     }
 
     async fn subscribe_shutdown<P>(
@@ -520,17 +1343,9 @@ impl HostBridge {
     where
         P: ProviderDispatch + Send + Sync + 'static,
     {
-        let shutdown_topic = format!(
-            "wasmbus.rpc.{}.{}.{}.shutdown",
-            &self.lattice_prefix, &self.host_data.provider_key, self.host_data.link_name
-        );
+        let shutdown_topic = format!("{}.shutdown", self.provider_rpc_topic());
         debug!("subscribing for shutdown : {}", &shutdown_topic);
-        let mut sub = self
-            .rpc_client()
-            .client()
-            .subscribe(shutdown_topic)
-            .await
-            .map_err(|e| RpcError::Nats(e.to_string()))?;
+        let mut sub = self.subscribe_host(shutdown_topic, None).await?;
 
         loop {
             let msg = sub.next().await;
@@ -555,11 +1370,11 @@ impl HostBridge {
                         error!(%error, "got error during provider shutdown processing");
                     }
                     let data = b"shutting down".to_vec();
-                    if let Err(error) = self.rpc_client().publish(reply_to, data).await {
+                    if let Err(error) = self.transport.reply(reply_to, None, data).await {
                         warn!(%error, "failed to send shutdown ack");
                     }
                     // unsubscribe from shutdown topic
-                    let _ = sub.unsubscribe().await;
+                    drop(sub);
 
                     // send shutdown signal to all listeners: quit all subscribers and signal main thread to quit
                     if let Err(e) = shutdown_tx.send(true) {
@@ -578,17 +1393,9 @@ impl HostBridge {
     where
         P: ProviderDispatch + Send + Sync + Clone + 'static,
     {
-        let ldput_topic = format!(
-            "wasmbus.rpc.{}.{}.{}.linkdefs.put",
-            &self.lattice_prefix, &self.host_data.provider_key, &self.host_data.link_name
-        );
+        let ldput_topic = format!("{}.linkdefs.put", self.provider_rpc_topic());
 
-        let mut sub = self
-            .rpc_client()
-            .client()
-            .subscribe(ldput_topic)
-            .await
-            .map_err(|e| RpcError::Nats(e.to_string()))?;
+        let mut sub = self.subscribe_host(ldput_topic, None).await?;
         let (this, provider) = (self.clone(), provider.clone());
         process_until_quit!(sub, quit, msg, {
             this.handle_link_put(msg, &provider).await
@@ -603,12 +1410,27 @@ impl HostBridge {
     {
         if let Some(ld) = self.parse_msg::<LinkDefinition>(&msg, "link.put") {
             let span = tracing::Span::current();
-            span.record("actor_id", &tracing::field::display(&ld.actor_id));
-            span.record("provider_id", &tracing::field::display(&ld.provider_id));
-            span.record("contract_id", &tracing::field::display(&ld.contract_id));
-            span.record("link_name", &tracing::field::display(&ld.link_name));
-            if self.is_linked(&ld.actor_id).await {
-                warn!("Ignoring duplicate link put");
+            span.record("actor_id", tracing::field::display(&ld.actor_id));
+            span.record("provider_id", tracing::field::display(&ld.provider_id));
+            span.record("contract_id", tracing::field::display(&ld.contract_id));
+            span.record("link_name", tracing::field::display(&ld.link_name));
+            if let Err(error) = self.check_link_contract(&ld) {
+                error!(%error, "put_link rejected");
+            } else if self.is_linked(&ld.actor_id).await {
+                info!("Updating link of linked actor");
+                match provider.update_config(&ld).await {
+                    Ok(true) => {
+                        self.put_link(ld).await;
+                    }
+                    Ok(false) => {
+                        warn!(link = ?ld.redacted(), "update_config denied, actor is no longer linked");
+                        self.delete_link(&ld.actor_id).await;
+                    }
+                    Err(error) => {
+                        error!(%error, link = ?ld.redacted(), "update_config failed, actor is no longer linked");
+                        self.delete_link(&ld.actor_id).await;
+                    }
+                }
             } else {
                 info!("Linking actor with provider");
                 match provider.put_link(&ld).await {
@@ -617,10 +1439,10 @@ impl HostBridge {
                     }
                     Ok(false) => {
                         // authorization failed or parameters were invalid
-                        warn!("put_link denied");
+                        warn!(link = ?ld.redacted(), "put_link denied");
                     }
                     Err(error) => {
-                        error!(%error, "put_link failed");
+                        error!(%error, link = ?ld.redacted(), "put_link failed");
                     }
                 }
             }
@@ -632,17 +1454,9 @@ impl HostBridge {
         P: ProviderDispatch + Send + Sync + Clone + 'static,
     {
         // Link Delete
-        let link_del_topic = format!(
-            "wasmbus.rpc.{}.{}.{}.linkdefs.del",
-            &self.lattice_prefix, &self.host_data.provider_key, &self.host_data.link_name
-        );
+        let link_del_topic = format!("{}.linkdefs.del", self.provider_rpc_topic());
         debug!(topic = %link_del_topic, "subscribing for link del");
-        let mut sub = self
-            .rpc_client()
-            .client()
-            .subscribe(link_del_topic.clone())
-            .await
-            .map_err(|e| RpcError::Nats(e.to_string()))?;
+        let mut sub = self.subscribe_host(link_del_topic.clone(), None).await?;
         let (this, provider) = (self.clone(), provider.clone());
         process_until_quit!(sub, quit, msg, {
             let span = tracing::trace_span!("subscribe_link_del", topic = %link_del_topic);
@@ -659,17 +1473,9 @@ impl HostBridge {
     where
         P: ProviderDispatch + Send + Sync + 'static,
     {
-        let topic = format!(
-            "wasmbus.rpc.{}.{}.{}.health",
-            &self.lattice_prefix, &self.host_data.provider_key, &self.host_data.link_name
-        );
+        let topic = format!("{}.health", self.provider_rpc_topic());
 
-        let mut sub = self
-            .rpc_client()
-            .client()
-            .subscribe(topic)
-            .await
-            .map_err(|e| RpcError::Nats(e.to_string()))?;
+        let mut sub = self.subscribe_host(topic, None).await?;
         let this = self.clone();
         process_until_quit!(sub, quit, msg, {
             let arg = HealthCheckRequest {};
@@ -680,26 +1486,86 @@ impl HostBridge {
                     message: Some(e.to_string()),
                 }
             });
-            let buf = if this.host_data.is_test() {
-                Ok(serde_json::to_vec(&resp).unwrap())
-            } else {
-                serialize(&resp)
-            };
-            match buf {
-                Ok(t) => {
-                    if let Some(reply_to) = msg.reply {
-                        if let Err(e) = this.rpc_client().publish(reply_to, t).await {
-                            error!(error = %e, "failed sending health check response");
-                        }
+            this.reply_health(msg.reply, &resp).await;
+        });
+        Ok(())
+    }
+
+    /// Subscribe to the aggregate health topic, which reports the combined
+    /// health of the provider and all of its links. See [HostBridge::aggregate_health]
+    async fn subscribe_aggregate_health<P>(
+        &self,
+        provider: P,
+        mut quit: QuitSignal,
+    ) -> RpcResult<()>
+    where
+        P: ProviderDispatch + Send + Sync + 'static,
+    {
+        let topic = format!("{}.health.aggregate", self.provider_rpc_topic());
+
+        let mut sub = self.subscribe_host(topic, None).await?;
+        let this = self.clone();
+        process_until_quit!(sub, quit, msg, {
+            let resp = this.aggregate_health(&provider).await;
+            this.reply_health(msg.reply, &resp).await;
+        });
+        Ok(())
+    }
+
+    async fn reply_health(&self, reply_to: Option<String>, resp: &HealthCheckResponse) {
+        let buf = if self.host_data.is_test() {
+            Ok(serde_json::to_vec(resp).unwrap())
+        } else {
+            serialize(resp)
+        };
+        match buf {
+            Ok(t) => {
+                if let Some(reply_to) = reply_to {
+                    if let Err(e) = self.transport.reply(reply_to, None, t).await {
+                        error!(error = %e, "failed sending health check response");
                     }
                 }
-                Err(e) => {
-                    // extremely unlikely that InvocationResponse would fail to serialize
-                    error!(error = %e, "failed serializing HealthCheckResponse");
-                }
             }
-        });
-        Ok(())
+            Err(e) => {
+                // extremely unlikely that InvocationResponse would fail to serialize
+                error!(error = %e, "failed serializing HealthCheckResponse");
+            }
+        }
+    }
+
+    /// Returns the combined health of the provider and each of its links.
+    /// The result is unhealthy if the provider, or any link, is unhealthy,
+    /// and the message lists the reason for each failure.
+    pub async fn aggregate_health<P>(&self, provider: &P) -> HealthCheckResponse
+    where
+        P: ProviderHandler + ?Sized,
+    {
+        let arg = HealthCheckRequest {};
+        let mut failures = Vec::new();
+        let mut record = |name: &str, resp: RpcResult<HealthCheckResponse>| match resp {
+            Ok(HealthCheckResponse { healthy: true, .. }) => {}
+            Ok(HealthCheckResponse { message, .. }) => {
+                failures.push(format!("{}: {}", name, message.unwrap_or_default()))
+            }
+            Err(e) => failures.push(format!("{name}: {e}")),
+        };
+        record("provider", provider.health_request(&arg).await);
+        let links: Vec<LinkDefinition> = self.links.read().await.values().cloned().collect();
+        for ld in links.iter() {
+            record(
+                &format!("link {}", &ld.actor_id),
+                provider.link_health_request(ld, &arg).await,
+            );
+        }
+        if failures.is_empty() {
+            HealthCheckResponse { healthy: true, message: None }
+        } else {
+            failures.sort();
+            HealthCheckResponse {
+                healthy: false,
+                message: Some(failures.join("; ")),
+            }
+        }
     }
 
     /// extra validation performed by providers
@@ -729,6 +1595,64 @@ impl HostBridge {
     }
 }
 
+/// Dispatches the message, converting a panic in the handler into an RpcError
+async fn dispatch_catching_panics<P>(
+    provider: &P,
+    ctx: &Context,
+    message: Message<'_>,
+) -> RpcResult<DispatchResult>
+where
+    P: MessageDispatch + Send + Sync,
+{
+    match AssertUnwindSafe(provider.dispatch_with_metadata(ctx, message))
+        .catch_unwind()
+        .await
+    {
+        Ok(result) => result,
+        Err(panic) => {
+            let reason = if let Some(s) = panic.downcast_ref::<&str>() {
+                s.to_string()
+            } else if let Some(s) = panic.downcast_ref::<String>() {
+                s.clone()
+            } else {
+                "unknown".to_string()
+            };
+            error!(%reason, "provider handler panicked");
+            Err(RpcError::Other(format!("handler panicked: {reason}")))
+        }
+    }
+}
+
+/// Returns `RpcError::InvalidParameter` if a header returned by a handler can't be
+/// written to the reply
+fn check_reply_headers(headers: &[(String, String)]) -> RpcResult<()> {
+    headers
+        .iter()
+        .try_for_each(|(name, value)| crate::rpc_client::check_header_syntax(name, value))
+}
+
+/// Converts the headers returned by a handler to nats headers for the reply
+fn to_header_map(headers: &[(String, String)]) -> Option<crate::async_nats::HeaderMap> {
+    if headers.is_empty() {
+        return None;
+    }
+    let mut map = crate::async_nats::HeaderMap::new();
+    for (name, value) in headers {
+        map.insert(name.as_str(), value.as_str());
+    }
+    Some(map)
+}
+
+/// Builds the Context for an inbound invocation that has passed validation
+fn invocation_context(inv: &Invocation, claims: Claims<jwt::Invocation>) -> Context {
+    Context {
+        actor: Some(inv.origin.public_key.clone()),
+        target: Some(inv.target.clone()),
+        claims: Some(claims),
+        ..Default::default()
+    }
+}
+
 pub struct ProviderTransport<'send> {
     pub bridge: &'send HostBridge,
     pub ld: &'send LinkDefinition,
@@ -783,19 +1707,648 @@ impl<'send> Transport for ProviderTransport<'send> {
         &self,
         _ctx: &Context,
         req: Message<'_>,
-        _opts: Option<SendOpts>,
+        opts: Option<SendOpts>,
     ) -> RpcResult<Vec<u8>> {
         let origin = self.ld.provider_entity();
         let target = self.ld.actor_entity();
         let timeout = Duration::from_millis(self.timeout_ms.load(Ordering::Relaxed));
-        let lattice = &self.bridge.lattice_prefix;
-        self.bridge
-            .rpc_client()
-            .send_timeout(origin, target, lattice, req, timeout)
-            .await
+        let lattice = self.bridge.lattice_prefix();
+        let link_client = self.bridge.link_rpc_client(&self.ld.actor_id).await;
+        let client = link_client.as_ref().unwrap_or_else(|| self.bridge.rpc_client());
+        match opts {
+            Some(opts) => {
+                client
+                    .send_with_opts(origin, target, lattice, req, &opts, Some(timeout))
+                    .await
+            }
+            None => client.send_timeout(origin, target, lattice, req, timeout).await,
+        }
     }
 
     fn set_timeout(&self, interval: Duration) {
         self.timeout_ms.store(interval.as_millis() as u64, Ordering::Relaxed);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        borrow::Cow,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+
+    use super::{
+        dispatch_catching_panics, invocation_context, to_header_map, HostBridge, ProviderConfig,
+        ProviderDispatch, ProviderHandler, ProviderOptions, EXPIRES_HEADER,
+    };
+    use crate::{
+        async_nats::ConnectOptions,
+        common::{Context, DispatchResult, Message, MessageDispatch},
+        core::{
+            HealthCheckRequest, HealthCheckResponse, HostData, Invocation, LinkDefinition,
+            WasmCloudEntity,
+        },
+        error::{RpcError, RpcResult},
+        provider_main::initialize_provider,
+        wascap::{
+            jwt,
+            prelude::{Claims, KeyPair},
+        },
+    };
+
+    #[test]
+    fn context_has_invocation_claims() {
+        let host_key = KeyPair::new_server();
+        let origin = WasmCloudEntity::new_actor("MACTOR").unwrap();
+        let target = WasmCloudEntity::new_provider("wasmcloud:thing", "default").unwrap();
+        let claims = Claims::<jwt::Invocation>::new(
+            host_key.public_key(),
+            "inv-1".to_string(),
+            &target.url(),
+            &origin.url(),
+            "hash",
+        );
+        let inv = Invocation {
+            origin,
+            target,
+            operation: "Thing.Do".to_string(),
+            encoded_claims: claims.encode(&host_key).unwrap(),
+            ..Default::default()
+        };
+        // claims as they would be after decoding and validation
+        let claims = Claims::<jwt::Invocation>::decode(&inv.encoded_claims).unwrap();
+
+        let ctx = invocation_context(&inv, claims);
+        assert_eq!(ctx.actor.as_deref(), Some("MACTOR"));
+        let claims = ctx.claims().expect("claims on context");
+        assert_eq!(claims.issuer, host_key.public_key());
+        assert_eq!(claims.subject, "inv-1");
+        let metadata = claims.metadata.as_ref().unwrap();
+        assert_eq!(metadata.origin_url, inv.origin.url());
+        assert_eq!(metadata.target_url, inv.target.url());
+
+        // contexts created locally don't have claims
+        assert!(crate::common::Context::default().claims().is_none());
+    }
+
+    struct Panicky;
+
+    #[async_trait]
+    impl MessageDispatch for Panicky {
+        async fn dispatch(&self, _ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+            match message.method {
+                "Panicky.Str" => panic!("boom"),
+                "Panicky.String" => panic!("boom {}", message.arg.len()),
+                _ => Ok(message.arg.to_vec()),
+            }
+        }
+    }
+
+    fn msg(method: &'static str) -> Message<'static> {
+        Message { method, arg: Cow::Borrowed(b"abc") }
+    }
+
+    #[tokio::test]
+    async fn panics_become_errors() {
+        let ctx = Context::default();
+        let provider = Panicky;
+
+        let err = dispatch_catching_panics(&provider, &ctx, msg("Panicky.Str"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, RpcError::Other(s) if s == "handler panicked: boom"),
+            "{err}"
+        );
+
+        let err = dispatch_catching_panics(&provider, &ctx, msg("Panicky.String"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, RpcError::Other(s) if s == "handler panicked: boom 3"),
+            "{err}"
+        );
+
+        // the same provider keeps handling requests
+        let ok = dispatch_catching_panics(&provider, &ctx, msg("Panicky.Echo"))
+            .await
+            .unwrap();
+        assert_eq!(ok.body, b"abc".to_vec());
+        assert!(ok.headers.is_empty());
+    }
+
+    /// Dispatcher that returns response headers
+    struct Cached;
+
+    #[async_trait]
+    impl MessageDispatch for Cached {
+        async fn dispatch(&self, ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+            Ok(self.dispatch_with_metadata(ctx, message).await?.body)
+        }
+
+        async fn dispatch_with_metadata(
+            &self,
+            _ctx: &Context,
+            message: Message<'_>,
+        ) -> RpcResult<DispatchResult> {
+            Ok(DispatchResult::from(message.arg.to_vec())
+                .with_header("Cache-Control", "max-age=60")
+                .with_header("Content-Type", "application/json"))
+        }
+    }
+
+    #[tokio::test]
+    async fn reply_headers() {
+        let ctx = Context::default();
+        let result = dispatch_catching_panics(&Cached, &ctx, msg("Cached.Get")).await.unwrap();
+        assert_eq!(result.body, b"abc".to_vec());
+
+        let headers = to_header_map(&result.headers).expect("reply headers");
+        assert_eq!(headers.get("Cache-Control").unwrap().as_str(), "max-age=60");
+        assert_eq!(
+            headers.get("Content-Type").unwrap().as_str(),
+            "application/json"
+        );
+        assert!(to_header_map(&[]).is_none());
+    }
+
+    #[test]
+    fn options_default() {
+        assert!(super::ProviderOptions::default().catch_panics);
+        assert!(!super::ProviderOptions::default().catch_panics(false).catch_panics);
+    }
+
+    /// Provider whose link to actor "MSICK" is unhealthy
+    struct SickLink;
+
+    #[async_trait]
+    impl ProviderHandler for SickLink {
+        async fn link_health_request(
+            &self,
+            ld: &LinkDefinition,
+            _arg: &HealthCheckRequest,
+        ) -> RpcResult<HealthCheckResponse> {
+            Ok(HealthCheckResponse {
+                healthy: ld.actor_id != "MSICK",
+                message: Some("backend unreachable".to_string()),
+            })
+        }
+    }
+
+    /// Bridge with a client that is never connected
+    async fn test_bridge(host_data: &HostData) -> HostBridge {
+        let nc = ConnectOptions::new()
+            .retry_on_initial_connect()
+            .connect("nats://127.0.0.1:1")
+            .await
+            .unwrap();
+        HostBridge::new_with_options(nc, host_data, ProviderOptions::default()).unwrap()
+    }
+
+    fn test_host_data() -> HostData {
+        HostData {
+            host_id: "_TEST_".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn aggregate_health() {
+        let bridge = test_bridge(&test_host_data()).await;
+        let link = |actor_id: &str| LinkDefinition {
+            actor_id: actor_id.to_string(),
+            ..Default::default()
+        };
+
+        bridge.put_link(link("MWELL")).await;
+        let resp = bridge.aggregate_health(&SickLink).await;
+        assert!(resp.healthy);
+
+        bridge.put_link(link("MSICK")).await;
+        let resp = bridge.aggregate_health(&SickLink).await;
+        assert!(!resp.healthy);
+        assert_eq!(resp.message.unwrap(), "link MSICK: backend unreachable");
+    }
+
+    /// Provider whose init waits until released, recording the order of callbacks
+    #[derive(Clone, Default)]
+    struct SlowInit {
+        events: Arc<Mutex<Vec<&'static str>>>,
+        release: Arc<tokio::sync::Notify>,
+        fail: bool,
+    }
+
+    impl SlowInit {
+        fn events(&self) -> Vec<&'static str> {
+            self.events.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl ProviderHandler for SlowInit {
+        async fn init(&self, _host_data: &HostData) -> RpcResult<()> {
+            self.events.lock().unwrap().push("init");
+            self.release.notified().await;
+            self.events.lock().unwrap().push("ready");
+            if self.fail {
+                return Err(RpcError::Other("no database".to_string()));
+            }
+            Ok(())
+        }
+
+        async fn put_link(&self, _ld: &LinkDefinition) -> RpcResult<bool> {
+            self.events.lock().unwrap().push("link");
+            Ok(true)
+        }
+    }
+
+    #[async_trait]
+    impl MessageDispatch for SlowInit {
+        async fn dispatch(&self, _ctx: &Context, _message: Message<'_>) -> RpcResult<Vec<u8>> {
+            self.events.lock().unwrap().push("dispatch");
+            Ok(Vec::new())
+        }
+    }
+
+    impl ProviderDispatch for SlowInit {}
+
+    #[tokio::test]
+    async fn init_completes_first() {
+        let mut host_data = test_host_data();
+        host_data.link_definitions = vec![LinkDefinition {
+            actor_id: "MACTOR".to_string(),
+            ..Default::default()
+        }];
+        let bridge = test_bridge(&host_data).await;
+        let provider = SlowInit::default();
+
+        let init = initialize_provider(&provider, &bridge, &host_data);
+        tokio::pin!(init);
+        // still waiting in init: links are not added, and nothing has been dispatched
+        assert!(tokio::time::timeout(Duration::from_millis(50), &mut init).await.is_err());
+        assert_eq!(provider.events(), vec!["init"]);
+        assert!(!bridge.is_linked("MACTOR").await);
+
+        provider.release.notify_one();
+        init.await.unwrap();
+        assert_eq!(provider.events(), vec!["init", "ready", "link"]);
+        assert!(bridge.is_linked("MACTOR").await);
+
+        // a failed init stops startup before links are added
+        let provider = SlowInit { fail: true, ..Default::default() };
+        provider.release.notify_one();
+        let err = initialize_provider(&provider, &bridge, &host_data).await.unwrap_err();
+        assert!(matches!(err, RpcError::ProviderInit(_)));
+        assert_eq!(provider.events(), vec!["init", "ready"]);
+    }
+
+    #[tokio::test]
+    async fn link_for_other_contract_rejected() {
+        let mut host_data = test_host_data();
+        let link = |actor_id: &str, contract_id: &str| LinkDefinition {
+            actor_id: actor_id.to_string(),
+            contract_id: contract_id.to_string(),
+            ..Default::default()
+        };
+        host_data.link_definitions = vec![
+            link("MRIGHT", "wasmcloud:keyvalue"),
+            link("MWRONG", "wasmcloud:httpserver"),
+        ];
+        let nc = ConnectOptions::new()
+            .retry_on_initial_connect()
+            .connect("nats://127.0.0.1:1")
+            .await
+            .unwrap();
+        let options = ProviderOptions::default().contract_id("wasmcloud:keyvalue".to_string());
+        let bridge = HostBridge::new_with_options(nc, &host_data, options).unwrap();
+        let provider = SlowInit::default();
+        provider.release.notify_one();
+
+        initialize_provider(&provider, &bridge, &host_data).await.unwrap();
+        // the provider's put_link is only called for the matching link
+        assert_eq!(provider.events(), vec!["init", "ready", "link"]);
+        assert!(bridge.is_linked("MRIGHT").await);
+        assert!(!bridge.is_linked("MWRONG").await);
+    }
+
+    #[tokio::test]
+    async fn duplicate_link_put_updates() {
+        let bridge = test_bridge(&test_host_data()).await;
+        let provider = SlowInit::default();
+        let link_put = |version: &str| {
+            let ld = LinkDefinition {
+                actor_id: "MACTOR".to_string(),
+                values: [("version".to_string(), version.to_string())].into_iter().collect(),
+                ..Default::default()
+            };
+            link_put_msg(&ld)
+        };
+
+        bridge.handle_link_put(link_put("1"), &provider).await;
+        bridge.handle_link_put(link_put("2"), &provider).await;
+        // the second put goes to update_config, which defaults to delete_link and put_link
+        assert_eq!(provider.events(), vec!["link", "link"]);
+        assert_eq!(bridge.links.read().await.len(), 1);
+        let ld = bridge.get_link("MACTOR").await.unwrap();
+        assert_eq!(ld.values.get("version").map(String::as_str), Some("2"));
+    }
+
+    /// A link.put message from the host for the link definition
+    fn link_put_msg(ld: &LinkDefinition) -> crate::async_nats::Message {
+        // test host data is sent as json
+        let payload = serde_json::to_vec(ld).unwrap();
+        crate::async_nats::Message {
+            subject: "linkdefs.put".to_string(),
+            reply: None,
+            length: payload.len(),
+            payload: payload.into(),
+            headers: None,
+            status: None,
+            description: None,
+        }
+    }
+
+    /// Provider whose put_link fails for an actor that's already linked,
+    /// and denies or fails links with version "denied" or "broken"
+    #[derive(Clone, Default)]
+    struct LinkOnce {
+        linked: Arc<Mutex<std::collections::HashMap<String, LinkDefinition>>>,
+    }
+
+    #[async_trait]
+    impl ProviderHandler for LinkOnce {
+        async fn put_link(&self, ld: &LinkDefinition) -> RpcResult<bool> {
+            match ld.values.get("version").map(String::as_str) {
+                Some("denied") => return Ok(false),
+                Some("broken") => return Err(RpcError::Other("broken link".to_string())),
+                _ => {}
+            }
+            let mut linked = self.linked.lock().unwrap();
+            if linked.contains_key(&ld.actor_id) {
+                return Err(RpcError::Other(format!(
+                    "{} is already linked",
+                    ld.actor_id
+                )));
+            }
+            linked.insert(ld.actor_id.clone(), ld.clone());
+            Ok(true)
+        }
+
+        async fn delete_link(&self, actor_id: &str) {
+            self.linked.lock().unwrap().remove(actor_id);
+        }
+    }
+
+    #[async_trait]
+    impl MessageDispatch for LinkOnce {
+        async fn dispatch(&self, _ctx: &Context, _message: Message<'_>) -> RpcResult<Vec<u8>> {
+            Ok(Vec::new())
+        }
+    }
+
+    impl ProviderDispatch for LinkOnce {}
+
+    #[tokio::test]
+    async fn default_update_config_relinks() {
+        let bridge = test_bridge(&test_host_data()).await;
+        let provider = LinkOnce::default();
+        let link = |version: &str| LinkDefinition {
+            actor_id: "MACTOR".to_string(),
+            values: [("version".to_string(), version.to_string())].into_iter().collect(),
+            ..Default::default()
+        };
+
+        assert!(provider.put_link(&link("1")).await.unwrap());
+        // the default removes the previous link before putting the new one
+        assert!(provider.update_config(&link("2")).await.unwrap());
+        let linked = provider.linked.lock().unwrap().clone();
+        assert_eq!(linked.len(), 1);
+        assert_eq!(
+            linked["MACTOR"].values.get("version").map(String::as_str),
+            Some("2")
+        );
+
+        // and through the bridge, a repeated put for a linked actor is an update
+        bridge.put_link(link("2")).await;
+        bridge.handle_link_put(link_put_msg(&link("3")), &provider).await;
+        let ld = bridge.get_link("MACTOR").await.unwrap();
+        assert_eq!(ld.values.get("version").map(String::as_str), Some("3"));
+        let linked = provider.linked.lock().unwrap().clone();
+        assert_eq!(
+            linked["MACTOR"].values.get("version").map(String::as_str),
+            Some("3")
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_update_unlinks() {
+        let bridge = test_bridge(&test_host_data()).await;
+        let provider = LinkOnce::default();
+        let link = |version: &str| LinkDefinition {
+            actor_id: "MACTOR".to_string(),
+            values: [("version".to_string(), version.to_string())].into_iter().collect(),
+            ..Default::default()
+        };
+
+        for version in ["denied", "broken"] {
+            bridge.handle_link_put(link_put_msg(&link("1")), &provider).await;
+            assert!(bridge.is_linked("MACTOR").await);
+
+            // the default update_config deleted the link before put_link failed,
+            // so the bridge drops it too, rather than keeping the previous link
+            bridge.handle_link_put(link_put_msg(&link(version)), &provider).await;
+            assert!(!bridge.is_linked("MACTOR").await, "{version}");
+            assert!(provider.linked.lock().unwrap().is_empty(), "{version}");
+        }
+
+        // a later put links the actor again
+        bridge.handle_link_put(link_put_msg(&link("2")), &provider).await;
+        let ld = bridge.get_link("MACTOR").await.unwrap();
+        assert_eq!(ld.values.get("version").map(String::as_str), Some("2"));
+        assert_eq!(provider.linked.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn expired_request_dropped() {
+        let bridge = test_bridge(&test_host_data()).await;
+        let provider = SlowInit::default();
+        let inv = || Invocation {
+            operation: "Test.Op".to_string(),
+            ..Default::default()
+        };
+        let expires = |offset: i128| {
+            let at = crate::Timestamp::now().as_nanos() as i128 + offset;
+            let mut headers = crate::async_nats::HeaderMap::new();
+            headers.insert(EXPIRES_HEADER, at.to_string().as_str());
+            headers
+        };
+
+        let headers = expires(-1_000_000_000);
+        let err = bridge
+            .handle_rpc(provider.clone(), inv(), Some(&headers), true)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, RpcError::DeadlineExceeded(m) if m.starts_with("Test.Op received")));
+        assert!(provider.events().is_empty());
+
+        // an unexpired request passes the check, and fails later because it isn't signed
+        let headers = expires(60_000_000_000);
+        let err = bridge
+            .handle_rpc(provider.clone(), inv(), Some(&headers), true)
+            .await
+            .unwrap_err();
+        assert!(!matches!(err, RpcError::DeadlineExceeded(_)), "{err}");
+    }
+
+    #[tokio::test]
+    async fn incompatible_protocol_rejected() {
+        use crate::rpc_client::{PROTOCOL_VERSION, PROTOCOL_VERSION_HEADER};
+
+        let bridge = test_bridge(&test_host_data()).await;
+        let provider = SlowInit::default();
+        let inv = || Invocation {
+            operation: "Test.Op".to_string(),
+            ..Default::default()
+        };
+        let version = |v: &str| {
+            let mut headers = crate::async_nats::HeaderMap::new();
+            headers.insert(PROTOCOL_VERSION_HEADER, v);
+            headers
+        };
+
+        let headers = version("999");
+        let err = bridge
+            .handle_rpc(provider.clone(), inv(), Some(&headers), true)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, RpcError::Rpc(m) if m.starts_with("unsupported protocol version 999")),
+            "{err}"
+        );
+
+        // a compatible version passes the check, and fails later because it isn't signed
+        let headers = version(&PROTOCOL_VERSION.to_string());
+        let err = bridge
+            .handle_rpc(provider.clone(), inv(), Some(&headers), true)
+            .await
+            .unwrap_err();
+        assert!(
+            !err.to_string().contains("unsupported protocol version"),
+            "{err}"
+        );
+        assert!(provider.events().is_empty());
+    }
+
+    #[tokio::test]
+    async fn exhausted_reconnects() {
+        use super::ProviderEvent;
+        use crate::rpc_client::ReconnectLimit;
+
+        // nothing listens on the port, so every attempt fails
+        let limit = ReconnectLimit::new(Some(2));
+        let nc = limit
+            .apply(
+                ConnectOptions::new().retry_on_initial_connect(),
+                Duration::from_millis(20),
+            )
+            .connect("nats://127.0.0.1:1")
+            .await
+            .unwrap();
+        let bridge = HostBridge::new_bridge(
+            nc,
+            None,
+            limit,
+            None,
+            &test_host_data(),
+            ProviderOptions::default(),
+        )
+        .unwrap();
+        let mut events = bridge.subscribe_events();
+        assert!(!bridge.is_connection_lost());
+
+        tokio::time::timeout(Duration::from_secs(5), bridge.connection_lost())
+            .await
+            .expect("connection lost");
+        assert!(bridge.is_connection_lost());
+        assert_eq!(events.recv().await.unwrap(), ProviderEvent::ConnectionLost);
+
+        // later sends fail right away, instead of waiting for a connection
+        let err = bridge
+            .rpc_client()
+            .publish("test.subject".to_string(), b"x".to_vec())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, RpcError::Nats(m) if m.contains("connection lost after 2 failed")),
+            "{err}"
+        );
+        let err = bridge
+            .rpc_client()
+            .send(
+                WasmCloudEntity::new_actor("MSENDER").unwrap(),
+                WasmCloudEntity::new_actor("MTARGET").unwrap(),
+                "default",
+                Message {
+                    method: "Test.Op",
+                    arg: Cow::Borrowed(b""),
+                },
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(&err, RpcError::Nats(_)), "{err}");
+    }
+
+    #[derive(Debug)]
+    struct Limits {
+        /// invariant: low + high == 100
+        low: u32,
+        high: u32,
+        name: String,
+    }
+
+    fn limits(low: u32) -> Limits {
+        Limits {
+            low,
+            high: 100 - low,
+            name: format!("limits-{low}"),
+        }
+    }
+
+    #[test]
+    fn config_swap_is_consistent() {
+        let config = ProviderConfig::new(limits(0));
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let config = config.clone();
+                let done = done.clone();
+                std::thread::spawn(move || {
+                    let mut reads = 0u64;
+                    while !done.load(std::sync::atomic::Ordering::Relaxed) || reads == 0 {
+                        let current = config.config();
+                        assert_eq!(current.low + current.high, 100);
+                        assert_eq!(current.name, format!("limits-{}", current.low));
+                        reads += 1;
+                    }
+                    reads
+                })
+            })
+            .collect();
+        for low in 0..2000 {
+            config.set_config(limits(low % 101));
+        }
+        done.store(true, std::sync::atomic::Ordering::Relaxed);
+        for reader in readers {
+            assert!(reader.join().unwrap() > 0);
+        }
+
+        // a snapshot is unaffected by later swaps
+        let before = config.config();
+        config.set_config(limits(42));
+        assert_eq!(before.low, 1999 % 101);
+        assert_eq!(config.config().low, 42);
+    }
+}
@@ -1,8 +1,6 @@
 #![cfg(not(target_arch = "wasm32"))]
 
-use crate::async_nats::{AuthError, ConnectOptions};
 use std::io::{BufRead, StderrLock, Write};
-use std::str::FromStr;
 
 use once_cell::sync::OnceCell;
 #[cfg(feature = "otel")]
@@ -30,8 +28,10 @@ use tracing_subscriber::{
 
 use crate::{
     core::HostData,
-    error::RpcError,
+    error::{RpcError, RpcResult},
     provider::{HostBridge, ProviderDispatch},
+    provider_transport::HostTransport,
+    rpc_client::ReconnectLimit,
 };
 
 lazy_static::lazy_static! {
@@ -135,77 +135,145 @@ pub async fn provider_run<P>(
 where
     P: ProviderDispatch + Send + Sync + Clone + 'static,
 {
+    host_data.validate()?;
     configure_tracing(
         friendly_name.unwrap_or_else(|| host_data.provider_key.clone()),
         host_data.structured_logging,
         host_data.log_level.clone(),
     );
 
-    let (shutdown_tx, mut shutdown_rx) = tokio::sync::broadcast::channel::<bool>(1);
-
     eprintln!(
         "Starting capability provider {} instance {} with nats url {}",
         &host_data.provider_key, &host_data.instance_id, &host_data.lattice_rpc_url,
     );
 
-    let nats_addr = if !host_data.lattice_rpc_url.is_empty() {
-        host_data.lattice_rpc_url.as_str()
-    } else {
-        crate::provider::DEFAULT_NATS_ADDR
-    };
-    let nats_server = async_nats::ServerAddr::from_str(nats_addr).map_err(|e| {
-        RpcError::InvalidParameter(format!("Invalid nats server url '{nats_addr}': {e}"))
-    })?;
+    let options = provider_dispatch.provider_options();
+    let limit = ReconnectLimit::new(options.max_reconnects);
+    let (nc, rtt) = host_data
+        .nats_connect_measured(
+            &options.connection_name_or_default(&host_data),
+            options.reconnect_max_backoff,
+            &limit,
+            options.connect_timeout,
+        )
+        .await?;
 
-    let nc = crate::rpc_client::with_connection_event_logging(
-        match (
-            host_data.lattice_rpc_user_jwt.trim(),
-            host_data.lattice_rpc_user_seed.trim(),
-        ) {
-            ("", "") => ConnectOptions::default(),
-            (rpc_jwt, rpc_seed) => {
-                let key_pair = std::sync::Arc::new(nkeys::KeyPair::from_seed(rpc_seed).unwrap());
-                let jwt = rpc_jwt.to_owned();
-                ConnectOptions::with_jwt(jwt, move |nonce| {
-                    let key_pair = key_pair.clone();
-                    async move { key_pair.sign(&nonce).map_err(AuthError::new) }
-                })
-            }
-        },
+    run_provider(provider_dispatch, host_data, nc, Some(rtt), limit, None).await
+}
+
+/// Runs the provider with a nats connection opened by the caller, instead of connecting
+/// with the settings in `host_data`. Useful for tests, or for embedding a provider
+/// in another process. Returns after the provider receives a shutdown message.
+///
+/// Unlike [provider_run], this does not configure tracing, so the caller can
+/// install its own subscriber.
+/// The host bridge may only be set once per process (see [get_host_bridge]),
+/// so a process can run only one provider this way.
+pub async fn run_provider_with_connection<P>(
+    provider_dispatch: P,
+    host_data: HostData,
+    nc: crate::async_nats::Client,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    P: ProviderDispatch + Send + Sync + Clone + 'static,
+{
+    host_data.validate()?;
+    run_provider(
+        provider_dispatch,
+        host_data,
+        nc,
+        None,
+        ReconnectLimit::default(),
+        None,
     )
-    .connect(nats_server)
-    .await?;
+    .await
+}
+
+/// Like [run_provider_with_connection], but the provider receives link, health,
+/// shutdown, and rpc messages, and sends its replies, through `transport`,
+/// such as a [MemoryTransport](crate::provider_transport::MemoryTransport) in tests.
+/// `nc` is still used for messages the provider sends to actors,
+/// and for rpc messages too large for a single message.
+pub async fn run_provider_with_transport<P>(
+    provider_dispatch: P,
+    host_data: HostData,
+    nc: crate::async_nats::Client,
+    transport: std::sync::Arc<dyn HostTransport>,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    P: ProviderDispatch + Send + Sync + Clone + 'static,
+{
+    host_data.validate()?;
+    run_provider(
+        provider_dispatch,
+        host_data,
+        nc,
+        None,
+        ReconnectLimit::default(),
+        Some(transport),
+    )
+    .await
+}
+
+/// Runs the provider on a connected nats client until it's shut down,
+/// or returns an error if the connection exceeds its reconnect limit.
+/// Host messages use `transport`, or the nats client if it's None.
+async fn run_provider<P>(
+    provider_dispatch: P,
+    host_data: HostData,
+    nc: crate::async_nats::Client,
+    connect_rtt: Option<std::time::Duration>,
+    reconnect_limit: ReconnectLimit,
+    transport: Option<std::sync::Arc<dyn HostTransport>>,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    P: ProviderDispatch + Send + Sync + Clone + 'static,
+{
+    let (shutdown_tx, mut shutdown_rx) = tokio::sync::broadcast::channel::<bool>(1);
 
     // initialize HostBridge
-    let bridge = HostBridge::new_client(nc, &host_data)?;
-    set_host_bridge(bridge).ok();
+    let options = provider_dispatch.provider_options();
+    let bridge = HostBridge::new_bridge(
+        nc,
+        connect_rtt,
+        reconnect_limit,
+        transport,
+        &host_data,
+        options,
+    )?;
+    if set_host_bridge(bridge).is_err() {
+        return Err(Box::new(RpcError::ProviderInit(
+            "a provider host bridge is already running in this process".to_string(),
+        )));
+    }
     let bridge = get_host_bridge();
 
-    // pre-populate provider and bridge with initial set of link definitions
-    // initialization of any link is fatal for provider startup
-    let initial_links = host_data.link_definitions.clone();
-    for ld in initial_links.into_iter() {
-        if let Err(e) = provider_dispatch.put_link(&ld).await {
-            eprintln!(
-                "Failed to initialize link during provider startup - ({:?}): {:?}",
-                &ld, e
-            );
-        } else {
-            bridge.put_link(ld).await;
-        }
-    }
+    initialize_provider(&provider_dispatch, bridge, &host_data).await?;
 
-    // subscribe to nats topics
+    // subscribe to nats topics, then signal that the provider is ready
     let _join = bridge
-        .connect(
-            provider_dispatch,
+        .connect_and_signal_ready(
+            provider_dispatch.clone(),
             &shutdown_tx,
-            &host_data.lattice_rpc_prefix,
+            bridge.lattice_prefix(),
         )
         .await;
 
-    // run until we receive a shutdown request from host
-    let _ = shutdown_rx.recv().await;
+    // run until we receive a shutdown request from host, or lose the connection
+    let lost = tokio::select! {
+        _ = shutdown_rx.recv() => false,
+        _ = bridge.connection_lost() => true,
+    };
+    if lost {
+        tracing::error!("nats connection lost; stopping provider");
+        provider_dispatch.connection_lost().await;
+        let _ = shutdown_tx.send(true);
+        let _ = tokio::task::spawn_blocking(crate::chunkify::shutdown).await;
+        return Err(Box::new(RpcError::Nats("nats connection lost".to_string())));
+    }
+
+    // cancel requests still waiting for responses
+    bridge.abort_requests().await;
 
     // close chunkifiers
     let _ = tokio::task::spawn_blocking(crate::chunkify::shutdown).await;
@@ -216,6 +284,170 @@ where
     Ok(())
 }
 
+/// Starts a registered provider: runs its init hook, then subscribes to its topics
+type StartProvider = Box<
+    dyn FnOnce(
+            tokio::sync::broadcast::Sender<bool>,
+        ) -> futures::future::BoxFuture<'static, RpcResult<()>>
+        + Send,
+>;
+
+/// Runs several providers in one process, sharing one nats connection.
+/// Each provider subscribes to the subjects of its own host data, so the messages for
+/// each provider key and link name reach only that provider.
+///
+/// The first provider registered becomes the process's host bridge (see [get_host_bridge]),
+/// which is used by senders constructed without a bridge. Other providers should send to
+/// actors with the bridge returned by [register](ProviderHost::register).
+/// Providers run for the life of the process, so their bridges are never freed.
+pub struct ProviderHost {
+    nc: crate::async_nats::Client,
+    transport: Option<std::sync::Arc<dyn HostTransport>>,
+    providers: Vec<(&'static HostBridge, StartProvider)>,
+}
+
+impl std::fmt::Debug for ProviderHost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProviderHost")
+            .field(
+                "providers",
+                &self.providers.iter().map(|(bridge, _)| bridge).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl ProviderHost {
+    /// Constructs a host for providers on a nats connection opened by the caller
+    pub fn new(nc: crate::async_nats::Client) -> ProviderHost {
+        ProviderHost {
+            nc,
+            transport: None,
+            providers: Vec::new(),
+        }
+    }
+
+    /// Constructs a host whose providers receive host messages through `transport`,
+    /// as with [run_provider_with_transport]
+    pub fn with_transport(
+        nc: crate::async_nats::Client,
+        transport: std::sync::Arc<dyn HostTransport>,
+    ) -> ProviderHost {
+        ProviderHost {
+            nc,
+            transport: Some(transport),
+            providers: Vec::new(),
+        }
+    }
+
+    /// Adds a provider, to be started by [run](ProviderHost::run).
+    /// Returns `RpcError::InvalidParameter` if another provider has the same rpc subject,
+    /// or an error if the host data is invalid.
+    pub fn register<P>(
+        &mut self,
+        host_data: HostData,
+        provider_dispatch: P,
+    ) -> RpcResult<&'static HostBridge>
+    where
+        P: ProviderDispatch + Send + Sync + Clone + 'static,
+    {
+        host_data.validate()?;
+        let subject = host_data.rpc_subject();
+        if self.providers.iter().any(|(b, _)| b.provider_rpc_topic() == subject) {
+            return Err(RpcError::InvalidParameter(format!(
+                "a provider is already registered for {subject}"
+            )));
+        }
+        let bridge = HostBridge::new_bridge(
+            self.nc.clone(),
+            None,
+            ReconnectLimit::default(),
+            self.transport.clone(),
+            &host_data,
+            provider_dispatch.provider_options(),
+        )?;
+        let bridge: &'static HostBridge = Box::leak(Box::new(bridge));
+        let start: StartProvider = Box::new(move |shutdown_tx| {
+            Box::pin(async move {
+                initialize_provider(&provider_dispatch, bridge, &host_data).await?;
+                let _join = bridge
+                    .connect_and_signal_ready(
+                        provider_dispatch,
+                        &shutdown_tx,
+                        bridge.lattice_prefix(),
+                    )
+                    .await;
+                Ok(())
+            })
+        });
+        self.providers.push((bridge, start));
+        Ok(bridge)
+    }
+
+    /// Starts the registered providers, in the order they were registered, and runs them
+    /// until each has received its shutdown message.
+    /// Returns an error if a provider fails to initialize.
+    pub async fn run(self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some((bridge, _)) = self.providers.first() {
+            // senders without a bridge use the first provider's
+            let _ = set_host_bridge((*bridge).clone());
+        }
+        let mut running = Vec::with_capacity(self.providers.len());
+        for (bridge, start) in self.providers {
+            let (shutdown_tx, shutdown_rx) = tokio::sync::broadcast::channel::<bool>(1);
+            start(shutdown_tx.clone()).await?;
+            running.push((bridge, shutdown_tx, shutdown_rx));
+        }
+        for (_, _shutdown_tx, shutdown_rx) in running.iter_mut() {
+            let _ = shutdown_rx.recv().await;
+        }
+        for (bridge, _, _) in running.iter() {
+            bridge.abort_requests().await;
+        }
+        let _ = tokio::task::spawn_blocking(crate::chunkify::shutdown).await;
+        for (bridge, _, _) in running {
+            bridge.flush().await;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the provider's init hook, then adds the initial set of links.
+/// This must complete before the bridge subscribes to nats topics.
+pub(crate) async fn initialize_provider<P>(
+    provider_dispatch: &P,
+    bridge: &HostBridge,
+    host_data: &HostData,
+) -> RpcResult<()>
+where
+    P: ProviderDispatch + Send + Sync + Clone + 'static,
+{
+    provider_dispatch
+        .init(host_data)
+        .await
+        .map_err(|e| RpcError::ProviderInit(format!("provider init failed: {e}")))?;
+
+    // pre-populate provider and bridge with initial set of link definitions
+    // initialization of any link is fatal for provider startup
+    let initial_links = host_data.link_definitions.clone();
+    for ld in initial_links.into_iter() {
+        let linked = match bridge.check_link_contract(&ld) {
+            Ok(()) => provider_dispatch.put_link(&ld).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = linked {
+            eprintln!(
+                "Failed to initialize link during provider startup - ({:?}): {:?}",
+                &ld.redacted(),
+                e
+            );
+        } else {
+            bridge.put_link(ld).await;
+        }
+    }
+    Ok(())
+}
+
 /// Loads configuration data sent from the host over stdin. The returned host data contains all the
 /// configuration information needed to connect to the lattice and any additional configuration
 /// provided to this provider (like `config_json`).
@@ -240,27 +472,12 @@ pub fn _load_host_data() -> Result<HostData, RpcError> {
             ))
         })?;
     }
-    // remove spaces, tabs, and newlines before and after base64-encoded data
-    let buffer = buffer.trim();
-    if buffer.is_empty() {
+    if buffer.trim().is_empty() {
         return Err(RpcError::Rpc(
             "stdin is empty - expecting host data configuration".to_string(),
         ));
     }
-    let bytes = base64::decode(buffer.as_bytes()).map_err(|e| {
-        RpcError::Rpc(format!(
-            "host data configuration passed through stdin has invalid encoding (expected base64): \
-             {e}"
-        ))
-    })?;
-    let host_data: HostData = serde_json::from_slice(&bytes).map_err(|e| {
-        RpcError::Rpc(format!(
-            "parsing host data: {}:\n{}",
-            e,
-            String::from_utf8_lossy(&bytes)
-        ))
-    })?;
-    Ok(host_data)
+    HostData::from_base64(&buffer)
 }
 
 #[cfg(feature = "otel")]
@@ -0,0 +1,304 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+//! Message transport of a running provider.
+//!
+//! The [HostBridge](crate::provider::HostBridge) receives link, health, shutdown, and rpc
+//! messages from subscriptions, and sends replies, through a [HostTransport].
+//! Providers use [NatsTransport] unless started with
+//! [run_provider_with_transport](crate::provider_main::run_provider_with_transport);
+//! tests can use [MemoryTransport] to run the provider without a nats server.
+//! For messages a provider sends to an actor, see
+//! [provider::ProviderTransport](crate::provider::ProviderTransport).
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use futures::{channel::mpsc, stream::BoxStream, StreamExt};
+
+use crate::{
+    async_nats::{HeaderMap, Message},
+    error::{RpcError, RpcResult},
+    rpc_client::RpcClient,
+};
+
+/// Messages received on a subscription. Dropping the stream ends the subscription.
+pub type Subscription = BoxStream<'static, Message>;
+
+/// Subscribes to the messages a provider receives, and sends its replies
+#[async_trait]
+pub trait HostTransport: Send + Sync {
+    /// Subscribes to a subject. If `queue_group` is set, each message is delivered to
+    /// only one of the subscribers in the group.
+    async fn subscribe(
+        &self,
+        subject: String,
+        queue_group: Option<String>,
+    ) -> RpcResult<Subscription>;
+
+    /// Sends a message with no reply-to
+    async fn publish(
+        &self,
+        subject: String,
+        headers: Option<HeaderMap>,
+        payload: Vec<u8>,
+    ) -> RpcResult<()>;
+
+    /// Replies to a received message. The default publishes to `reply_to`.
+    async fn reply(
+        &self,
+        reply_to: String,
+        headers: Option<HeaderMap>,
+        payload: Vec<u8>,
+    ) -> RpcResult<()> {
+        self.publish(reply_to, headers, payload).await
+    }
+
+    /// Waits until sent messages have been delivered. The default does nothing.
+    async fn flush(&self) -> RpcResult<()> {
+        Ok(())
+    }
+}
+
+/// Transport over the provider's nats connection. Sent messages get the rpc client's
+/// headers, wire tap, and timeout, and fail once the connection exceeds its reconnect limit.
+#[derive(Clone, Debug)]
+pub struct NatsTransport {
+    client: RpcClient,
+}
+
+impl NatsTransport {
+    /// Constructs a transport on the rpc client's connection
+    pub fn new(client: RpcClient) -> Self {
+        NatsTransport { client }
+    }
+}
+
+#[async_trait]
+impl HostTransport for NatsTransport {
+    async fn subscribe(
+        &self,
+        subject: String,
+        queue_group: Option<String>,
+    ) -> RpcResult<Subscription> {
+        let nc = self.client.client();
+        let sub = match queue_group {
+            Some(group) => nc.queue_subscribe(subject, group).await,
+            None => nc.subscribe(subject).await,
+        }
+        .map_err(|e| RpcError::Nats(e.to_string()))?;
+        Ok(sub.boxed())
+    }
+
+    async fn publish(
+        &self,
+        subject: String,
+        headers: Option<HeaderMap>,
+        payload: Vec<u8>,
+    ) -> RpcResult<()> {
+        self.client.publish_with_extra_headers(subject, headers, payload).await
+    }
+
+    async fn flush(&self) -> RpcResult<()> {
+        self.client
+            .client()
+            .flush()
+            .await
+            .map_err(|e| RpcError::Nats(e.to_string()))
+    }
+}
+
+struct MemorySubscriber {
+    subject: String,
+    queue_group: Option<String>,
+    tx: mpsc::UnboundedSender<Message>,
+}
+
+/// In-process transport for tests. Messages published to a subject are delivered to its
+/// subscribers, including those with `*` and `>` wildcards; each queue group gets one copy.
+/// Clones share subscriptions, so a test can keep one clone and give another to the provider.
+#[derive(Clone, Default)]
+pub struct MemoryTransport {
+    subscribers: Arc<Mutex<Vec<MemorySubscriber>>>,
+    next_inbox: Arc<std::sync::atomic::AtomicU64>,
+}
+
+impl std::fmt::Debug for MemoryTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryTransport")
+            .field("subscribers", &self.subscribers.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl MemoryTransport {
+    /// Constructs a transport with no subscribers
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delivers a message to the matching subscribers. Returns the number of receivers.
+    pub fn send(
+        &self,
+        subject: &str,
+        reply: Option<String>,
+        headers: Option<HeaderMap>,
+        payload: Vec<u8>,
+    ) -> usize {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|s| !s.tx.is_closed());
+        let mut groups: Vec<&str> = Vec::new();
+        let mut delivered = 0;
+        for sub in subscribers.iter() {
+            if !subject_matches(&sub.subject, subject) {
+                continue;
+            }
+            if let Some(group) = &sub.queue_group {
+                if groups.contains(&group.as_str()) {
+                    continue;
+                }
+                groups.push(group);
+            }
+            let message = Message {
+                subject: subject.to_string(),
+                reply: reply.clone(),
+                length: subject.len() + payload.len(),
+                payload: payload.clone().into(),
+                headers: headers.clone(),
+                status: None,
+                description: None,
+            };
+            if sub.tx.unbounded_send(message).is_ok() {
+                delivered += 1;
+            }
+        }
+        delivered
+    }
+
+    /// Sends a request and waits for the first reply.
+    /// Returns `RpcError::Timeout` if none arrives within `timeout`.
+    pub async fn request(
+        &self,
+        subject: &str,
+        payload: Vec<u8>,
+        timeout: Duration,
+    ) -> RpcResult<Message> {
+        self.send_request(subject, None, payload, timeout).await
+    }
+
+    /// Like [request](MemoryTransport::request), with headers
+    pub async fn request_with_headers(
+        &self,
+        subject: &str,
+        headers: HeaderMap,
+        payload: Vec<u8>,
+        timeout: Duration,
+    ) -> RpcResult<Message> {
+        self.send_request(subject, Some(headers), payload, timeout).await
+    }
+
+    async fn send_request(
+        &self,
+        subject: &str,
+        headers: Option<HeaderMap>,
+        payload: Vec<u8>,
+        timeout: Duration,
+    ) -> RpcResult<Message> {
+        let inbox = format!(
+            "_INBOX.memory.{}",
+            self.next_inbox.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+        );
+        let mut replies = self.subscribe(inbox.clone(), None).await?;
+        if self.send(subject, Some(inbox), headers, payload) == 0 {
+            return Err(RpcError::Nats(format!("no subscribers on {subject}")));
+        }
+        match tokio::time::timeout(timeout, replies.next()).await {
+            Ok(Some(reply)) => Ok(reply),
+            Ok(None) => Err(RpcError::Other("memory transport closed".to_string())),
+            Err(_) => Err(RpcError::Timeout(format!(
+                "no reply on {subject} within {timeout:?}"
+            ))),
+        }
+    }
+
+    /// The number of open subscriptions
+    pub fn subscriptions(&self) -> usize {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|s| !s.tx.is_closed());
+        subscribers.len()
+    }
+}
+
+#[async_trait]
+impl HostTransport for MemoryTransport {
+    async fn subscribe(
+        &self,
+        subject: String,
+        queue_group: Option<String>,
+    ) -> RpcResult<Subscription> {
+        let (tx, rx) = mpsc::unbounded();
+        self.subscribers
+            .lock()
+            .unwrap()
+            .push(MemorySubscriber { subject, queue_group, tx });
+        Ok(rx.boxed())
+    }
+
+    async fn publish(
+        &self,
+        subject: String,
+        headers: Option<HeaderMap>,
+        payload: Vec<u8>,
+    ) -> RpcResult<()> {
+        self.send(&subject, None, headers, payload);
+        Ok(())
+    }
+}
+
+/// Returns true if the subject matches the pattern, which may contain nats wildcards
+fn subject_matches(pattern: &str, subject: &str) -> bool {
+    let mut subject = subject.split('.');
+    for token in pattern.split('.') {
+        match (token, subject.next()) {
+            (">", Some(_)) => return true,
+            ("*", Some(_)) => {}
+            (token, Some(s)) if token == s => {}
+            _ => return false,
+        }
+    }
+    subject.next().is_none()
+}
+
+#[cfg(test)]
+mod test {
+    use futures::StreamExt;
+
+    use super::{subject_matches, HostTransport, MemoryTransport};
+
+    #[test]
+    fn wildcards() {
+        assert!(subject_matches("a.b.c", "a.b.c"));
+        assert!(subject_matches("a.*.c", "a.b.c"));
+        assert!(subject_matches("a.>", "a.b.c"));
+        assert!(!subject_matches("a.>", "a"));
+        assert!(!subject_matches("a.b", "a.b.c"));
+        assert!(!subject_matches("a.b.c", "a.b"));
+    }
+
+    #[tokio::test]
+    async fn queue_group_gets_one_copy() {
+        let transport = MemoryTransport::new();
+        let mut a = transport.subscribe("x".into(), Some("q".into())).await.unwrap();
+        let mut b = transport.subscribe("x".into(), Some("q".into())).await.unwrap();
+        let mut c = transport.subscribe("x".into(), None).await.unwrap();
+        assert_eq!(transport.send("x", None, None, b"hi".to_vec()), 2);
+        assert_eq!(a.next().await.unwrap().payload.as_ref(), b"hi");
+        assert_eq!(c.next().await.unwrap().payload.as_ref(), b"hi");
+        drop(a);
+        assert_eq!(transport.send("x", None, None, b"again".to_vec()), 2);
+        assert_eq!(b.next().await.unwrap().payload.as_ref(), b"again");
+        assert_eq!(transport.subscriptions(), 2);
+    }
+}
@@ -0,0 +1,279 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+//! Shared reply subscription for many outstanding requests.
+//!
+//! Each nats request normally subscribes to its own inbox. A [ReplyMux] subscribes once,
+//! to a wildcard under one inbox prefix, and gives each request a reply subject
+//! ending in a correlation id. Replies are routed to the request with that id,
+//! in whatever order they arrive.
+//! A mux from [RpcClient::reply_mux](crate::rpc_client::RpcClient::reply_mux) is cancelled
+//! with the client's requests by [abort_all](crate::rpc_client::RpcClient::abort_all).
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use futures::StreamExt;
+use tokio::sync::{oneshot, Notify};
+use tracing::debug;
+
+use crate::{
+    async_nats::Client,
+    error::{RpcError, RpcResult},
+    rpc_client::shutting_down,
+};
+
+/// Routes replies on a shared subscription to the requests waiting for them.
+/// Clones share the subscription, which ends when the last clone is dropped.
+#[derive(Clone)]
+pub struct ReplyMux {
+    client: Client,
+    inner: Arc<Inner>,
+    _router: Arc<Router>,
+}
+
+impl std::fmt::Debug for ReplyMux {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReplyMux")
+            .field("prefix", &self.inner.prefix)
+            .field("outstanding", &self.outstanding())
+            .finish()
+    }
+}
+
+struct Inner {
+    prefix: String,
+    next_id: AtomicU64,
+    pending: Mutex<HashMap<String, oneshot::Sender<Vec<u8>>>>,
+    /// wakes waiting requests when they are aborted
+    aborts: Arc<Notify>,
+}
+
+impl Inner {
+    /// Delivers a reply to the request whose correlation id ends the subject.
+    /// Replies for unknown ids, such as for requests that timed out, are dropped.
+    fn route(&self, subject: &str, payload: Vec<u8>) {
+        let id = match subject
+            .strip_prefix(self.prefix.as_str())
+            .and_then(|s| s.strip_prefix('.'))
+        {
+            Some(id) => id,
+            None => return,
+        };
+        let waiter = self.pending.lock().unwrap().remove(id);
+        match waiter {
+            Some(tx) => {
+                let _ = tx.send(payload);
+            }
+            None => debug!(%subject, "dropping reply with no outstanding request"),
+        }
+    }
+}
+
+/// Stops the routing task when the last ReplyMux clone is dropped
+struct Router(Option<tokio::task::JoinHandle<()>>);
+
+impl Drop for Router {
+    fn drop(&mut self) {
+        if let Some(task) = self.0.take() {
+            task.abort();
+        }
+    }
+}
+
+impl ReplyMux {
+    /// Subscribes to replies under a new inbox prefix
+    pub async fn new(client: Client) -> RpcResult<Self> {
+        Self::with_aborts(client, Arc::new(Notify::new())).await
+    }
+
+    /// Subscribes to replies under a new inbox prefix.
+    /// Waiting requests return `RpcError::Other("client shutting down")` when `aborts`
+    /// notifies its waiters.
+    pub(crate) async fn with_aborts(client: Client, aborts: Arc<Notify>) -> RpcResult<Self> {
+        let prefix = client.new_inbox();
+        let mut sub = client
+            .subscribe(format!("{prefix}.*"))
+            .await
+            .map_err(|e| RpcError::Nats(e.to_string()))?;
+        let mut mux = Self::with_prefix(client, prefix, aborts);
+        let inner = mux.inner.clone();
+        mux._router = Arc::new(Router(Some(tokio::spawn(async move {
+            while let Some(msg) = sub.next().await {
+                inner.route(&msg.subject, msg.payload.to_vec());
+            }
+        }))));
+        Ok(mux)
+    }
+
+    /// Constructs a mux without subscribing
+    fn with_prefix(client: Client, prefix: String, aborts: Arc<Notify>) -> Self {
+        ReplyMux {
+            client,
+            inner: Arc::new(Inner {
+                prefix,
+                next_id: AtomicU64::new(1),
+                pending: Mutex::new(HashMap::new()),
+                aborts,
+            }),
+            _router: Arc::new(Router(None)),
+        }
+    }
+
+    /// Reserves a reply subject for one request.
+    /// Send the request with [PendingReply::subject] as its reply-to,
+    /// then [wait](PendingReply::wait) for the reply.
+    pub fn register(&self) -> PendingReply {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed).to_string();
+        let (tx, rx) = oneshot::channel();
+        self.inner.pending.lock().unwrap().insert(id.clone(), tx);
+        PendingReply {
+            subject: format!("{}.{}", self.inner.prefix, &id),
+            id,
+            rx,
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// Publishes a request and waits for its reply.
+    /// Returns `RpcError::Timeout` if no reply arrives within `timeout`.
+    pub async fn request(
+        &self,
+        subject: String,
+        payload: Vec<u8>,
+        timeout: Duration,
+    ) -> RpcResult<Vec<u8>> {
+        let pending = self.register();
+        self.client
+            .publish_with_reply(subject, pending.subject.clone(), payload.into())
+            .await
+            .map_err(|e| RpcError::Nats(e.to_string()))?;
+        pending.wait(timeout).await
+    }
+
+    /// The number of requests waiting for a reply
+    pub fn outstanding(&self) -> usize {
+        self.inner.pending.lock().unwrap().len()
+    }
+}
+
+/// A reply subject reserved by [ReplyMux::register].
+/// Dropping it, or a timeout, releases the correlation id; a later reply is dropped.
+pub struct PendingReply {
+    subject: String,
+    id: String,
+    rx: oneshot::Receiver<Vec<u8>>,
+    inner: Arc<Inner>,
+}
+
+impl PendingReply {
+    /// The reply-to subject for the request
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    /// Waits for the reply. Returns `RpcError::Timeout` if none arrives within `timeout`.
+    pub async fn wait(mut self, timeout: Duration) -> RpcResult<Vec<u8>> {
+        let aborts = self.inner.aborts.clone();
+        let reply = tokio::select! {
+            reply = tokio::time::timeout(timeout, &mut self.rx) => reply,
+            _ = aborts.notified() => return Err(shutting_down()),
+        };
+        match reply {
+            Ok(Ok(payload)) => Ok(payload),
+            Ok(Err(_)) => Err(RpcError::Other("reply mux closed".to_string())),
+            Err(_) => Err(RpcError::Timeout(format!(
+                "no reply on {} within {:?}",
+                &self.subject, timeout
+            ))),
+        }
+    }
+}
+
+impl Drop for PendingReply {
+    fn drop(&mut self) {
+        self.inner.pending.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::sync::Notify;
+
+    use super::ReplyMux;
+    use crate::{async_nats::ConnectOptions, error::RpcError, rpc_client::CLIENT_SHUTTING_DOWN};
+
+    #[tokio::test]
+    async fn interleaved_replies() {
+        let nc = ConnectOptions::new()
+            .retry_on_initial_connect()
+            .connect("nats://127.0.0.1:1")
+            .await
+            .unwrap();
+        let mux = ReplyMux::with_prefix(nc, "_INBOX.test".to_string(), Arc::new(Notify::new()));
+
+        let pending = [mux.register(), mux.register(), mux.register()];
+        let subjects: Vec<String> = pending.iter().map(|p| p.subject().to_string()).collect();
+        assert_eq!(mux.outstanding(), 3);
+        let waits: Vec<_> = pending
+            .into_iter()
+            .map(|p| tokio::spawn(p.wait(Duration::from_secs(5))))
+            .collect();
+
+        // replies arrive out of order
+        for i in [2, 0, 1] {
+            mux.inner.route(&subjects[i], format!("reply {i}").into_bytes());
+        }
+        for (i, wait) in waits.into_iter().enumerate() {
+            assert_eq!(
+                wait.await.unwrap().unwrap(),
+                format!("reply {i}").into_bytes()
+            );
+        }
+        assert_eq!(mux.outstanding(), 0);
+
+        // a straggler times out, and its late reply is dropped
+        let late = mux.register();
+        let subject = late.subject().to_string();
+        let err = late.wait(Duration::from_millis(10)).await.unwrap_err();
+        assert!(matches!(err, RpcError::Timeout(_)));
+        assert_eq!(mux.outstanding(), 0);
+        mux.inner.route(&subject, b"too late".to_vec());
+    }
+
+    #[tokio::test]
+    async fn abort_wakes_waiting_requests() {
+        let nc = ConnectOptions::new()
+            .retry_on_initial_connect()
+            .connect("nats://127.0.0.1:1")
+            .await
+            .unwrap();
+        let aborts = Arc::new(Notify::new());
+        let mux = ReplyMux::with_prefix(nc, "_INBOX.test".to_string(), aborts.clone());
+
+        let waits: Vec<_> = (0..2)
+            .map(|_| tokio::spawn(mux.register().wait(Duration::from_secs(5))))
+            .collect();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        aborts.notify_waiters();
+        for wait in waits {
+            let err = tokio::time::timeout(Duration::from_secs(1), wait)
+                .await
+                .expect("request aborted")
+                .unwrap()
+                .unwrap_err();
+            assert!(
+                matches!(&err, RpcError::Other(s) if s == CLIENT_SHUTTING_DOWN),
+                "{err}"
+            );
+        }
+        assert_eq!(mux.outstanding(), 0);
+    }
+}
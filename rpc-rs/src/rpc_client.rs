@@ -2,9 +2,13 @@
 #![allow(clippy::let_and_return)] // fixes some false-positive clippy warnings with feature flags
 
 use std::{
+    collections::{HashMap, VecDeque},
     convert::{TryFrom, TryInto},
     fmt,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
     time::Duration,
 };
 
@@ -20,16 +24,351 @@ use tracing::{debug, error, info, instrument, trace, warn};
 use crate::otel::OtelHeaderInjector;
 use crate::{
     chunkify::{needs_chunking, ChunkEndpoint},
-    common::Message,
-    core::{Invocation, InvocationResponse, WasmCloudEntity},
+    common::{Message, Priority, SendOpts},
+    core::{
+        sanitize_subject_token, HostData, Invocation, InvocationResponse, LatticePrefix,
+        WasmCloudEntity, DEFAULT_LATTICE_PREFIX,
+    },
+    dispatch::SCHEMA_HASH_HEADER,
     error::{RpcError, RpcResult},
     provider_main::get_host_bridge_safe,
     wascap::{jwt, prelude::Claims},
+    Timestamp,
 };
 
 pub(crate) const DEFAULT_RPC_TIMEOUT_MILLIS: Duration = Duration::from_millis(2000);
 /// Amount of time to add to rpc timeout if chunkifying
 pub(crate) const CHUNK_RPC_EXTRA_TIME: Duration = Duration::from_secs(13);
+/// Nats message header carrying the sender's idempotency key, if any
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+/// Nats message header identifying the responder to a
+/// [scatter_gather](RpcClient::scatter_gather) request, as a json WasmCloudEntity
+pub const RESPONDER_HEADER: &str = "Wasmbus-Responder";
+/// Nats message header carrying the request's priority, if not Normal
+pub const PRIORITY_HEADER: &str = "Wasmbus-Priority";
+/// Nats message header carrying the time after which the sender no longer waits
+/// for a response, as nanoseconds since the unix epoch
+pub const EXPIRES_HEADER: &str = "Wasmbus-Expires";
+/// Nats message header carrying the [payload_checksum] of the message,
+/// if the sender set [SendOpts::checksum]
+pub const CHECKSUM_HEADER: &str = "Wasmbus-Checksum";
+/// Nats message header carrying the time the request was sent, by the sender's clock,
+/// as nanoseconds since the unix epoch, if the sender set [SendOpts::send_time]
+pub const SENT_AT_HEADER: &str = "Wasmbus-Sent-At";
+/// Nats message header listing the formats the sender can decode a response in,
+/// most preferred first, as comma-separated names, if the sender set [SendOpts::accept_formats]
+pub const ACCEPT_FORMATS_HEADER: &str = "Wasmbus-Accept-Formats";
+/// Nats message header naming the format of the payload: on a request, the format the
+/// sender encoded it in (see [SendOpts::format]), and on a response, the format the handler wrote it in,
+/// if it said (see [DispatchResult::with_format](crate::common::DispatchResult::with_format))
+pub const FORMAT_HEADER: &str = "Wasmbus-Format";
+/// Nats message header naming the encoding the sender can decompress a response in,
+/// if the sender set [SendOpts::accept_compression]
+pub const ACCEPT_ENCODING_HEADER: &str = "Wasmbus-Accept-Encoding";
+/// Nats message header on a compressed response, naming its encoding
+pub const ENCODING_HEADER: &str = "Wasmbus-Encoding";
+/// Name of raw deflate, the compression used for responses
+pub const DEFLATE_ENCODING: &str = "deflate";
+/// Largest size a compressed response may decompress to
+const MAX_INFLATED_SIZE: usize = 64 * 1024 * 1024;
+/// Message of the `RpcError::NotInitialized` returned to sends that waited out the
+/// [connect grace period](RpcClient::set_connect_grace)
+pub const CONNECTION_NOT_READY: &str = "connection not ready";
+/// How often a send in the connect grace period checks the connection
+const CONNECT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Message of the `RpcError::Other` returned to requests cancelled by [RpcClient::abort_all]
+pub const CLIENT_SHUTTING_DOWN: &str = "client shutting down";
+
+/// Nats message header carrying the sender's [PROTOCOL_VERSION]
+pub const PROTOCOL_VERSION_HEADER: &str = "Wasmbus-Protocol";
+/// Version of the invocation envelope and headers this crate sends.
+/// Increment it when a change would be misread by peers built with the previous version.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest protocol version this crate accepts
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Returns the checksum sent in the [CHECKSUM_HEADER] header: the crc32 of the payload, in hex
+pub fn payload_checksum(payload: &[u8]) -> String {
+    format!("{:08x}", crc32(payload))
+}
+
+/// Checks the payload against the message's [CHECKSUM_HEADER] header, if it has one.
+/// Returns `RpcError::Deser("checksum mismatch")` if the payload was changed in transit.
+pub(crate) fn verify_checksum(headers: Option<&HeaderMap>, payload: &[u8]) -> RpcResult<()> {
+    match headers.and_then(|h| h.get(CHECKSUM_HEADER)) {
+        Some(sum) if !sum.as_str().eq_ignore_ascii_case(&payload_checksum(payload)) => {
+            Err(RpcError::Deser("checksum mismatch".to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// crc32 (IEEE), as used by zip and ethernet
+fn crc32(buf: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut c = i as u32;
+            let mut k = 0;
+            while k < 8 {
+                c = if c & 1 != 0 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+                k += 1;
+            }
+            table[i] = c;
+            i += 1;
+        }
+        table
+    };
+    !buf.iter().fold(!0u32, |crc, b| {
+        TABLE[((crc ^ *b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Checks the value of a request's [PROTOCOL_VERSION_HEADER] header.
+/// A request without the header is from a peer that predates versioning, and is accepted.
+/// Returns `RpcError::Rpc` if the version is outside
+/// [MIN_PROTOCOL_VERSION]..=[PROTOCOL_VERSION], or isn't a number.
+pub fn check_protocol_version(header: Option<&str>) -> RpcResult<()> {
+    let value = match header {
+        Some(value) => value.trim(),
+        None => return Ok(()),
+    };
+    match value.parse::<u32>() {
+        Ok(v) if (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&v) => Ok(()),
+        _ => Err(RpcError::Rpc(format!(
+            "unsupported protocol version {value} (supported: {MIN_PROTOCOL_VERSION} to {PROTOCOL_VERSION})"
+        ))),
+    }
+}
+
+/// Returns the value of the [EXPIRES_HEADER] header for a request sent now with the timeout
+pub(crate) fn expires_header_value(timeout: Duration) -> String {
+    (Timestamp::now().as_nanos() + timeout.as_nanos()).to_string()
+}
+
+/// Parses the value of the [EXPIRES_HEADER] or [SENT_AT_HEADER] header
+pub(crate) fn parse_expires_header(value: &str) -> Option<Timestamp> {
+    let nanos: u128 = value.trim().parse().ok()?;
+    Some(Timestamp {
+        sec: i64::try_from(nanos / 1_000_000_000).ok()?,
+        nsec: (nanos % 1_000_000_000) as u32,
+    })
+}
+
+/// Tracing target of the records logged by [RpcClient::set_debug_logging]
+pub const RPC_LOG_TARGET: &str = "wasmbus_rpc::rpc_log";
+/// Default number of payload bytes shown in a debug log record
+pub const DEFAULT_PAYLOAD_PREVIEW: usize = 32;
+
+/// Logs one rpc message at debug level, with up to `max_preview` bytes of the payload in hex
+pub(crate) fn log_rpc(
+    direction: &str,
+    operation: &str,
+    latency: Option<Duration>,
+    payload: &[u8],
+    max_preview: usize,
+) {
+    let preview = payload_preview(payload, max_preview);
+    debug!(
+        target: RPC_LOG_TARGET,
+        %direction,
+        %operation,
+        ?latency,
+        payload_len = payload.len(),
+        payload = %preview,
+        "rpc"
+    );
+}
+
+/// Returns the hex of up to `max` bytes of the payload, with "..." if it was truncated
+fn payload_preview(payload: &[u8], max: usize) -> String {
+    let mut preview = data_encoding::HEXLOWER.encode(&payload[..payload.len().min(max)]);
+    if payload.len() > max {
+        preview.push_str("...");
+    }
+    preview
+}
+
+/// Default [ClientSubscription::set_lag_warning]
+pub const DEFAULT_LAG_WARNING: usize = 100;
+
+/// Most messages a [ClientSubscription] takes from its subscriber in one poll, so a busy
+/// subject whose messages are all filtered out doesn't keep the task from yielding
+const MAX_POLLED_MESSAGES: usize = 256;
+
+/// Messages received on a subscription made with [RpcClient::subscribe].
+/// Dropping it unsubscribes.
+#[derive(Debug)]
+pub struct ClientSubscription {
+    inner: crate::async_nats::Subscriber,
+    subject: String,
+    /// messages taken from the subscriber, but not yet consumed
+    buffered: VecDeque<crate::async_nats::Message>,
+    lag_warning: Option<usize>,
+    /// whether the lag is over the warning limit, so the warning is logged once each time it goes over
+    lagging: bool,
+    /// messages for which this returns false are dropped
+    filter: Option<MessageFilter>,
+    _slot: SubscriptionSlot,
+}
+
+/// Predicate of [RpcClient::subscribe_filtered]
+struct MessageFilter(Box<dyn Fn(&crate::async_nats::Message) -> bool + Send + Sync>);
+
+impl std::fmt::Debug for MessageFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("MessageFilter")
+    }
+}
+
+impl ClientSubscription {
+    /// Returns the number of messages that had been received but not consumed
+    /// when the last message was taken from the stream. The subscription looks ahead
+    /// only one message past the [lag warning](ClientSubscription::set_lag_warning) limit,
+    /// leaving the rest queued in the nats client, so the count stops there.
+    /// A count that stays high means the consumer is falling behind the publisher.
+    pub fn pending(&self) -> usize {
+        self.buffered.len()
+    }
+
+    /// Sets the number of [pending](ClientSubscription::pending) messages above which a
+    /// warning is logged, or None for no warning. Default is [DEFAULT_LAG_WARNING]
+    pub fn set_lag_warning(&mut self, limit: Option<usize>) {
+        self.lag_warning = limit;
+    }
+}
+
+impl futures::Stream for ClientSubscription {
+    type Item = crate::async_nats::Message;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        use std::task::Poll;
+
+        let this = &mut *self;
+        // look ahead just far enough to tell whether the consumer is past the lag limit.
+        // async-nats doesn't expose how many messages its subscriber holds
+        let wanted = 1 + this.lag_warning.map_or(0, |limit| limit + 1);
+        let mut ended = false;
+        let mut polled = 0;
+        while this.buffered.len() < wanted {
+            if polled == MAX_POLLED_MESSAGES {
+                if this.buffered.is_empty() {
+                    // more may be waiting: poll again after other tasks have run
+                    cx.waker().wake_by_ref();
+                }
+                break;
+            }
+            polled += 1;
+            match std::pin::Pin::new(&mut this.inner).poll_next(cx) {
+                Poll::Ready(Some(msg)) => {
+                    if this.filter.as_ref().map(|filter| (filter.0)(&msg)).unwrap_or(true) {
+                        this.buffered.push_back(msg)
+                    }
+                }
+                Poll::Ready(None) => {
+                    ended = true;
+                    break;
+                }
+                Poll::Pending => break,
+            }
+        }
+        let next = this.buffered.pop_front();
+        if let Some(limit) = this.lag_warning {
+            let lagging = this.buffered.len() > limit;
+            if lagging && !this.lagging {
+                warn!(subject = %this.subject, pending = this.buffered.len(), "subscription consumer is falling behind");
+            }
+            this.lagging = lagging;
+        }
+        match next {
+            Some(msg) => Poll::Ready(Some(msg)),
+            None if ended => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Subscriptions held by a client and its clones, and the limit on them
+#[derive(Debug)]
+struct SubscriptionCount {
+    held: AtomicUsize,
+    /// usize::MAX if there is no limit
+    max: AtomicUsize,
+}
+
+impl Default for SubscriptionCount {
+    fn default() -> Self {
+        SubscriptionCount {
+            held: AtomicUsize::new(0),
+            max: AtomicUsize::new(usize::MAX),
+        }
+    }
+}
+
+/// A place under the client's subscription limit, released when dropped
+#[derive(Debug)]
+struct SubscriptionSlot(Arc<SubscriptionCount>);
+
+impl Drop for SubscriptionSlot {
+    fn drop(&mut self) {
+        self.0.held.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Observer of the exact bytes an [RpcClient] sends and receives, for example for audit logs.
+/// `on_send` sees payloads after they are encoded, and `on_recv` sees payloads
+/// before they are decoded. Both are called inline, so they should return quickly.
+/// Install with [RpcClient::set_wire_tap], or for providers, [ProviderOptions::wire_tap](crate::provider::ProviderOptions::wire_tap).
+pub trait WireTap: Send + Sync {
+    /// Called with the subject and payload of each message sent
+    #[allow(unused_variables)]
+    fn on_send(&self, subject: &str, bytes: &[u8]) {}
+
+    /// Called with the subject, headers, and payload of each message sent.
+    /// The default calls `on_send`
+    #[allow(unused_variables)]
+    fn on_send_with_headers(&self, subject: &str, headers: Option<&HeaderMap>, bytes: &[u8]) {
+        self.on_send(subject, bytes)
+    }
+
+    /// Called with the subject and payload of each message received
+    #[allow(unused_variables)]
+    fn on_recv(&self, subject: &str, bytes: &[u8]) {}
+}
+
+impl fmt::Debug for dyn WireTap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WireTap")
+    }
+}
+
+/// Source of the ids of the invocations an [RpcClient] sends. The id is also the subject
+/// of the invocation's claims, so ids should be unique across the lattice.
+/// Install with [RpcClient::set_id_generator]; the default is [UuidGenerator].
+pub trait IdGenerator: Send + Sync {
+    /// Returns the id for the next invocation
+    fn next_id(&self) -> String;
+}
+
+impl fmt::Debug for dyn IdGenerator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("IdGenerator")
+    }
+}
+
+/// Generates a random uuid for each invocation, with [make_uuid]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UuidGenerator;
+
+impl IdGenerator for UuidGenerator {
+    fn next_id(&self) -> String {
+        make_uuid()
+    }
+}
 
 /// Send wasmbus rpc messages
 ///
@@ -53,6 +392,30 @@ pub struct RpcClient {
     host_id: String,
     /// timeout for rpc messages
     timeout: Option<Duration>,
+    /// round trip time to the nats server, measured after connecting
+    connect_rtt: Option<Duration>,
+    /// limit for message size, overriding the server's max_payload
+    max_payload: Option<usize>,
+    /// default timeouts for targets of particular contracts, overriding `timeout`
+    contract_timeouts: HashMap<String, Duration>,
+    /// lattice prefix, if the client was created from host data
+    lattice_prefix: Option<LatticePrefix>,
+    /// observer of sent and received bytes
+    wire_tap: Option<Arc<dyn WireTap>>,
+    /// source of invocation ids
+    id_generator: Arc<dyn IdGenerator>,
+    /// if set, each rpc is logged with up to this many bytes of its payload
+    debug_logging: Option<usize>,
+    /// counters reported by [RpcClient::stats], shared by clones
+    counters: Arc<ClientCounters>,
+    /// whether the connection has given up reconnecting
+    reconnect_limit: ReconnectLimit,
+    /// wakes outstanding requests when [RpcClient::abort_all] is called, shared by clones
+    aborts: Arc<tokio::sync::Notify>,
+    /// number of subscriptions held, and the limit on them, shared by clones
+    subscriptions: Arc<SubscriptionCount>,
+    /// how long a send waits for the initial connection, if the client checks
+    connect_grace: Option<Duration>,
 
     #[cfg(feature = "prometheus")]
     pub(crate) stats: Arc<RpcStats>,
@@ -65,6 +428,85 @@ impl fmt::Debug for RpcClient {
     }
 }
 
+/// An [RpcClient] that connects to nats when it's first used, for tools and short-lived
+/// processes that may finish without sending anything. It connects with the host's settings,
+/// as [RpcClient::from_host_data] does, and keeps the connection for later calls.
+/// If connecting fails, the call that needed the connection returns the error,
+/// and the next call tries again. Clones share the connection.
+#[derive(Clone)]
+pub struct LazyRpcClient {
+    host_data: Arc<HostData>,
+    client: Arc<tokio::sync::OnceCell<RpcClient>>,
+}
+
+impl fmt::Debug for LazyRpcClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LazyRpcClient()")
+    }
+}
+
+impl LazyRpcClient {
+    /// Constructs a client that will connect with the host data's settings. Nothing is
+    /// checked until the first call, so invalid settings are reported then
+    pub fn new(host_data: HostData) -> LazyRpcClient {
+        LazyRpcClient {
+            host_data: Arc::new(host_data),
+            client: Arc::new(tokio::sync::OnceCell::new()),
+        }
+    }
+
+    /// Returns true if the client has connected
+    pub fn is_connected(&self) -> bool {
+        self.client.initialized()
+    }
+
+    /// Returns the connected client, connecting if this is the first use
+    pub async fn client(&self) -> RpcResult<&RpcClient> {
+        self.client
+            .get_or_try_init(|| RpcClient::from_host_data(&self.host_data))
+            .await
+    }
+
+    /// Sends an rpc message, connecting first if needed. See [RpcClient::send]
+    pub async fn send<Target>(
+        &self,
+        origin: WasmCloudEntity,
+        target: Target,
+        lattice: &str,
+        message: Message<'_>,
+    ) -> RpcResult<Vec<u8>>
+    where
+        Target: Into<WasmCloudEntity>,
+    {
+        self.client().await?.send(origin, target, lattice, message).await
+    }
+
+    /// Sends an rpc message with send options, connecting first if needed.
+    /// See [RpcClient::send_with_opts]
+    pub async fn send_with_opts<Target>(
+        &self,
+        origin: WasmCloudEntity,
+        target: Target,
+        lattice: &str,
+        message: Message<'_>,
+        opts: &SendOpts,
+        timeout: Option<Duration>,
+    ) -> RpcResult<Vec<u8>>
+    where
+        Target: Into<WasmCloudEntity>,
+    {
+        self.client()
+            .await?
+            .send_with_opts(origin, target, lattice, message, opts, timeout)
+            .await
+    }
+
+    /// Publishes a nats message, connecting first if needed. See [RpcClient::publish]
+    pub async fn publish(&self, subject: String, payload: Vec<u8>) -> RpcResult<()> {
+        self.client().await?.publish(subject, payload).await
+    }
+}
+
 #[cfg(feature = "prometheus")]
 #[derive(Debug)]
 pub struct RpcStats {
@@ -97,20 +539,181 @@ pub struct RpcStats {
     pub(crate) rpc_recv_resp_bytes: IntCounter,
 }
 
+/// Information about the nats server an RpcClient is connected to
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct ServerInfo {
+    /// The unique identifier of the server
+    pub server_id: String,
+    /// The configured name of the server
+    pub server_name: String,
+    /// nats server version
+    pub version: String,
+    /// Maximum message payload size the server accepts, in bytes
+    pub max_payload: usize,
+    /// Round trip time measured right after connecting, if known.
+    /// This is set for connections made by the provider's HostBridge.
+    pub connect_rtt: Option<Duration>,
+}
+
+/// Counters behind [ClientStats]
+#[derive(Debug, Default)]
+struct ClientCounters {
+    requests_sent: AtomicU64,
+    requests_failed: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_recv: AtomicU64,
+}
+
+/// Activity of an [RpcClient] and its clones, returned by [RpcClient::stats].
+/// Unlike the `prometheus` metrics, these are always kept.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ClientStats {
+    /// Number of times the client's nats connection was re-established after
+    /// being disconnected, counted by the client's [ReconnectLimit] when the
+    /// connection options came from [with_connection_events], which includes
+    /// connections made from host data. Connections made otherwise are not counted.
+    pub reconnects: u64,
+    /// Number of rpc requests sent
+    pub requests_sent: u64,
+    /// Number of rpc sends that failed, including timeouts and error responses
+    pub requests_failed: u64,
+    /// Bytes of rpc requests sent, after serialization
+    pub bytes_sent: u64,
+    /// Bytes of responses received
+    pub bytes_recv: u64,
+}
+
+/// Returns the headers the client adds to each message, such as for tracing,
+/// with the extra headers
+fn with_client_headers(extra: Option<HeaderMap>) -> Option<HeaderMap> {
+    #[cfg(feature = "otel")]
+    let headers: Option<HeaderMap> = Some(OtelHeaderInjector::default_with_span().into());
+    #[cfg(not(feature = "otel"))]
+    let headers: Option<HeaderMap> = None;
+    match (headers, extra) {
+        (Some(mut headers), Some(extra)) => {
+            for (name, value) in extra.iter() {
+                headers.insert(name.clone(), value.clone());
+            }
+            Some(headers)
+        }
+        (headers, None) => headers,
+        (None, extra) => extra,
+    }
+}
+
+/// Reports a failed request as a nats error, except for a corrupted reply
+fn nats_request_error(error: RpcError) -> RpcError {
+    match error {
+        RpcError::Deser(_) | RpcError::Other(_) | RpcError::NotInitialized(_) => error,
+        error => RpcError::Nats(error.to_string()),
+    }
+}
+
+/// Returns the nats headers that carry the send options, if any are needed
+fn opts_headers(opts: &SendOpts) -> Option<HeaderMap> {
+    if opts.headers.is_empty()
+        && opts.idempotency_key.is_none()
+        && opts.schema_hash.is_none()
+        && opts.priority == Priority::Normal
+        && !opts.send_time
+        && opts.accept_formats.is_empty()
+        && opts.format.is_none()
+        && !opts.accept_compression
+    {
+        return None;
+    }
+    let mut headers = HeaderMap::new();
+    for (name, value) in opts.headers.iter() {
+        headers.append(name.as_str(), value);
+    }
+    if let Some(key) = opts.idempotency_key.as_deref() {
+        headers.insert(IDEMPOTENCY_KEY_HEADER, key);
+    }
+    if let Some(hash) = opts.schema_hash {
+        headers.insert(SCHEMA_HASH_HEADER, format!("{hash:016x}").as_str());
+    }
+    if opts.priority != Priority::Normal {
+        headers.insert(PRIORITY_HEADER, opts.priority.as_str());
+    }
+    if opts.send_time {
+        headers.insert(
+            SENT_AT_HEADER,
+            Timestamp::now().as_nanos().to_string().as_str(),
+        );
+    }
+    if opts.accept_compression {
+        headers.insert(ACCEPT_ENCODING_HEADER, DEFLATE_ENCODING);
+    }
+    if !opts.accept_formats.is_empty() {
+        let names: Vec<String> = opts.accept_formats.iter().map(|f| f.to_string()).collect();
+        headers.insert(ACCEPT_FORMATS_HEADER, names.join(",").as_str());
+    }
+    if let Some(format) = opts.format {
+        headers.insert(FORMAT_HEADER, format.to_string().as_str());
+    }
+    Some(headers)
+}
+
+/// Prefix of the headers wasmbus sends itself, such as [FORMAT_HEADER]
+const RESERVED_HEADER_PREFIX: &str = "wasmbus-";
+
+/// Checks that a custom header (see [SendOpts::headers]) can be sent: its name is not empty,
+/// has only ascii letters, digits, and '-', and doesn't start with the `Wasmbus-` prefix
+/// reserved for headers the rpc layer sends itself, in any case; and its value has no line breaks
+pub fn check_header(name: &str, value: &str) -> RpcResult<()> {
+    check_header_syntax(name, value)?;
+    if name.get(..RESERVED_HEADER_PREFIX.len()).map_or(false, |prefix| {
+        prefix.eq_ignore_ascii_case(RESERVED_HEADER_PREFIX)
+    }) {
+        return Err(RpcError::InvalidParameter(format!(
+            "header name '{name}' uses the reserved Wasmbus- prefix"
+        )));
+    }
+    Ok(())
+}
+
+/// Checks that a header can be written to a nats message: its name is not empty and
+/// has only ascii letters, digits, and '-', and its value has no line breaks.
+/// Unlike [check_header], `Wasmbus-` names are allowed.
+pub(crate) fn check_header_syntax(name: &str, value: &str) -> RpcResult<()> {
+    if name.is_empty() || name.parse::<crate::async_nats::HeaderName>().is_err() {
+        return Err(RpcError::InvalidParameter(format!(
+            "invalid header name '{name}'"
+        )));
+    }
+    if value.contains(['\r', '\n']) {
+        return Err(RpcError::InvalidParameter(format!(
+            "value of header '{name}' may not contain line breaks"
+        )));
+    }
+    Ok(())
+}
+
 /// Returns the rpc topic (subject) name for sending to an actor or provider.
 /// A provider entity must have the public_key and link_name fields filled in.
 /// An actor entity must have a public_key and an empty link_name.
+/// An empty lattice prefix is replaced by [DEFAULT_LATTICE_PREFIX].
 #[doc(hidden)]
 pub fn rpc_topic(entity: &WasmCloudEntity, lattice_prefix: &str) -> String {
+    let lattice_prefix = crate::core::lattice_prefix_or_default(lattice_prefix);
     if !entity.link_name.is_empty() {
         // provider target
         format!(
             "wasmbus.rpc.{}.{}.{}",
-            lattice_prefix, entity.public_key, entity.link_name
+            lattice_prefix,
+            sanitize_subject_token(&entity.public_key),
+            sanitize_subject_token(&entity.link_name)
         )
     } else {
         // actor target
-        format!("wasmbus.rpc.{}.{}", lattice_prefix, entity.public_key)
+        format!(
+            "wasmbus.rpc.{}.{}",
+            lattice_prefix,
+            sanitize_subject_token(&entity.public_key)
+        )
     }
 }
 
@@ -140,12 +743,110 @@ impl RpcClient {
             client: nats,
             host_id,
             timeout,
+            connect_rtt: None,
+            max_payload: None,
+            contract_timeouts: HashMap::new(),
+            lattice_prefix: None,
+            wire_tap: None,
+            id_generator: Arc::new(UuidGenerator),
+            debug_logging: None,
+            counters: Arc::new(ClientCounters::default()),
+            reconnect_limit: ReconnectLimit::default(),
+            aborts: Arc::new(tokio::sync::Notify::new()),
+            subscriptions: Arc::new(SubscriptionCount::default()),
+            connect_grace: None,
             #[cfg(feature = "prometheus")]
             stats: Arc::new(RpcStats::init(key_pair.public_key())),
             key: key_pair,
         }
     }
 
+    /// Connects to nats with the settings provided by the host, and returns a client
+    /// that signs invocations with the host's invocation key, and uses the host's
+    /// default rpc timeout and lattice prefix.
+    /// This is for provider code that needs its own client; providers run by
+    /// [provider_main](crate::provider::prelude::provider_main) can use the [HostBridge](crate::provider::HostBridge)'s client.
+    pub async fn from_host_data(host_data: &HostData) -> RpcResult<RpcClient> {
+        let lattice_prefix = host_data.lattice_prefix()?;
+        let key = Arc::new(host_data.invocation_key_pair()?);
+        let (nc, rtt) = host_data
+            .nats_connect_measured(
+                &host_data.nats_connection_name(),
+                DEFAULT_RECONNECT_MAX_BACKOFF,
+                &ReconnectLimit::default(),
+                DEFAULT_CONNECT_TIMEOUT,
+            )
+            .await?;
+        let mut client = RpcClient::new_client(
+            nc,
+            host_data.host_id.clone(),
+            host_data.default_rpc_timeout_ms.map(Duration::from_millis),
+            key,
+        );
+        client.set_connect_rtt(Some(rtt));
+        client.lattice_prefix = Some(lattice_prefix);
+        Ok(client)
+    }
+
+    /// Returns the lattice prefix, for clients created with [RpcClient::from_host_data]
+    /// or given one with [RpcClient::set_lattice_prefix]
+    pub fn lattice_prefix(&self) -> Option<&str> {
+        self.lattice_prefix.as_ref().map(LatticePrefix::as_str)
+    }
+
+    /// Sets the lattice prefix used by [RpcClient::call_actor]
+    pub fn set_lattice_prefix(&mut self, prefix: LatticePrefix) {
+        self.lattice_prefix = Some(prefix);
+    }
+
+    /// Installs an observer of the bytes this client sends and receives,
+    /// replacing any previous one. See [WireTap]
+    pub fn set_wire_tap(&mut self, tap: Arc<dyn WireTap>) {
+        self.wire_tap = Some(tap);
+    }
+
+    /// Replaces the source of invocation ids, for example with a deterministic
+    /// generator in tests. See [IdGenerator]
+    pub fn set_id_generator(&mut self, generator: Arc<dyn IdGenerator>) {
+        self.id_generator = generator;
+    }
+
+    /// Turns on logging of each rpc this client sends, and for a provider's client,
+    /// each rpc the provider handles: its operation, direction, latency,
+    /// and the hex of up to `max_preview` bytes of the payload
+    /// (see [DEFAULT_PAYLOAD_PREVIEW]). Records are logged at debug level
+    /// with the target [RPC_LOG_TARGET]. If the parameter is None, logging is off.
+    pub fn set_debug_logging(&mut self, max_preview: Option<usize>) {
+        self.debug_logging = max_preview;
+    }
+
+    /// Sets the reconnect limit of the client's connection, the one passed to
+    /// [ReconnectLimit::apply] and [with_connection_events] when connecting.
+    /// Once the connection is lost, requests and publishes fail with `RpcError::Nats`
+    /// without being sent. The limit also counts the reconnects in [stats](RpcClient::stats).
+    pub fn set_reconnect_limit(&mut self, limit: ReconnectLimit) {
+        self.reconnect_limit = limit;
+    }
+
+    /// Returns the payload preview length, if debug logging is on
+    pub(crate) fn debug_logging(&self) -> Option<usize> {
+        self.debug_logging
+    }
+
+    /// Reports bytes about to be sent to the wire tap, if any
+    pub(crate) fn tap_send(&self, subject: &str, headers: Option<&HeaderMap>, bytes: &[u8]) {
+        if let Some(tap) = &self.wire_tap {
+            tap.on_send_with_headers(subject, headers, bytes);
+        }
+    }
+
+    /// Reports received bytes to the wire tap, if any
+    pub(crate) fn tap_recv(&self, subject: &str, bytes: &[u8]) {
+        if let Some(tap) = &self.wire_tap {
+            tap.on_recv(subject, bytes);
+        }
+    }
+
     /// convenience method for returning async client
     pub fn client(&self) -> Client {
         self.client.clone()
@@ -157,6 +858,190 @@ impl RpcClient {
         self.timeout = timeout;
     }
 
+    /// Sets the default timeout for requests to targets with this contract id,
+    /// for contracts whose operations are slower or faster than most, such as a blobstore.
+    /// It replaces the client's default timeout if neither the `SendOpts`
+    /// nor the caller specifies one. If the parameter is None, unsets the contract's timeout.
+    pub fn set_contract_timeout(&mut self, contract_id: &str, timeout: Option<Duration>) {
+        match timeout {
+            Some(timeout) => {
+                self.contract_timeouts.insert(contract_id.to_string(), timeout);
+            }
+            None => {
+                self.contract_timeouts.remove(contract_id);
+            }
+        }
+    }
+
+    /// Returns the default timeout for requests to the target:
+    /// its contract's timeout, if set, otherwise the client's default timeout
+    pub fn default_timeout_for(&self, target: &WasmCloudEntity) -> Option<Duration> {
+        self.contract_timeouts.get(&target.contract_id).copied().or(self.timeout)
+    }
+
+    /// Sets the round trip time measured when the nats connection was established,
+    /// which is reported by [RpcClient::server_info]
+    pub fn set_connect_rtt(&mut self, rtt: Option<Duration>) {
+        self.connect_rtt = rtt;
+    }
+
+    /// Sets the size limit, in bytes, for serialized messages sent by this client.
+    /// Messages over the limit fail with RpcError::InvalidParameter before they are sent.
+    /// If None (the default), the max_payload advertised by the server is used.
+    pub fn set_max_payload(&mut self, max_payload: Option<usize>) {
+        self.max_payload = max_payload;
+    }
+
+    /// Sets the limit on subscriptions this client and its clones hold at once,
+    /// including those for streamed and gathered replies. When the limit is reached,
+    /// subscribing fails with `RpcError::Other("subscription limit reached")`
+    /// until a subscription is dropped. If None (the default), there is no limit.
+    /// Clones share one limit, so setting it on any clone changes it for all of them.
+    pub fn set_max_subscriptions(&mut self, max: Option<usize>) {
+        let max = max.unwrap_or(usize::MAX);
+        self.subscriptions.max.store(max, Ordering::Release);
+    }
+
+    /// Sets how long a send made before the nats connection is first established waits
+    /// for it. A send still waiting after the grace period fails with
+    /// `RpcError::NotInitialized("connection not ready")`, without being queued.
+    /// If None (the default), sends are queued by the nats client until it connects.
+    /// Sends made while reconnecting are not affected.
+    pub fn set_connect_grace(&mut self, grace: Option<Duration>) {
+        self.connect_grace = grace;
+    }
+
+    /// Waits, up to the connect grace period, until the nats connection is first established
+    async fn wait_for_connection(&self) -> RpcResult<()> {
+        let grace = match self.connect_grace {
+            Some(grace) => grace,
+            None => return Ok(()),
+        };
+        let started = std::time::Instant::now();
+        while self.client.connection_state() == async_nats::connection::State::Pending {
+            if started.elapsed() >= grace {
+                return Err(RpcError::NotInitialized(CONNECTION_NOT_READY.to_string()));
+            }
+            tokio::time::sleep(CONNECT_POLL_INTERVAL.min(grace - started.elapsed())).await;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of subscriptions held by this client and its clones
+    pub fn active_subscriptions(&self) -> usize {
+        self.subscriptions.held.load(Ordering::Relaxed)
+    }
+
+    /// Subscribes to the subject. Dropping the subscription unsubscribes,
+    /// and releases its place under the limit set by [set_max_subscriptions](RpcClient::set_max_subscriptions).
+    pub async fn subscribe(&self, subject: String) -> RpcResult<ClientSubscription> {
+        self.subscribe_with_filter(subject, None).await
+    }
+
+    /// Subscribes to the subject, as [subscribe](RpcClient::subscribe) does, keeping only
+    /// the messages for which the predicate returns true. It's called with each message
+    /// as received, so it can check the headers before the payload is decoded.
+    /// Dropped messages don't count as [pending](ClientSubscription::pending).
+    pub async fn subscribe_filtered<F>(
+        &self,
+        subject: String,
+        predicate: F,
+    ) -> RpcResult<ClientSubscription>
+    where
+        F: Fn(&crate::async_nats::Message) -> bool + Send + Sync + 'static,
+    {
+        let filter = MessageFilter(Box::new(predicate));
+        self.subscribe_with_filter(subject, Some(filter)).await
+    }
+
+    async fn subscribe_with_filter(
+        &self,
+        subject: String,
+        filter: Option<MessageFilter>,
+    ) -> RpcResult<ClientSubscription> {
+        let slot = self.subscription_slot()?;
+        let inner = self
+            .client
+            .subscribe(subject.clone())
+            .await
+            .map_err(|e| RpcError::Nats(e.to_string()))?;
+        Ok(ClientSubscription {
+            inner,
+            subject,
+            buffered: VecDeque::new(),
+            lag_warning: Some(DEFAULT_LAG_WARNING),
+            lagging: false,
+            filter,
+            _slot: slot,
+        })
+    }
+
+    /// Takes a place under the subscription limit, or returns an error if none is left
+    fn subscription_slot(&self) -> RpcResult<SubscriptionSlot> {
+        let max = self.subscriptions.max.load(Ordering::Acquire);
+        self.subscriptions
+            .held
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < max).then(|| n + 1)
+            })
+            .map_err(|_| RpcError::Other("subscription limit reached".to_string()))?;
+        Ok(SubscriptionSlot(self.subscriptions.clone()))
+    }
+
+    /// Returns an error if a message of this size is larger than the server accepts
+    fn check_payload_size(&self, len: usize) -> RpcResult<()> {
+        // max_payload is 0 if the client hasn't connected yet
+        let max = self.max_payload.unwrap_or_else(|| self.client.server_info().max_payload);
+        if max > 0 && len > max {
+            return Err(RpcError::InvalidParameter(format!(
+                "message exceeds max payload {len} > {max}. Try sending large data in chunks, or as a stream"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns information about the nats server, as reported by the server on the
+    /// most recent connect, and the connect round trip time, if it was measured.
+    pub fn server_info(&self) -> ServerInfo {
+        let info = self.client.server_info();
+        ServerInfo {
+            server_id: info.server_id,
+            server_name: info.server_name,
+            version: info.version,
+            max_payload: info.max_payload,
+            connect_rtt: self.connect_rtt,
+        }
+    }
+
+    /// Returns the client's counters, which are shared with its clones
+    pub fn stats(&self) -> ClientStats {
+        let c = &self.counters;
+        ClientStats {
+            reconnects: self.reconnect_limit.reconnects(),
+            requests_sent: c.requests_sent.load(Ordering::Relaxed),
+            requests_failed: c.requests_failed.load(Ordering::Relaxed),
+            bytes_sent: c.bytes_sent.load(Ordering::Relaxed),
+            bytes_recv: c.bytes_recv.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Cancels the requests, on this client and its clones, that are waiting for a response.
+    /// Each returns `RpcError::Other("client shutting down")`. This includes streamed replies
+    /// from [subscribe_replies](RpcClient::subscribe_replies), which end with that error,
+    /// [scatter_gather](RpcClient::scatter_gather), and requests on a
+    /// [reply_mux](RpcClient::reply_mux).
+    /// Requests sent after this returns are not affected.
+    pub fn abort_all(&self) {
+        self.aborts.notify_waiters();
+    }
+
+    /// Counts a failed send
+    fn count_result<T>(&self, rc: &RpcResult<T>) {
+        if rc.is_err() {
+            self.counters.requests_failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
     /// Send an rpc message using json-encoded data
     pub async fn send_json<Target, Resp>(
         &self,
@@ -179,8 +1064,9 @@ impl RpcClient {
     /// Send a wasmbus rpc message by wrapping with an Invocation before sending over nats.
     /// 'target' may be &str or String for sending to an actor, or a WasmCloudEntity (for actor or provider)
     /// If a response is not received within the default timeout, the Error RpcError::Timeout is returned.
-    /// If the client timeout has been set, this call is equivalent to send_timeout passing in the
-    /// default timeout.
+    /// The default timeout is the target contract's timeout (see [RpcClient::set_contract_timeout]),
+    /// or the client timeout. If either has been set, this call is equivalent to send_timeout
+    /// passing in that timeout.
     pub async fn send<Target>(
         &self,
         origin: WasmCloudEntity,
@@ -191,7 +1077,14 @@ impl RpcClient {
     where
         Target: Into<WasmCloudEntity>,
     {
-        let rc = self.inner_rpc(origin, target, lattice, message, true, self.timeout).await;
+        let target = target.into();
+        let timeout = self.default_timeout_for(&target);
+        let method = message.method;
+        let rc = self
+            .inner_rpc(origin, target, lattice, message, true, timeout, None)
+            .await
+            .map_err(|e| with_operation(e, method));
+        self.count_result(&rc);
         #[cfg(feature = "prometheus")]
         {
             if rc.is_err() {
@@ -201,6 +1094,34 @@ impl RpcClient {
         rc
     }
 
+    /// Sends a message to the actor with the public key `target`, and returns its response.
+    /// This is a shorter form of [send](RpcClient::send) for actor targets:
+    /// the origin is this client's signing key, the lattice is the client's
+    /// [lattice_prefix](RpcClient::lattice_prefix), or "default",
+    /// and the timeout is the client timeout.
+    /// Returns `RpcError::InvalidParameter`, without sending, if `target` isn't an actor key.
+    pub async fn call_actor(
+        &self,
+        target: &str,
+        operation: &str,
+        arg: &[u8],
+    ) -> RpcResult<Vec<u8>> {
+        let target = WasmCloudEntity::new_actor(target)?;
+        target.validate()?;
+        let origin = WasmCloudEntity {
+            public_key: self.key.public_key(),
+            ..Default::default()
+        };
+        let lattice = self.lattice_prefix().unwrap_or(DEFAULT_LATTICE_PREFIX);
+        let message = Message { method: operation, arg: arg.into() };
+        let rc = self
+            .inner_rpc(origin, target, lattice, message, true, self.timeout, None)
+            .await
+            .map_err(|e| with_operation(e, operation));
+        self.count_result(&rc);
+        rc
+    }
+
     /// Send a wasmbus rpc message, with a timeout.
     /// The rpc message is wrapped with an Invocation before sending over nats.
     /// 'target' may be &str or String for sending to an actor, or a WasmCloudEntity (for actor or provider)
@@ -216,7 +1137,12 @@ impl RpcClient {
     where
         Target: Into<WasmCloudEntity>,
     {
-        let rc = self.inner_rpc(origin, target, lattice, message, true, Some(timeout)).await;
+        let method = message.method;
+        let rc = self
+            .inner_rpc(origin, target, lattice, message, true, Some(timeout), None)
+            .await
+            .map_err(|e| with_operation(e, method));
+        self.count_result(&rc);
         #[cfg(feature = "prometheus")]
         {
             if rc.is_err() {
@@ -226,11 +1152,60 @@ impl RpcClient {
         rc
     }
 
-    /// Send a wasmbus rpc message without waiting for response.
-    /// This has somewhat limited utility and is only useful if
-    /// the message is declared to return no args, or if the caller
-    /// doesn't care about the response.
-    /// 'target' may be &str or String for sending to an actor,
+    /// Send a wasmbus rpc message with send options.
+    /// If the options contain an idempotency key, it is sent in the
+    /// [IDEMPOTENCY_KEY_HEADER] header so the receiver can detect retries.
+    /// A schema hash is sent in the [SCHEMA_HASH_HEADER] header.
+    /// With [SendOpts::checksum], the message's checksum is sent in the [CHECKSUM_HEADER] header,
+    /// and the response is checked against its own.
+    /// With [SendOpts::no_reply], the message is published with no reply subject,
+    /// and an empty response is returned without waiting.
+    /// [SendOpts::headers] are checked with [check_header] and sent with the message.
+    /// The timeout is chosen by [SendOpts::effective_timeout]: the options' timeout,
+    /// if set, otherwise `timeout`, otherwise the default timeout for the target
+    /// (see [RpcClient::default_timeout_for]).
+    pub async fn send_with_opts<Target>(
+        &self,
+        origin: WasmCloudEntity,
+        target: Target,
+        lattice: &str,
+        message: Message<'_>,
+        opts: &SendOpts,
+        timeout: Option<Duration>,
+    ) -> RpcResult<Vec<u8>>
+    where
+        Target: Into<WasmCloudEntity>,
+    {
+        let target = target.into();
+        let timeout = opts.effective_timeout(timeout.or_else(|| self.default_timeout_for(&target)));
+        let method = message.method;
+        let rc = self
+            .inner_rpc(
+                origin,
+                target,
+                lattice,
+                message,
+                !opts.no_reply,
+                timeout,
+                Some(opts),
+            )
+            .await
+            .map_err(|e| with_operation(e, method));
+        self.count_result(&rc);
+        #[cfg(feature = "prometheus")]
+        {
+            if rc.is_err() {
+                self.stats.rpc_sent_err.inc();
+            }
+        }
+        rc
+    }
+
+    /// Send a wasmbus rpc message without waiting for response.
+    /// This has somewhat limited utility and is only useful if
+    /// the message is declared to return no args, or if the caller
+    /// doesn't care about the response.
+    /// 'target' may be &str or String for sending to an actor,
     /// or a WasmCloudEntity (for actor or provider)
     #[doc(hidden)]
     pub async fn post<Target>(
@@ -243,7 +1218,8 @@ impl RpcClient {
     where
         Target: Into<WasmCloudEntity>,
     {
-        let rc = self.inner_rpc(origin, target, lattice, message, false, None).await;
+        let rc = self.inner_rpc(origin, target, lattice, message, false, None, None).await;
+        self.count_result(&rc);
         match rc {
             Err(e) => {
                 #[cfg(feature = "prometheus")]
@@ -256,6 +1232,7 @@ impl RpcClient {
 
     /// request or publish an rpc invocation
     #[instrument(level = "debug", skip(self, origin, target, message), fields( provider_id = tracing::field::Empty, method = tracing::field::Empty, lattice_id = tracing::field::Empty, subject = tracing::field::Empty, issuer = tracing::field::Empty, sender_key = tracing::field::Empty, contract_id = tracing::field::Empty, link_name = tracing::field::Empty, target_key = tracing::field::Empty ))]
+    #[allow(clippy::too_many_arguments)]
     async fn inner_rpc<Target>(
         &self,
         origin: WasmCloudEntity,
@@ -264,13 +1241,15 @@ impl RpcClient {
         message: Message<'_>,
         expect_response: bool,
         timeout: Option<Duration>,
+        opts: Option<&SendOpts>,
     ) -> RpcResult<Vec<u8>>
     where
         Target: Into<WasmCloudEntity>,
     {
+        self.reconnect_limit.check()?;
         let target = target.into();
         let origin_url = origin.url();
-        let subject = make_uuid();
+        let subject = self.id_generator.next_id();
         let issuer = self.key.public_key();
         let raw_target_url = target.url();
         let target_url = format!("{}/{}", raw_target_url, &message.method);
@@ -279,23 +1258,23 @@ impl RpcClient {
         // record here after we generate/derive the values
         let span = tracing::span::Span::current();
         if let Some(hb) = get_host_bridge_safe() {
-            span.record("provider_id", &tracing::field::display(&hb.provider_key()));
+            span.record("provider_id", tracing::field::display(&hb.provider_key()));
         }
-        span.record("method", &tracing::field::display(&message.method));
-        span.record("lattice_id", &tracing::field::display(&lattice));
-        span.record("subject", &tracing::field::display(&subject));
-        span.record("issuer", &tracing::field::display(&issuer));
+        span.record("method", tracing::field::display(&message.method));
+        span.record("lattice_id", tracing::field::display(&lattice));
+        span.record("subject", tracing::field::display(&subject));
+        span.record("issuer", tracing::field::display(&issuer));
         if !origin.public_key.is_empty() {
-            span.record("sender_key", &tracing::field::display(&origin.public_key));
+            span.record("sender_key", tracing::field::display(&origin.public_key));
         }
         if !target.contract_id.is_empty() {
-            span.record("contract_id", &tracing::field::display(&target.contract_id));
+            span.record("contract_id", tracing::field::display(&target.contract_id));
         }
         if !target.link_name.is_empty() {
-            span.record("link_name", &tracing::field::display(&target.link_name));
+            span.record("link_name", tracing::field::display(&target.link_name));
         }
         if !target.public_key.is_empty() {
-            span.record("target_key", &tracing::field::display(&target.public_key));
+            span.record("target_key", tracing::field::display(&target.public_key));
         }
         //debug!("rpc_client sending");
         let claims = Claims::<jwt::Invocation>::new(
@@ -306,11 +1285,23 @@ impl RpcClient {
             &invocation_hash(&target_url, &origin_url, message.method, &message.arg),
         );
 
+        for (name, value) in opts.iter().flat_map(|o| o.headers.iter()) {
+            check_header(name, value)?;
+        }
         let topic = rpc_topic(&target, lattice);
+        let mut headers = opts.and_then(opts_headers);
+        headers.get_or_insert_with(HeaderMap::new).insert(
+            PROTOCOL_VERSION_HEADER,
+            PROTOCOL_VERSION.to_string().as_str(),
+        );
         let method = message.method.to_string();
         let len = message.arg.len();
         let chunkify = needs_chunking(len);
 
+        let started = std::time::Instant::now();
+        if let Some(max) = self.debug_logging {
+            log_rpc("request", &method, None, &message.arg, max);
+        }
         let (invocation, body) = {
             let mut inv = Invocation {
                 origin,
@@ -330,6 +1321,12 @@ impl RpcClient {
             }
         };
         let nats_body = crate::common::serialize(&invocation)?;
+        self.check_payload_size(nats_body.len())?;
+        if opts.map_or(false, |o| o.checksum) {
+            headers
+                .get_or_insert_with(HeaderMap::new)
+                .insert(CHECKSUM_HEADER, payload_checksum(&nats_body).as_str());
+        }
         if let Some(body) = body {
             let inv_id = invocation.id.clone();
             debug!(invocation_id = %inv_id, %len, "chunkifying invocation");
@@ -355,6 +1352,10 @@ impl RpcClient {
             timeout
         };
 
+        self.counters.requests_sent.fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .bytes_sent
+            .fetch_add(nats_body.len() as u64, Ordering::Relaxed);
         #[cfg(feature = "prometheus")]
         {
             self.stats.rpc_sent.inc();
@@ -366,28 +1367,41 @@ impl RpcClient {
             }
         }
         if expect_response {
+            // stamp the expiry, so a receiver that dequeues the request
+            // after the sender has given up can skip it
+            if let Some(timeout) = timeout.or(self.timeout) {
+                headers
+                    .get_or_insert_with(HeaderMap::new)
+                    .insert(EXPIRES_HEADER, expires_header_value(timeout).as_str());
+            }
             let this = self.clone();
             let topic_ = topic.clone();
             let payload = if let Some(timeout) = timeout {
-                match tokio::time::timeout(timeout, this.request(topic, nats_body)).await {
+                match tokio::time::timeout(
+                    timeout,
+                    this.request_with_extra_headers(topic, headers, nats_body),
+                )
+                .await
+                {
                     Err(elapsed) => {
                         #[cfg(feature = "prometheus")]
                         self.stats.rpc_sent_timeouts.inc();
                         Err(RpcError::Timeout(elapsed.to_string()))
                     }
                     Ok(Ok(data)) => Ok(data),
-                    Ok(Err(err)) => Err(RpcError::Nats(err.to_string())),
+                    Ok(Err(err)) => Err(nats_request_error(err)),
                 }
             } else {
-                this.request(topic, nats_body)
+                this.request_with_extra_headers(topic, headers, nats_body)
                     .await
-                    .map_err(|e| RpcError::Nats(e.to_string()))
+                    .map_err(nats_request_error)
             }
             .map_err(|error| {
                 error!(%error, topic=%topic_, "sending request");
                 error
             })?;
 
+            self.counters.bytes_recv.fetch_add(payload.len() as u64, Ordering::Relaxed);
             let inv_response =
                 crate::common::deserialize::<InvocationResponse>(&payload).map_err(|e| {
                     RpcError::Deser(format!("response to {}: {}", &method, &e.to_string()))
@@ -414,16 +1428,19 @@ impl RpcClient {
                         inv_response.msg
                     };
                     trace!("rpc ok response");
+                    if let Some(max) = self.debug_logging {
+                        log_rpc("response", &method, Some(started.elapsed()), &msg, max);
+                    }
                     Ok(msg)
                 }
                 Some(err) => {
-                    // if error is Some(_), we must ignore the msg field
+                    // if error is Some(_), msg holds nothing but an optional domain error
                     error!(error = %err, "rpc error response");
-                    Err(RpcError::Rpc(err))
+                    Err(crate::error::error_from_response(err, &inv_response.msg))
                 }
             }
         } else {
-            self.publish(topic, nats_body)
+            self.publish_with_extra_headers(topic, headers, nats_body)
                 .await
                 .map_err(|e| RpcError::Nats(format!("publish error: {target_url}: {e}")))?;
             Ok(Vec::new())
@@ -436,39 +1453,173 @@ impl RpcClient {
     /// the appropriate time, an error will be returned.
     #[instrument(level = "debug", skip_all, fields(subject = %subject))]
     pub async fn request(&self, subject: String, payload: Vec<u8>) -> RpcResult<Vec<u8>> {
-        #[cfg(feature = "otel")]
-        let headers: Option<HeaderMap> = Some(OtelHeaderInjector::default_with_span().into());
-        #[cfg(not(feature = "otel"))]
-        let headers: Option<HeaderMap> = None;
+        self.request_with_extra_headers(subject, None, payload).await
+    }
 
+    /// Send a nats request, adding `extra` headers to any tracing headers
+    async fn request_with_extra_headers(
+        &self,
+        subject: String,
+        extra: Option<HeaderMap>,
+        payload: Vec<u8>,
+    ) -> RpcResult<Vec<u8>> {
+        self.reconnect_limit.check()?;
+        self.wait_for_connection().await?;
+        let headers = with_client_headers(extra);
+        self.tap_send(&subject, headers.as_ref(), &payload);
         let nc = self.client();
-        match self
-            .maybe_timeout(self.timeout, async move {
-                if let Some(headers) = headers {
-                    nc.request_with_headers(subject, headers, payload.into()).await
-                } else {
-                    nc.request(subject, payload.into()).await
-                }
-            })
-            .await
-        {
+        let aborted = self.aborts.notified();
+        let request = self.maybe_timeout(self.timeout, async move {
+            if let Some(headers) = headers {
+                nc.request_with_headers(subject, headers, payload.into()).await
+            } else {
+                nc.request(subject, payload.into()).await
+            }
+        });
+        let result = tokio::select! {
+            result = request => result,
+            _ = aborted => Err(shutting_down()),
+        };
+        match result {
             Err(error) => {
                 error!(%error, "sending request");
                 Err(error)
             }
-            Ok(message) => Ok(message.payload.to_vec()),
+            Ok(message) => {
+                self.tap_recv(&message.subject, &message.payload);
+                verify_checksum(message.headers.as_ref(), &message.payload)?;
+                decompress_payload(message.headers.as_ref(), &message.payload)
+            }
+        }
+    }
+
+    /// Returns a [ReplyMux](crate::reply_mux::ReplyMux) on this client's connection,
+    /// whose waiting requests are cancelled by [abort_all](RpcClient::abort_all)
+    pub async fn reply_mux(&self) -> RpcResult<crate::reply_mux::ReplyMux> {
+        crate::reply_mux::ReplyMux::with_aborts(self.client(), self.aborts.clone()).await
+    }
+
+    /// Send a nats request whose reply is a stream of messages, such as one served
+    /// with [serve_stream](crate::dispatch::serve_stream).
+    /// Items are returned in the order they were published. The stream ends after the
+    /// responder's end marker, or after an error, which is returned as `RpcError::Rpc`.
+    /// Dropping the stream unsubscribes from the replies.
+    pub async fn subscribe_replies(
+        &self,
+        subject: String,
+        payload: Vec<u8>,
+    ) -> RpcResult<crate::dispatch::ReplyStream> {
+        use crate::dispatch::{parse_frame, reply_items};
+        use futures::StreamExt;
+
+        let inbox = self.client.new_inbox();
+        let sub = self.subscribe(inbox.clone()).await?;
+        self.client
+            .publish_with_reply(subject, inbox, payload.into())
+            .await
+            .map_err(|e| RpcError::Nats(e.to_string()))?;
+        let items = reply_items(sub.map(|msg| parse_frame(msg.headers.as_ref(), &msg.payload)));
+        let aborted = Box::pin(self.aborts.clone().notified_owned());
+        Ok(
+            futures::stream::unfold(Some((items, aborted)), |state| async move {
+                let (mut items, mut aborted) = state?;
+                tokio::select! {
+                    item = items.next() => item.map(|item| (item, Some((items, aborted)))),
+                    _ = &mut aborted => Some((Err(shutting_down()), None)),
+                }
+            })
+            .boxed(),
+        )
+    }
+
+    /// Publishes a request once, and collects every reply that arrives within `window`,
+    /// for example to discover which actors of a contract are listening.
+    /// Each reply is paired with the responder named in its [RESPONDER_HEADER] header
+    /// (see [reply_as](RpcClient::reply_as)), or a default entity if the header is missing.
+    /// Replies are returned in the order they arrived.
+    pub async fn scatter_gather(
+        &self,
+        subject: String,
+        payload: Vec<u8>,
+        window: Duration,
+    ) -> RpcResult<Vec<(WasmCloudEntity, Vec<u8>)>> {
+        use futures::StreamExt;
+
+        let aborted = self.aborts.notified();
+        tokio::pin!(aborted);
+        let inbox = self.client.new_inbox();
+        let mut sub = self.subscribe(inbox.clone()).await?;
+        self.tap_send(&subject, None, &payload);
+        self.client
+            .publish_with_reply(subject, inbox, payload.into())
+            .await
+            .map_err(|e| RpcError::Nats(e.to_string()))?;
+        tokio::select! {
+            flushed = self.client.flush() => flushed.map_err(|e| RpcError::Nats(e.to_string()))?,
+            _ = &mut aborted => return Err(shutting_down()),
+        }
+
+        let deadline = tokio::time::Instant::now() + window;
+        let mut replies = Vec::new();
+        loop {
+            let msg = tokio::select! {
+                msg = tokio::time::timeout_at(deadline, sub.next()) => match msg {
+                    Ok(Some(msg)) => msg,
+                    _ => break,
+                },
+                _ = &mut aborted => return Err(shutting_down()),
+            };
+            self.tap_recv(&msg.subject, &msg.payload);
+            let responder = msg
+                .headers
+                .as_ref()
+                .and_then(|h| h.get(RESPONDER_HEADER))
+                .and_then(|v| serde_json::from_str(v.as_str()).ok())
+                .unwrap_or_default();
+            replies.push((responder, msg.payload.to_vec()));
         }
+        let _ = sub.inner.unsubscribe().await;
+        Ok(replies)
+    }
+
+    /// Replies to a [scatter_gather](RpcClient::scatter_gather) request,
+    /// naming `responder` as the sender
+    pub async fn reply_as(
+        &self,
+        reply_to: String,
+        responder: &WasmCloudEntity,
+        payload: Vec<u8>,
+    ) -> RpcResult<()> {
+        let mut headers = HeaderMap::new();
+        let entity = serde_json::to_string(responder).map_err(|e| RpcError::Ser(e.to_string()))?;
+        headers.insert(RESPONDER_HEADER, entity.as_str());
+        self.tap_send(&reply_to, Some(&headers), &payload);
+        self.client
+            .publish_with_headers(reply_to, headers, payload.into())
+            .await
+            .map_err(|e| RpcError::Nats(e.to_string()))?;
+        self.client.flush().await.map_err(|e| RpcError::Nats(e.to_string()))
     }
 
     /// Send a nats message with no reply-to. Do not wait for a response.
     /// This can be used for general nats messages, not just wasmbus actor/provider messages.
     #[instrument(level = "debug", skip_all, fields(subject = %subject))]
     pub async fn publish(&self, subject: String, payload: Vec<u8>) -> RpcResult<()> {
-        #[cfg(feature = "otel")]
-        let headers: Option<HeaderMap> = Some(OtelHeaderInjector::default_with_span().into());
-        #[cfg(not(feature = "otel"))]
-        let headers: Option<HeaderMap> = None;
+        self.publish_with_extra_headers(subject, None, payload).await
+    }
 
+    /// Send a nats message with no reply-to, with the headers in addition to any
+    /// the client adds itself
+    pub(crate) async fn publish_with_extra_headers(
+        &self,
+        subject: String,
+        extra: Option<HeaderMap>,
+        payload: Vec<u8>,
+    ) -> RpcResult<()> {
+        self.reconnect_limit.check()?;
+        self.wait_for_connection().await?;
+        let headers = with_client_headers(extra);
+        self.tap_send(&subject, headers.as_ref(), &payload);
         let nc = self.client();
         self.maybe_timeout(self.timeout, async move {
             if let Some(headers) = headers {
@@ -501,6 +1652,30 @@ impl RpcClient {
         response: InvocationResponse,
         lattice: &str,
     ) -> RpcResult<()> {
+        self.publish_invocation_response_with_headers(reply_to, response, lattice, None)
+            .await
+    }
+
+    /// Publishes the response with the nats headers, such as those a handler returned
+    /// in a [DispatchResult](crate::common::DispatchResult)
+    pub(crate) async fn publish_invocation_response_with_headers(
+        &self,
+        reply_to: String,
+        response: InvocationResponse,
+        lattice: &str,
+        headers: Option<HeaderMap>,
+    ) -> RpcResult<()> {
+        let buf = self.invocation_response_bytes(response, lattice).await?;
+        self.publish_with_extra_headers(reply_to, headers, buf).await
+    }
+
+    /// Serializes the response, first sending its body in chunks if it's too large
+    /// for one message
+    pub(crate) async fn invocation_response_bytes(
+        &self,
+        response: InvocationResponse,
+        lattice: &str,
+    ) -> RpcResult<Vec<u8>> {
         let content_length = Some(response.msg.len() as u64);
         let response = {
             let inv_id = response.invocation_id.clone();
@@ -523,13 +1698,9 @@ impl RpcClient {
             }
         };
 
-        match crate::common::serialize(&response) {
-            Ok(t) => Ok(self.publish(reply_to, t).await?),
-            Err(e) => {
-                // extremely unlikely that InvocationResponse would fail to serialize
-                Err(RpcError::Ser(format!("InvocationResponse: {e}")))
-            }
-        }
+        // extremely unlikely that InvocationResponse would fail to serialize
+        crate::common::serialize(&response)
+            .map_err(|e| RpcError::Ser(format!("InvocationResponse: {e}")))
     }
 
     pub async fn dechunk(&self, mut inv: Invocation, lattice: &str) -> RpcResult<Invocation> {
@@ -618,19 +1789,271 @@ impl RpcClient {
 
 /// helper method to add logging to a nats connection. Logs disconnection (warn level), reconnection (info level), error (error), slow consumer, and lame duck(warn) events.
 pub fn with_connection_event_logging(opts: ConnectOptions) -> ConnectOptions {
+    with_connection_events(opts, &ReconnectLimit::default())
+}
+
+/// Like [with_connection_event_logging], and also reports disconnects and reconnects
+/// to `limit`, which counts the reconnects, and restores a connection it had
+/// considered lost once it reconnects.
+pub fn with_connection_events(opts: ConnectOptions, limit: &ReconnectLimit) -> ConnectOptions {
     use crate::async_nats::Event;
-    opts.event_callback(|event| async move {
-        match event {
-            Event::Disconnected => warn!("nats client disconnected"),
-            Event::Connected => info!("nats client connected"),
-            Event::ClientError(err) => error!("nats client error: '{:?}'", err),
-            Event::ServerError(err) => error!("nats server error: '{:?}'", err),
-            Event::SlowConsumer(val) => warn!("nats slow consumer detected ({})", val),
-            Event::LameDuckMode => warn!("nats lame duck mode"),
+    let limit = limit.clone();
+    opts.event_callback(move |event| {
+        let limit = limit.clone();
+        async move {
+            limit.record_event(&event);
+            match event {
+                Event::Disconnected => warn!("nats client disconnected"),
+                Event::Connected => info!("nats client connected"),
+                Event::ClientError(err) => error!("nats client error: '{:?}'", err),
+                Event::ServerError(err) => error!("nats server error: '{:?}'", err),
+                Event::SlowConsumer(val) => warn!("nats slow consumer detected ({})", val),
+                Event::LameDuckMode => warn!("nats lame duck mode"),
+            }
         }
     })
 }
 
+/// Default upper limit on the delay between nats reconnect attempts
+pub const DEFAULT_RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(8);
+
+/// Default limit on the time to establish a nats connection
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay before the first reconnect attempt, before jitter
+const RECONNECT_BASE_DELAY: Duration = Duration::from_millis(100);
+
+/// Returns the delay before a nats reconnect attempt, after `attempts` failed attempts.
+/// The delay starts at 100ms and doubles with each attempt, up to `max_backoff`.
+/// It is then shortened by a random amount of up to half, so clients that lost
+/// their connection at the same time, such as providers restarted together,
+/// don't all reconnect at once.
+pub fn reconnect_delay(attempts: usize, max_backoff: Duration) -> Duration {
+    let ceiling = RECONNECT_BASE_DELAY
+        .saturating_mul(1u32 << attempts.min(20))
+        .min(max_backoff);
+    // the low 32 bits of a v4 uuid are random
+    let jitter = (uuid::Uuid::new_v4().as_u128() as u32) as f64 / u32::MAX as f64;
+    ceiling.mul_f64(1.0 - jitter / 2.0)
+}
+
+/// Sets the nats reconnect delay to [reconnect_delay], with the backoff limit
+pub fn with_reconnect_backoff(opts: ConnectOptions, max_backoff: Duration) -> ConnectOptions {
+    opts.reconnect_delay_callback(move |attempts| reconnect_delay(attempts, max_backoff))
+}
+
+type ConnectionLostFn = dyn Fn() + Send + Sync;
+
+/// Gives up on a nats connection after a number of consecutive failed reconnect attempts.
+///
+/// The nats client keeps trying to reconnect in the background indefinitely. When
+/// `max_reconnects` attempts in a row have failed, the connection is considered lost:
+/// the callbacks registered with [on_lost](ReconnectLimit::on_lost) run,
+/// and an [RpcClient] with this limit fails instead of sending.
+/// Clones share the same state. The default has no limit.
+///
+/// When the connection options also came from [with_connection_events], the limit
+/// counts the connection's [reconnects](ReconnectLimit::reconnects), and a lost
+/// connection that reconnects is no longer considered lost, so sends work again.
+#[derive(Clone, Default)]
+pub struct ReconnectLimit {
+    max_reconnects: Option<usize>,
+    lost: Arc<AtomicBool>,
+    on_lost: Arc<Mutex<Vec<Box<ConnectionLostFn>>>>,
+    /// set on disconnect, and cleared by the next connect
+    disconnected: Arc<AtomicBool>,
+    reconnects: Arc<AtomicU64>,
+}
+
+impl fmt::Debug for ReconnectLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReconnectLimit")
+            .field("max_reconnects", &self.max_reconnects)
+            .field("lost", &self.is_lost())
+            .field("reconnects", &self.reconnects())
+            .finish()
+    }
+}
+
+impl ReconnectLimit {
+    /// Constructs a limit of `max_reconnects` failed attempts, or no limit if None
+    pub fn new(max_reconnects: Option<usize>) -> Self {
+        ReconnectLimit { max_reconnects, ..Default::default() }
+    }
+
+    /// Sets the nats reconnect delay to [reconnect_delay], with the backoff limit,
+    /// and counts the attempts against this limit
+    pub fn apply(&self, opts: ConnectOptions, max_backoff: Duration) -> ConnectOptions {
+        let limit = self.clone();
+        opts.reconnect_delay_callback(move |attempts| {
+            limit.record_attempt(attempts);
+            reconnect_delay(attempts, max_backoff)
+        })
+    }
+
+    /// Registers a function called each time the connection is lost.
+    /// If it already is, the function is called now.
+    pub fn on_lost<F: Fn() + Send + Sync + 'static>(&self, f: F) {
+        let mut callbacks = self.on_lost.lock().unwrap();
+        if self.is_lost() {
+            drop(callbacks);
+            f();
+        } else {
+            callbacks.push(Box::new(f));
+        }
+    }
+
+    /// Returns true if the connection has exhausted its reconnect attempts
+    pub fn is_lost(&self) -> bool {
+        self.lost.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of times the connection was re-established after a disconnect
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+
+    /// Records a connection event. The nats client reports connecting on the initial
+    /// connect too, and may report it more than once per reconnect, so only the first
+    /// connect after each disconnect is counted.
+    pub(crate) fn record_event(&self, event: &crate::async_nats::Event) {
+        use crate::async_nats::Event;
+        match event {
+            Event::Disconnected => self.disconnected.store(true, Ordering::SeqCst),
+            Event::Connected => {
+                if self.disconnected.swap(false, Ordering::SeqCst) {
+                    self.reconnects.fetch_add(1, Ordering::Relaxed);
+                }
+                if self.lost.swap(false, Ordering::SeqCst) {
+                    info!("nats connection restored after exhausting reconnect attempts");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns `RpcError::Nats` if the connection is lost
+    pub(crate) fn check(&self) -> RpcResult<()> {
+        if self.is_lost() {
+            Err(RpcError::Nats(format!(
+                "nats connection lost after {} failed reconnect attempts",
+                self.max_reconnects.unwrap_or_default()
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Called before each connection attempt; `attempts` counts this one, and
+    /// resets after a successful connection
+    pub(crate) fn record_attempt(&self, attempts: usize) {
+        let max = match self.max_reconnects {
+            Some(max) => max,
+            None => return,
+        };
+        if attempts <= max || self.lost.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        error!(
+            max_reconnects = max,
+            "nats reconnect attempts exhausted: connection lost"
+        );
+        // called without the lock, so a callback may register another
+        let callbacks = std::mem::take(&mut *self.on_lost.lock().unwrap());
+        for f in &callbacks {
+            f();
+        }
+        let mut registered = self.on_lost.lock().unwrap();
+        let added = std::mem::replace(&mut *registered, callbacks);
+        registered.extend(added);
+    }
+}
+
+/// How [retry] repeats a failed operation
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Total number of attempts, including the first. At least 1.
+    #[must_use]
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Delay before the first retry. The delay doubles with each retry.
+    #[must_use]
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Upper limit on the delay between attempts
+    #[must_use]
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Delay after `failed` failed attempts
+    fn backoff(&self, failed: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(1u32 << (failed - 1).min(20))
+            .min(self.max_backoff)
+    }
+}
+
+/// Runs an async operation, such as several calls that should succeed or fail together,
+/// until it succeeds, fails with an error that isn't [retryable](RpcError::is_retryable),
+/// or has been attempted `policy.max_attempts` times.
+/// Returns the result of the last attempt.
+pub async fn retry<F, Fut, T>(policy: &RetryPolicy, mut f: F) -> RpcResult<T>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = RpcResult<T>>,
+{
+    let mut failed = 0;
+    loop {
+        match f().await {
+            Err(e) if e.is_retryable() && failed + 1 < policy.max_attempts => {
+                failed += 1;
+                let delay = policy.backoff(failed);
+                debug!(error = %e, attempt = failed, ?delay, "retrying");
+                tokio::time::sleep(delay).await;
+            }
+            rc => return rc,
+        }
+    }
+}
+
+/// The error returned to requests cancelled by [RpcClient::abort_all]
+pub(crate) fn shutting_down() -> RpcError {
+    RpcError::Other(CLIENT_SHUTTING_DOWN.to_string())
+}
+
+/// Names the operation in a failed send's error. An abort is returned as is,
+/// so every cancelled request returns the same error.
+fn with_operation(error: RpcError, op: &str) -> RpcError {
+    match error {
+        RpcError::Other(s) if s == CLIENT_SHUTTING_DOWN => RpcError::Other(s),
+        e => e.with_context(op),
+    }
+}
+
 #[derive(Clone)]
 pub struct InvocationArg {
     /// Sender of the message
@@ -660,6 +2083,25 @@ pub(crate) fn invocation_hash(
     data_encoding::HEXUPPER.encode(digest.as_slice())
 }
 
+/// Compresses a response payload with [DEFLATE_ENCODING]
+pub(crate) fn compress_payload(payload: &[u8]) -> Vec<u8> {
+    miniz_oxide::deflate::compress_to_vec(payload, 6)
+}
+
+/// Returns the payload of a response, decompressed if its [ENCODING_HEADER] names an encoding
+fn decompress_payload(headers: Option<&HeaderMap>, payload: &[u8]) -> RpcResult<Vec<u8>> {
+    match headers.and_then(|h| h.get(ENCODING_HEADER)).map(|v| v.as_str()) {
+        None => Ok(payload.to_vec()),
+        Some(DEFLATE_ENCODING) => {
+            miniz_oxide::inflate::decompress_to_vec_with_limit(payload, MAX_INFLATED_SIZE)
+                .map_err(|e| RpcError::Deser(format!("decompressing response: {e}")))
+        }
+        Some(other) => Err(RpcError::Deser(format!(
+            "unsupported response encoding '{other}'"
+        ))),
+    }
+}
+
 /// Create a new random uuid for invocations.
 /// Internally this (currently) uses the uuid crate, which uses 'getrandom',
 /// which uses the operating system's random number generator.
@@ -809,3 +2251,1052 @@ impl RpcStats {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        borrow::Cow,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use wascap::prelude::KeyPair;
+
+    use super::{
+        opts_headers, payload_checksum, payload_preview, reconnect_delay, retry, verify_checksum,
+        RetryPolicy, RpcClient, WireTap, CHECKSUM_HEADER, CLIENT_SHUTTING_DOWN, PRIORITY_HEADER,
+        RPC_LOG_TARGET,
+    };
+    use crate::{
+        async_nats::ConnectOptions,
+        common::{Message, Priority, SendOpts},
+        core::{LatticePrefix, WasmCloudEntity},
+        error::RpcError,
+    };
+
+    #[test]
+    fn protocol_versions() {
+        use super::{check_protocol_version, PROTOCOL_VERSION};
+
+        assert!(check_protocol_version(None).is_ok());
+        assert!(check_protocol_version(Some(&PROTOCOL_VERSION.to_string())).is_ok());
+        for incompatible in ["0", "99", "v1", ""] {
+            let err = check_protocol_version(Some(incompatible)).unwrap_err();
+            assert!(
+                matches!(&err, RpcError::Rpc(s) if s.starts_with("unsupported protocol version")),
+                "{incompatible}: {err}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_until_success() {
+        let policy = RetryPolicy::default().initial_backoff(Duration::from_millis(1));
+        let mut calls = 0;
+        let rc = retry(&policy, || {
+            calls += 1;
+            let attempt = calls;
+            async move {
+                if attempt < 3 {
+                    Err(RpcError::Timeout(format!("attempt {attempt}")))
+                } else {
+                    Ok(attempt)
+                }
+            }
+        })
+        .await;
+        assert_eq!(rc.unwrap(), 3);
+
+        // errors that would recur are not retried
+        let mut calls = 0;
+        let rc: Result<(), _> = retry(&policy, || {
+            calls += 1;
+            async { Err(RpcError::InvalidParameter("bad".to_string())) }
+        })
+        .await;
+        assert!(matches!(rc, Err(RpcError::InvalidParameter(_))));
+        assert_eq!(calls, 1);
+
+        // the last error is returned when attempts run out
+        let mut calls = 0;
+        let rc: Result<(), _> = retry(&policy.clone().max_attempts(2), || {
+            calls += 1;
+            async { Err(RpcError::Nats("disconnected".to_string())) }
+        })
+        .await;
+        assert!(matches!(rc, Err(RpcError::Nats(_))));
+        assert_eq!(calls, 2);
+    }
+
+    #[tokio::test]
+    async fn client_stats() {
+        let client = unconnected_client().await;
+        assert_eq!(client.stats().requests_sent, 0);
+        let origin = WasmCloudEntity::new_actor("MORIGIN").unwrap();
+        let target = WasmCloudEntity::new_actor("MTARGET").unwrap();
+        let message = Message {
+            method: "Counter.Increment",
+            arg: Cow::Owned(vec![1, 2, 3]),
+        };
+        // the request is queued until the client connects, so it times out
+        let err = client
+            .send_timeout(
+                origin,
+                target,
+                "default",
+                message,
+                Duration::from_millis(20),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, RpcError::Timeout(_)));
+
+        // clones share the counters
+        let stats = client.clone().stats();
+        assert_eq!(stats.requests_sent, 1);
+        assert_eq!(stats.requests_failed, 1);
+        assert!(stats.bytes_sent > 3, "{stats:?}");
+        assert_eq!(stats.bytes_recv, 0);
+    }
+
+    #[tokio::test]
+    async fn contract_default_timeout() {
+        let mut client = unconnected_client().await;
+        client.set_contract_timeout("wasmcloud:keyvalue", Some(Duration::from_millis(20)));
+        client.set_contract_timeout("wasmcloud:blobstore", Some(Duration::from_secs(30)));
+        let mut kv = WasmCloudEntity::new_provider("wasmcloud:keyvalue", "default").unwrap();
+        kv.public_key = "VKV".to_string();
+        let other = WasmCloudEntity::new_actor("MTARGET").unwrap();
+        assert_eq!(
+            client.default_timeout_for(&kv),
+            Some(Duration::from_millis(20))
+        );
+        assert_eq!(client.default_timeout_for(&other), None);
+
+        // the client has no timeout of its own, so only the contract's timeout ends this send
+        let origin = WasmCloudEntity::new_actor("MORIGIN").unwrap();
+        let message = Message {
+            method: "KeyValue.Get",
+            arg: Cow::Owned(b"key".to_vec()),
+        };
+        let rc = tokio::time::timeout(
+            Duration::from_secs(5),
+            client.send_with_opts(origin, kv, "default", message, &SendOpts::default(), None),
+        )
+        .await
+        .expect("contract timeout was not applied");
+        assert!(matches!(rc, Err(RpcError::Timeout(_))), "{rc:?}");
+    }
+
+    /// Log output captured by a test subscriber
+    #[derive(Clone, Default)]
+    struct Captured(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn debug_log_records() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut client = unconnected_client().await;
+        client.set_debug_logging(Some(4));
+        let origin = WasmCloudEntity::new_actor("MORIGIN").unwrap();
+        let target = WasmCloudEntity::new_actor("MTARGET").unwrap();
+        let message = Message {
+            method: "Audit.Record",
+            arg: Cow::Owned(vec![0xde, 0xad, 0xbe, 0xef, 0x01, 0x02]),
+        };
+        let _ = client
+            .send_timeout(
+                origin,
+                target,
+                "default",
+                message,
+                Duration::from_millis(20),
+            )
+            .await;
+
+        let logs = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let record = logs
+            .lines()
+            .find(|line| line.contains(RPC_LOG_TARGET))
+            .unwrap_or_else(|| panic!("no rpc log record in:\n{logs}"));
+        assert!(record.contains("direction=request"), "{record}");
+        assert!(record.contains("operation=Audit.Record"), "{record}");
+        assert!(record.contains("payload_len=6"), "{record}");
+        assert!(record.contains("payload=deadbeef..."), "{record}");
+
+        assert_eq!(payload_preview(&[0xab], 4), "ab");
+    }
+
+    /// client that doesn't need a server: connection is retried in the background
+    async fn unconnected_client() -> RpcClient {
+        let nc = ConnectOptions::new()
+            .retry_on_initial_connect()
+            .connect("nats://127.0.0.1:1")
+            .await
+            .unwrap();
+        RpcClient::new(
+            nc,
+            "HOST".to_string(),
+            None,
+            Arc::new(KeyPair::new_server()),
+        )
+    }
+
+    /// What a [serve_mock] server has seen
+    struct MockServer {
+        url: String,
+        /// number of connections accepted
+        accepted: Arc<AtomicUsize>,
+        /// PUB and HPUB lines the clients sent
+        published: tokio::sync::mpsc::UnboundedReceiver<String>,
+    }
+
+    impl MockServer {
+        /// Returns the next PUB or HPUB line a client sent, waiting up to 5s for it
+        async fn next_published(&mut self) -> String {
+            tokio::time::timeout(Duration::from_secs(5), self.published.recv())
+                .await
+                .expect("nothing published")
+                .expect("server stopped")
+        }
+    }
+
+    /// Answers clients on `listener` like a nats server. Each connection is sent INFO,
+    /// and PONG in reply to PING. Every other line a client sends is passed to `on_line`,
+    /// with the number of the connection (from 0), and its result is written back;
+    /// None closes the connection.
+    fn serve_mock<F>(listener: tokio::net::TcpListener, on_line: F) -> MockServer
+    where
+        F: Fn(usize, &str) -> Option<Vec<u8>> + Send + Sync + 'static,
+    {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let url = format!("nats://{}", listener.local_addr().unwrap());
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let (published_tx, published) = tokio::sync::mpsc::unbounded_channel();
+        let on_line = Arc::new(on_line);
+        tokio::spawn({
+            let accepted = accepted.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let connection = accepted.fetch_add(1, Ordering::SeqCst);
+                    let (on_line, published_tx) = (on_line.clone(), published_tx.clone());
+                    tokio::spawn(async move {
+                        let (reader, mut writer) = stream.into_split();
+                        let info =
+                            b"INFO {\"server_id\":\"test\",\"max_payload\":1048576,\"headers\":true}\r\n";
+                        if writer.write_all(info).await.is_err() {
+                            return;
+                        }
+                        let mut lines = BufReader::new(reader).lines();
+                        while let Ok(Some(line)) = lines.next_line().await {
+                            if line.starts_with("PUB ") || line.starts_with("HPUB ") {
+                                let _ = published_tx.send(line.clone());
+                            }
+                            let reply = if line == "PING" {
+                                Some(b"PONG\r\n".to_vec())
+                            } else {
+                                on_line(connection, &line)
+                            };
+                            match reply {
+                                Some(bytes) if writer.write_all(&bytes).await.is_ok() => {}
+                                _ => return,
+                            }
+                        }
+                    });
+                }
+            }
+        });
+        MockServer { url, accepted, published }
+    }
+
+    /// Starts a [serve_mock] server on a free local port
+    async fn mock_server<F>(on_line: F) -> MockServer
+    where
+        F: Fn(usize, &str) -> Option<Vec<u8>> + Send + Sync + 'static,
+    {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        serve_mock(listener, on_line)
+    }
+
+    /// Returns the subscription id of a SUB line, or None for other lines
+    fn sub_id(line: &str) -> Option<&str> {
+        line.strip_prefix("SUB ").and_then(|sub| sub.rsplit(' ').next())
+    }
+
+    /// Returns a MSG delivering the payload to subscription `sid`,
+    /// or an HMSG if there are headers, given as "Name: value" lines
+    fn mock_msg(subject: &str, sid: &str, headers: &[&str], payload: &str) -> Vec<u8> {
+        if headers.is_empty() {
+            return format!("MSG {subject} {sid} {}\r\n{payload}\r\n", payload.len()).into_bytes();
+        }
+        let headers: String = headers.iter().map(|h| format!("{h}\r\n")).collect();
+        let headers = format!("NATS/1.0\r\n{headers}\r\n");
+        format!(
+            "HMSG {subject} {sid} {} {}\r\n{headers}{payload}\r\n",
+            headers.len(),
+            headers.len() + payload.len()
+        )
+        .into_bytes()
+    }
+
+    #[tokio::test]
+    async fn reconnects_counted_per_client() {
+        use super::{with_connection_events, ReconnectLimit};
+
+        async fn connect(url: &str) -> RpcClient {
+            let limit = ReconnectLimit::default();
+            let opts = limit.apply(ConnectOptions::new(), Duration::from_millis(20));
+            let nc = with_connection_events(opts, &limit).connect(url).await.unwrap();
+            let mut client = RpcClient::new(
+                nc,
+                "HOST".to_string(),
+                None,
+                Arc::new(KeyPair::new_server()),
+            );
+            client.set_reconnect_limit(limit);
+            client
+        }
+
+        // the first connection is closed when the client publishes; later ones stay up
+        let flaky = mock_server(|connection, line| match connection {
+            0 if line.starts_with("PUB ") => None,
+            _ => Some(Vec::new()),
+        })
+        .await;
+        let steady = mock_server(|_, _| Some(Vec::new())).await;
+        let client = connect(&flaky.url).await;
+        let other = connect(&steady.url).await;
+
+        // the initial connect isn't a reconnect
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(client.stats().reconnects, 0);
+
+        let _ = client.publish("a".to_string(), b"hi".to_vec()).await;
+        let started = std::time::Instant::now();
+        while client.stats().reconnects == 0 {
+            assert!(started.elapsed() < Duration::from_secs(5), "no reconnect");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(flaky.accepted.load(Ordering::SeqCst), 2);
+        // the nats client reports connecting twice for a reconnect; it counts once
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(client.stats().reconnects, 1);
+        assert_eq!(client.clone().stats().reconnects, 1);
+        // the count belongs to the client's own connection
+        assert_eq!(other.stats().reconnects, 0);
+    }
+
+    #[tokio::test]
+    async fn lost_connection_restored() {
+        use super::{with_connection_events, ReconnectLimit};
+
+        // the first connection is closed when the client publishes, and the next
+        // three before the handshake completes; the fifth stays up
+        let server = mock_server(|connection, line| match connection {
+            0 if line.starts_with("PUB ") => None,
+            1..=3 => None,
+            _ => Some(Vec::new()),
+        })
+        .await;
+        let limit = ReconnectLimit::new(Some(1));
+        let lost = Arc::new(AtomicUsize::new(0));
+        limit.on_lost({
+            let lost = lost.clone();
+            move || {
+                lost.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        let opts = limit.apply(ConnectOptions::new(), Duration::from_millis(20));
+        let nc = with_connection_events(opts, &limit).connect(&server.url).await.unwrap();
+        let mut client = RpcClient::new(
+            nc,
+            "HOST".to_string(),
+            None,
+            Arc::new(KeyPair::new_server()),
+        );
+        client.set_reconnect_limit(limit.clone());
+
+        let _ = client.publish("a".to_string(), b"hi".to_vec()).await;
+        let started = std::time::Instant::now();
+        while lost.load(Ordering::SeqCst) == 0 || limit.is_lost() {
+            assert!(started.elapsed() < Duration::from_secs(5), "not restored");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(lost.load(Ordering::SeqCst), 1);
+        assert_eq!(server.accepted.load(Ordering::SeqCst), 5);
+        client.publish("b".to_string(), b"hi".to_vec()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn abort_all_cancels_pending_requests() {
+        let client = unconnected_client().await;
+        let pending: Vec<_> = (0..2)
+            .map(|n| {
+                let client = client.clone();
+                tokio::spawn(async move {
+                    let origin = WasmCloudEntity::new_actor("MORIGIN").unwrap();
+                    let target = WasmCloudEntity::new_actor("MTARGET").unwrap();
+                    let message = Message {
+                        method: "Slow.Thing",
+                        arg: Cow::Owned(vec![n]),
+                    };
+                    client.send(origin, target, "default", message).await
+                })
+            })
+            .collect();
+        // no server, so neither gets a response
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        client.abort_all();
+        for task in pending {
+            let err = tokio::time::timeout(Duration::from_secs(1), task)
+                .await
+                .expect("request aborted")
+                .unwrap()
+                .unwrap_err();
+            assert!(
+                matches!(&err, RpcError::Other(s) if s == CLIENT_SHUTTING_DOWN),
+                "{err}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn abort_all_ends_streams_and_gathers() {
+        use futures::StreamExt;
+
+        let client = unconnected_client().await;
+        let mut stream = client
+            .subscribe_replies("Logs.Tail".to_string(), Vec::new())
+            .await
+            .unwrap();
+        let gather = tokio::spawn({
+            let client = client.clone();
+            async move {
+                client
+                    .scatter_gather("Who.Is".to_string(), Vec::new(), Duration::from_secs(5))
+                    .await
+            }
+        });
+        let mux = client.reply_mux().await.unwrap();
+        let muxed = tokio::spawn(mux.register().wait(Duration::from_secs(5)));
+        let next = tokio::spawn(async move { (stream.next().await, stream.next().await) });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        client.abort_all();
+
+        let aborted =
+            |err: &RpcError| matches!(err, RpcError::Other(s) if s == CLIENT_SHUTTING_DOWN);
+        let wait = Duration::from_secs(1);
+        let (item, end) = tokio::time::timeout(wait, next).await.expect("stream aborted").unwrap();
+        assert!(aborted(&item.unwrap().unwrap_err()));
+        assert!(end.is_none());
+        let err = tokio::time::timeout(wait, gather).await.expect("gather aborted").unwrap();
+        assert!(aborted(&err.unwrap_err()));
+        let err = tokio::time::timeout(wait, muxed)
+            .await
+            .expect("mux request aborted")
+            .unwrap();
+        assert!(aborted(&err.unwrap_err()));
+    }
+
+    #[tokio::test]
+    async fn no_reply_returns_without_waiting() {
+        let client = unconnected_client().await;
+        let origin = WasmCloudEntity::new_actor("MORIGIN").unwrap();
+        let target = WasmCloudEntity::new_actor("MTARGET").unwrap();
+        let message = || Message {
+            method: "Metrics.Push",
+            arg: Cow::Borrowed(b"1"),
+        };
+        let timeout = Some(Duration::from_millis(200));
+
+        // with no server, a request waits for its timeout
+        let err = client
+            .send_with_opts(
+                origin.clone(),
+                target.clone(),
+                "default",
+                message(),
+                &SendOpts::default(),
+                timeout,
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, RpcError::Timeout(_)), "{err}");
+
+        // a one-way message is published, and returns at once
+        let opts = SendOpts::default().no_reply(true);
+        let started = std::time::Instant::now();
+        let resp = client
+            .send_with_opts(origin, target, "default", message(), &opts, timeout)
+            .await
+            .unwrap();
+        assert!(resp.is_empty());
+        assert!(started.elapsed() < Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn connect_grace() {
+        use super::CONNECTION_NOT_READY;
+
+        // nothing listens: the send gives up after the grace period
+        let mut client = unconnected_client().await;
+        client.set_connect_grace(Some(Duration::from_millis(50)));
+        let started = std::time::Instant::now();
+        let err = client.publish("a".to_string(), b"hi".to_vec()).await.unwrap_err();
+        assert!(
+            matches!(&err, RpcError::NotInitialized(s) if s == CONNECTION_NOT_READY),
+            "{err}"
+        );
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        // a server that answers after 100ms: the send waits for it
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("nats://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            serve_mock(listener, |_, _| Some(Vec::new()))
+        });
+        let nc = ConnectOptions::new()
+            .retry_on_initial_connect()
+            .connect(url)
+            .await
+            .unwrap();
+        let mut client = RpcClient::new(
+            nc,
+            "HOST".to_string(),
+            None,
+            Arc::new(KeyPair::new_server()),
+        );
+        client.set_connect_grace(Some(Duration::from_secs(5)));
+        client.publish("b".to_string(), b"hi".to_vec()).await.unwrap();
+        let line = server.await.unwrap().next_published().await;
+        assert!(line.contains(" b "), "{line}");
+    }
+
+    #[tokio::test]
+    async fn lazy_connect() {
+        use super::LazyRpcClient;
+        use crate::core::HostData;
+
+        let mut server = mock_server(|_, _| Some(Vec::new())).await;
+        let key = KeyPair::new_server();
+        let host_data = HostData {
+            host_id: key.public_key(),
+            invocation_seed: key.seed().unwrap(),
+            lattice_rpc_url: server.url.clone(),
+            ..Default::default()
+        };
+
+        let client = LazyRpcClient::new(host_data.clone());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(server.accepted.load(Ordering::SeqCst), 0);
+        assert!(!client.is_connected());
+
+        // the first publish connects, and sends
+        client.clone().publish("events".to_string(), b"hi".to_vec()).await.unwrap();
+        assert!(client.is_connected());
+        let line = server.next_published().await;
+        assert!(line.contains(" events "), "{line}");
+        assert_eq!(server.accepted.load(Ordering::SeqCst), 1);
+
+        // an rpc send connects too; the server never answers, so it times out
+        let client = LazyRpcClient::new(HostData {
+            default_rpc_timeout_ms: Some(100),
+            ..host_data.clone()
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(server.accepted.load(Ordering::SeqCst), 1);
+        let origin = WasmCloudEntity::new_actor("MORIGIN").unwrap();
+        let target = WasmCloudEntity::new_actor("MTARGET").unwrap();
+        let message = Message {
+            method: "Greeter.Hello",
+            arg: Cow::Borrowed(b"hi"),
+        };
+        let err = client.send(origin, target, "default", message).await.unwrap_err();
+        assert!(err.to_string().contains("timeout"), "{err}");
+        assert!(client.is_connected());
+        assert_eq!(server.accepted.load(Ordering::SeqCst), 2);
+        let line = server.next_published().await;
+        assert!(line.contains(" wasmbus.rpc.default.MTARGET "), "{line}");
+
+        // settings that can't connect are reported by the first call
+        let client = LazyRpcClient::new(HostData {
+            invocation_seed: "not a seed".to_string(),
+            ..host_data.clone()
+        });
+        assert!(client.publish("events".to_string(), b"hi".to_vec()).await.is_err());
+        assert!(!client.is_connected());
+
+        // as is a server that refuses the connection
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let refused = format!("nats://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let client = LazyRpcClient::new(HostData { lattice_rpc_url: refused, ..host_data });
+        let err = client.publish("events".to_string(), b"hi".to_vec()).await.unwrap_err();
+        assert!(matches!(err, RpcError::ProviderInit(_)), "{err}");
+        assert!(!client.is_connected());
+    }
+
+    #[tokio::test]
+    async fn subscription_lag() {
+        use futures::StreamExt;
+
+        // a server that publishes 20 messages as soon as the client subscribes
+        let server = mock_server(|_, line| match sub_id(line) {
+            Some(sid) => Some(
+                (0..20)
+                    .flat_map(|n| mock_msg("events", sid, &[], &n.to_string()))
+                    .collect(),
+            ),
+            None => Some(Vec::new()),
+        })
+        .await;
+        let nc = ConnectOptions::new().connect(&server.url).await.unwrap();
+        let client = RpcClient::new(
+            nc,
+            "HOST".to_string(),
+            None,
+            Arc::new(KeyPair::new_server()),
+        );
+        let mut sub = client.subscribe("events".to_string()).await.unwrap();
+        sub.set_lag_warning(Some(5));
+
+        // a slow consumer: the rest are waiting when it takes the first
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let first = tokio::time::timeout(Duration::from_secs(5), sub.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.payload.as_ref(), b"0");
+        // counted up to one past the limit; the rest wait in the nats client
+        assert_eq!(sub.pending(), 6);
+        let mut received = 1;
+        while received < 20 {
+            tokio::time::timeout(Duration::from_secs(5), sub.next())
+                .await
+                .unwrap()
+                .unwrap();
+            received += 1;
+        }
+        assert_eq!(sub.pending(), 0);
+    }
+
+    #[tokio::test]
+    async fn filtered_subscription() {
+        use futures::StreamExt;
+
+        // a server that publishes 6 messages, alternating between two kinds, when subscribed
+        let server = mock_server(|_, line| match sub_id(line) {
+            Some(sid) => Some(
+                (0..6)
+                    .flat_map(|n| {
+                        let kind = if n % 2 == 0 { "created" } else { "deleted" };
+                        let header = format!("X-Kind: {kind}");
+                        mock_msg("events", sid, &[&header], &n.to_string())
+                    })
+                    .collect(),
+            ),
+            None => Some(Vec::new()),
+        })
+        .await;
+        let nc = ConnectOptions::new().connect(&server.url).await.unwrap();
+        let client = RpcClient::new(
+            nc,
+            "HOST".to_string(),
+            None,
+            Arc::new(KeyPair::new_server()),
+        );
+        let mut sub = client
+            .subscribe_filtered("events".to_string(), |msg| {
+                let kind = msg.headers.as_ref().and_then(|h| h.get("X-Kind"));
+                kind.map(|v| v.as_str()) == Some("deleted")
+            })
+            .await
+            .unwrap();
+
+        let mut received = Vec::new();
+        while received.len() < 3 {
+            let msg = tokio::time::timeout(Duration::from_secs(5), sub.next())
+                .await
+                .unwrap()
+                .unwrap();
+            received.push(String::from_utf8(msg.payload.to_vec()).unwrap());
+        }
+        assert_eq!(received, vec!["1", "3", "5"]);
+        // nothing else matches
+        assert!(tokio::time::timeout(Duration::from_millis(100), sub.next()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn filter_yields_on_busy_subject() {
+        use super::MAX_POLLED_MESSAGES;
+        use futures::{FutureExt, StreamExt};
+
+        // many messages that don't match, then one that does
+        let server = mock_server(|_, line| match sub_id(line) {
+            Some(sid) => {
+                let mut msgs: Vec<u8> = (0..4 * MAX_POLLED_MESSAGES)
+                    .flat_map(|n| mock_msg("events", sid, &[], &n.to_string()))
+                    .collect();
+                msgs.extend(mock_msg("events", sid, &["X-Match: yes"], "last"));
+                Some(msgs)
+            }
+            None => Some(Vec::new()),
+        })
+        .await;
+        let nc = ConnectOptions::new().connect(&server.url).await.unwrap();
+        let client = RpcClient::new(
+            nc,
+            "HOST".to_string(),
+            None,
+            Arc::new(KeyPair::new_server()),
+        );
+        let mut sub = client
+            .subscribe_filtered("events".to_string(), |msg| {
+                msg.headers.as_ref().and_then(|h| h.get("X-Match")).is_some()
+            })
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        // one poll looks at a bounded number of messages, and returns
+        assert!(sub.next().now_or_never().is_none());
+        let msg = tokio::time::timeout(Duration::from_secs(5), sub.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.payload.as_ref(), b"last");
+    }
+
+    #[tokio::test]
+    async fn subscription_limit() {
+        let mut client = unconnected_client().await;
+        client.set_max_subscriptions(Some(2));
+        let first = client.subscribe("a".to_string()).await.unwrap();
+        let _second = client.clone().subscribe("b".to_string()).await.unwrap();
+        assert_eq!(client.active_subscriptions(), 2);
+
+        // clones share the limit, as they do the count
+        let mut clone = client.clone();
+        clone.set_max_subscriptions(Some(3));
+        let extra = client.subscribe("e".to_string()).await.unwrap();
+        drop(extra);
+        clone.set_max_subscriptions(Some(2));
+
+        let err = client.subscribe("c".to_string()).await.unwrap_err();
+        assert!(
+            matches!(&err, RpcError::Other(s) if s == "subscription limit reached"),
+            "{err}"
+        );
+        let err = client
+            .subscribe_replies("d".to_string(), Vec::new())
+            .await
+            .err()
+            .expect("over the limit");
+        assert!(matches!(err, RpcError::Other(_)), "{err}");
+
+        drop(first);
+        assert_eq!(client.active_subscriptions(), 1);
+        let _third = client.subscribe("c".to_string()).await.unwrap();
+        assert_eq!(client.active_subscriptions(), 2);
+    }
+
+    #[tokio::test]
+    async fn max_payload_precheck() {
+        let mut client = unconnected_client().await;
+        client.set_max_payload(Some(1000));
+        let origin = WasmCloudEntity::new_actor("MORIGIN").unwrap();
+        let target = WasmCloudEntity::new_actor("MTARGET").unwrap();
+        let message = Message {
+            method: "Big.Thing",
+            arg: Cow::Owned(vec![0u8; 2000]),
+        };
+        let err = client.send(origin, target, "default", message).await.unwrap_err();
+        match err {
+            RpcError::InvalidParameter(msg) => {
+                // the error names the operation
+                assert!(
+                    msg.starts_with("Big.Thing: message exceeds max payload "),
+                    "{msg}"
+                );
+                assert!(msg.contains("> 1000"), "{msg}");
+            }
+            other => panic!("unexpected error {other}"),
+        }
+
+        // with no limit from the server (not yet connected), the size isn't checked
+        client.set_max_payload(None);
+        assert!(client.check_payload_size(2_000_000).is_ok());
+        client.set_max_payload(Some(100));
+        assert!(client.check_payload_size(100).is_ok());
+        assert!(client.check_payload_size(101).is_err());
+    }
+
+    #[test]
+    fn priority_header() {
+        let header = |opts: SendOpts| {
+            opts_headers(&opts).and_then(|h| h.get(PRIORITY_HEADER).map(|v| v.as_str().to_string()))
+        };
+        assert_eq!(
+            header(SendOpts::default().priority(Priority::High)).as_deref(),
+            Some("high")
+        );
+        assert_eq!(
+            header(SendOpts::default().priority(Priority::Low)).as_deref(),
+            Some("low")
+        );
+        // Normal sends no header
+        assert!(opts_headers(&SendOpts::default().priority(Priority::Normal)).is_none());
+        assert_eq!(header(SendOpts::default().idempotency_key("k")), None);
+
+        for p in [Priority::Low, Priority::Normal, Priority::High] {
+            assert_eq!(Priority::from_header(p.as_str()), p);
+        }
+        assert_eq!(Priority::from_header("urgent"), Priority::Normal);
+    }
+
+    #[test]
+    fn compressed_response() {
+        use super::{compress_payload, decompress_payload, DEFLATE_ENCODING, ENCODING_HEADER};
+
+        let payload = vec![7u8; 2000];
+        let compressed = compress_payload(&payload);
+        assert!(compressed.len() < payload.len());
+        let mut headers = crate::async_nats::HeaderMap::new();
+        headers.insert(ENCODING_HEADER, DEFLATE_ENCODING);
+        assert_eq!(
+            decompress_payload(Some(&headers), &compressed).unwrap(),
+            payload
+        );
+        // no header: the payload is as sent
+        assert_eq!(decompress_payload(None, &payload).unwrap(), payload);
+
+        headers.insert(ENCODING_HEADER, "zstd");
+        let err = decompress_payload(Some(&headers), &compressed).unwrap_err();
+        assert!(matches!(err, RpcError::Deser(_)), "{err}");
+    }
+
+    #[tokio::test]
+    async fn custom_headers() {
+        use super::check_header;
+
+        let opts = SendOpts::default().header("X-Tenant", "acme").header("X-Route", "east");
+        let headers = opts_headers(&opts).unwrap();
+        assert_eq!(headers.get("X-Tenant").map(|v| v.as_str()), Some("acme"));
+        assert_eq!(headers.get("X-Route").map(|v| v.as_str()), Some("east"));
+
+        assert!(check_header("X-Tenant", "acme").is_ok());
+        for (name, value) in [
+            ("", "v"),
+            ("X Tenant", "v"),
+            ("X:Tenant", "v"),
+            ("X-Tenant", "a\r\nb"),
+            ("Wasmbus-Format", "json"),
+            ("wasmbus-checksum", "0"),
+        ] {
+            let err = check_header(name, value).unwrap_err();
+            assert!(matches!(err, RpcError::InvalidParameter(_)), "{err}");
+        }
+
+        // an illegal name fails the send before anything is sent
+        let mut client = unconnected_client().await;
+        let tap = Arc::new(SendRecorder::default());
+        client.set_wire_tap(tap.clone());
+        let target = WasmCloudEntity {
+            public_key: "MTARGET".to_string(),
+            ..Default::default()
+        };
+        let message = Message {
+            method: "Greeter.Hello",
+            arg: Cow::Borrowed(b"hi"),
+        };
+        let opts = SendOpts::default().header("Bad Name", "x");
+        let err = client
+            .send_with_opts(
+                WasmCloudEntity::default(),
+                target,
+                "default",
+                message,
+                &opts,
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, RpcError::InvalidParameter(_)), "{err}");
+        assert!(tap.sent.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn accept_formats_header() {
+        use super::ACCEPT_FORMATS_HEADER;
+        use crate::common::MessageFormat;
+
+        let opts =
+            SendOpts::default().accept_formats(vec![MessageFormat::Msgpack, MessageFormat::Cbor]);
+        let headers = opts_headers(&opts).unwrap();
+        assert_eq!(
+            headers.get(ACCEPT_FORMATS_HEADER).map(|v| v.as_str()),
+            Some("msgpack,cbor")
+        );
+        assert!(headers.get(super::FORMAT_HEADER).is_none());
+
+        // a sender names the format it wrote the request in
+        let headers = opts_headers(&SendOpts::default().format(Some(MessageFormat::Json))).unwrap();
+        assert_eq!(
+            headers.get(super::FORMAT_HEADER).map(|v| v.as_str()),
+            Some("json")
+        );
+    }
+
+    #[test]
+    fn reconnect_jitter() {
+        let max = Duration::from_secs(2);
+        for attempts in 0..40 {
+            let ceiling = (Duration::from_millis(100) * 2u32.pow(attempts.min(20) as u32)).min(max);
+            let delays: Vec<Duration> = (0..50).map(|_| reconnect_delay(attempts, max)).collect();
+            for delay in delays.iter() {
+                assert!(
+                    *delay <= ceiling && *delay >= ceiling / 2,
+                    "{attempts}: {delay:?}"
+                );
+            }
+            // delays are randomized, not fixed
+            assert!(
+                delays.iter().any(|d| *d != delays[0]),
+                "{attempts}: {delays:?}"
+            );
+        }
+    }
+
+    #[derive(Default)]
+    struct SendRecorder {
+        sent: std::sync::Mutex<Vec<(String, Vec<u8>)>>,
+    }
+
+    impl WireTap for SendRecorder {
+        fn on_send(&self, subject: &str, bytes: &[u8]) {
+            self.sent.lock().unwrap().push((subject.to_string(), bytes.to_vec()));
+        }
+    }
+
+    #[tokio::test]
+    async fn call_actor_subject() {
+        let mut client = unconnected_client().await;
+        client.timeout = Some(Duration::from_millis(20));
+        let tap = Arc::new(SendRecorder::default());
+        client.set_wire_tap(tap.clone());
+
+        // no server, so the calls time out after sending
+        assert!(client.call_actor("MTARGET", "Greeter.Hello", b"hi").await.is_err());
+        client.set_lattice_prefix(LatticePrefix::new("east").unwrap());
+        let _ = client.call_actor("MTARGET", "Greeter.Hello", b"hi").await;
+        let subjects: Vec<String> =
+            tap.sent.lock().unwrap().iter().map(|(s, _)| s.clone()).collect();
+        assert_eq!(
+            subjects,
+            vec!["wasmbus.rpc.default.MTARGET", "wasmbus.rpc.east.MTARGET"]
+        );
+
+        // not an actor: nothing is sent
+        for target in ["VPROVIDER", ""] {
+            let err = client.call_actor(target, "Greeter.Hello", b"hi").await.unwrap_err();
+            assert!(matches!(err, RpcError::InvalidParameter(_)), "{err}");
+        }
+        assert_eq!(tap.sent.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn sequential_invocation_ids() {
+        use super::IdGenerator;
+        use crate::core::Invocation;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// Numbers invocations from 1
+        #[derive(Default)]
+        struct Sequence(AtomicUsize);
+
+        impl IdGenerator for Sequence {
+            fn next_id(&self) -> String {
+                format!("inv-{}", self.0.fetch_add(1, Ordering::SeqCst) + 1)
+            }
+        }
+
+        let mut client = unconnected_client().await;
+        client.timeout = Some(Duration::from_millis(20));
+        let tap = Arc::new(SendRecorder::default());
+        client.set_wire_tap(tap.clone());
+        client.set_id_generator(Arc::new(Sequence::default()));
+        for _ in 0..3 {
+            let _ = client.call_actor("MTARGET", "Greeter.Hello", b"hi").await;
+        }
+        let ids: Vec<String> = tap
+            .sent
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, bytes)| crate::common::deserialize::<Invocation>(bytes).unwrap().id)
+            .collect();
+        assert_eq!(ids, vec!["inv-1", "inv-2", "inv-3"]);
+    }
+
+    #[tokio::test]
+    async fn wire_tap_sees_published_bytes() {
+        let mut client = unconnected_client().await;
+        // no tap installed: nothing to observe, and publishing still works
+        client.publish("audit.none".to_string(), b"a".to_vec()).await.unwrap();
+
+        let tap = Arc::new(SendRecorder::default());
+        client.set_wire_tap(tap.clone());
+        client
+            .publish("audit.one".to_string(), b"\x00bytes".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(
+            *tap.sent.lock().unwrap(),
+            vec![("audit.one".to_string(), b"\x00bytes".to_vec())]
+        );
+    }
+
+    #[test]
+    fn checksum_detects_flipped_byte() {
+        // the standard crc32 check value
+        assert_eq!(payload_checksum(b"123456789"), "cbf43926");
+
+        let payload = b"transfer 100 to alice".to_vec();
+        let mut headers = crate::async_nats::HeaderMap::new();
+        headers.insert(CHECKSUM_HEADER, payload_checksum(&payload).as_str());
+        verify_checksum(Some(&headers), &payload).unwrap();
+        // messages without the header aren't checked
+        verify_checksum(None, &payload).unwrap();
+
+        let mut corrupted = payload;
+        corrupted[9] ^= 0x01;
+        let err = verify_checksum(Some(&headers), &corrupted).unwrap_err();
+        assert!(
+            matches!(&err, RpcError::Deser(s) if s == "checksum mismatch"),
+            "{err}"
+        );
+    }
+}
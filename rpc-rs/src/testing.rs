@@ -0,0 +1,166 @@
+//! Test utilities. Enabled with the `testing` feature.
+
+use std::{
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use tracing::{debug, warn};
+
+use crate::core::HostData;
+
+/// Environment variable with the path of the nats-server binary.
+/// If not set, `nats-server` is found on the PATH.
+pub const NATS_SERVER_BIN_ENV: &str = "NATS_SERVER_BIN";
+
+/// Lattice prefix of [HostData::test_default]
+pub const TEST_LATTICE_PREFIX: &str = "default";
+/// Provider key of [HostData::test_default]
+pub const TEST_PROVIDER_KEY: &str = "VTESTPROVIDER";
+
+/// How long to wait for a new server to accept connections
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A nats-server process on an ephemeral port, for integration tests.
+/// The server is killed when this is dropped.
+#[derive(Debug)]
+pub struct NatsServer {
+    child: Child,
+    port: u16,
+}
+
+impl NatsServer {
+    /// Starts a server and waits until it accepts connections.
+    /// Returns an error if the binary can't be run or the server doesn't start.
+    pub fn start() -> std::io::Result<NatsServer> {
+        let bin = std::env::var(NATS_SERVER_BIN_ENV).unwrap_or_else(|_| "nats-server".into());
+        let port = free_port()?;
+        let child = Command::new(&bin)
+            .args(["-a", "127.0.0.1", "-p", &port.to_string()])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let mut server = NatsServer { child, port };
+        server.wait_ready()?;
+        debug!(url = %server.url(), "started nats-server");
+        Ok(server)
+    }
+
+    /// Starts a server, or returns None if the nats-server binary isn't installed,
+    /// so the test can skip itself
+    pub fn try_start() -> Option<NatsServer> {
+        match NatsServer::start() {
+            Ok(server) => Some(server),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                warn!("skipping test: nats-server not found (set {NATS_SERVER_BIN_ENV})");
+                None
+            }
+            Err(e) => panic!("starting nats-server: {e}"),
+        }
+    }
+
+    /// Client url of the server
+    pub fn url(&self) -> String {
+        format!("nats://127.0.0.1:{}", self.port)
+    }
+
+    /// Port the server listens on
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    fn wait_ready(&mut self) -> std::io::Result<()> {
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, self.port));
+        let started = Instant::now();
+        loop {
+            if let Some(status) = self.child.try_wait()? {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    format!("nats-server exited: {status}"),
+                ));
+            }
+            if TcpStream::connect_timeout(&addr, Duration::from_millis(100)).is_ok() {
+                return Ok(());
+            }
+            if started.elapsed() > STARTUP_TIMEOUT {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "nats-server did not start",
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+impl Drop for NatsServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Returns a port that was free when checked
+fn free_port() -> std::io::Result<u16> {
+    Ok(TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?.local_addr()?.port())
+}
+
+impl HostData {
+    /// Host data for a provider running under test: `host_id` is "_TEST_",
+    /// so [is_test](HostData::is_test) is true, the lattice prefix and provider key
+    /// are [TEST_LATTICE_PREFIX] and [TEST_PROVIDER_KEY], the link name is "default",
+    /// and other fields are empty. It passes [validate](HostData::validate).
+    /// Override fields with the `with_` methods.
+    pub fn test_default() -> HostData {
+        HostData {
+            host_id: "_TEST_".to_string(),
+            lattice_rpc_prefix: TEST_LATTICE_PREFIX.to_string(),
+            provider_key: TEST_PROVIDER_KEY.to_string(),
+            link_name: "default".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the lattice prefix
+    #[must_use]
+    pub fn with_lattice_rpc_prefix(mut self, val: &str) -> HostData {
+        self.lattice_rpc_prefix = val.to_string();
+        self
+    }
+
+    /// Sets the nats url, such as [NatsServer::url]
+    #[must_use]
+    pub fn with_lattice_rpc_url(mut self, val: &str) -> HostData {
+        self.lattice_rpc_url = val.to_string();
+        self
+    }
+
+    /// Sets the provider key
+    #[must_use]
+    pub fn with_provider_key(mut self, val: &str) -> HostData {
+        self.provider_key = val.to_string();
+        self
+    }
+
+    /// Sets the link name
+    #[must_use]
+    pub fn with_link_name(mut self, val: &str) -> HostData {
+        self.link_name = val.to_string();
+        self
+    }
+
+    /// Sets the provider's configuration, as returned by `config_json_raw`
+    #[must_use]
+    pub fn with_config_json(mut self, val: &str) -> HostData {
+        self.config_json = Some(val.to_string());
+        self
+    }
+
+    /// Adds a link definition the provider receives at startup
+    #[must_use]
+    pub fn with_link(mut self, ld: crate::core::LinkDefinition) -> HostData {
+        self.link_definitions.push(ld);
+        self
+    }
+}
@@ -8,6 +8,9 @@
 //! and to/from other timezones,
 //! the [chrono](https://crates.io/crate/chrono) crate is recommended.
 //!
+//! To send a timestamp as milliseconds since the epoch, instead of seconds and
+//! nanoseconds, annotate the field with `#[serde(with = "wasmbus_rpc::timestamp::millis")]`.
+//!
 
 use serde::{Deserialize, Serialize};
 use std::{
@@ -48,6 +51,39 @@ impl Timestamp {
     pub fn as_nanos(&self) -> u128 {
         (self.sec as u128 * 1_000_000_000) + self.nsec as u128
     }
+
+    /// Returns the number of milliseconds since the UNIX EPOCH, negative if before it.
+    /// Sub-millisecond precision is truncated toward the past.
+    pub fn to_millis(&self) -> i64 {
+        self.sec
+            .saturating_mul(1_000)
+            .saturating_add((self.nsec / 1_000_000) as i64)
+    }
+
+    /// Constructs a timestamp from milliseconds since the UNIX EPOCH, which may be negative
+    pub fn from_millis(millis: i64) -> Timestamp {
+        Timestamp {
+            sec: millis.div_euclid(1_000),
+            nsec: millis.rem_euclid(1_000) as u32 * 1_000_000,
+        }
+    }
+}
+
+/// Serde helper that writes a [Timestamp] as an integer number of milliseconds
+/// since the UNIX EPOCH, for use with `#[serde(with = "wasmbus_rpc::timestamp::millis")]`
+pub mod millis {
+    use super::Timestamp;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    /// Serializes the timestamp as epoch milliseconds
+    pub fn serialize<S: Serializer>(ts: &Timestamp, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(ts.to_millis())
+    }
+
+    /// Deserializes a timestamp from epoch milliseconds
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Timestamp, D::Error> {
+        i64::deserialize(deserializer).map(Timestamp::from_millis)
+    }
 }
 
 impl Default for Timestamp {
@@ -220,3 +256,38 @@ fn timestamp_ordering() {
     // not equals
     assert_ne!(t1, t4);
 }
+
+#[test]
+fn timestamp_millis() {
+    let t = Timestamp { sec: 1_700_000_000, nsec: 123_456_789 };
+    assert_eq!(t.to_millis(), 1_700_000_000_123);
+    assert_eq!(
+        Timestamp::from_millis(1_700_000_000_123),
+        Timestamp { sec: 1_700_000_000, nsec: 123_000_000 }
+    );
+
+    // pre-epoch: 1.5s before is 2s before, plus half a second
+    let t = Timestamp::from_millis(-1_500);
+    assert_eq!(t, Timestamp { sec: -2, nsec: 500_000_000 });
+    assert_eq!(t.to_millis(), -1_500);
+    for millis in [0, 1, -1, 999, -999, -1_000, 86_400_000, -86_400_001] {
+        assert_eq!(Timestamp::from_millis(millis).to_millis(), millis);
+    }
+}
+
+#[test]
+fn timestamp_millis_serde() {
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Event {
+        #[serde(with = "crate::timestamp::millis")]
+        at: Timestamp,
+    }
+
+    let event = Event { at: Timestamp::from_millis(-1_500) };
+    let json = serde_json::to_string(&event).unwrap();
+    assert_eq!(json, r#"{"at":-1500}"#);
+    assert_eq!(serde_json::from_str::<Event>(&json).unwrap(), event);
+
+    let buf = crate::common::serialize(&event).unwrap();
+    assert_eq!(crate::common::deserialize::<Event>(&buf).unwrap(), event);
+}
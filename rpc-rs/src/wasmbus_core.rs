@@ -249,14 +249,7 @@ pub fn decode_health_check_response(
             for __i in 0..(len as usize) {
                 match __i {
                     0 => healthy = Some(d.bool()?),
-                    1 => {
-                        message = if crate::cbor::Type::Null == d.datatype()? {
-                            d.skip()?;
-                            Some(None)
-                        } else {
-                            Some(Some(d.str()?.to_string()))
-                        }
-                    }
+                    1 => message = Some(d.optional("message", |d| d.str().map(|v| v.to_string()))?),
 
                     _ => d.skip()?,
                 }
@@ -267,12 +260,7 @@ pub fn decode_health_check_response(
                 match d.str()? {
                     "healthy" => healthy = Some(d.bool()?),
                     "message" => {
-                        message = if crate::cbor::Type::Null == d.datatype()? {
-                            d.skip()?;
-                            Some(None)
-                        } else {
-                            Some(Some(d.str()?.to_string()))
-                        }
+                        message = Some(d.optional("message", |d| d.str().map(|v| v.to_string()))?)
                     }
                     _ => d.skip()?,
                 }
@@ -434,31 +422,20 @@ pub fn decode_host_data(d: &mut crate::cbor::Decoder<'_>) -> Result<HostData, Rp
                         })?)
                     }
                     12 => {
-                        config_json = if crate::cbor::Type::Null == d.datatype()? {
-                            d.skip()?;
-                            Some(None)
-                        } else {
-                            Some(Some(d.str()?.to_string()))
-                        }
+                        config_json =
+                            Some(d.optional("configJson", |d| d.str().map(|v| v.to_string()))?)
                     }
                     13 => {
-                        default_rpc_timeout_ms = if crate::cbor::Type::Null == d.datatype()? {
-                            d.skip()?;
-                            Some(None)
-                        } else {
-                            Some(Some(d.u64()?))
-                        }
+                        default_rpc_timeout_ms =
+                            Some(d.optional("defaultRpcTimeoutMs", |d| d.u64())?)
                     }
                     14 => structured_logging = Some(d.bool()?),
                     15 => {
-                        log_level = if crate::cbor::Type::Null == d.datatype()? {
-                            d.skip()?;
-                            Some(None)
-                        } else {
-                            Some(Some(decode_log_level(d).map_err(|e| {
+                        log_level = Some(d.optional("logLevel", |d| {
+                            Ok(decode_log_level(d).map_err(|e| {
                                 format!("decoding 'org.wasmcloud.core#LogLevel': {}", e)
-                            })?))
-                        }
+                            })?)
+                        })?)
                     }
 
                     _ => d.skip()?,
@@ -493,31 +470,20 @@ pub fn decode_host_data(d: &mut crate::cbor::Decoder<'_>) -> Result<HostData, Rp
                         })?)
                     }
                     "configJson" => {
-                        config_json = if crate::cbor::Type::Null == d.datatype()? {
-                            d.skip()?;
-                            Some(None)
-                        } else {
-                            Some(Some(d.str()?.to_string()))
-                        }
+                        config_json =
+                            Some(d.optional("configJson", |d| d.str().map(|v| v.to_string()))?)
                     }
                     "defaultRpcTimeoutMs" => {
-                        default_rpc_timeout_ms = if crate::cbor::Type::Null == d.datatype()? {
-                            d.skip()?;
-                            Some(None)
-                        } else {
-                            Some(Some(d.u64()?))
-                        }
+                        default_rpc_timeout_ms =
+                            Some(d.optional("defaultRpcTimeoutMs", |d| d.u64())?)
                     }
                     "structuredLogging" => structured_logging = Some(d.bool()?),
                     "logLevel" => {
-                        log_level = if crate::cbor::Type::Null == d.datatype()? {
-                            d.skip()?;
-                            Some(None)
-                        } else {
-                            Some(Some(decode_log_level(d).map_err(|e| {
+                        log_level = Some(d.optional("logLevel", |d| {
+                            Ok(decode_log_level(d).map_err(|e| {
                                 format!("decoding 'org.wasmcloud.core#LogLevel': {}", e)
-                            })?))
-                        }
+                            })?)
+                        })?)
                     }
                     _ => d.skip()?,
                 }
@@ -772,23 +738,13 @@ pub fn decode_invocation(d: &mut crate::cbor::Decoder<'_>) -> Result<Invocation,
                     4 => id = Some(d.str()?.to_string()),
                     5 => encoded_claims = Some(d.str()?.to_string()),
                     6 => host_id = Some(d.str()?.to_string()),
-                    7 => {
-                        content_length = if crate::cbor::Type::Null == d.datatype()? {
-                            d.skip()?;
-                            Some(None)
-                        } else {
-                            Some(Some(d.u64()?))
-                        }
-                    }
+                    7 => content_length = Some(d.optional("contentLength", |d| d.u64())?),
                     8 => {
-                        trace_context = if crate::cbor::Type::Null == d.datatype()? {
-                            d.skip()?;
-                            Some(None)
-                        } else {
-                            Some(Some(decode_trace_context(d).map_err(|e| {
+                        trace_context = Some(d.optional("traceContext", |d| {
+                            Ok(decode_trace_context(d).map_err(|e| {
                                 format!("decoding 'org.wasmcloud.core#TraceContext': {}", e)
-                            })?))
-                        }
+                            })?)
+                        })?)
                     }
 
                     _ => d.skip()?,
@@ -814,22 +770,14 @@ pub fn decode_invocation(d: &mut crate::cbor::Decoder<'_>) -> Result<Invocation,
                     "encodedClaims" => encoded_claims = Some(d.str()?.to_string()),
                     "hostId" => host_id = Some(d.str()?.to_string()),
                     "contentLength" => {
-                        content_length = if crate::cbor::Type::Null == d.datatype()? {
-                            d.skip()?;
-                            Some(None)
-                        } else {
-                            Some(Some(d.u64()?))
-                        }
+                        content_length = Some(d.optional("contentLength", |d| d.u64())?)
                     }
                     "traceContext" => {
-                        trace_context = if crate::cbor::Type::Null == d.datatype()? {
-                            d.skip()?;
-                            Some(None)
-                        } else {
-                            Some(Some(decode_trace_context(d).map_err(|e| {
+                        trace_context = Some(d.optional("traceContext", |d| {
+                            Ok(decode_trace_context(d).map_err(|e| {
                                 format!("decoding 'org.wasmcloud.core#TraceContext': {}", e)
-                            })?))
-                        }
+                            })?)
+                        })?)
                     }
                     _ => d.skip()?,
                 }
@@ -968,22 +916,8 @@ pub fn decode_invocation_response(
                 match __i {
                     0 => msg = Some(d.bytes()?.to_vec()),
                     1 => invocation_id = Some(d.str()?.to_string()),
-                    2 => {
-                        error = if crate::cbor::Type::Null == d.datatype()? {
-                            d.skip()?;
-                            Some(None)
-                        } else {
-                            Some(Some(d.str()?.to_string()))
-                        }
-                    }
-                    3 => {
-                        content_length = if crate::cbor::Type::Null == d.datatype()? {
-                            d.skip()?;
-                            Some(None)
-                        } else {
-                            Some(Some(d.u64()?))
-                        }
-                    }
+                    2 => error = Some(d.optional("error", |d| d.str().map(|v| v.to_string()))?),
+                    3 => content_length = Some(d.optional("contentLength", |d| d.u64())?),
 
                     _ => d.skip()?,
                 }
@@ -995,20 +929,10 @@ pub fn decode_invocation_response(
                     "msg" => msg = Some(d.bytes()?.to_vec()),
                     "invocationId" => invocation_id = Some(d.str()?.to_string()),
                     "error" => {
-                        error = if crate::cbor::Type::Null == d.datatype()? {
-                            d.skip()?;
-                            Some(None)
-                        } else {
-                            Some(Some(d.str()?.to_string()))
-                        }
+                        error = Some(d.optional("error", |d| d.str().map(|v| v.to_string()))?)
                     }
                     "contentLength" => {
-                        content_length = if crate::cbor::Type::Null == d.datatype()? {
-                            d.skip()?;
-                            Some(None)
-                        } else {
-                            Some(Some(d.u64()?))
-                        }
+                        content_length = Some(d.optional("contentLength", |d| d.u64())?)
                     }
                     _ => d.skip()?,
                 }
@@ -1402,6 +1326,16 @@ pub trait ActorReceiver: MessageDispatch + Actor {
             ))),
         }
     }
+
+    /// Operations of the Actor service, with their input and output type names
+    fn service_operation_info(&self) -> &'static [crate::common::OperationInfo] {
+        &[crate::common::OperationInfo {
+            name: "Actor.HealthRequest",
+            input_type: Some("HealthCheckRequest"),
+            output_type: Some("HealthCheckResponse"),
+            one_way: false,
+        }]
+    }
 }
 
 /// ActorSender sends messages to a Actor service
@@ -0,0 +1,78 @@
+//! a provider reports the peak memory allocated by each dispatch
+//! (requires the `alloc-tracking` feature)
+#![cfg(test)]
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::ProviderHarness;
+use wasmbus_rpc::{
+    alloc_tracking::{MetricsSink, TrackingAllocator},
+    provider::prelude::*,
+};
+
+#[global_allocator]
+static ALLOC: TrackingAllocator = TrackingAllocator::new();
+
+/// Records the allocations reported by the provider
+#[derive(Default)]
+struct Recorder {
+    peaks: Mutex<Vec<(String, usize)>>,
+}
+
+impl MetricsSink for Recorder {
+    fn record_allocation(&self, operation: &str, peak_bytes: usize) {
+        self.peaks.lock().unwrap().push((operation.to_string(), peak_bytes));
+    }
+}
+
+/// Provider with an operation that allocates a megabyte, and one that allocates almost nothing
+#[derive(Clone)]
+struct Mem {
+    sink: Arc<Recorder>,
+}
+
+#[async_trait]
+impl ProviderHandler for Mem {
+    fn provider_options(&self) -> ProviderOptions {
+        ProviderOptions::default().metrics_sink(self.sink.clone())
+    }
+}
+
+#[async_trait]
+impl MessageDispatch for Mem {
+    async fn dispatch(&self, _ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+        match message.method {
+            "Mem.Heavy" => {
+                let buf = vec![1u8; 1 << 20];
+                tokio::task::yield_now().await;
+                Ok(vec![buf.iter().fold(0u8, |a, b| a.wrapping_add(*b))])
+            }
+            "Mem.Light" => Ok(vec![1]),
+            _ => Err(RpcError::MethodNotHandled(message.method.to_string())),
+        }
+    }
+}
+
+impl ProviderDispatch for Mem {}
+
+#[tokio::test]
+async fn heavy_handler_reports_more() {
+    let provider = Mem { sink: Arc::new(Recorder::default()) };
+    let harness = ProviderHarness::start(provider.clone()).await;
+
+    for method in ["Mem.Heavy", "Mem.Light"] {
+        let resp = harness.request(harness.invocation(method, b"").await).await;
+        assert_eq!(resp.error, None);
+    }
+
+    let peaks = provider.sink.peaks.lock().unwrap().clone();
+    let names: Vec<&str> = peaks.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, vec!["Mem.Heavy", "Mem.Light"]);
+    let (heavy, light) = (peaks[0].1, peaks[1].1);
+    assert!(heavy >= 1 << 20, "{heavy}");
+    assert!(light < heavy / 100, "{light} {heavy}");
+
+    harness.shutdown().await;
+}
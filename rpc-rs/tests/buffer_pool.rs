@@ -0,0 +1,75 @@
+//! Pooled serialization allocates less than growing a new Vec per message
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use serde::Serialize;
+use wasmbus_rpc::{buffer_pool, common::serialize};
+
+/// Counts allocations and reallocations
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+#[derive(Serialize)]
+struct Message {
+    name: String,
+    values: Vec<u32>,
+}
+
+/// Returns the number of allocations made while serializing the message `count` times
+fn allocations(msg: &Message, count: usize) -> usize {
+    let start = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..count {
+        let buf = serialize(msg).unwrap();
+        assert!(!buf.is_empty());
+    }
+    ALLOCATIONS.load(Ordering::Relaxed) - start
+}
+
+#[test]
+fn pooled_serialize_allocates_less() {
+    let msg = Message {
+        name: "x".repeat(100),
+        values: (0..500).collect(),
+    };
+    let expected = serialize(&msg).unwrap();
+
+    let unpooled = allocations(&msg, 1000);
+
+    buffer_pool::set_pooled(true);
+    assert_eq!(serialize(&msg).unwrap(), expected);
+    let pooled = allocations(&msg, 1000);
+    buffer_pool::set_pooled(false);
+
+    // one allocation per message, for the returned Vec
+    assert!(
+        pooled <= 1000,
+        "pooled: {pooled} allocations, unpooled: {unpooled}"
+    );
+    assert!(
+        pooled < unpooled,
+        "pooled: {pooled} allocations, unpooled: {unpooled}"
+    );
+    assert_eq!(buffer_pool::global().idle(), 1);
+}
@@ -0,0 +1,65 @@
+//! Sending from an actor to a provider in the same process, without nats
+use std::{borrow::Cow, time::Duration};
+
+use async_trait::async_trait;
+use wasmbus_rpc::{
+    common::{ChannelTransport, Context, Message, MessageDispatch, Transport},
+    error::{RpcError, RpcResult},
+};
+
+/// Provider that upper-cases its input, and reports the calling actor
+#[derive(Clone, Default)]
+struct Shouter {}
+
+#[async_trait]
+impl MessageDispatch for Shouter {
+    async fn dispatch(&self, ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+        match message.method {
+            "Shout.Loud" => Ok(message.arg.to_ascii_uppercase()),
+            "Shout.Caller" => Ok(ctx.actor.clone().unwrap_or_default().into_bytes()),
+            "Shout.Slow" => {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok(Vec::new())
+            }
+            _ => Err(RpcError::MethodNotHandled(message.method.to_string())),
+        }
+    }
+}
+
+fn msg<'m>(method: &'m str, arg: &'m [u8]) -> Message<'m> {
+    Message { method, arg: Cow::Borrowed(arg) }
+}
+
+#[tokio::test]
+async fn actor_to_provider() {
+    let transport = ChannelTransport::new(Shouter::default());
+    let ctx = Context {
+        actor: Some("MACTOR".to_string()),
+        ..Default::default()
+    };
+
+    let resp = transport.send(&ctx, msg("Shout.Loud", b"hello"), None).await.unwrap();
+    assert_eq!(resp, b"HELLO");
+
+    // clones share the dispatcher
+    let resp = transport.clone().send(&ctx, msg("Shout.Caller", b""), None).await.unwrap();
+    assert_eq!(resp, b"MACTOR");
+
+    // handler errors are returned to the sender
+    let err = transport.send(&ctx, msg("Shout.Quiet", b""), None).await.unwrap_err();
+    assert!(matches!(err, RpcError::MethodNotHandled(m) if m == "Shout.Quiet"));
+}
+
+#[tokio::test]
+async fn send_timeout() {
+    let transport = ChannelTransport::new(Shouter::default());
+    transport.set_timeout(Duration::from_millis(20));
+    let ctx = Context::default();
+
+    let err = transport.send(&ctx, msg("Shout.Slow", b""), None).await.unwrap_err();
+    assert!(matches!(err, RpcError::Timeout(_)));
+
+    // a slow request doesn't hold up the next one
+    let resp = transport.send(&ctx, msg("Shout.Loud", b"ok"), None).await.unwrap();
+    assert_eq!(resp, b"OK");
+}
@@ -0,0 +1,284 @@
+//! fixture shared by the provider tests: a provider run over the in-memory transport,
+//! and a client that signs invocations the way the host would
+#![allow(dead_code)]
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use wascap::prelude::KeyPair;
+use wasmbus_rpc::{
+    async_nats::{self, ConnectOptions, HeaderMap},
+    common::{deserialize, SendOpts},
+    core::{HostData, InvocationResponse, LinkDefinition, WasmCloudEntity},
+    provider::prelude::*,
+    provider_transport::MemoryTransport,
+    rpc_client::{RpcClient, WireTap},
+    testing::TEST_PROVIDER_KEY,
+};
+
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Subscriptions a running provider holds: rpc, link put and del, shutdown,
+/// health, and aggregate health
+pub const PROVIDER_SUBSCRIPTIONS: usize = 6;
+
+/// The actor linked to the test provider
+pub const ACTOR_ID: &str = "MACTOR";
+
+/// Records the messages an rpc client sends, with their headers
+#[derive(Default)]
+pub struct SendRecorder {
+    sent: Mutex<Vec<(HeaderMap, Vec<u8>)>>,
+}
+
+impl SendRecorder {
+    /// Returns true if nothing was sent since the last message was taken
+    pub fn is_empty(&self) -> bool {
+        self.sent.lock().unwrap().is_empty()
+    }
+
+    /// Returns the last message sent
+    pub fn last(&self) -> Vec<u8> {
+        self.last_with_headers().1
+    }
+
+    /// Returns the headers and payload of the last message sent
+    pub fn last_with_headers(&self) -> (HeaderMap, Vec<u8>) {
+        self.sent.lock().unwrap().pop().expect("invocation sent")
+    }
+}
+
+impl WireTap for SendRecorder {
+    fn on_send_with_headers(&self, _subject: &str, headers: Option<&HeaderMap>, bytes: &[u8]) {
+        let headers = headers.cloned().unwrap_or_default();
+        self.sent.lock().unwrap().push((headers, bytes.to_vec()));
+    }
+}
+
+/// Returns a nats client with no server behind it; everything the provider
+/// hears comes through the transport
+pub async fn unconnected_nats() -> async_nats::Client {
+    ConnectOptions::new()
+        .retry_on_initial_connect()
+        .connect("nats://127.0.0.1:1")
+        .await
+        .expect("nats client")
+}
+
+/// Returns test host data that trusts the cluster key, with ACTOR_ID linked to the provider.
+/// The host has a real identity, so the provider talks to it as it would in a lattice
+pub fn host_data(host_key: &KeyPair, cluster_key: &KeyPair, provider_key: &str) -> HostData {
+    let mut host_data = HostData::test_default().with_provider_key(provider_key);
+    host_data.host_id = host_key.public_key();
+    host_data.invocation_seed = host_key.seed().expect("host seed");
+    host_data.cluster_issuers = vec![cluster_key.public_key()];
+    let mut ld = LinkDefinition::default();
+    ld.actor_id = ACTOR_ID.to_string();
+    ld.provider_id = provider_key.to_string();
+    ld.link_name = host_data.link_name.clone();
+    host_data.with_link(ld)
+}
+
+/// Signs invocations from ACTOR_ID, as the host would send them
+pub struct Invoker {
+    pub client: RpcClient,
+    pub tap: Arc<SendRecorder>,
+    lattice_prefix: String,
+}
+
+impl Invoker {
+    pub fn new(nc: async_nats::Client, cluster_key: KeyPair, lattice_prefix: &str) -> Self {
+        let mut client = RpcClient::new(
+            nc,
+            KeyPair::new_server().public_key(),
+            Some(TIMEOUT),
+            Arc::new(cluster_key),
+        );
+        let tap = Arc::new(SendRecorder::default());
+        client.set_wire_tap(tap.clone());
+        Invoker {
+            client,
+            tap,
+            lattice_prefix: lattice_prefix.to_string(),
+        }
+    }
+
+    /// Returns a signed invocation of the method on the provider
+    pub async fn invocation(&self, provider_key: &str, method: &str, arg: &[u8]) -> Vec<u8> {
+        let opts = SendOpts::default();
+        self.invocation_with_opts(provider_key, method, arg, &opts).await.1
+    }
+
+    /// Returns a signed invocation of the method on the provider, sent with the options,
+    /// and the headers that went with it
+    pub async fn invocation_with_opts(
+        &self,
+        provider_key: &str,
+        method: &str,
+        arg: &[u8],
+        opts: &SendOpts,
+    ) -> (HeaderMap, Vec<u8>) {
+        let origin = WasmCloudEntity {
+            public_key: ACTOR_ID.to_string(),
+            ..Default::default()
+        };
+        let target = WasmCloudEntity {
+            public_key: provider_key.to_string(),
+            link_name: "default".to_string(),
+            ..Default::default()
+        };
+        let message = Message { method, arg: arg.into() };
+        let send =
+            self.client
+                .send_with_opts(origin, target, &self.lattice_prefix, message, opts, None);
+        tokio::pin!(send);
+        // no nats server will answer, so stop waiting once the tap has seen the send
+        while self.tap.is_empty() {
+            tokio::select! {
+                _ = &mut send => break,
+                _ = tokio::time::sleep(Duration::from_millis(1)) => {}
+            }
+        }
+        self.tap.last_with_headers()
+    }
+}
+
+/// Waits until the condition is true, or panics
+pub async fn eventually(what: &str, cond: impl Fn() -> bool) {
+    let started = std::time::Instant::now();
+    while !cond() {
+        assert!(started.elapsed() < TIMEOUT, "timed out waiting for {what}");
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+}
+
+/// Sends the host's shutdown request to the provider on the rpc subject
+pub async fn shutdown(transport: &MemoryTransport, host_data: &HostData) {
+    let body = format!(r#"{{"host_id":"{}"}}"#, &host_data.host_id);
+    let resp = transport
+        .request(
+            &format!("{}.shutdown", host_data.rpc_subject()),
+            body.into_bytes(),
+            TIMEOUT,
+        )
+        .await
+        .expect("shutdown reply");
+    assert_eq!(resp.payload.as_ref(), b"shutting down");
+}
+
+/// A provider running over the in-memory transport
+pub struct ProviderHarness {
+    pub host_data: HostData,
+    pub transport: MemoryTransport,
+    pub invoker: Invoker,
+    run: tokio::task::JoinHandle<Result<(), String>>,
+}
+
+impl ProviderHarness {
+    /// Runs the provider with ACTOR_ID linked, and waits for it to subscribe
+    pub async fn start<P>(provider: P) -> Self
+    where
+        P: ProviderDispatch + Send + Sync + Clone + 'static,
+    {
+        Self::start_with(provider, |host_data| host_data).await
+    }
+
+    /// Runs the provider with host data adjusted by `configure`
+    pub async fn start_with<P>(provider: P, configure: impl FnOnce(HostData) -> HostData) -> Self
+    where
+        P: ProviderDispatch + Send + Sync + Clone + 'static,
+    {
+        Self::start_on(MemoryTransport::new(), provider, configure).await
+    }
+
+    /// Runs the provider over the transport, for a provider that holds a clone of it,
+    /// with host data adjusted by `configure`
+    pub async fn start_on<P>(
+        transport: MemoryTransport,
+        provider: P,
+        configure: impl FnOnce(HostData) -> HostData,
+    ) -> Self
+    where
+        P: ProviderDispatch + Send + Sync + Clone + 'static,
+    {
+        let cluster_key = KeyPair::new_cluster();
+        let host_data = configure(host_data(
+            &KeyPair::new_server(),
+            &cluster_key,
+            TEST_PROVIDER_KEY,
+        ));
+        let nc = unconnected_nats().await;
+        let run = tokio::spawn({
+            let (host_data, nc) = (host_data.clone(), nc.clone());
+            let transport = Arc::new(transport.clone());
+            async move {
+                run_provider_with_transport(provider, host_data, nc, transport)
+                    .await
+                    .map_err(|e| e.to_string())
+            }
+        });
+        eventually("provider subscriptions", || {
+            transport.subscriptions() == PROVIDER_SUBSCRIPTIONS
+        })
+        .await;
+        let invoker = Invoker::new(nc, cluster_key, &host_data.lattice_rpc_prefix);
+        ProviderHarness { host_data, transport, invoker, run }
+    }
+
+    /// The provider's rpc subject
+    pub fn topic(&self) -> String {
+        self.host_data.rpc_subject()
+    }
+
+    /// Returns a signed invocation of the method on the provider
+    pub async fn invocation(&self, method: &str, arg: &[u8]) -> Vec<u8> {
+        self.invoker.invocation(&self.host_data.provider_key, method, arg).await
+    }
+
+    /// Returns a signed invocation of the method on the provider, sent with the options,
+    /// and the headers that went with it
+    pub async fn invocation_with_opts(
+        &self,
+        method: &str,
+        arg: &[u8],
+        opts: &SendOpts,
+    ) -> (HeaderMap, Vec<u8>) {
+        self.invoker
+            .invocation_with_opts(&self.host_data.provider_key, method, arg, opts)
+            .await
+    }
+
+    /// Sends the invocation, and returns the provider's response
+    pub async fn request(&self, invocation: Vec<u8>) -> InvocationResponse {
+        let resp = self
+            .transport
+            .request(&self.topic(), invocation, TIMEOUT)
+            .await
+            .expect("provider reply");
+        deserialize(&resp.payload).expect("invocation response")
+    }
+
+    /// Sends the invocation with headers, and returns the reply as received
+    pub async fn request_with_headers(
+        &self,
+        headers: HeaderMap,
+        invocation: Vec<u8>,
+    ) -> async_nats::Message {
+        self.transport
+            .request_with_headers(&self.topic(), headers, invocation, TIMEOUT)
+            .await
+            .expect("provider reply")
+    }
+
+    /// Shuts the provider down, and waits for it to return
+    pub async fn shutdown(self) {
+        shutdown(&self.transport, &self.host_data).await;
+        tokio::time::timeout(TIMEOUT, self.run)
+            .await
+            .expect("provider stopped")
+            .expect("provider task panicked")
+            .expect("provider ran");
+    }
+}
@@ -0,0 +1,58 @@
+//! Actor health responders: the derived default, and a custom implementation
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use wasmbus_rpc::{
+    actor::prelude::*,
+    core::{HealthCheckRequest, HealthCheckResponse},
+};
+
+#[derive(Default, HealthResponder)]
+struct AlwaysHealthy {}
+
+#[derive(Default, HealthResponder)]
+#[health_responder(custom)]
+struct Conditional {
+    ready: AtomicBool,
+}
+
+#[async_trait]
+impl HealthResponder for Conditional {
+    async fn health(
+        &self,
+        _ctx: &Context,
+        _arg: &HealthCheckRequest,
+    ) -> RpcResult<HealthCheckResponse> {
+        Ok(if self.ready.load(Ordering::Relaxed) {
+            HealthCheckResponse { healthy: true, message: None }
+        } else {
+            HealthCheckResponse {
+                healthy: false,
+                message: Some("not ready".to_string()),
+            }
+        })
+    }
+}
+
+#[tokio::test]
+async fn derived_health() {
+    let ctx = Context::default();
+    let resp = AlwaysHealthy::default()
+        .health_request(&ctx, &HealthCheckRequest::default())
+        .await
+        .unwrap();
+    assert!(resp.healthy);
+}
+
+#[tokio::test]
+async fn custom_health() {
+    let ctx = Context::default();
+    let actor = Conditional::default();
+
+    let resp = actor.health_request(&ctx, &HealthCheckRequest::default()).await.unwrap();
+    assert!(!resp.healthy);
+    assert_eq!(resp.message.as_deref(), Some("not ready"));
+
+    actor.ready.store(true, Ordering::Relaxed);
+    let resp = actor.health_request(&ctx, &HealthCheckRequest::default()).await.unwrap();
+    assert!(resp.healthy);
+}
@@ -0,0 +1,74 @@
+//! test provider connection strategies (requires a nats server)
+#![cfg(test)]
+
+use wasmbus_rpc::{
+    async_nats::connection::State,
+    core::{HostData, LinkDefinition},
+    error::RpcResult,
+    provider::{ConnectionStrategy, HostBridge, ProviderOptions},
+};
+
+const TEST_NATS_ADDR: &str = "nats://127.0.0.1:4222";
+
+fn host_data() -> HostData {
+    let mut host_data = HostData::default();
+    host_data.host_id = "_TEST_".to_string();
+    host_data.lattice_rpc_prefix = "test_link_connections".to_string();
+    host_data.lattice_rpc_url = std::env::var("NATS_URL").unwrap_or_else(|_| TEST_NATS_ADDR.into());
+    host_data.provider_key = "VPROVIDER".to_string();
+    host_data.link_name = "default".to_string();
+    host_data
+}
+
+fn link(actor_id: &str) -> LinkDefinition {
+    let mut ld = LinkDefinition::default();
+    ld.actor_id = actor_id.to_string();
+    ld.provider_id = "VPROVIDER".to_string();
+    ld.link_name = "default".to_string();
+    ld.contract_id = "wasmcloud:test".to_string();
+    ld
+}
+
+async fn bridge(strategy: ConnectionStrategy) -> RpcResult<HostBridge> {
+    let host_data = host_data();
+    let nc = host_data.nats_connect().await?;
+    HostBridge::new_with_options(
+        nc,
+        &host_data,
+        ProviderOptions::default().connection_strategy(strategy),
+    )
+}
+
+#[tokio::test]
+async fn per_link_connections() -> RpcResult<()> {
+    let bridge = bridge(ConnectionStrategy::PerLink).await?;
+
+    bridge.put_link(link("MACTOR_1")).await;
+    bridge.put_link(link("MACTOR_2")).await;
+    let client_1 = bridge.link_rpc_client("MACTOR_1").await.expect("link 1 connection");
+    assert_eq!(client_1.client().connection_state(), State::Connected);
+    let info = client_1.server_info();
+    assert!(info.connect_rtt.is_some());
+    assert!(!info.version.is_empty());
+    assert!(bridge.link_rpc_client("MACTOR_2").await.is_some());
+
+    bridge.delete_link("MACTOR_1").await;
+    assert!(!bridge.is_linked("MACTOR_1").await);
+    assert!(bridge.link_rpc_client("MACTOR_1").await.is_none());
+    // other links keep their connection
+    assert!(bridge.link_rpc_client("MACTOR_2").await.is_some());
+
+    bridge.delete_link("MACTOR_2").await;
+    assert!(bridge.link_rpc_client("MACTOR_2").await.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn shared_connection() -> RpcResult<()> {
+    let bridge = bridge(ConnectionStrategy::Shared).await?;
+
+    bridge.put_link(link("MACTOR_1")).await;
+    assert!(bridge.is_linked("MACTOR_1").await);
+    assert!(bridge.link_rpc_client("MACTOR_1").await.is_none());
+    Ok(())
+}
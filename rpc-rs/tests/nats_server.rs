@@ -0,0 +1,88 @@
+//! Round trip through a nats-server started by the test (requires the `testing` feature)
+use std::{sync::Arc, time::Duration};
+
+use futures::StreamExt;
+use wascap::prelude::KeyPair;
+use wasmbus_rpc::{core::WasmCloudEntity, rpc_client::RpcClient, testing::NatsServer};
+
+async fn client(server: &NatsServer) -> RpcClient {
+    let nc = wasmbus_rpc::async_nats::connect(server.url()).await.unwrap();
+    RpcClient::new(
+        nc,
+        "HOST".to_string(),
+        Some(Duration::from_secs(2)),
+        Arc::new(KeyPair::new_server()),
+    )
+}
+
+#[tokio::test]
+async fn request_round_trip() {
+    let server = match NatsServer::try_start() {
+        Some(server) => server,
+        None => return,
+    };
+    let client = client(&server).await;
+    let nc = client.client();
+
+    let mut sub = nc.subscribe("test.echo".to_string()).await.unwrap();
+    let responder = {
+        let client = client.clone();
+        tokio::spawn(async move {
+            let msg = sub.next().await.unwrap();
+            let mut reply = b"echo: ".to_vec();
+            reply.extend_from_slice(&msg.payload);
+            client.publish(msg.reply.unwrap(), reply).await.unwrap();
+        })
+    };
+
+    let resp = client.request("test.echo".to_string(), b"hello".to_vec()).await.unwrap();
+    assert_eq!(resp, b"echo: hello");
+    responder.await.unwrap();
+}
+
+#[tokio::test]
+async fn scatter_gather() {
+    let server = match NatsServer::try_start() {
+        Some(server) => server,
+        None => return,
+    };
+    let client = client(&server).await;
+
+    let mut responders = Vec::new();
+    for key in ["MONE", "MTWO"] {
+        let client = client.clone();
+        let mut sub = client.client().subscribe("test.ping".to_string()).await.unwrap();
+        responders.push(tokio::spawn(async move {
+            let msg = sub.next().await.unwrap();
+            let me = WasmCloudEntity::new_actor(key).unwrap();
+            client
+                .reply_as(msg.reply.unwrap(), &me, key.as_bytes().to_vec())
+                .await
+                .unwrap();
+        }));
+    }
+
+    let mut replies = client
+        .scatter_gather(
+            "test.ping".to_string(),
+            b"ping".to_vec(),
+            Duration::from_millis(500),
+        )
+        .await
+        .unwrap();
+    replies.sort_by(|a, b| a.0.public_key.cmp(&b.0.public_key));
+    let replies: Vec<(String, Vec<u8>)> = replies
+        .into_iter()
+        .map(|(entity, payload)| (entity.public_key, payload))
+        .collect();
+    assert_eq!(
+        replies,
+        vec![
+            ("MONE".to_string(), b"MONE".to_vec()),
+            ("MTWO".to_string(), b"MTWO".to_vec())
+        ]
+    );
+    for r in responders {
+        r.await.unwrap();
+    }
+}
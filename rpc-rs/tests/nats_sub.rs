@@ -8,6 +8,8 @@ use tracing::{debug, error, info};
 use wascap::prelude::KeyPair;
 use wasmbus_rpc::{
     async_nats::{ConnectOptions, ServerAddr},
+    common::{Context, Message},
+    dispatch::{serve_stream, ReplyStream, StreamDispatch, DEFAULT_YIELD_INTERVAL},
     error::{RpcError, RpcResult},
     rpc_client::{with_connection_event_logging, RpcClient},
 };
@@ -272,6 +274,67 @@ async fn queue_sub() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test(tokio::test)]
+async fn server_info() -> Result<(), Box<dyn std::error::Error>> {
+    let client = make_client(None).await?;
+    let info = client.server_info();
+    assert!(!info.version.is_empty());
+    assert!(!info.server_id.is_empty());
+    assert!(info.max_payload > 0);
+    // not measured for clients that weren't connected by the provider
+    assert!(info.connect_rtt.is_none());
+    Ok(())
+}
+
+/// Replies to "Counter.Count" with the numbers 1..=n
+struct Counter;
+
+#[async_trait::async_trait]
+impl StreamDispatch for Counter {
+    async fn dispatch_stream(
+        &self,
+        _ctx: &Context,
+        message: Message<'_>,
+    ) -> RpcResult<ReplyStream> {
+        use futures::StreamExt;
+        let n: u8 = String::from_utf8_lossy(&message.arg).parse().unwrap_or_default();
+        Ok(futures::stream::iter((1..=n).map(|i| Ok(vec![i]))).boxed())
+    }
+}
+
+#[test(tokio::test)]
+async fn streamed_replies() -> Result<(), Box<dyn std::error::Error>> {
+    use futures::StreamExt;
+
+    let server = make_client(None).await?;
+    let client = make_client(None).await?;
+    let subject = "test_stream_replies";
+    let mut sub = server.client().subscribe(subject.to_string()).await?;
+    tokio::spawn(async move {
+        if let Some(msg) = sub.next().await {
+            let message = Message {
+                method: "Counter.Count",
+                arg: msg.payload.to_vec().into(),
+            };
+            serve_stream(
+                &server,
+                &Counter,
+                &Context::default(),
+                message,
+                msg.reply.unwrap(),
+                DEFAULT_YIELD_INTERVAL,
+            )
+            .await
+            .unwrap();
+        }
+    });
+
+    let replies = client.subscribe_replies(subject.to_string(), b"3".to_vec()).await?;
+    let replies: Vec<Vec<u8>> = wait_for(replies.map(|r| r.unwrap()).collect(), FIVE_SEC).await?;
+    assert_eq!(replies, vec![vec![1], vec![2], vec![3]]);
+    Ok(())
+}
+
 async fn wait_for<O, F: futures::Future<Output = O>>(
     f: F,
     timeout: Duration,
@@ -279,3 +342,96 @@ async fn wait_for<O, F: futures::Future<Output = O>>(
     let res: O = tokio::time::timeout(timeout, f).await?;
     Ok(res)
 }
+
+#[test(tokio::test)]
+async fn client_from_host_data() -> Result<(), Box<dyn std::error::Error>> {
+    let mut host_data = wasmbus_rpc::core::HostData::default();
+    host_data.host_id = "_TEST_".to_string();
+    host_data.lattice_rpc_url = nats_url();
+    host_data.lattice_rpc_prefix = "test_from_host_data".to_string();
+    host_data.provider_key = "VPROVIDER".to_string();
+    host_data.link_name = "default".to_string();
+    host_data.default_rpc_timeout_ms = Some(1500);
+
+    let client = RpcClient::from_host_data(&host_data).await?;
+    assert_eq!(client.lattice_prefix(), Some("test_from_host_data"));
+    let info = client.server_info();
+    assert!(info.connect_rtt.is_some());
+
+    // the client is connected: a request to a subject without subscribers fails fast
+    let resp = client.request("test.from_host_data.none".to_string(), b"x".to_vec()).await;
+    assert!(matches!(resp, Err(RpcError::Nats(_))), "{resp:?}");
+
+    // an invalid lattice prefix is rejected before connecting
+    host_data.lattice_rpc_prefix = "bad.*".to_string();
+    assert!(RpcClient::from_host_data(&host_data).await.is_err());
+    Ok(())
+}
+
+/// Records the bytes seen by the wire tap
+#[derive(Default)]
+struct Recorder {
+    sent: std::sync::Mutex<Vec<(String, Vec<u8>)>>,
+    received: std::sync::Mutex<Vec<(String, Vec<u8>)>>,
+}
+
+impl wasmbus_rpc::rpc_client::WireTap for Recorder {
+    fn on_send(&self, subject: &str, bytes: &[u8]) {
+        self.sent.lock().unwrap().push((subject.to_string(), bytes.to_vec()));
+    }
+
+    fn on_recv(&self, subject: &str, bytes: &[u8]) {
+        self.received.lock().unwrap().push((subject.to_string(), bytes.to_vec()));
+    }
+}
+
+#[test(tokio::test)]
+async fn wire_tap() -> Result<(), Box<dyn std::error::Error>> {
+    use futures::StreamExt;
+    use wasmbus_rpc::{
+        common::{deserialize, serialize},
+        core::{Invocation, InvocationResponse, WasmCloudEntity},
+    };
+
+    let actor = format!("MTAP{}", uuid::Uuid::new_v4().as_simple());
+    let topic = format!("wasmbus.rpc.default.{actor}");
+    let responder = make_client(None).await?.client();
+    let mut sub = responder.subscribe(topic.clone()).await?;
+    tokio::spawn(async move {
+        if let Some(msg) = sub.next().await {
+            let inv: Invocation = deserialize(&msg.payload).unwrap();
+            let mut resp = InvocationResponse::default();
+            resp.invocation_id = inv.id;
+            resp.msg = b"pong".to_vec();
+            let reply = serialize(&resp).unwrap();
+            responder.publish(msg.reply.unwrap(), reply.into()).await.unwrap();
+        }
+    });
+
+    let tap = Arc::new(Recorder::default());
+    let mut client = make_client(Some(FIVE_SEC)).await?;
+    client.set_wire_tap(tap.clone());
+    let origin = WasmCloudEntity::new_provider("wasmcloud:test", "default")?;
+    let target = WasmCloudEntity::new_actor(&actor)?;
+    let message = Message {
+        method: "Test.Ping",
+        arg: b"ping".to_vec().into(),
+    };
+    let resp = client.send(origin, target, "default", message).await?;
+    assert_eq!(resp, b"pong");
+
+    // the tap saw the encoded invocation, and the response before it was decoded
+    let sent = tap.sent.lock().unwrap();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].0, topic);
+    let inv: Invocation = deserialize(&sent[0].1)?;
+    assert_eq!(
+        (inv.operation.as_str(), inv.msg.as_slice()),
+        ("Test.Ping", &b"ping"[..])
+    );
+    let received = tap.received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    let resp: InvocationResponse = deserialize(&received[0].1)?;
+    assert_eq!((resp.invocation_id, resp.msg), (inv.id, b"pong".to_vec()));
+    Ok(())
+}
@@ -0,0 +1,32 @@
+//! Operation names and type names reported by derived dispatchers
+use wasmbus_rpc::{
+    actor::prelude::*,
+    common::OperationInfo,
+    dispatch::{IdempotencyCache, OperationAliases},
+};
+
+#[derive(Actor, Default, HealthResponder)]
+#[services(Actor)]
+struct Sample {}
+
+#[test]
+fn derived_operation_info() {
+    let expected = vec![OperationInfo {
+        name: "Actor.HealthRequest",
+        input_type: Some("HealthCheckRequest"),
+        output_type: Some("HealthCheckResponse"),
+        one_way: false,
+    }];
+    // the generated receiver's list doesn't make the dispatcher's ambiguous
+    assert_eq!(Sample::default().operation_info(), expected);
+    assert_eq!(
+        ActorReceiver::service_operation_info(&Sample::default()),
+        expected.as_slice()
+    );
+
+    // dispatch wrappers report the operations of the dispatcher they wrap
+    let wrapped = OperationAliases::new(Sample::default());
+    assert_eq!(wrapped.operation_info(), expected);
+    let wrapped = IdempotencyCache::new(wrapped, std::time::Duration::from_secs(1));
+    assert_eq!(wrapped.operation_info(), expected);
+}
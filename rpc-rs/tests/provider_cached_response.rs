@@ -0,0 +1,103 @@
+//! a provider sends a cached response as it was saved, serializing it again only for
+//! senders that want another format
+#![cfg(test)]
+
+mod common;
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use common::ProviderHarness;
+use wasmbus_rpc::{
+    async_nats::HeaderMap,
+    common::{deserialize, CachedResponse, DispatchResult, MessageFormat},
+    core::InvocationResponse,
+    provider::prelude::*,
+    rpc_client::{ACCEPT_FORMATS_HEADER, FORMAT_HEADER},
+};
+
+/// Number of times a Catalog has been serialized
+static SERIALIZED: AtomicUsize = AtomicUsize::new(0);
+
+struct Catalog(Vec<&'static str>);
+
+impl serde::Serialize for Catalog {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        SERIALIZED.fetch_add(1, Ordering::SeqCst);
+        self.0.serialize(s)
+    }
+}
+
+/// Provider that returns the same catalog to every request
+#[derive(Clone)]
+struct Cached {
+    catalog: Arc<CachedResponse<Catalog>>,
+}
+
+#[async_trait]
+impl ProviderHandler for Cached {
+    fn provider_options(&self) -> ProviderOptions {
+        ProviderOptions::default().message_formats(vec![MessageFormat::Cbor, MessageFormat::Json])
+    }
+}
+
+#[async_trait]
+impl MessageDispatch for Cached {
+    async fn dispatch(&self, ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+        Ok(self.dispatch_with_metadata(ctx, message).await?.body)
+    }
+
+    async fn dispatch_with_metadata(
+        &self,
+        ctx: &Context,
+        message: Message<'_>,
+    ) -> RpcResult<DispatchResult> {
+        match message.method {
+            "Cached.List" => self.catalog.dispatch_result(ctx),
+            _ => Err(RpcError::MethodNotHandled(message.method.to_string())),
+        }
+    }
+}
+
+impl ProviderDispatch for Cached {}
+
+#[tokio::test]
+async fn cached_response_sent_verbatim() -> Result<(), Box<dyn std::error::Error>> {
+    let catalog = CachedResponse::new(Catalog(vec!["apple", "pear"]), MessageFormat::Cbor)?;
+    let saved = catalog.bytes().to_vec();
+    assert_eq!(SERIALIZED.load(Ordering::SeqCst), 1);
+
+    let harness = ProviderHarness::start(Cached { catalog: Arc::new(catalog) }).await;
+    let invocation = harness.invocation("Cached.List", b"").await;
+
+    // senders that name no format, or accept cbor, get the saved bytes
+    let mut accept_cbor = HeaderMap::new();
+    accept_cbor.insert(ACCEPT_FORMATS_HEADER, "cbor");
+    for headers in [HeaderMap::new(), accept_cbor] {
+        let resp = harness.request_with_headers(headers, invocation.clone()).await;
+        let format = resp.headers.as_ref().and_then(|h| h.get(FORMAT_HEADER));
+        assert_eq!(format.map(|v| v.as_str()), Some("cbor"));
+        let resp: InvocationResponse = deserialize(&resp.payload)?;
+        assert_eq!(resp.error, None);
+        assert_eq!(resp.msg, saved);
+    }
+    assert_eq!(SERIALIZED.load(Ordering::SeqCst), 1);
+
+    // a sender that wants json gets the value serialized again
+    let mut accept_json = HeaderMap::new();
+    accept_json.insert(ACCEPT_FORMATS_HEADER, "json");
+    let resp = harness.request_with_headers(accept_json, invocation).await;
+    let format = resp.headers.as_ref().and_then(|h| h.get(FORMAT_HEADER));
+    assert_eq!(format.map(|v| v.as_str()), Some("json"));
+    let resp: InvocationResponse = deserialize(&resp.payload)?;
+    assert_eq!(
+        serde_json::from_slice::<Vec<String>>(&resp.msg)?,
+        vec!["apple", "pear"]
+    );
+    assert_eq!(SERIALIZED.load(Ordering::SeqCst), 2);
+
+    harness.shutdown().await;
+    Ok(())
+}
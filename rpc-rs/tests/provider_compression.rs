@@ -0,0 +1,72 @@
+//! a provider compresses responses according to the policy for each operation
+#![cfg(test)]
+
+mod common;
+
+use common::ProviderHarness;
+use wasmbus_rpc::{
+    async_nats::HeaderMap,
+    common::deserialize,
+    core::InvocationResponse,
+    provider::prelude::*,
+    rpc_client::{ACCEPT_ENCODING_HEADER, DEFLATE_ENCODING, ENCODING_HEADER},
+};
+
+/// Provider whose operations both return 4KB, under different compression policies
+#[derive(Clone, Default)]
+struct Store;
+
+#[async_trait]
+impl ProviderHandler for Store {
+    fn provider_options(&self) -> ProviderOptions {
+        ProviderOptions::default()
+            .compression_threshold(Some(1024))
+            .compression_policy("Store.ReadBlob", CompressionPolicy::Always)
+            .compression_policy("Store.Get", CompressionPolicy::Never)
+    }
+}
+
+#[async_trait]
+impl MessageDispatch for Store {
+    async fn dispatch(&self, _ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+        match message.method {
+            "Store.ReadBlob" | "Store.Get" => Ok(vec![b'x'; 4096]),
+            _ => Err(RpcError::MethodNotHandled(message.method.to_string())),
+        }
+    }
+}
+
+impl ProviderDispatch for Store {}
+
+#[tokio::test]
+async fn policy_per_operation() -> Result<(), Box<dyn std::error::Error>> {
+    let harness = ProviderHarness::start(Store).await;
+    let mut accept = HeaderMap::new();
+    accept.insert(ACCEPT_ENCODING_HEADER, DEFLATE_ENCODING);
+    let mut encodings = Vec::new();
+    for method in ["Store.ReadBlob", "Store.Get"] {
+        let invocation = harness.invocation(method, b"").await;
+        let resp = harness.request_with_headers(accept.clone(), invocation).await;
+        let encoding = resp
+            .headers
+            .as_ref()
+            .and_then(|h| h.get(ENCODING_HEADER))
+            .map(|v| v.as_str().to_string());
+        let payload = match encoding.as_deref() {
+            Some(DEFLATE_ENCODING) => {
+                assert!(resp.payload.len() < 4096, "{}", resp.payload.len());
+                miniz_oxide::inflate::decompress_to_vec(&resp.payload).expect("inflate")
+            }
+            _ => resp.payload.to_vec(),
+        };
+        let resp: InvocationResponse = deserialize(&payload)?;
+        assert_eq!(resp.error, None);
+        assert_eq!(resp.msg, vec![b'x'; 4096]);
+        encodings.push(encoding);
+    }
+    // the blob read is compressed; the get, over the threshold, is not
+    assert_eq!(encodings, vec![Some(DEFLATE_ENCODING.to_string()), None]);
+
+    harness.shutdown().await;
+    Ok(())
+}
@@ -0,0 +1,112 @@
+//! run a provider on a nats connection opened by the test (requires a nats server)
+#![cfg(test)]
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use wasmbus_rpc::{
+    common::{deserialize, serialize},
+    core::{HealthCheckRequest, HealthCheckResponse, HostData, LinkDefinition},
+    provider::prelude::*,
+};
+
+const TEST_NATS_ADDR: &str = "nats://127.0.0.1:4222";
+
+/// Provider that records the callbacks it receives
+#[derive(Clone, Default)]
+struct Recorder {
+    events: Arc<Mutex<Vec<String>>>,
+}
+
+impl Recorder {
+    fn record(&self, event: String) {
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+#[async_trait]
+impl ProviderHandler for Recorder {
+    async fn init(&self, _host_data: &HostData) -> RpcResult<()> {
+        self.record("init".to_string());
+        Ok(())
+    }
+
+    async fn put_link(&self, ld: &LinkDefinition) -> RpcResult<bool> {
+        self.record(format!("link {}", ld.actor_id));
+        Ok(true)
+    }
+
+    async fn shutdown(&self) -> Result<(), std::convert::Infallible> {
+        self.record("shutdown".to_string());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MessageDispatch for Recorder {
+    async fn dispatch(&self, _ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+        Err(RpcError::MethodNotHandled(message.method.to_string()))
+    }
+}
+
+impl ProviderDispatch for Recorder {}
+
+#[tokio::test]
+async fn provider_on_caller_connection() -> Result<(), Box<dyn std::error::Error>> {
+    let nats_url = std::env::var("NATS_URL").unwrap_or_else(|_| TEST_NATS_ADDR.into());
+    let nc = wasmbus_rpc::async_nats::connect(nats_url).await?;
+
+    let mut host_data = HostData::default();
+    host_data.host_id = "_TEST_".to_string();
+    host_data.lattice_rpc_prefix = "test_provider_connection".to_string();
+    // the provider must not connect here
+    host_data.lattice_rpc_url = "nats://127.0.0.1:1".to_string();
+    host_data.provider_key = "VPROVIDER".to_string();
+    host_data.link_name = "default".to_string();
+    let mut ld = LinkDefinition::default();
+    ld.actor_id = "MACTOR".to_string();
+    host_data.link_definitions = vec![ld];
+    let topic = host_data.rpc_subject();
+
+    let provider = Recorder::default();
+    let run = tokio::spawn({
+        let provider = provider.clone();
+        let nc = nc.clone();
+        async move {
+            run_provider_with_connection(provider, host_data, nc)
+                .await
+                .map_err(|e| e.to_string())
+        }
+    });
+
+    // the provider answers health checks once it has subscribed
+    let health = serialize(&HealthCheckRequest::default())?;
+    let mut resp = None;
+    for _ in 0..50 {
+        let req = nc.request(format!("{topic}.health"), health.clone().into());
+        if let Ok(Ok(msg)) = tokio::time::timeout(Duration::from_millis(100), req).await {
+            resp = Some(deserialize::<HealthCheckResponse>(&msg.payload)?);
+            break;
+        }
+    }
+    assert!(resp.expect("no health response").healthy);
+
+    let msg = nc
+        .request(
+            format!("{topic}.shutdown"),
+            br#"{"host_id":"_TEST_"}"#.to_vec().into(),
+        )
+        .await?;
+    assert_eq!(msg.payload.as_ref(), b"shutting down");
+
+    tokio::time::timeout(Duration::from_secs(5), run)
+        .await?
+        .expect("provider task panicked")?;
+    assert_eq!(
+        *provider.events.lock().unwrap(),
+        vec!["init", "link MACTOR", "shutdown"]
+    );
+    Ok(())
+}
@@ -0,0 +1,77 @@
+//! a provider with a dedup window handles a redelivered invocation once
+#![cfg(test)]
+
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use common::ProviderHarness;
+use wasmbus_rpc::{
+    common::{deserialize, serialize},
+    core::Invocation,
+    provider::prelude::*,
+};
+
+/// Provider that counts the calls it handles
+#[derive(Clone, Default)]
+struct Counter {
+    calls: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl ProviderHandler for Counter {
+    fn provider_options(&self) -> ProviderOptions {
+        ProviderOptions::default().dedup_window(Some(Duration::from_secs(60)))
+    }
+}
+
+#[async_trait]
+impl MessageDispatch for Counter {
+    async fn dispatch(&self, _ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+        match message.method {
+            "Counter.Add" => {
+                let n = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+                Ok(n.to_string().into_bytes())
+            }
+            _ => Err(RpcError::MethodNotHandled(message.method.to_string())),
+        }
+    }
+}
+
+impl ProviderDispatch for Counter {}
+
+#[tokio::test]
+async fn redelivery_is_dropped() -> Result<(), Box<dyn std::error::Error>> {
+    let provider = Counter::default();
+    let harness = ProviderHarness::start(provider.clone()).await;
+    let invocation = harness.invocation("Counter.Add", b"").await;
+
+    // a forged invocation reusing the id is rejected, and doesn't claim the id
+    let mut forged: Invocation = deserialize(&invocation)?;
+    forged.msg = b"forged".to_vec();
+    let resp = harness.request(serialize(&forged)?).await;
+    assert!(resp.error.is_some());
+    assert_eq!(provider.calls.load(Ordering::SeqCst), 0);
+
+    let resp = harness.request(invocation.clone()).await;
+    assert_eq!(resp.error, None);
+    assert_eq!(resp.msg, b"1");
+
+    // the same invocation again gets no reply, and doesn't reach the handler
+    let err = harness
+        .transport
+        .request(&harness.topic(), invocation, Duration::from_millis(200))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, RpcError::Timeout(_)), "{err}");
+    assert_eq!(provider.calls.load(Ordering::SeqCst), 1);
+
+    harness.shutdown().await;
+    Ok(())
+}
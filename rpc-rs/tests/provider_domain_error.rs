@@ -0,0 +1,43 @@
+//! a provider's domain error reaches the caller as a ProviderError, with its details
+#![cfg(test)]
+
+mod common;
+
+use common::{ProviderHarness, TIMEOUT};
+use wasmbus_rpc::{error::ProviderError, provider::prelude::*};
+
+/// Provider that refuses every withdrawal
+#[derive(Clone)]
+struct Bank;
+
+impl ProviderHandler for Bank {}
+
+#[async_trait]
+impl MessageDispatch for Bank {
+    async fn dispatch(&self, _ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+        match message.method {
+            "Bank.Withdraw" => Err(ProviderError::new("insufficient_funds", "balance too low")
+                .with_details(&42u64)?
+                .into()),
+            _ => Err(RpcError::MethodNotHandled(message.method.to_string())),
+        }
+    }
+}
+
+impl ProviderDispatch for Bank {}
+
+#[tokio::test]
+async fn domain_error_reaches_client() -> Result<(), Box<dyn std::error::Error>> {
+    let harness = ProviderHarness::start(Bank).await;
+
+    let invocation = harness.invocation("Bank.Withdraw", b"").await;
+    let resp = harness.transport.request(&harness.topic(), invocation, TIMEOUT).await?;
+    let err = RpcError::from_response_bytes(&resp.payload)?;
+    let pe = err.provider_error().expect("domain error");
+    assert_eq!(pe.code, "insufficient_funds");
+    assert_eq!(pe.message, "balance too low");
+    assert_eq!(pe.details::<u64>()?, 42);
+
+    harness.shutdown().await;
+    Ok(())
+}
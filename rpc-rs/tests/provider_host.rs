@@ -0,0 +1,74 @@
+//! run two providers in one process, over the in-memory transport
+#![cfg(test)]
+
+mod common;
+
+use std::sync::Arc;
+
+use common::{eventually, host_data, shutdown, Invoker, PROVIDER_SUBSCRIPTIONS, TIMEOUT};
+use wascap::prelude::KeyPair;
+use wasmbus_rpc::{
+    common::deserialize, core::InvocationResponse, provider::prelude::*,
+    provider_transport::MemoryTransport,
+};
+
+/// Provider that replies with its name
+#[derive(Clone)]
+struct Named(&'static str);
+
+#[async_trait]
+impl ProviderHandler for Named {}
+
+#[async_trait]
+impl MessageDispatch for Named {
+    async fn dispatch(&self, _ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+        match message.method {
+            "Named.Who" => Ok(self.0.as_bytes().to_vec()),
+            _ => Err(RpcError::MethodNotHandled(message.method.to_string())),
+        }
+    }
+}
+
+impl ProviderDispatch for Named {}
+
+#[tokio::test]
+async fn each_provider_gets_its_own_rpcs() -> Result<(), Box<dyn std::error::Error>> {
+    let cluster_key = KeyPair::new_cluster();
+    let host_key = KeyPair::new_server();
+    let first = host_data(&host_key, &cluster_key, "VPROVIDERA");
+    let second = host_data(&host_key, &cluster_key, "VPROVIDERB");
+
+    let nc = common::unconnected_nats().await;
+    let transport = MemoryTransport::new();
+    let mut host = ProviderHost::with_transport(nc.clone(), Arc::new(transport.clone()));
+    let mut events = host.register(first.clone(), Named("first"))?.subscribe_events();
+    host.register(second.clone(), Named("second"))?;
+    let err = host.register(second.clone(), Named("again")).unwrap_err();
+    assert!(matches!(err, RpcError::InvalidParameter(_)), "{err}");
+    let run = tokio::spawn(async move { host.run().await.map_err(|e| e.to_string()) });
+
+    eventually("provider subscriptions", || {
+        transport.subscriptions() == 2 * PROVIDER_SUBSCRIPTIONS
+    })
+    .await;
+    let event = tokio::time::timeout(TIMEOUT, events.recv()).await??;
+    assert_eq!(event, ProviderEvent::Ready);
+
+    let invoker = Invoker::new(nc, cluster_key, &first.lattice_rpc_prefix);
+    for (host_data, name) in [(&first, "first"), (&second, "second")] {
+        let inv = invoker.invocation(&host_data.provider_key, "Named.Who", b"").await;
+        let resp = transport.request(&host_data.rpc_subject(), inv, TIMEOUT).await?;
+        let resp: InvocationResponse = deserialize(&resp.payload)?;
+        assert_eq!(resp.error, None);
+        assert_eq!(resp.msg, name.as_bytes());
+    }
+
+    // the host returns after both providers are shut down
+    for host_data in [&first, &second] {
+        shutdown(&transport, host_data).await;
+    }
+    tokio::time::timeout(TIMEOUT, run)
+        .await?
+        .expect("provider host panicked")?;
+    Ok(())
+}
@@ -0,0 +1,54 @@
+//! a provider is signalled ready once, after its host subscriptions are live
+#![cfg(test)]
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::{eventually, ProviderHarness, PROVIDER_SUBSCRIPTIONS};
+use wasmbus_rpc::{provider::prelude::*, provider_transport::MemoryTransport};
+
+/// Provider that records the number of subscriptions on the transport when it's ready
+#[derive(Clone)]
+struct Readiness {
+    transport: MemoryTransport,
+    ready: Arc<Mutex<Vec<usize>>>,
+}
+
+#[async_trait]
+impl ProviderHandler for Readiness {
+    async fn ready(&self) {
+        let subscriptions = self.transport.subscriptions();
+        self.ready.lock().unwrap().push(subscriptions);
+    }
+}
+
+#[async_trait]
+impl MessageDispatch for Readiness {
+    async fn dispatch(&self, _ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+        Err(RpcError::MethodNotHandled(message.method.to_string()))
+    }
+}
+
+impl ProviderDispatch for Readiness {}
+
+#[tokio::test]
+async fn ready_after_subscribing() {
+    let transport = MemoryTransport::new();
+    let provider = Readiness {
+        transport: transport.clone(),
+        ready: Arc::new(Mutex::new(Vec::new())),
+    };
+    let harness =
+        ProviderHarness::start_on(transport, provider.clone(), |host_data| host_data).await;
+    eventually("provider ready", || {
+        !provider.ready.lock().unwrap().is_empty()
+    })
+    .await;
+
+    harness.shutdown().await;
+    assert_eq!(
+        *provider.ready.lock().unwrap(),
+        vec![PROVIDER_SUBSCRIPTIONS]
+    );
+}
@@ -0,0 +1,99 @@
+//! a provider replies in the format of the request, unless its handler names another,
+//! and names a format only when the handler wrote the reply in it
+#![cfg(test)]
+
+mod common;
+
+use common::{ProviderHarness, TIMEOUT};
+use wasmbus_rpc::{
+    async_nats::HeaderMap,
+    common::{deserialize, serialize, serialize_as, DispatchResult, MessageFormat},
+    core::InvocationResponse,
+    provider::prelude::*,
+    rpc_client::FORMAT_HEADER,
+};
+
+/// Provider that writes cbor by default, and json if asked
+#[derive(Clone)]
+struct Formats;
+
+#[async_trait]
+impl ProviderHandler for Formats {
+    fn provider_options(&self) -> ProviderOptions {
+        ProviderOptions::default().message_formats(vec![MessageFormat::Cbor, MessageFormat::Json])
+    }
+}
+
+#[async_trait]
+impl MessageDispatch for Formats {
+    async fn dispatch(&self, ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+        Ok(self.dispatch_with_metadata(ctx, message).await?.body)
+    }
+
+    async fn dispatch_with_metadata(
+        &self,
+        ctx: &Context,
+        message: Message<'_>,
+    ) -> RpcResult<DispatchResult> {
+        match message.method {
+            "Formats.Get" => DispatchResult::serialize_reply(ctx, &vec![1u32, 2, 3]),
+            // msgpack, without saying so
+            "Formats.Raw" => serialize(&vec![1u32, 2, 3]).map(DispatchResult::from),
+            // always cbor, whatever the request was
+            "Formats.Cbor" => serialize_as(&vec![1u32, 2, 3], MessageFormat::Cbor)
+                .map(|body| DispatchResult::from(body).with_format(MessageFormat::Cbor)),
+            _ => Err(RpcError::MethodNotHandled(message.method.to_string())),
+        }
+    }
+}
+
+impl ProviderDispatch for Formats {}
+
+#[tokio::test]
+async fn reply_in_request_format() -> Result<(), Box<dyn std::error::Error>> {
+    let harness = ProviderHarness::start(Formats).await;
+    let mut invocations = Vec::new();
+    for method in ["Formats.Get", "Formats.Cbor", "Formats.Raw"] {
+        invocations.push(harness.invocation(method, b"{}").await);
+    }
+    let mut json_request = HeaderMap::new();
+    json_request.insert(FORMAT_HEADER, "json");
+
+    // a json request gets a json reply
+    let resp = harness
+        .request_with_headers(json_request.clone(), invocations[0].clone())
+        .await;
+    let format = resp.headers.as_ref().and_then(|h| h.get(FORMAT_HEADER));
+    assert_eq!(format.map(|v| v.as_str()), Some("json"));
+    let resp: InvocationResponse = deserialize(&resp.payload)?;
+    assert_eq!(resp.error, None);
+    assert_eq!(
+        serde_json::from_slice::<Vec<u32>>(&resp.msg)?,
+        vec![1, 2, 3]
+    );
+
+    // a request that doesn't name its format gets no format header
+    let resp = harness
+        .transport
+        .request(&harness.topic(), invocations[0].clone(), TIMEOUT)
+        .await?;
+    assert!(resp.headers.as_ref().and_then(|h| h.get(FORMAT_HEADER)).is_none());
+
+    // the handler's own choice wins
+    let resp = harness
+        .request_with_headers(json_request.clone(), invocations[1].clone())
+        .await;
+    let format = resp.headers.as_ref().and_then(|h| h.get(FORMAT_HEADER));
+    assert_eq!(format.map(|v| v.as_str()), Some("cbor"));
+    let resp: InvocationResponse = deserialize(&resp.payload)?;
+    assert_eq!(resp.msg.first(), Some(&0x7f));
+
+    // a reply the handler didn't label isn't labelled with the requested format
+    let resp = harness.request_with_headers(json_request, invocations[2].clone()).await;
+    assert!(resp.headers.as_ref().and_then(|h| h.get(FORMAT_HEADER)).is_none());
+    let resp: InvocationResponse = deserialize(&resp.payload)?;
+    assert_eq!(deserialize::<Vec<u32>>(&resp.msg)?, vec![1, 2, 3]);
+
+    harness.shutdown().await;
+    Ok(())
+}
@@ -0,0 +1,78 @@
+//! headers a handler sets reach the reply through dispatch wrappers,
+//! and headers that can't be sent fail the request
+#![cfg(test)]
+
+mod common;
+
+use common::ProviderHarness;
+use wasmbus_rpc::{common::DispatchResult, dispatch::SingleFlight, provider::prelude::*};
+
+/// Dispatcher that sets reply headers
+#[derive(Clone)]
+struct Tagged;
+
+#[async_trait]
+impl MessageDispatch for Tagged {
+    async fn dispatch(&self, ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+        Ok(self.dispatch_with_metadata(ctx, message).await?.body)
+    }
+
+    async fn dispatch_with_metadata(
+        &self,
+        _ctx: &Context,
+        message: Message<'_>,
+    ) -> RpcResult<DispatchResult> {
+        let reply = DispatchResult::from(message.arg.to_vec());
+        match message.method {
+            "Tagged.Get" => Ok(reply.with_header("Cache-Control", "max-age=60")),
+            "Tagged.BadName" => Ok(reply.with_header("Cache Control", "max-age=60")),
+            "Tagged.BadValue" => Ok(reply.with_header("Cache-Control", "max-age=60\r\nX-Evil: 1")),
+            _ => Err(RpcError::MethodNotHandled(message.method.to_string())),
+        }
+    }
+}
+
+/// Provider that dispatches through a wrapper
+#[derive(Clone)]
+struct Wrapped(SingleFlight<Tagged>);
+
+impl ProviderHandler for Wrapped {}
+
+#[async_trait]
+impl MessageDispatch for Wrapped {
+    async fn dispatch(&self, ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+        self.0.dispatch(ctx, message).await
+    }
+
+    async fn dispatch_with_metadata(
+        &self,
+        ctx: &Context,
+        message: Message<'_>,
+    ) -> RpcResult<DispatchResult> {
+        self.0.dispatch_with_metadata(ctx, message).await
+    }
+}
+
+impl ProviderDispatch for Wrapped {}
+
+#[tokio::test]
+async fn reply_headers() {
+    let harness = ProviderHarness::start(Wrapped(SingleFlight::new(Tagged))).await;
+
+    let invocation = harness.invocation("Tagged.Get", b"abc").await;
+    let resp = harness.request_with_headers(Default::default(), invocation).await;
+    let cache = resp.headers.as_ref().and_then(|h| h.get("Cache-Control"));
+    assert_eq!(cache.map(|v| v.as_str()), Some("max-age=60"));
+
+    for (method, problem) in [
+        ("Tagged.BadName", "invalid header name 'Cache Control'"),
+        ("Tagged.BadValue", "may not contain line breaks"),
+    ] {
+        let resp = harness.request(harness.invocation(method, b"abc").await).await;
+        let error = resp.error.expect("request fails");
+        assert!(error.contains(problem), "{error}");
+        assert!(resp.msg.is_empty());
+    }
+
+    harness.shutdown().await;
+}
@@ -0,0 +1,58 @@
+//! a handler that runs past the provider's handler timeout is answered with a server-side timeout
+#![cfg(test)]
+
+mod common;
+
+use std::time::Duration;
+
+use common::{ProviderHarness, TIMEOUT};
+use wasmbus_rpc::provider::{prelude::*, SERVER_TIMEOUT};
+
+/// Provider with a handler that takes longer than its handler timeout
+#[derive(Clone, Default)]
+struct Slow;
+
+#[async_trait]
+impl ProviderHandler for Slow {
+    fn provider_options(&self) -> ProviderOptions {
+        ProviderOptions::default().handler_timeout(Some(Duration::from_millis(100)))
+    }
+}
+
+#[async_trait]
+impl MessageDispatch for Slow {
+    async fn dispatch(&self, _ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+        match message.method {
+            "Slow.Report" => {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                Ok(Vec::new())
+            }
+            _ => Err(RpcError::MethodNotHandled(message.method.to_string())),
+        }
+    }
+}
+
+impl ProviderDispatch for Slow {}
+
+#[tokio::test]
+async fn server_side_timeout() -> Result<(), Box<dyn std::error::Error>> {
+    let harness = ProviderHarness::start(Slow).await;
+    let invocation = harness.invocation("Slow.Report", b"").await;
+
+    // the caller hears from the provider well before its own timeout
+    let started = std::time::Instant::now();
+    let resp = harness.transport.request(&harness.topic(), invocation, TIMEOUT).await?;
+    assert!(
+        started.elapsed() < Duration::from_secs(2),
+        "{:?}",
+        started.elapsed()
+    );
+    let err = RpcError::from_response_bytes(&resp.payload)?;
+    assert!(
+        matches!(&err, RpcError::DeadlineExceeded(s) if s == &format!("Slow.Report: {SERVER_TIMEOUT}")),
+        "{err}"
+    );
+
+    harness.shutdown().await;
+    Ok(())
+}
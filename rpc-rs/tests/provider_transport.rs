@@ -0,0 +1,207 @@
+//! run a provider over the in-memory transport, without a nats server
+#![cfg(test)]
+
+mod common;
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use common::{eventually, ProviderHarness, ACTOR_ID, TIMEOUT};
+use wasmbus_rpc::{
+    async_nats::HeaderMap,
+    common::{deserialize, serialize, MessageFormat, OperationInfo, SendOpts},
+    core::{HealthCheckRequest, HealthCheckResponse, InvocationResponse, LinkDefinition},
+    provider::prelude::*,
+    rpc_client::{
+        payload_checksum, ACCEPT_FORMATS_HEADER, CHECKSUM_HEADER, FORMAT_HEADER, SENT_AT_HEADER,
+    },
+    Timestamp,
+};
+
+/// Provider that records the callbacks it receives, and echoes rpc messages
+#[derive(Clone, Default)]
+struct Echo {
+    events: Arc<Mutex<Vec<String>>>,
+}
+
+impl Echo {
+    fn record(&self, event: String) {
+        self.events.lock().unwrap().push(event);
+    }
+
+    fn events(&self) -> Vec<String> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl ProviderHandler for Echo {
+    async fn put_link(&self, ld: &LinkDefinition) -> RpcResult<bool> {
+        self.record(format!("link {}", ld.actor_id));
+        Ok(true)
+    }
+
+    async fn shutdown(&self) -> Result<(), std::convert::Infallible> {
+        self.record("shutdown".to_string());
+        Ok(())
+    }
+
+    fn provider_version(&self) -> Option<String> {
+        Some("1.2.3".to_string())
+    }
+
+    fn provider_options(&self) -> ProviderOptions {
+        ProviderOptions::default().message_formats(vec![MessageFormat::Cbor])
+    }
+}
+
+const ONE_WAY_OPS: &[OperationInfo] = &[OperationInfo {
+    name: "Echo.Log",
+    input_type: None,
+    output_type: None,
+    one_way: true,
+}];
+
+#[async_trait]
+impl MessageDispatch for Echo {
+    async fn dispatch(&self, ctx: &Context, message: Message<'_>) -> RpcResult<Vec<u8>> {
+        match message.method {
+            "Echo.Say" => Ok(message.arg.to_vec()),
+            "Echo.Tenant" => Ok(ctx.header("X-Tenant").unwrap_or_default().as_bytes().to_vec()),
+            "Echo.Skew" => serialize(&ctx.apparent_skew().map(|skew| skew.as_millis() as u64)),
+            "Echo.Log" => {
+                self.record(format!("log {}", String::from_utf8_lossy(&message.arg)));
+                Ok(Vec::new())
+            }
+            _ => Err(RpcError::MethodNotHandled(message.method.to_string())),
+        }
+    }
+
+    fn operation_info(&self) -> Vec<OperationInfo> {
+        ONE_WAY_OPS.to_vec()
+    }
+}
+
+impl ProviderDispatch for Echo {}
+
+#[tokio::test]
+async fn provider_over_memory_transport() -> Result<(), Box<dyn std::error::Error>> {
+    // start unlinked, to see the link arrive
+    let provider = Echo::default();
+    let harness = ProviderHarness::start_with(provider.clone(), |mut host_data| {
+        host_data.link_definitions.clear();
+        host_data
+    })
+    .await;
+    let (transport, topic) = (harness.transport.clone(), harness.topic());
+
+    let mut ld = LinkDefinition::default();
+    ld.actor_id = ACTOR_ID.to_string();
+    ld.provider_id = harness.host_data.provider_key.clone();
+    ld.link_name = harness.host_data.link_name.clone();
+    transport.send(
+        &format!("{topic}.linkdefs.put"),
+        None,
+        None,
+        serialize(&ld)?,
+    );
+    eventually("link", || {
+        provider.events().contains(&"link MACTOR".to_string())
+    })
+    .await;
+
+    let health = serialize(&HealthCheckRequest::default())?;
+    let resp = transport.request(&format!("{topic}.health"), health, TIMEOUT).await?;
+    assert!(deserialize::<HealthCheckResponse>(&resp.payload)?.healthy);
+
+    // a signed invocation, as the host would send it
+    let invocation = harness.invocation("Echo.Say", b"hello").await;
+    let resp = transport.request(&topic, invocation.clone(), TIMEOUT).await?;
+    let resp: InvocationResponse = deserialize(&resp.payload)?;
+    assert_eq!(resp.error, None);
+    assert_eq!(resp.msg, b"hello");
+
+    // with a checksum, the response is checksummed too
+    let mut headers = HeaderMap::new();
+    headers.insert(CHECKSUM_HEADER, payload_checksum(&invocation).as_str());
+    let resp = transport
+        .request_with_headers(&topic, headers.clone(), invocation.clone(), TIMEOUT)
+        .await?;
+    let sum = resp
+        .headers
+        .as_ref()
+        .and_then(|h| h.get(CHECKSUM_HEADER))
+        .expect("checksum");
+    assert_eq!(sum.as_str(), payload_checksum(&resp.payload));
+
+    // the echo isn't written in a negotiated format, so the reply names none
+    let mut accept = HeaderMap::new();
+    accept.insert(ACCEPT_FORMATS_HEADER, "json,cbor");
+    let resp = transport
+        .request_with_headers(&topic, accept, invocation.clone(), TIMEOUT)
+        .await?;
+    assert!(resp.headers.as_ref().and_then(|h| h.get(FORMAT_HEADER)).is_none());
+
+    // a byte flipped in transit is rejected
+    let mut corrupted = invocation;
+    let last = corrupted.len() - 1;
+    corrupted[last] ^= 0x20;
+    let resp = transport.request_with_headers(&topic, headers, corrupted, TIMEOUT).await?;
+    let resp: InvocationResponse = deserialize(&resp.payload)?;
+    assert!(resp.error.unwrap_or_default().contains("checksum mismatch"));
+
+    // a send time from a clock 30s behind ours shows up as skew
+    let invocation = harness.invocation("Echo.Skew", b"").await;
+    let resp = transport.request(&topic, invocation.clone(), TIMEOUT).await?;
+    let resp: InvocationResponse = deserialize(&resp.payload)?;
+    assert_eq!(deserialize::<Option<u64>>(&resp.msg)?, None);
+    let sent_at = Timestamp::now().as_nanos() - Duration::from_secs(30).as_nanos();
+    let mut headers = HeaderMap::new();
+    headers.insert(SENT_AT_HEADER, sent_at.to_string().as_str());
+    let resp = transport
+        .request_with_headers(&topic, headers, invocation, TIMEOUT)
+        .await?;
+    let resp: InvocationResponse = deserialize(&resp.payload)?;
+    let skew = deserialize::<Option<u64>>(&resp.msg)?.expect("skew");
+    assert!((30_000..31_000).contains(&skew), "{skew}");
+
+    // custom headers reach the handler's context
+    let opts = SendOpts::default().header("X-Tenant", "acme");
+    let (headers, invocation) = harness.invocation_with_opts("Echo.Tenant", b"", &opts).await;
+    let resp = transport
+        .request_with_headers(&topic, headers, invocation, TIMEOUT)
+        .await?;
+    let resp: InvocationResponse = deserialize(&resp.payload)?;
+    assert_eq!(resp.msg, b"acme");
+
+    // a one-way operation is handled, with no reply
+    let invocation = harness.invocation("Echo.Log", b"quiet").await;
+    let err = transport
+        .request(&topic, invocation, Duration::from_millis(200))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, RpcError::Timeout(_)), "{err}");
+    eventually("one-way call", || {
+        provider.events().contains(&"log quiet".to_string())
+    })
+    .await;
+
+    // the built-in version operation needs no handler
+    let invocation = harness.invocation(VERSION_OPERATION, b"").await;
+    let resp = transport.request(&topic, invocation, TIMEOUT).await?;
+    let resp: InvocationResponse = deserialize(&resp.payload)?;
+    assert_eq!(resp.error, None);
+    let info: VersionInfo = deserialize(&resp.msg)?;
+    assert_eq!(info.weld_version, wasmbus_rpc::WELD_CRATE_VERSION);
+    assert_eq!(info.provider_version.as_deref(), Some("1.2.3"));
+
+    harness.shutdown().await;
+    assert_eq!(
+        provider.events(),
+        vec!["link MACTOR", "log quiet", "shutdown"]
+    );
+    eventually("unsubscribe", || transport.subscriptions() == 0).await;
+    Ok(())
+}
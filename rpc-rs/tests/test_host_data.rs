@@ -0,0 +1,38 @@
+//! Provider set up from HostData::test_default (requires the `testing` feature)
+use wasmbus_rpc::{
+    async_nats::ConnectOptions,
+    core::{HostData, LinkDefinition},
+    provider::{HostBridge, ProviderOptions},
+    testing::{TEST_LATTICE_PREFIX, TEST_PROVIDER_KEY},
+};
+
+#[tokio::test]
+async fn test_default_provider() {
+    let mut ld = LinkDefinition::default();
+    ld.actor_id = "MACTOR".to_string();
+    ld.provider_id = TEST_PROVIDER_KEY.to_string();
+    ld.link_name = "backup".to_string();
+    let host_data = HostData::test_default()
+        .with_link_name("backup")
+        .with_config_json(r#"{"region":"east"}"#)
+        .with_link(ld);
+    assert!(host_data.is_test());
+    host_data.validate().unwrap();
+
+    // a client that is never connected stands in for the lattice
+    let nc = ConnectOptions::new()
+        .retry_on_initial_connect()
+        .connect("nats://127.0.0.1:1")
+        .await
+        .unwrap();
+    let bridge = HostBridge::new_with_options(nc, &host_data, ProviderOptions::default()).unwrap();
+    assert_eq!(bridge.host_id(), "_TEST_");
+    assert_eq!(bridge.lattice_prefix(), TEST_LATTICE_PREFIX);
+    assert_eq!(bridge.provider_key(), TEST_PROVIDER_KEY);
+    assert_eq!(bridge.link_name(), "backup");
+    assert_eq!(bridge.config_json_raw(), Some(r#"{"region":"east"}"#));
+    assert_eq!(
+        bridge.provider_rpc_topic(),
+        format!("wasmbus.rpc.{TEST_LATTICE_PREFIX}.{TEST_PROVIDER_KEY}.backup")
+    );
+}